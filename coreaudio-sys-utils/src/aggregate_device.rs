@@ -15,3 +15,12 @@ pub const AGGREGATE_DEVICE_SUB_DEVICE_LIST_KEY: &str = "subdevices";
 
 // A compile-time static string mapped to kAudioSubDeviceUIDKey
 pub const SUB_DEVICE_UID_KEY: &str = "uid";
+
+// The process taps are available since macOS 14.2, so the selectors below are not in the
+// bindings generated by coreaudio-sys yet.
+
+// kAudioAggregateDevicePropertyTapList: 'tap#'
+pub const AGGREGATE_DEVICE_PROPERTY_TAP_LIST: u32 = 0x7461_7023;
+
+// kAudioTapPropertyUID: 'tuid'
+pub const TAP_PROPERTY_UID: u32 = 0x7475_6964;
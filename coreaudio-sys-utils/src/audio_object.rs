@@ -1,31 +1,176 @@
+use crate::process_tap;
 use coreaudio_sys::*;
 use std::fmt;
 use std::os::raw::c_void;
 use std::ptr;
 
-pub fn audio_object_has_property(id: AudioObjectID, address: &AudioObjectPropertyAddress) -> bool {
-    unsafe { AudioObjectHasProperty(id, address) != 0 }
+#[allow(non_camel_case_types)]
+pub type audio_object_property_listener_proc =
+    extern "C" fn(AudioObjectID, u32, *const AudioObjectPropertyAddress, *mut c_void) -> OSStatus;
+
+// HardwareAbstraction: The operations on the AudioObjects of the HAL.
+// ------------------------------------------------------------------------------------------------
+// All the `audio_object_*` functions below are forwarded to the `HardwareAbstraction` they're
+// given. `CoreAudioHardware` calls the CoreAudio APIs directly. Another implementation, like an
+// in-memory fake system, can be given instead.
+pub trait HardwareAbstraction: Send + Sync {
+    fn has_property(&self, id: AudioObjectID, address: &AudioObjectPropertyAddress) -> bool;
+
+    fn get_property_data_size(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        qualifier_size: usize,
+        qualifier_data: *const c_void,
+        size: *mut usize,
+    ) -> OSStatus;
+
+    fn get_property_data(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        qualifier_size: usize,
+        qualifier_data: *const c_void,
+        size: *mut usize,
+        data: *mut c_void,
+    ) -> OSStatus;
+
+    fn set_property_data(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        size: usize,
+        data: *const c_void,
+    ) -> OSStatus;
+
+    fn add_property_listener(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        listener: audio_object_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus;
+
+    fn remove_property_listener(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        listener: audio_object_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus;
+
+    fn create_process_tap(&self, device_uid: CFStringRef, tap: *mut AudioObjectID) -> OSStatus;
+
+    fn destroy_process_tap(&self, tap: AudioObjectID) -> OSStatus;
+}
+
+#[derive(Debug, Default)]
+pub struct CoreAudioHardware;
+
+impl HardwareAbstraction for CoreAudioHardware {
+    fn has_property(&self, id: AudioObjectID, address: &AudioObjectPropertyAddress) -> bool {
+        unsafe { AudioObjectHasProperty(id, address) != 0 }
+    }
+
+    fn get_property_data_size(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        qualifier_size: usize,
+        qualifier_data: *const c_void,
+        size: *mut usize,
+    ) -> OSStatus {
+        unsafe {
+            AudioObjectGetPropertyDataSize(
+                id,
+                address,
+                qualifier_size as UInt32,
+                qualifier_data,
+                size as *mut UInt32,
+            )
+        }
+    }
+
+    fn get_property_data(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        qualifier_size: usize,
+        qualifier_data: *const c_void,
+        size: *mut usize,
+        data: *mut c_void,
+    ) -> OSStatus {
+        unsafe {
+            AudioObjectGetPropertyData(
+                id,
+                address,
+                qualifier_size as UInt32,
+                qualifier_data,
+                size as *mut UInt32,
+                data,
+            )
+        }
+    }
+
+    fn set_property_data(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        size: usize,
+        data: *const c_void,
+    ) -> OSStatus {
+        unsafe { AudioObjectSetPropertyData(id, address, 0, ptr::null(), size as UInt32, data) }
+    }
+
+    fn add_property_listener(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        listener: audio_object_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        unsafe { AudioObjectAddPropertyListener(id, address, Some(listener), data) }
+    }
+
+    fn remove_property_listener(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        listener: audio_object_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        unsafe { AudioObjectRemovePropertyListener(id, address, Some(listener), data) }
+    }
+
+    fn create_process_tap(&self, device_uid: CFStringRef, tap: *mut AudioObjectID) -> OSStatus {
+        unsafe { process_tap::create_process_tap(device_uid, &mut *tap) }
+    }
+
+    fn destroy_process_tap(&self, tap: AudioObjectID) -> OSStatus {
+        process_tap::destroy_process_tap(tap)
+    }
+}
+
+pub fn audio_object_has_property(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioObjectID,
+    address: &AudioObjectPropertyAddress,
+) -> bool {
+    hardware.has_property(id, address)
 }
 
 pub fn audio_object_get_property_data<T>(
+    hardware: &dyn HardwareAbstraction,
     id: AudioObjectID,
     address: &AudioObjectPropertyAddress,
     size: *mut usize,
     data: *mut T,
 ) -> OSStatus {
-    unsafe {
-        AudioObjectGetPropertyData(
-            id,
-            address,
-            0,
-            ptr::null(),
-            size as *mut UInt32,
-            data as *mut c_void,
-        )
-    }
+    hardware.get_property_data(id, address, 0, ptr::null(), size, data as *mut c_void)
 }
 
 pub fn audio_object_get_property_data_with_qualifier<T, Q>(
+    hardware: &dyn HardwareAbstraction,
     id: AudioObjectID,
     address: &AudioObjectPropertyAddress,
     qualifier_size: usize,
@@ -33,82 +178,85 @@ pub fn audio_object_get_property_data_with_qualifier<T, Q>(
     size: *mut usize,
     data: *mut T,
 ) -> OSStatus {
-    unsafe {
-        AudioObjectGetPropertyData(
-            id,
-            address,
-            qualifier_size as UInt32,
-            qualifier_data as *const c_void,
-            size as *mut UInt32,
-            data as *mut c_void,
-        )
-    }
+    hardware.get_property_data(
+        id,
+        address,
+        qualifier_size,
+        qualifier_data as *const c_void,
+        size,
+        data as *mut c_void,
+    )
 }
 
 pub fn audio_object_get_property_data_size(
+    hardware: &dyn HardwareAbstraction,
     id: AudioObjectID,
     address: &AudioObjectPropertyAddress,
     size: *mut usize,
 ) -> OSStatus {
-    unsafe { AudioObjectGetPropertyDataSize(id, address, 0, ptr::null(), size as *mut UInt32) }
+    hardware.get_property_data_size(id, address, 0, ptr::null(), size)
 }
 
 pub fn audio_object_get_property_data_size_with_qualifier<Q>(
+    hardware: &dyn HardwareAbstraction,
     id: AudioObjectID,
     address: &AudioObjectPropertyAddress,
     qualifier_size: usize,
     qualifier_data: *const Q,
     size: *mut usize,
 ) -> OSStatus {
-    unsafe {
-        AudioObjectGetPropertyDataSize(
-            id,
-            address,
-            qualifier_size as UInt32,
-            qualifier_data as *const c_void,
-            size as *mut UInt32,
-        )
-    }
+    hardware.get_property_data_size(
+        id,
+        address,
+        qualifier_size,
+        qualifier_data as *const c_void,
+        size,
+    )
 }
 
 pub fn audio_object_set_property_data<T>(
+    hardware: &dyn HardwareAbstraction,
     id: AudioObjectID,
     address: &AudioObjectPropertyAddress,
     size: usize,
     data: *const T,
 ) -> OSStatus {
-    unsafe {
-        AudioObjectSetPropertyData(
-            id,
-            address,
-            0,
-            ptr::null(),
-            size as UInt32,
-            data as *const c_void,
-        )
-    }
+    hardware.set_property_data(id, address, size, data as *const c_void)
 }
 
-#[allow(non_camel_case_types)]
-pub type audio_object_property_listener_proc =
-    extern "C" fn(AudioObjectID, u32, *const AudioObjectPropertyAddress, *mut c_void) -> OSStatus;
-
 pub fn audio_object_add_property_listener<T>(
+    hardware: &dyn HardwareAbstraction,
     id: AudioObjectID,
     address: &AudioObjectPropertyAddress,
     listener: audio_object_property_listener_proc,
     data: *mut T,
 ) -> OSStatus {
-    unsafe { AudioObjectAddPropertyListener(id, address, Some(listener), data as *mut c_void) }
+    hardware.add_property_listener(id, address, listener, data as *mut c_void)
 }
 
 pub fn audio_object_remove_property_listener<T>(
+    hardware: &dyn HardwareAbstraction,
     id: AudioObjectID,
     address: &AudioObjectPropertyAddress,
     listener: audio_object_property_listener_proc,
     data: *mut T,
 ) -> OSStatus {
-    unsafe { AudioObjectRemovePropertyListener(id, address, Some(listener), data as *mut c_void) }
+    hardware.remove_property_listener(id, address, listener, data as *mut c_void)
+}
+
+pub fn audio_hardware_create_process_tap(
+    hardware: &dyn HardwareAbstraction,
+    device_uid: CFStringRef,
+    tap: &mut AudioObjectID,
+) -> OSStatus {
+    hardware.create_process_tap(device_uid, tap)
+}
+
+pub fn audio_hardware_destroy_process_tap(
+    hardware: &dyn HardwareAbstraction,
+    tap: AudioObjectID,
+) -> OSStatus {
+    hardware.destroy_process_tap(tap)
 }
 
 #[derive(Debug)]
@@ -132,6 +280,7 @@ impl fmt::Display for PropertySelector {
             }
             sys::kAudioDevicePropertyDeviceIsAlive => "kAudioDevicePropertyDeviceIsAlive",
             sys::kAudioDevicePropertyDataSource => "kAudioDevicePropertyDataSource",
+            sys::kAudioHardwarePropertyDevices => "kAudioHardwarePropertyDevices",
             _ => "Unknown",
         };
         write!(f, "{}", s)
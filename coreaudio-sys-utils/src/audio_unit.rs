@@ -3,7 +3,260 @@ use std::convert::TryFrom;
 use std::os::raw::c_void;
 use std::ptr;
 
+// The status of `audio_unit_new` when no AudioComponent matches the description, which none of
+// the CoreAudio errors means: 'ncmp'.
+pub const AUDIO_COMPONENT_NOT_FOUND: OSStatus = 0x6e63_6d70;
+
+#[allow(non_camel_case_types)]
+pub type audio_unit_property_listener_proc =
+    extern "C" fn(*mut c_void, AudioUnit, AudioUnitPropertyID, AudioUnitScope, AudioUnitElement);
+
+// AudioUnitAbstraction: The operations on the AudioUnits.
+// ------------------------------------------------------------------------------------------------
+// All the `audio_unit_*` functions below are forwarded to the `AudioUnitAbstraction` they're
+// given. `CoreAudioUnits` calls the CoreAudio APIs directly. Another implementation, like a
+// simulated AudioUnit driven by a virtual clock, can be given instead.
+pub trait AudioUnitAbstraction: Send + Sync {
+    fn new_unit(&self, desc: &AudioComponentDescription, unit: &mut AudioUnit) -> OSStatus;
+
+    fn dispose(&self, unit: AudioUnit) -> OSStatus;
+
+    fn get_property_info(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        size: *mut usize,
+        writable: *mut bool,
+    ) -> OSStatus;
+
+    fn get_property(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        data: *mut c_void,
+        size: *mut usize,
+    ) -> OSStatus;
+
+    fn set_property(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        data: *const c_void,
+        size: usize,
+    ) -> OSStatus;
+
+    fn get_parameter(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitParameterID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        value: *mut AudioUnitParameterValue,
+    ) -> OSStatus;
+
+    fn set_parameter(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitParameterID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        value: AudioUnitParameterValue,
+        buffer_offset_in_frames: UInt32,
+    ) -> OSStatus;
+
+    fn initialize(&self, unit: AudioUnit) -> OSStatus;
+
+    fn uninitialize(&self, unit: AudioUnit) -> OSStatus;
+
+    fn start(&self, unit: AudioUnit) -> OSStatus;
+
+    fn stop(&self, unit: AudioUnit) -> OSStatus;
+
+    fn render(
+        &self,
+        unit: AudioUnit,
+        io_action_flags: *mut AudioUnitRenderActionFlags,
+        in_time_stamp: *const AudioTimeStamp,
+        in_output_bus_number: u32,
+        in_number_frames: u32,
+        io_data: *mut AudioBufferList,
+    ) -> OSStatus;
+
+    fn add_property_listener(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitPropertyID,
+        listener: audio_unit_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus;
+
+    fn remove_property_listener_with_user_data(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitPropertyID,
+        listener: audio_unit_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus;
+}
+
+#[derive(Debug, Default)]
+pub struct CoreAudioUnits;
+
+impl AudioUnitAbstraction for CoreAudioUnits {
+    fn new_unit(&self, desc: &AudioComponentDescription, unit: &mut AudioUnit) -> OSStatus {
+        let comp = unsafe { AudioComponentFindNext(ptr::null_mut(), desc) };
+        if comp.is_null() {
+            return AUDIO_COMPONENT_NOT_FOUND;
+        }
+        unsafe { AudioComponentInstanceNew(comp, unit) }
+    }
+
+    fn dispose(&self, unit: AudioUnit) -> OSStatus {
+        unsafe { AudioComponentInstanceDispose(unit) }
+    }
+
+    fn get_property_info(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        size: *mut usize,
+        writable: *mut bool,
+    ) -> OSStatus {
+        unsafe {
+            AudioUnitGetPropertyInfo(
+                unit,
+                property,
+                scope,
+                element,
+                size as *mut UInt32,
+                writable as *mut Boolean,
+            )
+        }
+    }
+
+    fn get_property(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        data: *mut c_void,
+        size: *mut usize,
+    ) -> OSStatus {
+        unsafe { AudioUnitGetProperty(unit, property, scope, element, data, size as *mut UInt32) }
+    }
+
+    fn set_property(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        data: *const c_void,
+        size: usize,
+    ) -> OSStatus {
+        unsafe { AudioUnitSetProperty(unit, property, scope, element, data, size as UInt32) }
+    }
+
+    fn get_parameter(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitParameterID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        value: *mut AudioUnitParameterValue,
+    ) -> OSStatus {
+        unsafe { AudioUnitGetParameter(unit, id, scope, element, value) }
+    }
+
+    fn set_parameter(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitParameterID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        value: AudioUnitParameterValue,
+        buffer_offset_in_frames: UInt32,
+    ) -> OSStatus {
+        unsafe { AudioUnitSetParameter(unit, id, scope, element, value, buffer_offset_in_frames) }
+    }
+
+    fn initialize(&self, unit: AudioUnit) -> OSStatus {
+        unsafe { AudioUnitInitialize(unit) }
+    }
+
+    fn uninitialize(&self, unit: AudioUnit) -> OSStatus {
+        unsafe { AudioUnitUninitialize(unit) }
+    }
+
+    fn start(&self, unit: AudioUnit) -> OSStatus {
+        unsafe { AudioOutputUnitStart(unit) }
+    }
+
+    fn stop(&self, unit: AudioUnit) -> OSStatus {
+        unsafe { AudioOutputUnitStop(unit) }
+    }
+
+    fn render(
+        &self,
+        unit: AudioUnit,
+        io_action_flags: *mut AudioUnitRenderActionFlags,
+        in_time_stamp: *const AudioTimeStamp,
+        in_output_bus_number: u32,
+        in_number_frames: u32,
+        io_data: *mut AudioBufferList,
+    ) -> OSStatus {
+        unsafe {
+            AudioUnitRender(
+                unit,
+                io_action_flags,
+                in_time_stamp,
+                in_output_bus_number,
+                in_number_frames,
+                io_data,
+            )
+        }
+    }
+
+    fn add_property_listener(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitPropertyID,
+        listener: audio_unit_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        unsafe { AudioUnitAddPropertyListener(unit, id, Some(listener), data) }
+    }
+
+    fn remove_property_listener_with_user_data(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitPropertyID,
+        listener: audio_unit_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        unsafe { AudioUnitRemovePropertyListenerWithUserData(unit, id, Some(listener), data) }
+    }
+}
+
+pub fn audio_unit_new(
+    units: &dyn AudioUnitAbstraction,
+    desc: &AudioComponentDescription,
+    unit: &mut AudioUnit,
+) -> OSStatus {
+    units.new_unit(desc, unit)
+}
+
 pub fn audio_unit_get_property_info(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     property: AudioUnitPropertyID,
     scope: AudioUnitScope,
@@ -13,19 +266,12 @@ pub fn audio_unit_get_property_info(
 ) -> OSStatus {
     assert!(!unit.is_null());
     assert!(UInt32::try_from(*size).is_ok()); // Check if `size` can be converted to a UInt32.
-    unsafe {
-        AudioUnitGetPropertyInfo(
-            unit,
-            property,
-            scope,
-            element,
-            size as *mut usize as *mut UInt32,
-            writable.map_or(ptr::null_mut(), |v| v as *mut bool as *mut Boolean),
-        )
-    }
+    let writable = writable.map_or(ptr::null_mut(), |v| v as *mut bool);
+    units.get_property_info(unit, property, scope, element, size, writable)
 }
 
 pub fn audio_unit_get_property<T>(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     property: AudioUnitPropertyID,
     scope: AudioUnitScope,
@@ -35,19 +281,12 @@ pub fn audio_unit_get_property<T>(
 ) -> OSStatus {
     assert!(!unit.is_null());
     assert!(UInt32::try_from(*size).is_ok()); // Check if `size` can be converted to a UInt32.
-    unsafe {
-        AudioUnitGetProperty(
-            unit,
-            property,
-            scope,
-            element,
-            data as *mut T as *mut c_void,
-            size as *mut usize as *mut UInt32,
-        )
-    }
+    let data = data as *mut T as *mut c_void;
+    units.get_property(unit, property, scope, element, data, size)
 }
 
 pub fn audio_unit_set_property<T>(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     property: AudioUnitPropertyID,
     scope: AudioUnitScope,
@@ -56,19 +295,12 @@ pub fn audio_unit_set_property<T>(
     size: usize,
 ) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe {
-        AudioUnitSetProperty(
-            unit,
-            property,
-            scope,
-            element,
-            data as *const T as *const c_void,
-            size as UInt32,
-        )
-    }
+    let data = data as *const T as *const c_void;
+    units.set_property(unit, property, scope, element, data, size)
 }
 
 pub fn audio_unit_get_parameter(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     id: AudioUnitParameterID,
     scope: AudioUnitScope,
@@ -76,18 +308,12 @@ pub fn audio_unit_get_parameter(
     value: &mut AudioUnitParameterValue,
 ) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe {
-        AudioUnitGetParameter(
-            unit,
-            id,
-            scope,
-            element,
-            value as *mut AudioUnitParameterValue,
-        )
-    }
+    let value = value as *mut AudioUnitParameterValue;
+    units.get_parameter(unit, id, scope, element, value)
 }
 
 pub fn audio_unit_set_parameter(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     id: AudioUnitParameterID,
     scope: AudioUnitScope,
@@ -96,34 +322,35 @@ pub fn audio_unit_set_parameter(
     buffer_offset_in_frames: UInt32,
 ) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe { AudioUnitSetParameter(unit, id, scope, element, value, buffer_offset_in_frames) }
+    units.set_parameter(unit, id, scope, element, value, buffer_offset_in_frames)
 }
 
-pub fn audio_unit_initialize(unit: AudioUnit) -> OSStatus {
+pub fn audio_unit_initialize(units: &dyn AudioUnitAbstraction, unit: AudioUnit) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe { AudioUnitInitialize(unit) }
+    units.initialize(unit)
 }
 
-pub fn audio_unit_uninitialize(unit: AudioUnit) -> OSStatus {
+pub fn audio_unit_uninitialize(units: &dyn AudioUnitAbstraction, unit: AudioUnit) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe { AudioUnitUninitialize(unit) }
+    units.uninitialize(unit)
 }
 
-pub fn dispose_audio_unit(unit: AudioUnit) -> OSStatus {
-    unsafe { AudioComponentInstanceDispose(unit) }
+pub fn dispose_audio_unit(units: &dyn AudioUnitAbstraction, unit: AudioUnit) -> OSStatus {
+    units.dispose(unit)
 }
 
-pub fn audio_output_unit_start(unit: AudioUnit) -> OSStatus {
+pub fn audio_output_unit_start(units: &dyn AudioUnitAbstraction, unit: AudioUnit) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe { AudioOutputUnitStart(unit) }
+    units.start(unit)
 }
 
-pub fn audio_output_unit_stop(unit: AudioUnit) -> OSStatus {
+pub fn audio_output_unit_stop(units: &dyn AudioUnitAbstraction, unit: AudioUnit) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe { AudioOutputUnitStop(unit) }
+    units.stop(unit)
 }
 
 pub fn audio_unit_render(
+    units: &dyn AudioUnitAbstraction,
     in_unit: AudioUnit,
     io_action_flags: &mut AudioUnitRenderActionFlags,
     in_time_stamp: &AudioTimeStamp,
@@ -132,40 +359,34 @@ pub fn audio_unit_render(
     io_data: &mut AudioBufferList,
 ) -> OSStatus {
     assert!(!in_unit.is_null());
-    unsafe {
-        AudioUnitRender(
-            in_unit,
-            io_action_flags,
-            in_time_stamp,
-            in_output_bus_number,
-            in_number_frames,
-            io_data,
-        )
-    }
+    units.render(
+        in_unit,
+        io_action_flags,
+        in_time_stamp,
+        in_output_bus_number,
+        in_number_frames,
+        io_data,
+    )
 }
 
-#[allow(non_camel_case_types)]
-pub type audio_unit_property_listener_proc =
-    extern "C" fn(*mut c_void, AudioUnit, AudioUnitPropertyID, AudioUnitScope, AudioUnitElement);
-
 pub fn audio_unit_add_property_listener<T>(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     id: AudioUnitPropertyID,
     listener: audio_unit_property_listener_proc,
     data: *mut T,
 ) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe { AudioUnitAddPropertyListener(unit, id, Some(listener), data as *mut c_void) }
+    units.add_property_listener(unit, id, listener, data as *mut c_void)
 }
 
 pub fn audio_unit_remove_property_listener_with_user_data<T>(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     id: AudioUnitPropertyID,
     listener: audio_unit_property_listener_proc,
     data: *mut T,
 ) -> OSStatus {
     assert!(!unit.is_null());
-    unsafe {
-        AudioUnitRemovePropertyListenerWithUserData(unit, id, Some(listener), data as *mut c_void)
-    }
+    units.remove_property_listener_with_user_data(unit, id, listener, data as *mut c_void)
 }
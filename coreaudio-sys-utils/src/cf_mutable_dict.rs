@@ -10,6 +10,11 @@ impl CFMutableDictRef {
             CFDictionaryAddValue(self.0, key as *const c_void, value as *const c_void);
         }
     }
+
+    pub fn get_value<K>(&self, key: *const K) -> *const c_void {
+        assert!(!self.0.is_null());
+        unsafe { CFDictionaryGetValue(self.0, key as *const c_void) }
+    }
 }
 
 impl Default for CFMutableDictRef {
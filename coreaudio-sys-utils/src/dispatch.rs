@@ -1,17 +1,33 @@
+#[cfg(not(feature = "thread-queue"))]
 use coreaudio_sys::*;
 
-use std::ffi::CString;
-use std::mem;
-use std::os::raw::c_void;
-use std::ptr;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+#[cfg(not(feature = "thread-queue"))]
+use std::{ffi::CString, mem, os::raw::c_void, ptr};
 
-// Queue: A wrapper around `dispatch_queue_t`.
+// Queue: The serial task queue used by the backend.
 // ------------------------------------------------------------------------------------------------
+// Both implementations run the tasks one by one, in the order they are scheduled, and cancel the
+// tasks scheduled after `run_final`. The libdispatch one is used by default. The one built on a
+// std thread is used with the `thread-queue` feature, so the scheduling logic can be run without
+// libdispatch.
+#[cfg(not(feature = "thread-queue"))]
+pub type Queue = DispatchQueue;
+#[cfg(feature = "thread-queue")]
+pub type Queue = ThreadQueue;
+
+// DispatchQueue: A wrapper around `dispatch_queue_t`.
+// ------------------------------------------------------------------------------------------------
+#[cfg(not(feature = "thread-queue"))]
 #[derive(Debug)]
-pub struct Queue(dispatch_queue_t);
+pub struct DispatchQueue(dispatch_queue_t);
 
-impl Queue {
+#[cfg(not(feature = "thread-queue"))]
+impl DispatchQueue {
     pub fn new(label: &str) -> Self {
         const DISPATCH_QUEUE_SERIAL: dispatch_queue_attr_t =
             ptr::null_mut::<dispatch_queue_attr_s>();
@@ -24,7 +40,7 @@ impl Queue {
 
     pub fn run_async<F>(&self, work: F)
     where
-        F: Send + FnOnce(),
+        F: Send + FnOnce() + 'static,
     {
         let should_cancel = self.get_should_cancel();
         let (closure, executor) = Self::create_closure_and_executor(|| {
@@ -131,13 +147,110 @@ impl Queue {
     }
 }
 
-impl Drop for Queue {
+// ThreadQueue: A serial queue running the tasks on a std thread.
+// ------------------------------------------------------------------------------------------------
+type Task = Box<dyn FnOnce() + Send>;
+
+#[derive(Clone, Debug)]
+pub struct ThreadQueue {
+    sender: Arc<Mutex<mpsc::Sender<Task>>>,
+    should_cancel: Arc<AtomicBool>,
+}
+
+impl ThreadQueue {
+    pub fn new(label: &str) -> Self {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        // The thread exits once all the clones of the queue are dropped and the pending tasks
+        // are done, like the libdispatch queue being released asynchronously.
+        thread::Builder::new()
+            .name(label.to_string())
+            .spawn(move || {
+                for task in receiver {
+                    // Keep the thread alive after a panicking task, so the later tasks still run.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(task));
+                }
+            })
+            .expect("Failed to spawn the queue thread");
+        Self {
+            sender: Arc::new(Mutex::new(sender)),
+            should_cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn run_async<F>(&self, work: F)
+    where
+        F: Send + FnOnce() + 'static,
+    {
+        let should_cancel = self.should_cancel.clone();
+        self.dispatch(move || {
+            if should_cancel.load(Ordering::SeqCst) {
+                return;
+            }
+            work();
+        });
+    }
+
+    pub fn run_sync<F>(&self, work: F)
+    where
+        F: Send + FnOnce(),
+    {
+        let should_cancel = self.should_cancel.clone();
+        self.dispatch_and_wait(move || {
+            if should_cancel.load(Ordering::SeqCst) {
+                return;
+            }
+            work();
+        });
+    }
+
+    pub fn run_final<F>(&self, work: F)
+    where
+        F: Send + FnOnce(),
+    {
+        let should_cancel = self.should_cancel.clone();
+        self.dispatch_and_wait(move || {
+            work();
+            should_cancel.store(true, Ordering::SeqCst);
+        });
+    }
+
+    fn dispatch<F>(&self, work: F)
+    where
+        F: Send + FnOnce() + 'static,
+    {
+        // The receiver lives as long as the thread, which only exits after the sender is dropped.
+        self.sender.lock().unwrap().send(Box::new(work)).unwrap();
+    }
+
+    // Like `dispatch_sync_f`, run `work` on the calling thread once the tasks scheduled before are
+    // done, while the queue thread waits for it. So `work` can borrow the data of the caller, and
+    // its panic unwinds into the caller, releasing the queue thread on the way.
+    fn dispatch_and_wait<F>(&self, work: F)
+    where
+        F: FnOnce(),
+    {
+        let (reached_sender, reached_receiver) = mpsc::channel::<()>();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        self.dispatch(move || {
+            reached_sender.send(()).unwrap();
+            // Returns an error once the sender is dropped.
+            let _ = release_receiver.recv();
+        });
+        reached_receiver.recv().unwrap();
+        work();
+        drop(release_sender);
+    }
+}
+
+#[cfg(not(feature = "thread-queue"))]
+impl Drop for DispatchQueue {
     fn drop(&mut self) {
         self.release();
     }
 }
 
-impl Clone for Queue {
+#[cfg(not(feature = "thread-queue"))]
+impl Clone for DispatchQueue {
     fn clone(&self) -> Self {
         // TODO: It's incredibly unsafe to call `transmute` directly.
         //       Find another way to release the queue.
@@ -150,57 +263,113 @@ impl Clone for Queue {
     }
 }
 
-#[test]
-fn run_tasks_in_order() {
-    let mut visited = Vec::<u32>::new();
+#[cfg(test)]
+macro_rules! test_queue {
+    ($name:ident, $queue:ty) => {
+        mod $name {
+            type Queue = $queue;
 
-    // Rust compilter doesn't allow a pointer to be passed across threads.
-    // A hacky way to do that is to cast the pointer into a value, then
-    // the value, which is actually an address, can be copied into threads.
-    let ptr = &mut visited as *mut Vec<u32> as usize;
+            #[test]
+            fn run_tasks_in_order() {
+                let mut visited = Vec::<u32>::new();
 
-    fn visit(v: u32, visited_ptr: usize) {
-        let visited = unsafe { &mut *(visited_ptr as *mut Vec<u32>) };
-        visited.push(v);
-    };
+                // Rust compilter doesn't allow a pointer to be passed across threads.
+                // A hacky way to do that is to cast the pointer into a value, then
+                // the value, which is actually an address, can be copied into threads.
+                let ptr = &mut visited as *mut Vec<u32> as usize;
 
-    let queue = Queue::new("Run tasks in order");
+                fn visit(v: u32, visited_ptr: usize) {
+                    let visited = unsafe { &mut *(visited_ptr as *mut Vec<u32>) };
+                    visited.push(v);
+                };
 
-    queue.run_sync(move || visit(1, ptr));
-    queue.run_sync(move || visit(2, ptr));
-    queue.run_async(move || visit(3, ptr));
-    queue.run_async(move || visit(4, ptr));
-    // Call sync here to block the current thread and make sure all the tasks are done.
-    queue.run_sync(move || visit(5, ptr));
+                let queue = Queue::new("Run tasks in order");
 
-    assert_eq!(visited, vec![1, 2, 3, 4, 5]);
-}
+                queue.run_sync(move || visit(1, ptr));
+                queue.run_sync(move || visit(2, ptr));
+                queue.run_async(move || visit(3, ptr));
+                queue.run_async(move || visit(4, ptr));
+                // Call sync here to block the current thread and make sure all the tasks are done.
+                queue.run_sync(move || visit(5, ptr));
 
-#[test]
-fn run_final_task() {
-    let mut visited = Vec::<u32>::new();
+                assert_eq!(visited, vec![1, 2, 3, 4, 5]);
+            }
 
-    {
-        // Rust compilter doesn't allow a pointer to be passed across threads.
-        // A hacky way to do that is to cast the pointer into a value, then
-        // the value, which is actually an address, can be copied into threads.
-        let ptr = &mut visited as *mut Vec<u32> as usize;
+            #[test]
+            fn run_final_task() {
+                let mut visited = Vec::<u32>::new();
 
-        fn visit(v: u32, visited_ptr: usize) {
-            let visited = unsafe { &mut *(visited_ptr as *mut Vec<u32>) };
-            visited.push(v);
-        };
+                {
+                    // Rust compilter doesn't allow a pointer to be passed across threads.
+                    // A hacky way to do that is to cast the pointer into a value, then
+                    // the value, which is actually an address, can be copied into threads.
+                    let ptr = &mut visited as *mut Vec<u32> as usize;
 
-        let queue = Queue::new("Task after run_final will be cancelled");
+                    fn visit(v: u32, visited_ptr: usize) {
+                        let visited = unsafe { &mut *(visited_ptr as *mut Vec<u32>) };
+                        visited.push(v);
+                    };
 
-        queue.run_sync(move || visit(1, ptr));
-        queue.run_async(move || visit(2, ptr));
-        queue.run_final(move || visit(3, ptr));
-        queue.run_async(move || visit(4, ptr));
-        queue.run_sync(move || visit(5, ptr));
-    }
-    // `queue` will be dropped asynchronously and then the `finalizer` of the `queue`
-    // should be fired to clean up the `context` set in the `queue`.
+                    let queue = Queue::new("Task after run_final will be cancelled");
+
+                    queue.run_sync(move || visit(1, ptr));
+                    queue.run_async(move || visit(2, ptr));
+                    queue.run_final(move || visit(3, ptr));
+                    queue.run_async(move || visit(4, ptr));
+                    queue.run_sync(move || visit(5, ptr));
+                }
+                // `queue` will be dropped asynchronously and then the `finalizer` of the `queue`
+                // should be fired to clean up the `context` set in the `queue`.
+
+                assert_eq!(visited, vec![1, 2, 3]);
+            }
+
+            #[test]
+            fn run_tasks_with_clones() {
+                let mut visited = Vec::<u32>::new();
+                let ptr = &mut visited as *mut Vec<u32> as usize;
+
+                fn visit(v: u32, visited_ptr: usize) {
+                    let visited = unsafe { &mut *(visited_ptr as *mut Vec<u32>) };
+                    visited.push(v);
+                };
+
+                let queue = Queue::new("Clones share the tasks");
+                let clone = queue.clone();
+                queue.run_async(move || visit(1, ptr));
+                clone.run_async(move || visit(2, ptr));
+                drop(queue);
+                // The clone is still usable after the original queue is dropped.
+                clone.run_final(move || visit(3, ptr));
+                clone.run_sync(move || visit(4, ptr));
+
+                assert_eq!(visited, vec![1, 2, 3]);
+            }
+        }
+    };
+}
+
+#[cfg(all(test, not(feature = "thread-queue")))]
+test_queue!(dispatch_queue, super::DispatchQueue);
+#[cfg(test)]
+test_queue!(thread_queue, super::ThreadQueue);
+
+#[test]
+fn run_tasks_after_panics_on_thread_queue() {
+    let queue = ThreadQueue::new("Panicking tasks");
+    let visited = Arc::new(Mutex::new(Vec::<u32>::new()));
+
+    // A panicking task doesn't stop the queue.
+    queue.run_async(|| panic!("async task"));
+    let v = visited.clone();
+    queue.run_async(move || v.lock().unwrap().push(1));
+
+    // A panic of a sync task reaches the caller, and the queue goes on.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        queue.run_sync(|| panic!("sync task"));
+    }));
+    assert!(result.is_err());
+    queue.run_sync(|| visited.lock().unwrap().push(2));
 
-    assert_eq!(visited, vec![1, 2, 3]);
+    assert_eq!(*visited.lock().unwrap(), vec![1, 2]);
 }
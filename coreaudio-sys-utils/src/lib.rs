@@ -6,6 +6,7 @@ pub mod audio_object;
 pub mod audio_unit;
 pub mod cf_mutable_dict;
 pub mod dispatch;
+pub mod process_tap;
 pub mod string;
 
 pub mod sys {
@@ -0,0 +1,96 @@
+use coreaudio_sys::*;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+
+// Process taps capture the audio played to an output device. They are created from a
+// CATapDescription, which is an Objective-C object, by AudioHardwareCreateProcessTap. Both are
+// only available since macOS 14.2, so they are looked up at runtime instead of being linked.
+
+type Id = *mut c_void;
+type Sel = *mut c_void;
+
+#[link(name = "objc")]
+extern "C" {
+    fn objc_getClass(name: *const c_char) -> Id;
+    fn sel_registerName(name: *const c_char) -> Sel;
+    fn objc_msgSend();
+}
+
+extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+const RTLD_DEFAULT: *mut c_void = -2isize as *mut c_void;
+
+fn selector(name: &'static [u8]) -> Sel {
+    assert_eq!(name.last(), Some(&0));
+    unsafe { sel_registerName(name.as_ptr() as *const c_char) }
+}
+
+fn class(name: &'static [u8]) -> Id {
+    assert_eq!(name.last(), Some(&0));
+    unsafe { objc_getClass(name.as_ptr() as *const c_char) }
+}
+
+fn symbol(name: &'static [u8]) -> *mut c_void {
+    assert_eq!(name.last(), Some(&0));
+    unsafe { dlsym(RTLD_DEFAULT, name.as_ptr() as *const c_char) }
+}
+
+// Create a private tap on the first stream of the output device whose UID is `device_uid`. The
+// tapped audio is still played by the device.
+pub fn create_process_tap(device_uid: CFStringRef, tap: &mut AudioObjectID) -> OSStatus {
+    type CreateProcessTap = unsafe extern "C" fn(Id, *mut AudioObjectID) -> OSStatus;
+    type SendId = unsafe extern "C" fn(Id, Sel) -> Id;
+    type SendVoid = unsafe extern "C" fn(Id, Sel);
+    type SendBool = unsafe extern "C" fn(Id, Sel, u8);
+    type SendInit = unsafe extern "C" fn(Id, Sel, Id, Id, isize) -> Id;
+
+    let create = symbol(b"AudioHardwareCreateProcessTap\0");
+    let tap_description = class(b"CATapDescription\0");
+    let array = class(b"NSArray\0");
+    if create.is_null() || tap_description.is_null() || array.is_null() {
+        return kAudioHardwareUnsupportedOperationError as OSStatus;
+    }
+
+    unsafe {
+        let create: CreateProcessTap = mem::transmute(create);
+        let send_id: SendId = mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        let send_void: SendVoid = mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        let send_bool: SendBool = mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        let send_init: SendInit = mem::transmute(objc_msgSend as unsafe extern "C" fn());
+
+        // No process is excluded from the tap.
+        let excluded = send_id(send_id(array, selector(b"alloc\0")), selector(b"init\0"));
+        let description = send_init(
+            send_id(tap_description, selector(b"alloc\0")),
+            selector(b"initExcludingProcesses:andDeviceUID:withStream:\0"),
+            excluded,
+            device_uid as Id,
+            0,
+        );
+        send_void(excluded, selector(b"release\0"));
+        if description.is_null() {
+            return kAudioHardwareIllegalOperationError as OSStatus;
+        }
+
+        // Hide the tap from the other processes.
+        send_bool(description, selector(b"setPrivate:\0"), 1);
+        let status = create(description, tap);
+        send_void(description, selector(b"release\0"));
+        status
+    }
+}
+
+pub fn destroy_process_tap(tap: AudioObjectID) -> OSStatus {
+    type DestroyProcessTap = unsafe extern "C" fn(AudioObjectID) -> OSStatus;
+
+    let destroy = symbol(b"AudioHardwareDestroyProcessTap\0");
+    if destroy.is_null() {
+        return kAudioHardwareUnsupportedOperationError as OSStatus;
+    }
+    unsafe {
+        let destroy: DestroyProcessTap = mem::transmute(destroy);
+        destroy(tap)
+    }
+}
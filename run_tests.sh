@@ -28,6 +28,7 @@ cargo clippy -p $SUB_CRATE -- -D warnings
 
 # Regular Tests
 cargo test -p $SUB_CRATE
+cargo test -p $SUB_CRATE --features thread-queue
 
 # Run tests in the main crate
 # -------------------------------------------------------------------------------------------------
@@ -39,6 +40,9 @@ cargo clippy -- -D warnings
 
 # Regular Tests
 cargo test --verbose
+# The simulated tests run on a fake system without any audio device, but they still build
+# against CoreAudio, so they only run on macOS.
+cargo test test_simulated --features thread-queue
 cargo test test_configure_output -- --ignored
 cargo test test_aggregate -- --ignored --test-threads=1
 
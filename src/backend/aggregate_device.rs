@@ -4,12 +4,23 @@ use std::time::{SystemTime, UNIX_EPOCH};
 const APPLE_EVENT_TIMEOUT: OSStatus = -1712;
 pub const DRIFT_COMPENSATION: u32 = 1;
 
+// The data of the listener waiting for a new device: the hardware to list the devices on, and the
+// devices it found.
+type DevicesListenerData = (
+    *const dyn HardwareAbstraction,
+    Arc<(Mutex<Vec<AudioObjectID>>, Condvar)>,
+);
+
 #[derive(Debug)]
 pub struct AggregateDevice {
     plugin_id: AudioObjectID,
     device_id: AudioObjectID,
     input_id: AudioObjectID,
     output_id: AudioObjectID,
+    // The process tap capturing the output device for the loopback streams.
+    tap_id: AudioObjectID,
+    // The HAL the device is created on, and destroyed on once it's dropped.
+    platform: Platform,
 }
 
 impl AggregateDevice {
@@ -31,15 +42,17 @@ impl AggregateDevice {
     // [1] https://lists.apple.com/archives/coreaudio-api/2005/Jul/msg00150.html
     // [2] CoreAudio.framework/Headers/AudioHardware.h
     pub fn new(
+        platform: &Platform,
         input_id: AudioObjectID,
         output_id: AudioObjectID,
     ) -> std::result::Result<Self, OSStatus> {
-        let plugin_id = Self::get_system_plugin_id()?;
-        let device_id = Self::create_blank_device_sync(plugin_id)?;
-        Self::set_sub_devices_sync(device_id, input_id, output_id)?;
-        Self::set_master_device(device_id)?;
-        Self::activate_clock_drift_compensation(device_id)?;
-        Self::workaround_for_airpod(device_id, input_id, output_id)?;
+        let hardware = platform.hardware();
+        let plugin_id = Self::get_system_plugin_id(hardware)?;
+        let device_id = Self::create_blank_device_sync(hardware, plugin_id)?;
+        Self::set_sub_devices_sync(hardware, device_id, input_id, output_id)?;
+        Self::set_master_device(hardware, device_id)?;
+        Self::activate_clock_drift_compensation(hardware, device_id)?;
+        Self::workaround_for_airpod(hardware, device_id, input_id, output_id)?;
         cubeb_log!(
             "Add devices input {} and output {} into an aggregate device {}",
             input_id,
@@ -51,15 +64,50 @@ impl AggregateDevice {
             device_id,
             input_id,
             output_id,
+            tap_id: kAudioObjectUnknown,
+            platform: platform.clone(),
         })
     }
 
+    // A loopback aggregate device captures the audio played to the output device through a
+    // process tap, and exposes it as its input. The output device is the only sub device, so its
+    // clock drives the aggregate device.
+    pub fn new_loopback(
+        platform: &Platform,
+        output_id: AudioObjectID,
+    ) -> std::result::Result<Self, OSStatus> {
+        let hardware = platform.hardware();
+        let plugin_id = Self::get_system_plugin_id(hardware)?;
+        // The tap and the device are destroyed if any of the following steps fails.
+        let mut device = Self {
+            plugin_id,
+            device_id: kAudioObjectUnknown,
+            input_id: kAudioObjectUnknown,
+            output_id,
+            tap_id: Self::create_tap(hardware, output_id)?,
+            platform: platform.clone(),
+        };
+        device.device_id = Self::create_blank_device_sync(hardware, plugin_id)?;
+        Self::set_loopback_sub_devices_sync(hardware, device.device_id, output_id)?;
+        Self::set_master_sub_device(hardware, device.device_id, output_id)?;
+        Self::set_tap_list_sync(hardware, device.device_id, device.tap_id)?;
+        cubeb_log!(
+            "Add tap {} on output {} into an aggregate device {}",
+            device.tap_id,
+            output_id,
+            device.device_id
+        );
+        Ok(device)
+    }
+
     pub fn get_device_id(&self) -> AudioObjectID {
         self.device_id
     }
 
     // The following APIs are set to `pub` for testing purpose.
-    pub fn get_system_plugin_id() -> std::result::Result<AudioObjectID, OSStatus> {
+    pub fn get_system_plugin_id(
+        hardware: &dyn HardwareAbstraction,
+    ) -> std::result::Result<AudioObjectID, OSStatus> {
         let address = AudioObjectPropertyAddress {
             mSelector: kAudioHardwarePropertyPlugInForBundleID,
             mScope: kAudioObjectPropertyScopeGlobal,
@@ -67,8 +115,12 @@ impl AggregateDevice {
         };
 
         let mut size: usize = 0;
-        let status =
-            audio_object_get_property_data_size(kAudioObjectSystemObject, &address, &mut size);
+        let status = audio_object_get_property_data_size(
+            hardware,
+            kAudioObjectSystemObject,
+            &address,
+            &mut size,
+        );
         if status != NO_ERR {
             return Err(status);
         }
@@ -85,6 +137,7 @@ impl AggregateDevice {
         assert_eq!(size, mem::size_of_val(&translation_value));
 
         let status = audio_object_get_property_data(
+            hardware,
             kAudioObjectSystemObject,
             &address,
             &mut size,
@@ -102,13 +155,18 @@ impl AggregateDevice {
     }
 
     pub fn create_blank_device_sync(
+        hardware: &dyn HardwareAbstraction,
         plugin_id: AudioObjectID,
     ) -> std::result::Result<AudioObjectID, OSStatus> {
         let waiting_time = Duration::new(5, 0);
 
         let condvar_pair = Arc::new((Mutex::new(Vec::<AudioObjectID>::new()), Condvar::new()));
-        let mut cloned_condvar_pair = condvar_pair.clone();
-        let data_ptr = &mut cloned_condvar_pair as *mut Arc<(Mutex<Vec<AudioObjectID>>, Condvar)>;
+        // The listener lists the devices on the same hardware.
+        let mut listener_data = (
+            hardware as *const dyn HardwareAbstraction,
+            condvar_pair.clone(),
+        );
+        let data_ptr = &mut listener_data as *mut _;
 
         let address = get_property_address(
             Property::HardwareDevices,
@@ -116,6 +174,7 @@ impl AggregateDevice {
         );
         assert_eq!(
             audio_object_add_property_listener(
+                hardware,
                 kAudioObjectSystemObject,
                 &address,
                 devices_changed_callback,
@@ -127,6 +186,7 @@ impl AggregateDevice {
         let _teardown = finally(|| {
             assert_eq!(
                 audio_object_remove_property_listener(
+                    hardware,
                     kAudioObjectSystemObject,
                     &address,
                     devices_changed_callback,
@@ -136,7 +196,7 @@ impl AggregateDevice {
             );
         });
 
-        let device = Self::create_blank_device(plugin_id)?;
+        let device = Self::create_blank_device(hardware, plugin_id)?;
 
         // Wait until the aggregate is created.
         let &(ref lock, ref cvar) = &*condvar_pair;
@@ -161,10 +221,10 @@ impl AggregateDevice {
             data: *mut c_void,
         ) -> OSStatus {
             assert_eq!(id, kAudioObjectSystemObject);
-            let pair = unsafe { &mut *(data as *mut Arc<(Mutex<Vec<AudioObjectID>>, Condvar)>) };
+            let (hardware, pair) = unsafe { &mut *(data as *mut DevicesListenerData) };
             let &(ref lock, ref cvar) = &**pair;
             let mut devices = lock.lock().unwrap();
-            *devices = audiounit_get_devices();
+            *devices = audiounit_get_devices(unsafe { &**hardware });
             cvar.notify_one();
             NO_ERR
         }
@@ -173,6 +233,7 @@ impl AggregateDevice {
     }
 
     pub fn create_blank_device(
+        hardware: &dyn HardwareAbstraction,
         plugin_id: AudioObjectID,
     ) -> std::result::Result<AudioObjectID, OSStatus> {
         assert_ne!(plugin_id, kAudioObjectUnknown);
@@ -184,7 +245,7 @@ impl AggregateDevice {
         };
 
         let mut size: usize = 0;
-        let status = audio_object_get_property_data_size(plugin_id, &address, &mut size);
+        let status = audio_object_get_property_data_size(hardware, plugin_id, &address, &mut size);
         if status != NO_ERR {
             return Err(status);
         }
@@ -243,6 +304,7 @@ impl AggregateDevice {
 
             // This call will fire `audiounit_collection_changed_callback` indirectly!
             audio_object_get_property_data_with_qualifier(
+                hardware,
                 plugin_id,
                 &address,
                 mem::size_of_val(&device_dict),
@@ -260,12 +322,42 @@ impl AggregateDevice {
     }
 
     pub fn set_sub_devices_sync(
+        hardware: &dyn HardwareAbstraction,
         device_id: AudioDeviceID,
         input_id: AudioDeviceID,
         output_id: AudioDeviceID,
     ) -> std::result::Result<(), OSStatus> {
+        Self::set_property_sync(
+            hardware,
+            device_id,
+            kAudioAggregateDevicePropertyFullSubDeviceList,
+            || Self::set_sub_devices(hardware, device_id, input_id, output_id),
+        )
+        .map_err(|e| {
+            if e == APPLE_EVENT_TIMEOUT {
+                cubeb_log!(
+                    "Time out for waiting for adding devices({}, {}) to aggregate device {}!",
+                    input_id,
+                    output_id,
+                    device_id
+                );
+            }
+            e
+        })
+    }
+
+    // Run `set` and wait until the `selector` property of the aggregate device changes.
+    fn set_property_sync<F>(
+        hardware: &dyn HardwareAbstraction,
+        device_id: AudioDeviceID,
+        selector: AudioObjectPropertySelector,
+        set: F,
+    ) -> std::result::Result<(), OSStatus>
+    where
+        F: FnOnce() -> std::result::Result<(), OSStatus>,
+    {
         let address = AudioObjectPropertyAddress {
-            mSelector: kAudioAggregateDevicePropertyFullSubDeviceList,
+            mSelector: selector,
             mScope: kAudioObjectPropertyScopeGlobal,
             mElement: kAudioObjectPropertyElementMaster,
         };
@@ -278,6 +370,7 @@ impl AggregateDevice {
 
         assert_eq!(
             audio_object_add_property_listener(
+                hardware,
                 device_id,
                 &address,
                 devices_changed_callback,
@@ -289,6 +382,7 @@ impl AggregateDevice {
         let _teardown = finally(|| {
             assert_eq!(
                 audio_object_remove_property_listener(
+                    hardware,
                     device_id,
                     &address,
                     devices_changed_callback,
@@ -298,21 +392,13 @@ impl AggregateDevice {
             );
         });
 
-        Self::set_sub_devices(device_id, input_id, output_id)?;
+        set()?;
 
-        // Wait until the sub devices are added.
+        // Wait until the property is changed.
         let &(ref lock, ref cvar) = &*condvar_pair;
         let device = lock.lock().unwrap();
         if *device != device_id {
-            let (dev, timeout_res) = cvar.wait_timeout(device, waiting_time).unwrap();
-            if timeout_res.timed_out() {
-                cubeb_log!(
-                    "Time out for waiting for adding devices({}, {}) to aggregate device {}!",
-                    input_id,
-                    output_id,
-                    device_id
-                );
-            }
+            let (dev, _) = cvar.wait_timeout(device, waiting_time).unwrap();
             if *dev != device_id {
                 return Err(APPLE_EVENT_TIMEOUT);
             }
@@ -336,6 +422,7 @@ impl AggregateDevice {
     }
 
     pub fn set_sub_devices(
+        hardware: &dyn HardwareAbstraction,
         device_id: AudioDeviceID,
         input_id: AudioDeviceID,
         output_id: AudioDeviceID,
@@ -345,20 +432,20 @@ impl AggregateDevice {
         assert_ne!(output_id, kAudioObjectUnknown);
         assert_ne!(input_id, output_id);
 
-        let output_sub_devices = Self::get_sub_devices(output_id)?;
-        let input_sub_devices = Self::get_sub_devices(input_id)?;
+        let output_sub_devices = Self::get_sub_devices(hardware, output_id)?;
+        let input_sub_devices = Self::get_sub_devices(hardware, input_id)?;
 
         unsafe {
             let sub_devices = CFArrayCreateMutable(ptr::null(), 0, &kCFTypeArrayCallBacks);
             // The order of the items in the array is significant and is used to determine the order of the streams
             // of the AudioAggregateDevice.
             for device in output_sub_devices {
-                let uid = get_device_global_uid(device)?;
+                let uid = get_device_global_uid(hardware, device)?;
                 CFArrayAppendValue(sub_devices, uid.get_raw() as *const c_void);
             }
 
             for device in input_sub_devices {
-                let uid = get_device_global_uid(device)?;
+                let uid = get_device_global_uid(hardware, device)?;
                 CFArrayAppendValue(sub_devices, uid.get_raw() as *const c_void);
             }
 
@@ -369,7 +456,8 @@ impl AggregateDevice {
             };
 
             let size = mem::size_of::<CFMutableArrayRef>();
-            let status = audio_object_set_property_data(device_id, &address, size, &sub_devices);
+            let status =
+                audio_object_set_property_data(hardware, device_id, &address, size, &sub_devices);
             CFRelease(sub_devices as *const c_void);
             if status == NO_ERR {
                 Ok(())
@@ -379,7 +467,126 @@ impl AggregateDevice {
         }
     }
 
+    fn set_loopback_sub_devices_sync(
+        hardware: &dyn HardwareAbstraction,
+        device_id: AudioDeviceID,
+        output_id: AudioDeviceID,
+    ) -> std::result::Result<(), OSStatus> {
+        assert_ne!(device_id, kAudioObjectUnknown);
+        assert_ne!(output_id, kAudioObjectUnknown);
+
+        let output_sub_devices = Self::get_sub_devices(hardware, output_id)?;
+        let mut uids = Vec::with_capacity(output_sub_devices.len());
+        for device in output_sub_devices {
+            uids.push(get_device_global_uid(hardware, device)?);
+        }
+        Self::set_property_sync(
+            hardware,
+            device_id,
+            kAudioAggregateDevicePropertyFullSubDeviceList,
+            || {
+                Self::set_string_list(
+                    hardware,
+                    device_id,
+                    kAudioAggregateDevicePropertyFullSubDeviceList,
+                    &uids,
+                )
+            },
+        )
+    }
+
+    fn set_tap_list_sync(
+        hardware: &dyn HardwareAbstraction,
+        device_id: AudioDeviceID,
+        tap_id: AudioObjectID,
+    ) -> std::result::Result<(), OSStatus> {
+        assert_ne!(device_id, kAudioObjectUnknown);
+        assert_ne!(tap_id, kAudioObjectUnknown);
+
+        let uid = Self::get_tap_uid(hardware, tap_id)?;
+        Self::set_property_sync(
+            hardware,
+            device_id,
+            AGGREGATE_DEVICE_PROPERTY_TAP_LIST,
+            || {
+                Self::set_string_list(
+                    hardware,
+                    device_id,
+                    AGGREGATE_DEVICE_PROPERTY_TAP_LIST,
+                    &[uid],
+                )
+            },
+        )
+    }
+
+    // Set the property of the aggregate device to a CFArray of the `strings`.
+    fn set_string_list(
+        hardware: &dyn HardwareAbstraction,
+        device_id: AudioDeviceID,
+        selector: AudioObjectPropertySelector,
+        strings: &[StringRef],
+    ) -> std::result::Result<(), OSStatus> {
+        unsafe {
+            let list = CFArrayCreateMutable(ptr::null(), 0, &kCFTypeArrayCallBacks);
+            for string in strings {
+                CFArrayAppendValue(list, string.get_raw() as *const c_void);
+            }
+
+            let address = AudioObjectPropertyAddress {
+                mSelector: selector,
+                mScope: kAudioObjectPropertyScopeGlobal,
+                mElement: kAudioObjectPropertyElementMaster,
+            };
+
+            let size = mem::size_of::<CFMutableArrayRef>();
+            let status = audio_object_set_property_data(hardware, device_id, &address, size, &list);
+            CFRelease(list as *const c_void);
+            if status == NO_ERR {
+                Ok(())
+            } else {
+                Err(status)
+            }
+        }
+    }
+
+    pub fn create_tap(
+        hardware: &dyn HardwareAbstraction,
+        output_id: AudioDeviceID,
+    ) -> std::result::Result<AudioObjectID, OSStatus> {
+        assert_ne!(output_id, kAudioObjectUnknown);
+        let uid = get_device_global_uid(hardware, output_id)?;
+        let mut tap_id = kAudioObjectUnknown;
+        let status = audio_hardware_create_process_tap(hardware, uid.get_raw() as _, &mut tap_id);
+        if status == NO_ERR {
+            assert_ne!(tap_id, kAudioObjectUnknown);
+            Ok(tap_id)
+        } else {
+            Err(status)
+        }
+    }
+
+    pub fn get_tap_uid(
+        hardware: &dyn HardwareAbstraction,
+        tap_id: AudioObjectID,
+    ) -> std::result::Result<StringRef, OSStatus> {
+        let address = AudioObjectPropertyAddress {
+            mSelector: TAP_PROPERTY_UID,
+            mScope: kAudioObjectPropertyScopeGlobal,
+            mElement: kAudioObjectPropertyElementMaster,
+        };
+        let mut size = mem::size_of::<CFStringRef>();
+        let mut uid: CFStringRef = ptr::null();
+        let status =
+            audio_object_get_property_data(hardware, tap_id, &address, &mut size, &mut uid);
+        if status == NO_ERR {
+            Ok(StringRef::new(uid as _))
+        } else {
+            Err(status)
+        }
+    }
+
     pub fn get_sub_devices(
+        hardware: &dyn HardwareAbstraction,
         device_id: AudioDeviceID,
     ) -> std::result::Result<Vec<AudioObjectID>, OSStatus> {
         assert_ne!(device_id, kAudioObjectUnknown);
@@ -391,7 +598,7 @@ impl AggregateDevice {
             mElement: kAudioObjectPropertyElementMaster,
         };
         let mut size: usize = 0;
-        let rv = audio_object_get_property_data_size(device_id, &address, &mut size);
+        let rv = audio_object_get_property_data_size(hardware, device_id, &address, &mut size);
 
         if rv == kAudioHardwareUnknownPropertyError as OSStatus {
             // Return a vector containing the device itself if the device has no sub devices.
@@ -406,6 +613,7 @@ impl AggregateDevice {
         let count = size / mem::size_of::<AudioObjectID>();
         sub_devices = allocate_array(count);
         let rv = audio_object_get_property_data(
+            hardware,
             device_id,
             &address,
             &mut size,
@@ -419,7 +627,21 @@ impl AggregateDevice {
         }
     }
 
-    pub fn set_master_device(device_id: AudioDeviceID) -> std::result::Result<(), OSStatus> {
+    pub fn set_master_device(
+        hardware: &dyn HardwareAbstraction,
+        device_id: AudioDeviceID,
+    ) -> std::result::Result<(), OSStatus> {
+        // Master become the 1st output sub device
+        let output_device_id = audiounit_get_default_device_id(hardware, DeviceType::OUTPUT);
+        Self::set_master_sub_device(hardware, device_id, output_device_id)
+    }
+
+    // Make the 1st sub device of `output_device_id` the master of the aggregate device.
+    fn set_master_sub_device(
+        hardware: &dyn HardwareAbstraction,
+        device_id: AudioDeviceID,
+        output_device_id: AudioDeviceID,
+    ) -> std::result::Result<(), OSStatus> {
         assert_ne!(device_id, kAudioObjectUnknown);
         let address = AudioObjectPropertyAddress {
             mSelector: kAudioAggregateDevicePropertyMasterSubDevice,
@@ -427,15 +649,14 @@ impl AggregateDevice {
             mElement: kAudioObjectPropertyElementMaster,
         };
 
-        // Master become the 1st output sub device
-        let output_device_id = audiounit_get_default_device_id(DeviceType::OUTPUT);
         assert_ne!(output_device_id, kAudioObjectUnknown);
-        let output_sub_devices = Self::get_sub_devices(output_device_id)?;
+        let output_sub_devices = Self::get_sub_devices(hardware, output_device_id)?;
         assert!(!output_sub_devices.is_empty());
-        let master_sub_device_uid = get_device_global_uid(output_sub_devices[0]).unwrap();
+        let master_sub_device_uid = get_device_global_uid(hardware, output_sub_devices[0]).unwrap();
         let master_sub_device = master_sub_device_uid.get_raw();
         let size = mem::size_of::<CFStringRef>();
-        let status = audio_object_set_property_data(device_id, &address, size, &master_sub_device);
+        let status =
+            audio_object_set_property_data(hardware, device_id, &address, size, &master_sub_device);
         if status == NO_ERR {
             Ok(())
         } else {
@@ -444,6 +665,7 @@ impl AggregateDevice {
     }
 
     pub fn activate_clock_drift_compensation(
+        hardware: &dyn HardwareAbstraction,
         device_id: AudioObjectID,
     ) -> std::result::Result<(), OSStatus> {
         assert_ne!(device_id, kAudioObjectUnknown);
@@ -459,6 +681,7 @@ impl AggregateDevice {
 
         let mut size: usize = 0;
         let status = audio_object_get_property_data_size_with_qualifier(
+            hardware,
             device_id,
             &address,
             qualifier_data_size,
@@ -476,6 +699,7 @@ impl AggregateDevice {
         );
         let mut sub_devices: Vec<AudioObjectID> = allocate_array(subdevices_num);
         let status = audio_object_get_property_data_with_qualifier(
+            hardware,
             device_id,
             &address,
             qualifier_data_size,
@@ -496,6 +720,7 @@ impl AggregateDevice {
         // Start from the second device since the first is the master clock
         for device in &sub_devices[1..] {
             let status = audio_object_set_property_data(
+                hardware,
                 *device,
                 &address,
                 mem::size_of::<u32>(),
@@ -513,6 +738,7 @@ impl AggregateDevice {
     }
 
     pub fn destroy_device(
+        hardware: &dyn HardwareAbstraction,
         plugin_id: AudioObjectID,
         mut device_id: AudioDeviceID,
     ) -> std::result::Result<(), OSStatus> {
@@ -526,13 +752,19 @@ impl AggregateDevice {
         };
 
         let mut size: usize = 0;
-        let status = audio_object_get_property_data_size(plugin_id, &address, &mut size);
+        let status = audio_object_get_property_data_size(hardware, plugin_id, &address, &mut size);
         if status != NO_ERR {
             return Err(status);
         }
         assert!(size > 0);
 
-        let status = audio_object_get_property_data(plugin_id, &address, &mut size, &mut device_id);
+        let status = audio_object_get_property_data(
+            hardware,
+            plugin_id,
+            &address,
+            &mut size,
+            &mut device_id,
+        );
         if status == NO_ERR {
             Ok(())
         } else {
@@ -541,6 +773,7 @@ impl AggregateDevice {
     }
 
     pub fn workaround_for_airpod(
+        hardware: &dyn HardwareAbstraction,
         device_id: AudioDeviceID,
         input_id: AudioDeviceID,
         output_id: AudioDeviceID,
@@ -550,26 +783,29 @@ impl AggregateDevice {
         assert_ne!(output_id, kAudioObjectUnknown);
         assert_ne!(input_id, output_id);
 
-        let label = get_device_label(input_id, DeviceType::INPUT)?;
+        let label = get_device_label(hardware, input_id, DeviceType::INPUT)?;
         let input_label = label.into_string();
 
-        let label = get_device_label(output_id, DeviceType::OUTPUT)?;
+        let label = get_device_label(hardware, output_id, DeviceType::OUTPUT)?;
         let output_label = label.into_string();
 
         if input_label.contains("AirPods") && output_label.contains("AirPods") {
             let input_rate =
-                get_device_sample_rate(input_id, DeviceType::INPUT | DeviceType::OUTPUT)?;
+                get_device_sample_rate(hardware, input_id, DeviceType::INPUT | DeviceType::OUTPUT)?;
             cubeb_log!(
                 "The nominal rate of the input device {}: {}",
                 input_id,
                 input_rate
             );
 
-            let output_rate =
-                match get_device_sample_rate(output_id, DeviceType::INPUT | DeviceType::OUTPUT) {
-                    Ok(rate) => format!("{}", rate),
-                    Err(e) => format!("Error {}", e),
-                };
+            let output_rate = match get_device_sample_rate(
+                hardware,
+                output_id,
+                DeviceType::INPUT | DeviceType::OUTPUT,
+            ) {
+                Ok(rate) => format!("{}", rate),
+                Err(e) => format!("Error {}", e),
+            };
             cubeb_log!(
                 "The nominal rate of the output device {}: {}",
                 output_id,
@@ -583,6 +819,7 @@ impl AggregateDevice {
             };
 
             let status = audio_object_set_property_data(
+                hardware,
                 device_id,
                 &addr,
                 mem::size_of::<f64>(),
@@ -604,6 +841,8 @@ impl Default for AggregateDevice {
             device_id: kAudioObjectUnknown,
             input_id: kAudioObjectUnknown,
             output_id: kAudioObjectUnknown,
+            tap_id: kAudioObjectUnknown,
+            platform: Platform::default(),
         }
     }
 }
@@ -611,7 +850,9 @@ impl Default for AggregateDevice {
 impl Drop for AggregateDevice {
     fn drop(&mut self) {
         if self.plugin_id != kAudioObjectUnknown && self.device_id != kAudioObjectUnknown {
-            if let Err(r) = Self::destroy_device(self.plugin_id, self.device_id) {
+            if let Err(r) =
+                Self::destroy_device(self.platform.hardware(), self.plugin_id, self.device_id)
+            {
                 cubeb_log!(
                     "Failed to destroyed aggregate device {}. Error: {}",
                     self.device_id,
@@ -621,5 +862,13 @@ impl Drop for AggregateDevice {
                 cubeb_log!("Destroyed aggregate device {}", self.device_id);
             }
         }
+        if self.tap_id != kAudioObjectUnknown {
+            let r = audio_hardware_destroy_process_tap(self.platform.hardware(), self.tap_id);
+            if r != NO_ERR {
+                cubeb_log!("Failed to destroyed tap {}. Error: {}", self.tap_id, r);
+            } else {
+                cubeb_log!("Destroyed tap {}", self.tap_id);
+            }
+        }
     }
 }
@@ -0,0 +1,154 @@
+use super::*;
+
+// An AudioBufferList with room for `capacity` buffers, since the type only has room for one. The
+// AudioUnits of the planar streams render or take a buffer per channel.
+#[derive(Debug)]
+pub struct BufferList {
+    // Aligned for the AudioBufferList, whose buffers follow the count.
+    storage: Vec<AudioBuffer>,
+    capacity: usize,
+}
+
+impl BufferList {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        // The count, padded to the alignment of the buffers, takes at most one more buffer.
+        assert!(mem::size_of::<AudioBufferList>() <= 2 * mem::size_of::<AudioBuffer>());
+        let empty = AudioBuffer {
+            mNumberChannels: 0,
+            mDataByteSize: 0,
+            mData: ptr::null_mut(),
+        };
+        Self {
+            storage: vec![empty; capacity + 1],
+            capacity,
+        }
+    }
+
+    #[allow(clippy::cast_ptr_alignment)] // The storage is aligned for the AudioBuffers.
+    pub fn as_mut_ptr(&mut self) -> *mut AudioBufferList {
+        self.storage.as_mut_ptr() as *mut AudioBufferList
+    }
+
+    // Describe `buffers` empty buffers of `channels` channels each, for AudioUnitRender to fill
+    // with `frames` frames of `sample_size`-byte samples.
+    pub fn prepare(&mut self, buffers: usize, channels: u32, sample_size: usize, frames: u32) {
+        assert!(buffers <= self.capacity);
+        let list = unsafe { &mut *self.as_mut_ptr() };
+        list.mNumberBuffers = buffers as u32;
+        for buffer in self.buffers_mut() {
+            buffer.mNumberChannels = channels;
+            buffer.mDataByteSize = channels * sample_size as u32 * frames;
+            buffer.mData = ptr::null_mut();
+        }
+    }
+
+    // The buffers in the list, up to its capacity.
+    pub fn buffers_mut(&mut self) -> &mut [AudioBuffer] {
+        let list = unsafe { &mut *self.as_mut_ptr() };
+        let count = cmp::min(list.mNumberBuffers as usize, self.capacity);
+        unsafe { slice::from_raw_parts_mut(list.mBuffers.as_mut_ptr(), count) }
+    }
+}
+
+// The number of buffers of the data in `desc`, which has a buffer per channel if it's not
+// interleaved.
+pub fn buffer_count(desc: &AudioStreamBasicDescription) -> usize {
+    if desc.mFormatFlags & kAudioFormatFlagIsNonInterleaved != 0 {
+        desc.mChannelsPerFrame as usize
+    } else {
+        1
+    }
+}
+
+// The description of the data in `desc` with a buffer per channel, whose frames are one sample.
+pub fn non_interleaved(desc: &AudioStreamBasicDescription) -> AudioStreamBasicDescription {
+    let mut desc = *desc;
+    if desc.mFormatFlags & kAudioFormatFlagIsNonInterleaved == 0 && desc.mChannelsPerFrame > 0 {
+        desc.mFormatFlags |= kAudioFormatFlagIsNonInterleaved;
+        desc.mBytesPerFrame /= desc.mChannelsPerFrame;
+        desc.mBytesPerPacket = desc.mBytesPerFrame * desc.mFramesPerPacket;
+    }
+    desc
+}
+
+// Copy the `frames` frames of `buffers`, each holding the next `mNumberChannels` channels
+// interleaved, into the interleaved `data` of `channels` channels of `sample_size`-byte samples.
+// The channels, or the frames, missing in the buffers are silent.
+pub fn gather_buffers(
+    buffers: &[AudioBuffer],
+    data: &mut [u8],
+    channels: usize,
+    sample_size: usize,
+    frames: usize,
+) {
+    let frame_size = channels * sample_size;
+    assert!(data.len() >= frames * frame_size);
+    for byte in data[..frames * frame_size].iter_mut() {
+        *byte = 0;
+    }
+    let mut first_channel = 0;
+    for buffer in buffers {
+        let buffer_channels = buffer.mNumberChannels as usize;
+        if buffer.mData.is_null() || buffer_channels == 0 || first_channel >= channels {
+            first_channel += buffer_channels;
+            continue;
+        }
+        let buffer_frame_size = buffer_channels * sample_size;
+        let buffer_frames = cmp::min(frames, buffer.mDataByteSize as usize / buffer_frame_size);
+        let bytes = unsafe {
+            slice::from_raw_parts(buffer.mData as *const u8, buffer_frames * buffer_frame_size)
+        };
+        let copied_size = cmp::min(buffer_channels, channels - first_channel) * sample_size;
+        let offset = first_channel * sample_size;
+        for (frame, buffer_frame) in data
+            .chunks_mut(frame_size)
+            .zip(bytes.chunks(buffer_frame_size))
+        {
+            frame[offset..offset + copied_size].copy_from_slice(&buffer_frame[..copied_size]);
+        }
+        first_channel += buffer_channels;
+    }
+}
+
+// Copy the `frames` interleaved frames of `data`, of `channels` channels of `sample_size`-byte
+// samples, into `buffers`, each taking the next `mNumberChannels` channels interleaved. The
+// channels missing in the data are silent.
+pub fn scatter_buffers(
+    data: &[u8],
+    buffers: &mut [AudioBuffer],
+    channels: usize,
+    sample_size: usize,
+    frames: usize,
+) {
+    let frame_size = channels * sample_size;
+    assert!(data.len() >= frames * frame_size);
+    let mut first_channel = 0;
+    for buffer in buffers {
+        let buffer_channels = buffer.mNumberChannels as usize;
+        if buffer.mData.is_null() || buffer_channels == 0 {
+            first_channel += buffer_channels;
+            continue;
+        }
+        let buffer_frame_size = buffer_channels * sample_size;
+        let buffer_frames = cmp::min(frames, buffer.mDataByteSize as usize / buffer_frame_size);
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(buffer.mData as *mut u8, buffer_frames * buffer_frame_size)
+        };
+        let copied_size =
+            cmp::min(buffer_channels, channels.saturating_sub(first_channel)) * sample_size;
+        let offset = first_channel * sample_size;
+        for (buffer_frame, frame) in bytes
+            .chunks_mut(buffer_frame_size)
+            .zip(data.chunks(frame_size))
+        {
+            if copied_size > 0 {
+                buffer_frame[..copied_size].copy_from_slice(&frame[offset..offset + copied_size]);
+            }
+            for byte in buffer_frame[copied_size..].iter_mut() {
+                *byte = 0;
+            }
+        }
+        first_channel += buffer_channels;
+    }
+}
@@ -1,82 +1,67 @@
 use std::fmt;
+use std::iter;
 use std::os::raw::c_void;
 use std::slice;
+use std::time::Duration;
 
-use cubeb_backend::SampleFormat;
-
-use super::ringbuf::RingBuffer;
-
-use self::LinearInputBuffer::*;
-use self::RingBufferConsumer::*;
-use self::RingBufferProducer::*;
+use super::ringbuf::{Consumer, Producer, RingBuffer};
+use super::sample::{I24In32, Sample, StreamFormat, I24};
 
 // When running duplex callbacks, the input data is fed to a ring buffer, and then later copied to
 // a linear piece of memory is used to hold the input samples, so that they are passed to the audio
-// callback that delivers it to the callees. Their size depends on the buffer size requested
-// initially.  This is to be tuned when changing tlength and fragsize, but this value works for
-// now.
-const INPUT_BUFFER_CAPACITY: usize = 4096;
-
-pub enum RingBufferConsumer {
-    IntegerRingBufferConsumer(ringbuf::Consumer<i16>),
-    FloatRingBufferConsumer(ringbuf::Consumer<f32>),
-}
+// callback that delivers it to the callees. Their size depends on the stream parameters, see
+// `input_buffer_capacity`.
 
-pub enum RingBufferProducer {
-    IntegerRingBufferProducer(ringbuf::Producer<i16>),
-    FloatRingBufferProducer(ringbuf::Producer<f32>),
-}
+// The input buffered on top of the callbacks, to absorb the scheduling jitter and the late start
+// of the output, unless it's set by `StreamOptions::input_buffer_margin`.
+pub const DEFAULT_INPUT_BUFFER_MARGIN: Duration = Duration::from_millis(50);
 
-pub enum LinearInputBuffer {
-    IntegerLinearInputBuffer(Vec<i16>),
-    FloatLinearInputBuffer(Vec<f32>),
+// The capacity, in samples, of the input buffer of a stream with `channels` input channels at
+// `input_rate`, whose output runs at `output_rate` and whose callbacks ask for `latency_frames`
+// frames. The buffer holds two callbacks of each side, one being the reserve of the duplex
+// streams on separate clocks and the other one a callback running late, plus `margin`.
+pub fn input_buffer_capacity(
+    channels: u32,
+    input_rate: f64,
+    output_rate: f64,
+    latency_frames: u32,
+    margin: Duration,
+) -> usize {
+    assert!(input_rate > 0.0);
+    assert!(output_rate > 0.0);
+    let input_callback_frames = f64::from(latency_frames);
+    let output_callback_frames = (f64::from(latency_frames) * input_rate / output_rate).ceil();
+    let margin_nanos = margin.as_secs() * 1_000_000_000 + u64::from(margin.subsec_nanos());
+    let margin_frames = (margin_nanos as f64 * input_rate / 1_000_000_000.0).ceil();
+    let frames = 2.0 * (input_callback_frames + output_callback_frames) + margin_frames;
+    frames as usize * channels as usize
 }
 
-pub struct BufferManager {
-    consumer: RingBufferConsumer,
-    producer: RingBufferProducer,
-    linear_input_buffer: LinearInputBuffer,
+pub struct BufferManager<T: Sample> {
+    consumer: Consumer<T>,
+    producer: Producer<T>,
+    linear_input_buffer: Vec<T>,
 }
 
-impl BufferManager {
-    // When opening a duplex stream, the sample-spec are guaranteed to match. It's ok to have
-    // either the input or output sample-spec here.
-    pub fn new(format: SampleFormat) -> BufferManager {
-        if format == SampleFormat::S16LE || format == SampleFormat::S16BE {
-            let ring = RingBuffer::<i16>::new(INPUT_BUFFER_CAPACITY);
-            let (prod, cons) = ring.split();
-            BufferManager {
-                producer: IntegerRingBufferProducer(prod),
-                consumer: IntegerRingBufferConsumer(cons),
-                linear_input_buffer: IntegerLinearInputBuffer(Vec::<i16>::with_capacity(
-                    INPUT_BUFFER_CAPACITY,
-                )),
-            }
-        } else {
-            let ring = RingBuffer::<f32>::new(INPUT_BUFFER_CAPACITY);
-            let (prod, cons) = ring.split();
-            BufferManager {
-                producer: FloatRingBufferProducer(prod),
-                consumer: FloatRingBufferConsumer(cons),
-                linear_input_buffer: FloatLinearInputBuffer(Vec::<f32>::with_capacity(
-                    INPUT_BUFFER_CAPACITY,
-                )),
-            }
+impl<T: Sample> BufferManager<T> {
+    pub fn new(capacity: usize) -> Self {
+        let ring = RingBuffer::<T>::new(capacity);
+        let (producer, consumer) = ring.split();
+        BufferManager {
+            producer,
+            consumer,
+            linear_input_buffer: Vec::with_capacity(capacity),
         }
     }
-    pub fn push_silent_data(&mut self, silent_samples: usize) {
-        let pushed = match &mut self.producer {
-            RingBufferProducer::FloatRingBufferProducer(p) => {
-                let mut silent_buffer = [0. as f32; INPUT_BUFFER_CAPACITY];
-                let silent_slice = silent_buffer.split_at_mut(silent_samples).0;
-                p.push_slice(silent_slice)
-            }
-            RingBufferProducer::IntegerRingBufferProducer(p) => {
-                let mut silent_buffer = [0 as i16; INPUT_BUFFER_CAPACITY];
-                let silent_slice = silent_buffer.split_at_mut(silent_samples).0;
-                p.push_slice(silent_slice)
-            }
-        };
+    // The capacity of the ring buffer, in samples.
+    pub fn capacity(&self) -> usize {
+        self.consumer.capacity()
+    }
+    // Return the samples pushed, which are fewer than `silent_samples` if the buffer is full.
+    pub fn push_silent_data(&mut self, silent_samples: usize) -> usize {
+        let pushed = self
+            .producer
+            .push_iter(&mut iter::repeat_n(T::default(), silent_samples));
         if pushed != silent_samples {
             cubeb_log!(
                 "Input ringbuffer full, could only push {} instead of {}",
@@ -84,100 +69,113 @@ impl BufferManager {
                 silent_samples
             );
         }
+        pushed
     }
-    pub fn push_data(&mut self, input_data: *const c_void, read_samples: usize) {
-        let pushed = match &mut self.producer {
-            RingBufferProducer::FloatRingBufferProducer(p) => {
-                let input_data =
-                    unsafe { slice::from_raw_parts::<f32>(input_data as *const f32, read_samples) };
-                p.push_slice(input_data)
-            }
-            RingBufferProducer::IntegerRingBufferProducer(p) => {
-                let input_data =
-                    unsafe { slice::from_raw_parts::<i16>(input_data as *const i16, read_samples) };
-                p.push_slice(input_data)
-            }
-        };
-        if pushed != read_samples {
+    // Return the samples pushed, which are fewer than `input_data.len()` if the buffer is full.
+    pub fn push_data(&mut self, input_data: &[T]) -> usize {
+        let pushed = self.producer.push_slice(input_data);
+        if pushed != input_data.len() {
             cubeb_log!(
                 "Input ringbuffer full, could only push {} instead of {}",
                 pushed,
-                read_samples
+                input_data.len()
             );
         }
+        pushed
     }
-    fn pull_data(&mut self, input_data: *mut c_void, needed_samples: usize) {
-        match &mut self.consumer {
-            IntegerRingBufferConsumer(p) => {
-                let mut input: &mut [i16] = unsafe {
-                    slice::from_raw_parts_mut::<i16>(input_data as *mut i16, needed_samples)
-                };
-                let read = p.pop_slice(&mut input);
-                if read < needed_samples {
-                    for i in 0..(needed_samples - read) {
-                        input[read + i] = 0;
-                    }
-                }
-            }
-            FloatRingBufferConsumer(p) => {
-                let mut input: &mut [f32] = unsafe {
-                    slice::from_raw_parts_mut::<f32>(input_data as *mut f32, needed_samples)
-                };
-                let read = p.pop_slice(&mut input);
-                if read < needed_samples {
-                    for i in 0..(needed_samples - read) {
-                        input[read + i] = 0.0;
-                    }
-                }
-            }
+    // Fill `input` with the buffered samples, padded with silence if fewer are buffered.
+    fn pull_data(consumer: &mut Consumer<T>, input: &mut [T]) {
+        let read = consumer.pop_slice(input);
+        for sample in input[read..].iter_mut() {
+            *sample = T::default();
         }
     }
-    pub fn get_linear_data(&mut self, nsamples: usize) -> *mut c_void {
-        let p: *mut c_void;
-        match &mut self.linear_input_buffer {
-            LinearInputBuffer::IntegerLinearInputBuffer(b) => {
-                b.resize(nsamples, 0);
-                p = b.as_mut_ptr() as *mut c_void;
-            }
-            LinearInputBuffer::FloatLinearInputBuffer(b) => {
-                b.resize(nsamples, 0.);
-                p = b.as_mut_ptr() as *mut c_void;
-            }
-        }
-        self.pull_data(p, nsamples);
-
-        p
+    pub fn get_linear_data(&mut self, nsamples: usize) -> &mut [T] {
+        self.linear_input_buffer.resize(nsamples, T::default());
+        Self::pull_data(&mut self.consumer, &mut self.linear_input_buffer);
+        &mut self.linear_input_buffer
     }
     pub fn available_samples(&self) -> usize {
-        match &self.consumer {
-            IntegerRingBufferConsumer(p) => p.len(),
-            FloatRingBufferConsumer(p) => p.len(),
-        }
+        self.consumer.len()
     }
     pub fn trim(&mut self, final_size: usize) {
-        match &mut self.consumer {
-            IntegerRingBufferConsumer(c) => {
-                let available = c.len();
-                assert!(available >= final_size);
-                let to_pop = available - final_size;
-                let mut buffer = [0 as i16; INPUT_BUFFER_CAPACITY];
-                let mut slice_to_pop = buffer.split_at_mut(to_pop);
-                c.pop_slice(&mut slice_to_pop.0);
-            }
-            FloatRingBufferConsumer(c) => {
-                let available = c.len();
-                assert!(available >= final_size);
-                let to_pop = available - final_size;
-                let mut buffer = [0 as f32; INPUT_BUFFER_CAPACITY];
-                let mut slice_to_pop = buffer.split_at_mut(to_pop);
-                c.pop_slice(&mut slice_to_pop.0);
-            }
-        }
+        let available = self.consumer.len();
+        assert!(available >= final_size);
+        self.consumer.discard(available - final_size);
     }
 }
 
-impl fmt::Debug for BufferManager {
+impl<T: Sample> fmt::Debug for BufferManager<T> {
     fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
         Ok(())
     }
 }
+
+// The `BufferManager` of the sample type of the stream format, taking and returning the untyped
+// buffers of the callbacks.
+#[derive(Debug)]
+pub enum InputBuffer {
+    Integer(BufferManager<i16>),
+    Integer24(BufferManager<I24>),
+    Integer24In32(BufferManager<I24In32>),
+    Integer32(BufferManager<i32>),
+    Float(BufferManager<f32>),
+    Double(BufferManager<f64>),
+}
+
+macro_rules! dispatch {
+    ($buffer:expr, $manager:ident => $body:expr) => {
+        match $buffer {
+            InputBuffer::Integer($manager) => $body,
+            InputBuffer::Integer24($manager) => $body,
+            InputBuffer::Integer24In32($manager) => $body,
+            InputBuffer::Integer32($manager) => $body,
+            InputBuffer::Float($manager) => $body,
+            InputBuffer::Double($manager) => $body,
+        }
+    };
+}
+
+impl InputBuffer {
+    // When opening a duplex stream, the sample-spec are guaranteed to match. It's ok to have
+    // either the input or output sample-spec here.
+    pub fn new(format: StreamFormat, capacity: usize) -> Self {
+        match format {
+            StreamFormat::S16LE | StreamFormat::S16BE => {
+                InputBuffer::Integer(BufferManager::new(capacity))
+            }
+            StreamFormat::S24LE => InputBuffer::Integer24(BufferManager::new(capacity)),
+            StreamFormat::S24In32LE => InputBuffer::Integer24In32(BufferManager::new(capacity)),
+            StreamFormat::S32LE => InputBuffer::Integer32(BufferManager::new(capacity)),
+            StreamFormat::Float32LE | StreamFormat::Float32BE => {
+                InputBuffer::Float(BufferManager::new(capacity))
+            }
+            StreamFormat::Float64LE => InputBuffer::Double(BufferManager::new(capacity)),
+        }
+    }
+    pub fn capacity(&self) -> usize {
+        dispatch!(self, m => m.capacity())
+    }
+    pub fn push_silent_data(&mut self, silent_samples: usize) -> usize {
+        dispatch!(self, m => m.push_silent_data(silent_samples))
+    }
+    // `input_data` holds `read_samples` samples of the stream format.
+    pub fn push_data(&mut self, input_data: *const c_void, read_samples: usize) -> usize {
+        assert!(!input_data.is_null() || read_samples == 0);
+        if read_samples == 0 {
+            return 0;
+        }
+        dispatch!(self, m => m.push_data(unsafe {
+            slice::from_raw_parts(input_data as *const _, read_samples)
+        }))
+    }
+    pub fn get_linear_data(&mut self, nsamples: usize) -> *mut c_void {
+        dispatch!(self, m => m.get_linear_data(nsamples).as_mut_ptr() as *mut c_void)
+    }
+    pub fn available_samples(&self) -> usize {
+        dispatch!(self, m => m.available_samples())
+    }
+    pub fn trim(&mut self, final_size: usize) {
+        dispatch!(self, m => m.trim(final_size))
+    }
+}
@@ -0,0 +1,65 @@
+// The channels of a stream mapped to channels of its device, instead of its first channels or
+// the channels of its layout. The map holds the 0-based device channel of each stream channel,
+// in order.
+
+use std::ops::Deref;
+
+// Whether `map` maps the `channels` channels of a stream to the `device_channels` channels of
+// its device.
+pub fn is_valid_channel_map(map: &[u32], channels: u32, device_channels: u32) -> bool {
+    map.len() == channels as usize && map.iter().all(|channel| *channel < device_channels)
+}
+
+// A map validated against its stream and device when the stream is created. The device may have
+// fewer channels after reinit, which `fits` tells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelMap(Vec<u32>);
+
+impl ChannelMap {
+    pub fn new(map: &[u32], channels: u32, device_channels: u32) -> Option<Self> {
+        if is_valid_channel_map(map, channels, device_channels) {
+            Some(ChannelMap(map.to_vec()))
+        } else {
+            None
+        }
+    }
+
+    // Whether the mapped channels are among the `device_channels` channels of a device.
+    pub fn fits(&self, device_channels: u32) -> bool {
+        self.0.iter().all(|channel| *channel < device_channels)
+    }
+}
+
+impl Deref for ChannelMap {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+// Copy the `map.len()` channels of `map` out of the `frames` interleaved frames of `input`, of
+// `device_channels` channels of `sample_size`-byte samples, into the interleaved `output`.
+pub fn extract_channels(
+    input: &[u8],
+    output: &mut [u8],
+    device_channels: usize,
+    map: &[u32],
+    sample_size: usize,
+    frames: usize,
+) {
+    let input_frame_size = device_channels * sample_size;
+    let output_frame_size = map.len() * sample_size;
+    assert!(input.len() >= frames * input_frame_size);
+    assert!(output.len() >= frames * output_frame_size);
+    for (input_frame, output_frame) in input
+        .chunks(input_frame_size)
+        .zip(output.chunks_mut(output_frame_size))
+        .take(frames)
+    {
+        for (channel, sample) in map.iter().zip(output_frame.chunks_mut(sample_size)) {
+            let start = *channel as usize * sample_size;
+            sample.copy_from_slice(&input_frame[start..start + sample_size]);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use super::*;
 
 pub fn get_device_uid(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<StringRef, OSStatus> {
@@ -9,7 +10,7 @@ pub fn get_device_uid(
     let address = get_property_address(Property::DeviceUID, devtype);
     let mut size = mem::size_of::<CFStringRef>();
     let mut uid: CFStringRef = ptr::null();
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut uid);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut uid);
     if err == NO_ERR {
         Ok(StringRef::new(uid as _))
     } else {
@@ -18,6 +19,7 @@ pub fn get_device_uid(
 }
 
 pub fn get_device_model_uid(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<StringRef, OSStatus> {
@@ -26,7 +28,7 @@ pub fn get_device_model_uid(
     let address = get_property_address(Property::ModelUID, devtype);
     let mut size = mem::size_of::<CFStringRef>();
     let mut uid: CFStringRef = ptr::null();
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut uid);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut uid);
     if err == NO_ERR {
         Ok(StringRef::new(uid as _))
     } else {
@@ -36,6 +38,7 @@ pub fn get_device_model_uid(
 
 #[allow(dead_code)] // `pub` for running test
 pub fn get_device_transport_type(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<u32, OSStatus> {
@@ -44,7 +47,7 @@ pub fn get_device_transport_type(
     let address = get_property_address(Property::TransportType, devtype);
     let mut size = mem::size_of::<u32>();
     let mut transport: u32 = 0;
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut transport);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut transport);
     if err == NO_ERR {
         Ok(transport)
     } else {
@@ -52,7 +55,64 @@ pub fn get_device_transport_type(
     }
 }
 
+pub fn get_device_is_alive(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioDeviceID,
+) -> std::result::Result<bool, OSStatus> {
+    assert_ne!(id, kAudioObjectUnknown);
+
+    let address = get_property_address(
+        Property::DeviceIsAlive,
+        DeviceType::INPUT | DeviceType::OUTPUT,
+    );
+    let mut size = mem::size_of::<u32>();
+    let mut alive: u32 = 0;
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut alive);
+    if err == NO_ERR {
+        Ok(alive != 0)
+    } else {
+        Err(err)
+    }
+}
+
+pub fn get_device_volume(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioDeviceID,
+    devtype: DeviceType,
+) -> std::result::Result<f32, OSStatus> {
+    assert_ne!(id, kAudioObjectUnknown);
+
+    let address = get_property_address(Property::DeviceVolume, devtype);
+    let mut size = mem::size_of::<f32>();
+    let mut volume: f32 = 0.0;
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut volume);
+    if err == NO_ERR {
+        Ok(volume)
+    } else {
+        Err(err)
+    }
+}
+
+pub fn set_device_volume(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioDeviceID,
+    devtype: DeviceType,
+    volume: f32,
+) -> std::result::Result<(), OSStatus> {
+    assert_ne!(id, kAudioObjectUnknown);
+
+    let address = get_property_address(Property::DeviceVolume, devtype);
+    let size = mem::size_of::<f32>();
+    let err = audio_object_set_property_data(hardware, id, &address, size, &volume);
+    if err == NO_ERR {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
 pub fn get_device_source(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<u32, OSStatus> {
@@ -61,7 +121,7 @@ pub fn get_device_source(
     let address = get_property_address(Property::DeviceSource, devtype);
     let mut size = mem::size_of::<u32>();
     let mut source: u32 = 0;
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut source);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut source);
     if err == NO_ERR {
         Ok(source)
     } else {
@@ -70,12 +130,13 @@ pub fn get_device_source(
 }
 
 pub fn get_device_source_name(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<StringRef, OSStatus> {
     assert_ne!(id, kAudioObjectUnknown);
 
-    let mut source: u32 = get_device_source(id, devtype)?;
+    let mut source: u32 = get_device_source(hardware, id, devtype)?;
     let address = get_property_address(Property::DeviceSourceName, devtype);
     let mut size = mem::size_of::<AudioValueTranslation>();
     let mut name: CFStringRef = ptr::null();
@@ -85,7 +146,7 @@ pub fn get_device_source_name(
         mOutputData: &mut name as *mut CFStringRef as *mut c_void,
         mOutputDataSize: mem::size_of::<CFStringRef>() as u32,
     };
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut trl);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut trl);
     if err == NO_ERR {
         Ok(StringRef::new(name as _))
     } else {
@@ -94,6 +155,7 @@ pub fn get_device_source_name(
 }
 
 pub fn get_device_name(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<StringRef, OSStatus> {
@@ -102,7 +164,7 @@ pub fn get_device_name(
     let address = get_property_address(Property::DeviceName, devtype);
     let mut size = mem::size_of::<CFStringRef>();
     let mut name: CFStringRef = ptr::null();
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut name);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut name);
     if err == NO_ERR {
         Ok(StringRef::new(name as _))
     } else {
@@ -111,6 +173,7 @@ pub fn get_device_name(
 }
 
 pub fn get_device_manufacturer(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<StringRef, OSStatus> {
@@ -119,7 +182,7 @@ pub fn get_device_manufacturer(
     let address = get_property_address(Property::DeviceManufacturer, devtype);
     let mut size = mem::size_of::<CFStringRef>();
     let mut manufacturer: CFStringRef = ptr::null();
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut manufacturer);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut manufacturer);
     if err == NO_ERR {
         Ok(StringRef::new(manufacturer as _))
     } else {
@@ -128,6 +191,7 @@ pub fn get_device_manufacturer(
 }
 
 pub fn get_device_buffer_frame_size_range(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<AudioValueRange, OSStatus> {
@@ -136,7 +200,7 @@ pub fn get_device_buffer_frame_size_range(
     let address = get_property_address(Property::DeviceBufferFrameSizeRange, devtype);
     let mut size = mem::size_of::<AudioValueRange>();
     let mut range = AudioValueRange::default();
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut range);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut range);
     if err == NO_ERR {
         Ok(range)
     } else {
@@ -145,6 +209,7 @@ pub fn get_device_buffer_frame_size_range(
 }
 
 pub fn get_device_latency(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<u32, OSStatus> {
@@ -153,7 +218,7 @@ pub fn get_device_latency(
     let address = get_property_address(Property::DeviceLatency, devtype);
     let mut size = mem::size_of::<u32>();
     let mut latency: u32 = 0;
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut latency);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut latency);
     if err == NO_ERR {
         Ok(latency)
     } else {
@@ -162,6 +227,7 @@ pub fn get_device_latency(
 }
 
 pub fn get_device_streams(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<Vec<AudioStreamID>, OSStatus> {
@@ -170,13 +236,14 @@ pub fn get_device_streams(
     let address = get_property_address(Property::DeviceStreams, devtype);
 
     let mut size: usize = 0;
-    let err = audio_object_get_property_data_size(id, &address, &mut size);
+    let err = audio_object_get_property_data_size(hardware, id, &address, &mut size);
     if err != NO_ERR {
         return Err(err);
     }
 
     let mut streams: Vec<AudioObjectID> = allocate_array_by_size(size);
-    let err = audio_object_get_property_data(id, &address, &mut size, streams.as_mut_ptr());
+    let err =
+        audio_object_get_property_data(hardware, id, &address, &mut size, streams.as_mut_ptr());
     if err == NO_ERR {
         Ok(streams)
     } else {
@@ -185,6 +252,7 @@ pub fn get_device_streams(
 }
 
 pub fn get_device_sample_rate(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<f64, OSStatus> {
@@ -193,7 +261,7 @@ pub fn get_device_sample_rate(
     let address = get_property_address(Property::DeviceSampleRate, devtype);
     let mut size = mem::size_of::<f64>();
     let mut rate: f64 = 0.0;
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut rate);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut rate);
     if err == NO_ERR {
         Ok(rate)
     } else {
@@ -202,6 +270,7 @@ pub fn get_device_sample_rate(
 }
 
 pub fn get_ranges_of_device_sample_rate(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<Vec<AudioValueRange>, OSStatus> {
@@ -210,13 +279,14 @@ pub fn get_ranges_of_device_sample_rate(
     let address = get_property_address(Property::DeviceSampleRates, devtype);
 
     let mut size: usize = 0;
-    let err = audio_object_get_property_data_size(id, &address, &mut size);
+    let err = audio_object_get_property_data_size(hardware, id, &address, &mut size);
     if err != NO_ERR {
         return Err(err);
     }
 
     let mut ranges: Vec<AudioValueRange> = allocate_array_by_size(size);
-    let err = audio_object_get_property_data(id, &address, &mut size, ranges.as_mut_ptr());
+    let err =
+        audio_object_get_property_data(hardware, id, &address, &mut size, ranges.as_mut_ptr());
     if err == NO_ERR {
         Ok(ranges)
     } else {
@@ -225,6 +295,7 @@ pub fn get_ranges_of_device_sample_rate(
 }
 
 pub fn get_device_stream_format(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<AudioStreamBasicDescription, OSStatus> {
@@ -233,7 +304,7 @@ pub fn get_device_stream_format(
     let address = get_property_address(Property::DeviceStreamFormat, devtype);
     let mut size = mem::size_of::<AudioStreamBasicDescription>();
     let mut format = AudioStreamBasicDescription::default();
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut format);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut format);
     if err == NO_ERR {
         Ok(format)
     } else {
@@ -243,6 +314,7 @@ pub fn get_device_stream_format(
 
 #[allow(clippy::cast_ptr_alignment)] // Allow casting *mut u8 to *mut AudioBufferList
 pub fn get_device_stream_configuration(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<Vec<AudioBuffer>, OSStatus> {
@@ -250,14 +322,14 @@ pub fn get_device_stream_configuration(
 
     let address = get_property_address(Property::DeviceStreamConfiguration, devtype);
     let mut size: usize = 0;
-    let err = audio_object_get_property_data_size(id, &address, &mut size);
+    let err = audio_object_get_property_data_size(hardware, id, &address, &mut size);
     if err != NO_ERR {
         return Err(err);
     }
 
     let mut data: Vec<u8> = allocate_array_by_size(size);
     let ptr = data.as_mut_ptr() as *mut AudioBufferList;
-    let err = audio_object_get_property_data(id, &address, &mut size, ptr);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, ptr);
     if err != NO_ERR {
         return Err(err);
     }
@@ -269,7 +341,31 @@ pub fn get_device_stream_configuration(
     Ok(buffers.to_vec())
 }
 
+pub fn get_device_preferred_channel_layout(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioDeviceID,
+    devtype: DeviceType,
+) -> std::result::Result<AutoRelease<AudioChannelLayout>, OSStatus> {
+    assert_ne!(id, kAudioObjectUnknown);
+
+    let address = get_property_address(Property::DevicePreferredChannelLayout, devtype);
+    let mut size: usize = 0;
+    let err = audio_object_get_property_data_size(hardware, id, &address, &mut size);
+    if err != NO_ERR {
+        return Err(err);
+    }
+
+    let mut layout = make_sized_audio_channel_layout(size);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, layout.as_mut());
+    if err == NO_ERR {
+        Ok(layout)
+    } else {
+        Err(err)
+    }
+}
+
 pub fn get_stream_latency(
+    hardware: &dyn HardwareAbstraction,
     id: AudioStreamID,
     devtype: DeviceType,
 ) -> std::result::Result<u32, OSStatus> {
@@ -278,7 +374,7 @@ pub fn get_stream_latency(
     let address = get_property_address(Property::StreamLatency, devtype);
     let mut size = mem::size_of::<u32>();
     let mut latency: u32 = 0;
-    let err = audio_object_get_property_data(id, &address, &mut size, &mut latency);
+    let err = audio_object_get_property_data(hardware, id, &address, &mut size, &mut latency);
     if err == NO_ERR {
         Ok(latency)
     } else {
@@ -292,6 +388,7 @@ pub enum Property {
     DeviceLatency,
     DeviceManufacturer,
     DeviceName,
+    DevicePreferredChannelLayout,
     DeviceSampleRate,
     DeviceSampleRates,
     DeviceSource,
@@ -300,6 +397,7 @@ pub enum Property {
     DeviceStreamFormat,
     DeviceStreams,
     DeviceUID,
+    DeviceVolume,
     HardwareDefaultInputDevice,
     HardwareDefaultOutputDevice,
     HardwareDevices,
@@ -316,6 +414,7 @@ impl From<Property> for AudioObjectPropertySelector {
             Property::DeviceLatency => kAudioDevicePropertyLatency,
             Property::DeviceManufacturer => kAudioObjectPropertyManufacturer,
             Property::DeviceName => kAudioObjectPropertyName,
+            Property::DevicePreferredChannelLayout => kAudioDevicePropertyPreferredChannelLayout,
             Property::DeviceSampleRate => kAudioDevicePropertyNominalSampleRate,
             Property::DeviceSampleRates => kAudioDevicePropertyAvailableNominalSampleRates,
             Property::DeviceSource => kAudioDevicePropertyDataSource,
@@ -324,6 +423,7 @@ impl From<Property> for AudioObjectPropertySelector {
             Property::DeviceStreamFormat => kAudioDevicePropertyStreamFormat,
             Property::DeviceStreams => kAudioDevicePropertyStreams,
             Property::DeviceUID => kAudioDevicePropertyDeviceUID,
+            Property::DeviceVolume => kAudioDevicePropertyVolumeScalar,
             Property::HardwareDefaultInputDevice => kAudioHardwarePropertyDefaultInputDevice,
             Property::HardwareDefaultOutputDevice => kAudioHardwarePropertyDefaultOutputDevice,
             Property::HardwareDevices => kAudioHardwarePropertyDevices,
@@ -0,0 +1,274 @@
+use super::sample::{I24In32, Sample, StreamFormat, I24};
+use std::cmp;
+use std::os::raw::c_void;
+use std::slice;
+
+// The largest correction of the input rate, in both directions. The clocks of the audio devices
+// are usually within 100 ppm of their nominal rates.
+const MAX_CORRECTION: f64 = 0.001;
+// The time constant of the smoothing of the fill level, in seconds. The level jumps by a whole
+// callback when the input and output callbacks change their order, so only its trend counts.
+const SMOOTHING_TIME: f64 = 0.5;
+// The response time of the controller, in seconds. A slow response keeps the pitch changes of
+// the correction inaudible.
+const RESPONSE_TIME: f64 = 4.0;
+
+// A PI controller estimating the drift between the input and output clocks from the fill level
+// of the input buffer. The level grows when the input clock is faster than the output clock, and
+// shrinks when it's slower, so the input is consumed at `ratio` times the nominal rate to bring
+// the level back to the target.
+#[derive(Debug)]
+pub struct DriftEstimator {
+    // The nominal input rate, to convert the time constants into frames.
+    rate: f64,
+    level: Option<f64>,
+    integral: f64,
+    correction: f64,
+}
+
+impl DriftEstimator {
+    pub fn new(rate: f64) -> Self {
+        assert!(rate > 0.0);
+        Self {
+            rate,
+            level: None,
+            integral: 0.0,
+            correction: 0.0,
+        }
+    }
+
+    // The input frames to consume for each input frame at the nominal rate.
+    pub fn ratio(&self) -> f64 {
+        1.0 + self.correction
+    }
+
+    // Update the estimate by the `level` of the buffer, in frames, observed `elapsed` frames after
+    // the last update, and return the new ratio.
+    pub fn update(&mut self, level: usize, target: usize, elapsed: usize) -> f64 {
+        let elapsed = elapsed as f64;
+        let level = level as f64;
+        let smoothed = match self.level {
+            None => level,
+            Some(previous) => {
+                let alpha = (elapsed / (SMOOTHING_TIME * self.rate)).min(1.0);
+                previous + alpha * (level - previous)
+            }
+        };
+        self.level = Some(smoothed);
+
+        // A critically damped response for the rate of change of the level, which is the drift
+        // minus the correction, in frames per frame.
+        let error = smoothed - target as f64;
+        let omega = 1.0 / (RESPONSE_TIME * self.rate);
+        self.integral = (self.integral + omega * omega * error * elapsed)
+            .max(-MAX_CORRECTION)
+            .min(MAX_CORRECTION);
+        self.correction = (2.0 * omega * error + self.integral)
+            .max(-MAX_CORRECTION)
+            .min(MAX_CORRECTION);
+        self.ratio()
+    }
+}
+
+pub trait DriftSample: Copy + Default {
+    fn lerp(self, next: Self, t: f32) -> Self;
+}
+
+impl DriftSample for f32 {
+    fn lerp(self, next: Self, t: f32) -> Self {
+        self + (next - self) * t
+    }
+}
+
+impl DriftSample for i16 {
+    fn lerp(self, next: Self, t: f32) -> Self {
+        let a = f32::from(self);
+        (a + (f32::from(next) - a) * t).round() as i16
+    }
+}
+
+// The samples wider than 16 bits are interpolated at full precision.
+macro_rules! impl_drift_sample {
+    ($($t:ty),*) => {
+        $(
+            impl DriftSample for $t {
+                fn lerp(self, next: Self, t: f32) -> Self {
+                    let a = self.to_f64();
+                    Self::from_f64(a + (next.to_f64() - a) * f64::from(t))
+                }
+            }
+        )*
+    };
+}
+
+impl_drift_sample!(I24, I24In32, i32, f64);
+
+// Linear interpolation of the interleaved input frames at a fractional step. The last frames are
+// kept for the next call, since the interpolation of a frame needs the input frame after it.
+#[derive(Debug)]
+struct Interpolator<T> {
+    pending: Vec<T>,
+    output: Vec<T>,
+}
+
+impl<T: DriftSample> Interpolator<T> {
+    // The buffers are allocated for the callbacks of up to `samples` samples, so they're not
+    // reallocated on the audio thread.
+    fn new(samples: usize) -> Self {
+        Self {
+            pending: Vec::with_capacity(samples),
+            output: Vec::with_capacity(samples),
+        }
+    }
+
+    fn pending_frames(&self, channels: usize) -> usize {
+        self.pending.len() / channels
+    }
+
+    fn process(
+        &mut self,
+        input: *const c_void,
+        samples: usize,
+        channels: usize,
+        position: &mut f64,
+        ratio: f64,
+        frames: usize,
+    ) -> *mut c_void {
+        if samples > 0 {
+            let input = unsafe { slice::from_raw_parts(input as *const T, samples) };
+            self.pending.extend_from_slice(input);
+        }
+        self.output.clear();
+        for frame in 0..frames {
+            let p = *position + frame as f64 * ratio;
+            let index = p as usize;
+            let t = (p - index as f64) as f32;
+            // The frames past the pending input, if the caller passed fewer frames than
+            // `input_frames` asked for, are silent.
+            let start = index * channels;
+            if (index + 2) * channels > self.pending.len() {
+                let len = self.output.len();
+                self.output.resize(len + channels, T::default());
+                continue;
+            }
+            let current = &self.pending[start..start + channels];
+            let next = &self.pending[start + channels..start + 2 * channels];
+            for (a, b) in current.iter().zip(next.iter()) {
+                self.output.push(a.lerp(*b, t));
+            }
+        }
+        let end = *position + frames as f64 * ratio;
+        let consumed = end as usize;
+        let consumed_samples = cmp::min(consumed * channels, self.pending.len());
+        self.pending.drain(..consumed_samples);
+        *position = end - consumed as f64;
+        self.output.as_mut_ptr() as *mut c_void
+    }
+}
+
+#[derive(Debug)]
+enum InterpolatorBuffer {
+    Integer(Interpolator<i16>),
+    Integer24(Interpolator<I24>),
+    Integer24In32(Interpolator<I24In32>),
+    Integer32(Interpolator<i32>),
+    Float(Interpolator<f32>),
+    Double(Interpolator<f64>),
+}
+
+macro_rules! dispatch {
+    ($buffer:expr, $interpolator:ident => $body:expr) => {
+        match $buffer {
+            InterpolatorBuffer::Integer($interpolator) => $body,
+            InterpolatorBuffer::Integer24($interpolator) => $body,
+            InterpolatorBuffer::Integer24In32($interpolator) => $body,
+            InterpolatorBuffer::Integer32($interpolator) => $body,
+            InterpolatorBuffer::Float($interpolator) => $body,
+            InterpolatorBuffer::Double($interpolator) => $body,
+        }
+    };
+}
+
+// Resample the input by the ratio of the `DriftEstimator` for the duplex streams whose input and
+// output run on different devices, so the input buffer neither runs dry nor piles up.
+#[derive(Debug)]
+pub struct DriftCompensator {
+    estimator: DriftEstimator,
+    channels: usize,
+    // The position of the next output frame, relative to the first pending input frame.
+    position: f64,
+    buffer: InterpolatorBuffer,
+}
+
+impl DriftCompensator {
+    // The compensator of the input for the callbacks asking for `max_frames` frames at most, the
+    // input frames needed by the largest output callback at the nominal input rate.
+    pub fn new(format: StreamFormat, channels: usize, rate: f64, max_frames: usize) -> Self {
+        assert_ne!(channels, 0);
+        // The input of a callback is at most `MAX_CORRECTION` longer than its output, plus the
+        // frames kept for the next callback.
+        let frames = (max_frames as f64 * (1.0 + MAX_CORRECTION)).ceil() as usize + 2;
+        let samples = frames * channels;
+        let buffer = match format {
+            StreamFormat::S16LE | StreamFormat::S16BE => {
+                InterpolatorBuffer::Integer(Interpolator::new(samples))
+            }
+            StreamFormat::S24LE => InterpolatorBuffer::Integer24(Interpolator::new(samples)),
+            StreamFormat::S24In32LE => {
+                InterpolatorBuffer::Integer24In32(Interpolator::new(samples))
+            }
+            StreamFormat::S32LE => InterpolatorBuffer::Integer32(Interpolator::new(samples)),
+            StreamFormat::Float32LE | StreamFormat::Float32BE => {
+                InterpolatorBuffer::Float(Interpolator::new(samples))
+            }
+            StreamFormat::Float64LE => InterpolatorBuffer::Double(Interpolator::new(samples)),
+        };
+        Self {
+            estimator: DriftEstimator::new(rate),
+            channels,
+            position: 0.0,
+            buffer,
+        }
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.estimator.ratio()
+    }
+
+    // The capacities of the pending input and the output buffers, in samples.
+    #[cfg(test)]
+    pub fn capacities(&self) -> (usize, usize) {
+        dispatch!(&self.buffer, b => (b.pending.capacity(), b.output.capacity()))
+    }
+
+    // Adjust the ratio by the frames `buffered` for the callback asking for `frames` frames.
+    pub fn update(&mut self, buffered: usize, target: usize, frames: usize) -> f64 {
+        self.estimator.update(buffered, target, frames)
+    }
+
+    // The input frames to pass to `process` to get `frames` frames.
+    pub fn input_frames(&self, frames: usize) -> usize {
+        if frames == 0 {
+            return 0;
+        }
+        let end = self.position + (frames - 1) as f64 * self.ratio();
+        let pending = dispatch!(&self.buffer, b => b.pending_frames(self.channels));
+        (end as usize + 2).saturating_sub(pending)
+    }
+
+    // Resample the `input_frames` frames got by `input_frames(frames)` into `frames` frames, and
+    // return the buffer of the result, which is valid until the next call.
+    pub fn process(
+        &mut self,
+        input: *const c_void,
+        input_frames: usize,
+        frames: usize,
+    ) -> *mut c_void {
+        assert!(!input.is_null() || input_frames == 0);
+        let samples = input_frames * self.channels;
+        let ratio = self.ratio();
+        let channels = self.channels;
+        let position = &mut self.position;
+        dispatch!(&mut self.buffer, b => b.process(input, samples, channels, position, ratio, frames))
+    }
+}
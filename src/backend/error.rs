@@ -0,0 +1,170 @@
+use super::*;
+use std::fmt;
+
+// The backend operations that can fail. Each one is reported with the OSStatus
+// returned by the system, if there is one, and the device it was run against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    CreateAudioUnit,
+    EnableIO,
+    SetDevice,
+    CreateStreamDescription,
+    GetStreamFormat,
+    SetStreamFormat,
+    CheckInputChannels,
+    CheckOutputChannels,
+    GetBufferSize,
+    SetBufferSize,
+    WaitForBufferSize,
+    SetMaxFramesPerSlice,
+    SetCallback,
+    InitializeAudioUnit,
+    StartAudioUnit,
+    StopAudioUnit,
+    CreateAggregateDevice,
+    InstallListener,
+    UninstallListener,
+    GetDeviceVolume,
+    SetDeviceVolume,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::CreateAudioUnit => "create audiounit",
+            Operation::EnableIO => "enable audiounit io",
+            Operation::SetDevice => "set device to audiounit",
+            Operation::CreateStreamDescription => "create stream description",
+            Operation::GetStreamFormat => "get stream format",
+            Operation::SetStreamFormat => "set stream format",
+            Operation::CheckInputChannels => "check input channels",
+            Operation::CheckOutputChannels => "check output channels",
+            Operation::GetBufferSize => "get buffer size",
+            Operation::SetBufferSize => "set buffer size",
+            Operation::WaitForBufferSize => "wait for buffer size change",
+            Operation::SetMaxFramesPerSlice => "set maximum frames per slice",
+            Operation::SetCallback => "set audiounit callback",
+            Operation::InitializeAudioUnit => "initialize audiounit",
+            Operation::StartAudioUnit => "start audiounit",
+            Operation::StopAudioUnit => "stop audiounit",
+            Operation::CreateAggregateDevice => "create aggregate device",
+            Operation::InstallListener => "install listener",
+            Operation::UninstallListener => "uninstall listener",
+            Operation::GetDeviceVolume => "get device volume",
+            Operation::SetDeviceVolume => "set device volume",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackendError {
+    operation: Operation,
+    // NO_ERR if the failure doesn't come from a system call.
+    status: OSStatus,
+    device: AudioObjectID,
+}
+
+impl BackendError {
+    pub fn new(operation: Operation, status: OSStatus) -> Self {
+        Self {
+            operation,
+            status,
+            device: kAudioObjectUnknown,
+        }
+    }
+
+    pub fn check(operation: Operation, status: OSStatus) -> std::result::Result<(), Self> {
+        if status == NO_ERR {
+            Ok(())
+        } else {
+            Err(Self::new(operation, status))
+        }
+    }
+
+    // Record the device involved, unless a more specific one is already recorded.
+    pub fn with_device(mut self, device: AudioObjectID) -> Self {
+        if self.device == kAudioObjectUnknown {
+            self.device = device;
+        }
+        self
+    }
+
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn status(&self) -> OSStatus {
+        self.status
+    }
+
+    pub fn device(&self) -> AudioObjectID {
+        self.device
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fail to {}", self.operation)?;
+        if self.device != kAudioObjectUnknown {
+            write!(f, " on device {}", self.device)?;
+        }
+        if self.status != NO_ERR {
+            write!(f, ". Error: {}", self.status)?;
+            // Most of the CoreAudio errors are four-character codes.
+            let code = (self.status as u32).to_be_bytes();
+            if code.iter().all(|c| c.is_ascii_graphic() || *c == b' ') {
+                write!(f, " ('{}')", String::from_utf8_lossy(&code))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<BackendError> for Error {
+    fn from(e: BackendError) -> Self {
+        // There is no AudioUnit to create on this system, whatever the device.
+        if e.status == AUDIO_COMPONENT_NOT_FOUND {
+            return Error::error();
+        }
+        let is_one_of = |statuses: &[u32]| statuses.iter().any(|s| *s as OSStatus == e.status);
+        if is_one_of(&[
+            kAudioHardwareBadObjectError,
+            kAudioHardwareBadDeviceError,
+            kAudioHardwareBadStreamError,
+            kAudioHardwareNotRunningError,
+            kAudioDevicePermissionsError,
+        ]) {
+            return Error::device_unavailable();
+        }
+        if is_one_of(&[kAudioDeviceUnsupportedFormatError])
+            || e.status == kAudioUnitErr_FormatNotSupported
+        {
+            return Error::invalid_format();
+        }
+        if is_one_of(&[
+            kAudioHardwareUnknownPropertyError,
+            kAudioHardwareUnsupportedOperationError,
+        ]) || e.status == kAudioUnitErr_InvalidProperty
+        {
+            return Error::not_supported();
+        }
+        if is_one_of(&[kAudioHardwareIllegalOperationError])
+            || e.status == kAudioUnitErr_InvalidPropertyValue
+            || e.status == kAudio_ParamError as OSStatus
+        {
+            return Error::invalid_parameter();
+        }
+
+        match e.operation {
+            Operation::CreateAudioUnit
+            | Operation::SetDevice
+            | Operation::CheckInputChannels
+            | Operation::CheckOutputChannels => Error::device_unavailable(),
+            Operation::CreateStreamDescription | Operation::SetStreamFormat => {
+                Error::invalid_format()
+            }
+            _ => Error::error(),
+        }
+    }
+}
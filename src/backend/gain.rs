@@ -0,0 +1,229 @@
+use super::sample::{I24In32, Sample, StreamFormat, I24};
+use std::os::raw::c_void;
+use std::slice;
+use std::time::Duration;
+
+// The length of the fade-in after starting or switching devices and the fade-out before
+// stopping or switching devices, unless it's set by `StreamOptions::gain_ramp`.
+pub const DEFAULT_GAIN_RAMP: Duration = Duration::from_millis(10);
+
+pub fn gain_ramp_frames(duration: Duration, rate: u32) -> usize {
+    let nanos = duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos());
+    (nanos * u64::from(rate) / 1_000_000_000) as usize
+}
+
+pub trait RampSample: Copy {
+    fn scale(self, gain: f32) -> Self;
+}
+
+impl RampSample for f32 {
+    fn scale(self, gain: f32) -> Self {
+        self * gain
+    }
+}
+
+impl RampSample for i16 {
+    fn scale(self, gain: f32) -> Self {
+        (f32::from(self) * gain).round() as i16
+    }
+}
+
+// The samples wider than 16 bits are scaled at full precision.
+macro_rules! impl_ramp_sample {
+    ($($t:ty),*) => {
+        $(
+            impl RampSample for $t {
+                fn scale(self, gain: f32) -> Self {
+                    Self::from_f64(self.to_f64() * f64::from(gain))
+                }
+            }
+        )*
+    };
+}
+
+impl_ramp_sample!(I24, I24In32, i32, f64);
+
+// The big-endian samples are scaled in the native order, and swapped back.
+#[derive(Clone, Copy, Debug, Default)]
+struct I16BE(i16);
+
+impl RampSample for I16BE {
+    fn scale(self, gain: f32) -> Self {
+        I16BE(i16::from_be(self.0).scale(gain).to_be())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct F32BE(u32);
+
+impl RampSample for F32BE {
+    fn scale(self, gain: f32) -> Self {
+        let sample = f32::from_bits(u32::from_be(self.0));
+        F32BE(sample.scale(gain).to_bits().to_be())
+    }
+}
+
+// A gain moving linearly to its target, one step per frame, to avoid the clicks when the audio
+// starts or stops abruptly.
+#[derive(Clone, Copy, Debug)]
+pub struct GainRamp {
+    gain: f32,
+    target: f32,
+    step: f32,
+}
+
+impl Default for GainRamp {
+    // The ramp starts silent so the first frames are faded in.
+    fn default() -> Self {
+        Self {
+            gain: 0.0,
+            target: 0.0,
+            step: 0.0,
+        }
+    }
+}
+
+impl GainRamp {
+    // Move the gain to 1.0 in `frames` frames.
+    pub fn fade_in(&mut self, frames: usize) {
+        self.set_target(1.0, frames);
+    }
+
+    // Move the gain to 0.0 in `frames` frames.
+    pub fn fade_out(&mut self, frames: usize) {
+        self.set_target(0.0, frames);
+    }
+
+    // Whether the fade-out is done.
+    pub fn is_silent(&self) -> bool {
+        self.target == 0.0 && self.gain == 0.0
+    }
+
+    fn set_target(&mut self, target: f32, frames: usize) {
+        if self.target == target {
+            return;
+        }
+        self.target = target;
+        // The whole range is covered in `frames` frames, or at once if there is no ramp.
+        self.step = if frames == 0 {
+            1.0
+        } else {
+            1.0 / frames as f32
+        };
+    }
+
+    fn next_gain(&mut self) -> f32 {
+        if (self.target - self.gain).abs() <= self.step {
+            self.gain = self.target;
+        } else if self.target > self.gain {
+            self.gain += self.step;
+        } else {
+            self.gain -= self.step;
+        }
+        self.gain
+    }
+
+    pub fn apply<T: RampSample>(&mut self, data: &mut [T], channels: usize) {
+        assert_ne!(channels, 0);
+        if self.gain == self.target && self.gain == 1.0 {
+            return;
+        }
+        for frame in data.chunks_mut(channels) {
+            let gain = self.next_gain();
+            for sample in frame.iter_mut() {
+                *sample = sample.scale(gain);
+            }
+        }
+    }
+
+    // Apply the ramp to `frames` interleaved frames in the buffer of the stream format.
+    pub fn apply_to_buffer(
+        &mut self,
+        format: StreamFormat,
+        buffer: *mut c_void,
+        channels: usize,
+        frames: usize,
+    ) {
+        assert!(!buffer.is_null());
+        let samples = frames * channels;
+        match format {
+            StreamFormat::S16LE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut i16, samples) };
+                self.apply(data, channels);
+            }
+            StreamFormat::S16BE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut I16BE, samples) };
+                self.apply(data, channels);
+            }
+            StreamFormat::S24LE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut I24, samples) };
+                self.apply(data, channels);
+            }
+            StreamFormat::S24In32LE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut I24In32, samples) };
+                self.apply(data, channels);
+            }
+            StreamFormat::S32LE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut i32, samples) };
+                self.apply(data, channels);
+            }
+            StreamFormat::Float32LE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut f32, samples) };
+                self.apply(data, channels);
+            }
+            StreamFormat::Float32BE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut F32BE, samples) };
+                self.apply(data, channels);
+            }
+            StreamFormat::Float64LE => {
+                let data = unsafe { slice::from_raw_parts_mut(buffer as *mut f64, samples) };
+                self.apply(data, channels);
+            }
+        }
+    }
+
+    // Apply the ramp to `frames` frames in the buffers of one channel each.
+    pub fn apply_to_planes<I>(&mut self, format: StreamFormat, planes: I, frames: usize)
+    where
+        I: IntoIterator<Item = *mut c_void>,
+    {
+        // Each channel goes through the same ramp.
+        let start = *self;
+        for plane in planes {
+            *self = start;
+            self.apply_to_buffer(format, plane, 1, frames);
+        }
+    }
+}
+
+// Scale the interleaved samples in the buffer of the stream format by `gain`. The samples are
+// silenced whatever they are when the gain is 0.0.
+pub fn apply_gain(format: StreamFormat, buffer: *mut c_void, samples: usize, gain: f32) {
+    fn scale<T: RampSample + Default>(buffer: *mut c_void, samples: usize, gain: f32) {
+        let data = unsafe { slice::from_raw_parts_mut(buffer as *mut T, samples) };
+        if gain == 0.0 {
+            for sample in data.iter_mut() {
+                *sample = T::default();
+            }
+        } else {
+            for sample in data.iter_mut() {
+                *sample = sample.scale(gain);
+            }
+        }
+    }
+
+    assert!(!buffer.is_null());
+    if gain == 1.0 {
+        return;
+    }
+    match format {
+        StreamFormat::S16LE => scale::<i16>(buffer, samples, gain),
+        StreamFormat::S16BE => scale::<I16BE>(buffer, samples, gain),
+        StreamFormat::S24LE => scale::<I24>(buffer, samples, gain),
+        StreamFormat::S24In32LE => scale::<I24In32>(buffer, samples, gain),
+        StreamFormat::S32LE => scale::<i32>(buffer, samples, gain),
+        StreamFormat::Float32LE => scale::<f32>(buffer, samples, gain),
+        StreamFormat::Float32BE => scale::<F32BE>(buffer, samples, gain),
+        StreamFormat::Float64LE => scale::<f64>(buffer, samples, gain),
+    }
+}
@@ -1,6 +1,10 @@
-use cubeb_backend::{ChannelLayout, SampleFormat};
+use super::channel_map::ChannelMap;
+use super::sample::{I24In32, Sample, StreamFormat, I24};
+use cubeb_backend::ChannelLayout;
+use std::marker::PhantomData;
 use std::mem;
 use std::os::raw::{c_int, c_void};
+use std::slice;
 
 extern crate audio_mixer;
 pub use self::audio_mixer::Channel;
@@ -58,55 +62,280 @@ fn get_default_channel_order(channel_count: usize) -> Vec<audio_mixer::Channel>
     channels
 }
 
+// The channels of a stream of `channel_count` channels in `layout`, or in the default order if
+// the layout doesn't have as many channels.
+fn get_stream_channel_order(
+    channel_count: usize,
+    layout: ChannelLayout,
+) -> Vec<audio_mixer::Channel> {
+    if channel_count as u32 != layout.bits().count_ones() {
+        cubeb_log!("Mismatch between stream channels and layout. Apply default layout instead");
+        get_default_channel_order(channel_count)
+    } else {
+        get_channel_order(layout)
+    }
+}
+
+// The `channels` of a device of `channel_count` channels, or the default ones if they don't
+// match.
+fn get_device_channel_order(
+    channel_count: usize,
+    mut channels: Vec<audio_mixer::Channel>,
+) -> Vec<audio_mixer::Channel> {
+    // When having one or two channel, force mono or stereo. Some devices (namely,
+    // Bose QC35, mark 1 and 2), expose a single channel mapped to the right for
+    // some reason.
+    // TODO: Only apply this setting when device is Bose QC35 (by device_property.rs).
+    if channel_count == 1 {
+        channels = vec![audio_mixer::Channel::FrontCenter];
+    } else if channel_count == 2 {
+        channels = vec![
+            audio_mixer::Channel::FrontLeft,
+            audio_mixer::Channel::FrontRight,
+        ];
+    }
+
+    let all_silence = vec![audio_mixer::Channel::Silence; channel_count];
+    if channels.is_empty() || channel_count != channels.len() || all_silence == channels {
+        cubeb_log!("Mismatch between device channels and layout. Apply default layout instead");
+        channels = get_default_channel_order(channel_count);
+    }
+    channels
+}
+
+// A mixer of the samples wider than 16 bits, at full precision, since the ones of `audio_mixer`
+// only take i16 or f32. It uses the same mixing coefficients.
+#[derive(Debug)]
+struct WideMixer<T> {
+    input_channels: Vec<audio_mixer::Channel>,
+    output_channels: Vec<audio_mixer::Channel>,
+    // The coefficient from the input channel j to the output channel i is `coefficients[i][j]`.
+    coefficients: Vec<Vec<f64>>,
+    sample: PhantomData<T>,
+}
+
+impl<T: Sample> WideMixer<T> {
+    fn new(
+        input_channels: &[audio_mixer::Channel],
+        output_channels: &[audio_mixer::Channel],
+    ) -> Self {
+        // The coefficients of the f32 mixer aren't normalized. Read them by mixing one input
+        // channel at a time.
+        let mixer = audio_mixer::Mixer::<f32>::new(input_channels, output_channels);
+        let mut coefficients = vec![vec![0.0; input_channels.len()]; output_channels.len()];
+        let mut input = vec![0.0; input_channels.len()];
+        let mut output = vec![0.0; output_channels.len()];
+        for j in 0..input_channels.len() {
+            input[j] = 1.0;
+            mixer.mix(&input, &mut output);
+            input[j] = 0.0;
+            for (row, value) in coefficients.iter_mut().zip(output.iter()) {
+                row[j] = f64::from(*value);
+            }
+        }
+        // Like the ones of i16, the coefficients of the integers are normalized so the mix can't
+        // overflow.
+        if !T::IS_FLOAT {
+            let max_sum = coefficients
+                .iter()
+                .map(|row| row.iter().sum::<f64>())
+                .fold(0.0, f64::max);
+            if max_sum > 1.0 {
+                for coefficient in coefficients.iter_mut().flat_map(|row| row.iter_mut()) {
+                    *coefficient /= max_sum;
+                }
+            }
+        }
+        Self {
+            input_channels: input_channels.to_vec(),
+            output_channels: output_channels.to_vec(),
+            coefficients,
+            sample: PhantomData,
+        }
+    }
+
+    // Copy the input channel j to the output channel `map[j]`, leaving the other output channels
+    // silent.
+    fn route(
+        input_channels: &[audio_mixer::Channel],
+        output_channels: &[audio_mixer::Channel],
+        map: &[u32],
+    ) -> Self {
+        assert_eq!(map.len(), input_channels.len());
+        let mut coefficients = vec![vec![0.0; input_channels.len()]; output_channels.len()];
+        for (j, channel) in map.iter().enumerate() {
+            coefficients[*channel as usize][j] = 1.0;
+        }
+        Self {
+            input_channels: input_channels.to_vec(),
+            output_channels: output_channels.to_vec(),
+            coefficients,
+            sample: PhantomData,
+        }
+    }
+
+    fn input_channels(&self) -> &[audio_mixer::Channel] {
+        &self.input_channels
+    }
+
+    fn output_channels(&self) -> &[audio_mixer::Channel] {
+        &self.output_channels
+    }
+
+    fn mix(&self, input_buffer: &[T], output_buffer: &mut [T]) {
+        assert_eq!(input_buffer.len(), self.input_channels.len());
+        assert_eq!(output_buffer.len(), self.output_channels.len());
+        for (output, row) in output_buffer.iter_mut().zip(self.coefficients.iter()) {
+            let value = row
+                .iter()
+                .zip(input_buffer.iter())
+                .fold(0.0, |value, (coefficient, input)| {
+                    value + coefficient * input.to_f64()
+                });
+            *output = T::from_f64(value);
+        }
+    }
+}
+
 #[derive(Debug)]
 enum MixerType {
     IntegerMixer(audio_mixer::Mixer<i16>),
+    Integer24Mixer(WideMixer<I24>),
+    Integer24In32Mixer(WideMixer<I24In32>),
+    Integer32Mixer(WideMixer<i32>),
     FloatMixer(audio_mixer::Mixer<f32>),
+    DoubleMixer(WideMixer<f64>),
+    // The routers of the formats mixed by `audio_mixer`, which can't route.
+    IntegerRouter(WideMixer<i16>),
+    FloatRouter(WideMixer<f32>),
+}
+
+macro_rules! dispatch {
+    ($mixer:expr, $m:ident => $body:expr) => {
+        match $mixer {
+            MixerType::IntegerMixer($m) => $body,
+            MixerType::Integer24Mixer($m) => $body,
+            MixerType::Integer24In32Mixer($m) => $body,
+            MixerType::Integer32Mixer($m) => $body,
+            MixerType::FloatMixer($m) => $body,
+            MixerType::DoubleMixer($m) => $body,
+            MixerType::IntegerRouter($m) => $body,
+            MixerType::FloatRouter($m) => $body,
+        }
+    };
+}
+
+// Mix the `frames` interleaved frames of `input_buffer_ptr` into `output_buffer_ptr`, one frame
+// at a time by `mix`.
+fn mix_frames<T, F: Fn(&[T], &mut [T])>(
+    input_channels: usize,
+    output_channels: usize,
+    input_buffer_ptr: *const (),
+    output_buffer_ptr: *mut (),
+    frames: usize,
+    mix: F,
+) {
+    let input_buffer =
+        unsafe { slice::from_raw_parts(input_buffer_ptr as *const T, frames * input_channels) };
+    let output_buffer =
+        unsafe { slice::from_raw_parts_mut(output_buffer_ptr as *mut T, frames * output_channels) };
+    for (input, output) in input_buffer
+        .chunks(input_channels)
+        .zip(output_buffer.chunks_mut(output_channels))
+    {
+        mix(input, output);
+    }
 }
 
 impl MixerType {
     fn new(
-        format: SampleFormat,
+        format: StreamFormat,
         input_channels: &[audio_mixer::Channel],
         output_channels: &[audio_mixer::Channel],
     ) -> Self {
         match format {
-            SampleFormat::S16LE | SampleFormat::S16BE | SampleFormat::S16NE => {
+            StreamFormat::S16LE | StreamFormat::S16BE => {
                 cubeb_log!("Create an integer type(i16) mixer");
                 Self::IntegerMixer(audio_mixer::Mixer::<i16>::new(
                     input_channels,
                     output_channels,
                 ))
             }
-            SampleFormat::Float32LE | SampleFormat::Float32BE | SampleFormat::Float32NE => {
+            StreamFormat::S24LE => {
+                cubeb_log!("Create a packed 24-bit integer type mixer");
+                Self::Integer24Mixer(WideMixer::new(input_channels, output_channels))
+            }
+            StreamFormat::S24In32LE => {
+                cubeb_log!("Create a 24-bit integer type(in i32) mixer");
+                Self::Integer24In32Mixer(WideMixer::new(input_channels, output_channels))
+            }
+            StreamFormat::S32LE => {
+                cubeb_log!("Create an integer type(i32) mixer");
+                Self::Integer32Mixer(WideMixer::new(input_channels, output_channels))
+            }
+            StreamFormat::Float32LE | StreamFormat::Float32BE => {
                 cubeb_log!("Create an floating type(f32) mixer");
                 Self::FloatMixer(audio_mixer::Mixer::<f32>::new(
                     input_channels,
                     output_channels,
                 ))
             }
+            StreamFormat::Float64LE => {
+                cubeb_log!("Create a floating type(f64) mixer");
+                Self::DoubleMixer(WideMixer::new(input_channels, output_channels))
+            }
+        }
+    }
+
+    // Route the input channel j to the output channel `map[j]` instead of mixing the channels.
+    fn route(
+        format: StreamFormat,
+        input_channels: &[audio_mixer::Channel],
+        output_channels: &[audio_mixer::Channel],
+        map: &[u32],
+    ) -> Self {
+        cubeb_log!("Create a router to the output channels {:?}", map);
+        match format {
+            StreamFormat::S16LE | StreamFormat::S16BE => {
+                Self::IntegerRouter(WideMixer::route(input_channels, output_channels, map))
+            }
+            StreamFormat::S24LE => {
+                Self::Integer24Mixer(WideMixer::route(input_channels, output_channels, map))
+            }
+            StreamFormat::S24In32LE => {
+                Self::Integer24In32Mixer(WideMixer::route(input_channels, output_channels, map))
+            }
+            StreamFormat::S32LE => {
+                Self::Integer32Mixer(WideMixer::route(input_channels, output_channels, map))
+            }
+            StreamFormat::Float32LE | StreamFormat::Float32BE => {
+                Self::FloatRouter(WideMixer::route(input_channels, output_channels, map))
+            }
+            StreamFormat::Float64LE => {
+                Self::DoubleMixer(WideMixer::route(input_channels, output_channels, map))
+            }
         }
     }
 
     fn sample_size(&self) -> usize {
         match self {
             MixerType::IntegerMixer(_) => mem::size_of::<i16>(),
+            MixerType::Integer24Mixer(_) => mem::size_of::<I24>(),
+            MixerType::Integer24In32Mixer(_) => mem::size_of::<I24In32>(),
+            MixerType::Integer32Mixer(_) => mem::size_of::<i32>(),
             MixerType::FloatMixer(_) => mem::size_of::<f32>(),
+            MixerType::DoubleMixer(_) => mem::size_of::<f64>(),
+            MixerType::IntegerRouter(_) => mem::size_of::<i16>(),
+            MixerType::FloatRouter(_) => mem::size_of::<f32>(),
         }
     }
 
     fn input_channels(&self) -> &[Channel] {
-        match self {
-            MixerType::IntegerMixer(m) => m.input_channels(),
-            MixerType::FloatMixer(m) => m.input_channels(),
-        }
+        dispatch!(self, m => m.input_channels())
     }
 
     fn output_channels(&self) -> &[Channel] {
-        match self {
-            MixerType::IntegerMixer(m) => m.output_channels(),
-            MixerType::FloatMixer(m) => m.output_channels(),
-        }
+        dispatch!(self, m => m.output_channels())
     }
 
     fn mix(
@@ -117,8 +346,6 @@ impl MixerType {
         output_buffer_size: usize,
         frames: usize,
     ) {
-        use std::slice;
-
         // Check input buffer size.
         let size_needed = frames * self.input_channels().len() * self.sample_size();
         assert!(input_buffer_size >= size_needed);
@@ -126,38 +353,16 @@ impl MixerType {
         let size_needed = frames * self.output_channels().len() * self.sample_size();
         assert!(output_buffer_size >= size_needed);
 
-        match self {
-            MixerType::IntegerMixer(m) => {
-                let in_buf_ptr = input_buffer_ptr as *const i16;
-                let out_buf_ptr = output_buffer_ptr as *mut i16;
-                let input_buffer = unsafe {
-                    slice::from_raw_parts(in_buf_ptr, frames * self.input_channels().len())
-                };
-                let output_buffer = unsafe {
-                    slice::from_raw_parts_mut(out_buf_ptr, frames * self.output_channels().len())
-                };
-                let mut in_buf = input_buffer.chunks(self.input_channels().len());
-                let mut out_buf = output_buffer.chunks_mut(self.output_channels().len());
-                for _ in 0..frames {
-                    m.mix(in_buf.next().unwrap(), out_buf.next().unwrap());
-                }
-            }
-            MixerType::FloatMixer(m) => {
-                let in_buf_ptr = input_buffer_ptr as *const f32;
-                let out_buf_ptr = output_buffer_ptr as *mut f32;
-                let input_buffer = unsafe {
-                    slice::from_raw_parts(in_buf_ptr, frames * self.input_channels().len())
-                };
-                let output_buffer = unsafe {
-                    slice::from_raw_parts_mut(out_buf_ptr, frames * self.output_channels().len())
-                };
-                let mut in_buf = input_buffer.chunks(self.input_channels().len());
-                let mut out_buf = output_buffer.chunks_mut(self.output_channels().len());
-                for _ in 0..frames {
-                    m.mix(in_buf.next().unwrap(), out_buf.next().unwrap());
-                }
-            }
-        };
+        let input_channels = self.input_channels().len();
+        let output_channels = self.output_channels().len();
+        dispatch!(self, m => mix_frames(
+            input_channels,
+            output_channels,
+            input_buffer_ptr,
+            output_buffer_ptr,
+            frames,
+            |input, output| m.mix(input, output),
+        ));
     }
 }
 
@@ -170,11 +375,11 @@ pub struct Mixer {
 
 impl Mixer {
     pub fn new(
-        format: SampleFormat,
+        format: StreamFormat,
         in_channel_count: usize,
         input_layout: ChannelLayout,
         out_channel_count: usize,
-        mut output_channels: Vec<audio_mixer::Channel>,
+        output_channels: Vec<audio_mixer::Channel>,
     ) -> Self {
         cubeb_log!(
             "Create a mixer with input channel count: {}, input layout: {:?}, \
@@ -184,42 +389,61 @@ impl Mixer {
             out_channel_count,
             output_channels
         );
-
-        let input_channels = if in_channel_count as u32 != input_layout.bits().count_ones() {
-            cubeb_log!("Mismatch between input channels and layout. Apply default layout instead");
-            get_default_channel_order(in_channel_count)
-        } else {
-            get_channel_order(input_layout)
-        };
-
-        // When having one or two channel, force mono or stereo. Some devices (namely,
-        // Bose QC35, mark 1 and 2), expose a single channel mapped to the right for
-        // some reason.
-        // TODO: Only apply this setting when device is Bose QC35 (by device_property.rs).
-        if out_channel_count == 1 {
-            output_channels = vec![audio_mixer::Channel::FrontCenter];
-        } else if out_channel_count == 2 {
-            output_channels = vec![
-                audio_mixer::Channel::FrontLeft,
-                audio_mixer::Channel::FrontRight,
-            ];
-        }
-
-        let all_silence = vec![audio_mixer::Channel::Silence; out_channel_count];
-        if output_channels.is_empty()
-            || out_channel_count != output_channels.len()
-            || all_silence == output_channels
-        {
-            cubeb_log!("Mismatch between output channels and layout. Apply default layout instead");
-            output_channels = get_default_channel_order(out_channel_count);
+        let input_channels = get_stream_channel_order(in_channel_count, input_layout);
+        let output_channels = get_device_channel_order(out_channel_count, output_channels);
+        Self {
+            mixer: MixerType::new(format, &input_channels, &output_channels),
+            buffer: Vec::new(),
         }
+    }
 
+    // Mix the `in_channel_count` channels captured by the device, in `input_channels`, into the
+    // `out_channel_count` channels of the input stream, in `output_layout`.
+    pub fn for_input(
+        format: StreamFormat,
+        in_channel_count: usize,
+        input_channels: Vec<audio_mixer::Channel>,
+        out_channel_count: usize,
+        output_layout: ChannelLayout,
+    ) -> Self {
+        cubeb_log!(
+            "Create an input mixer with input channel count: {}, input channels: {:?}, \
+             out channel count: {}, output layout: {:?}",
+            in_channel_count,
+            input_channels,
+            out_channel_count,
+            output_layout
+        );
+        let input_channels = get_device_channel_order(in_channel_count, input_channels);
+        let output_channels = get_stream_channel_order(out_channel_count, output_layout);
         Self {
             mixer: MixerType::new(format, &input_channels, &output_channels),
             buffer: Vec::new(),
         }
     }
 
+    // Copy the channels of the stream to the channels of `channel_map` among the
+    // `out_channel_count` channels of the device, instead of mixing them by their layouts. The
+    // other channels are silent. None if the device doesn't have the mapped channels.
+    pub fn with_channel_map(
+        format: StreamFormat,
+        out_channel_count: usize,
+        channel_map: &ChannelMap,
+    ) -> Option<Self> {
+        if !channel_map.fits(out_channel_count as u32) {
+            return None;
+        }
+        Some(Self {
+            mixer: MixerType::route(
+                format,
+                &get_default_channel_order(channel_map.len()),
+                &get_default_channel_order(out_channel_count),
+                channel_map,
+            ),
+            buffer: Vec::new(),
+        })
+    }
+
     pub fn update_buffer_size(&mut self, frames: usize) -> bool {
         let size_needed = frames * self.mixer.input_channels().len() * self.mixer.sample_size();
         let elements_needed = size_needed / mem::size_of::<u8>();
@@ -448,3 +672,201 @@ fn test_get_default_channel_order() {
         }
     }
 }
+
+// Mix the interleaved stereo `input` into `output_channels`, in the samples of `format`.
+#[cfg(test)]
+fn mix_stereo<T: Copy + Default>(
+    format: StreamFormat,
+    input: &[T],
+    output_channels: Vec<Channel>,
+) -> Vec<T> {
+    let out_channel_count = output_channels.len();
+    let mixer = Mixer::new(
+        format,
+        2,
+        ChannelLayout::STEREO,
+        out_channel_count,
+        output_channels,
+    );
+    run_mixer(mixer, input, 2, out_channel_count)
+}
+
+// Route the interleaved stereo `input` to the channels of `map` among `out_channel_count`
+// channels, in the samples of `format`.
+#[cfg(test)]
+fn route_stereo<T: Copy + Default>(
+    format: StreamFormat,
+    input: &[T],
+    out_channel_count: usize,
+    map: &[u32],
+) -> Vec<T> {
+    let map = ChannelMap::new(map, 2, out_channel_count as u32).unwrap();
+    let mixer = Mixer::with_channel_map(format, out_channel_count, &map).unwrap();
+    run_mixer(mixer, input, 2, out_channel_count)
+}
+
+#[cfg(test)]
+fn run_mixer<T: Copy + Default>(
+    mut mixer: Mixer,
+    input: &[T],
+    in_channel_count: usize,
+    out_channel_count: usize,
+) -> Vec<T> {
+    let frames = input.len() / in_channel_count;
+    mixer.update_buffer_size(frames);
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            input.as_ptr(),
+            mixer.get_buffer_mut_ptr() as *mut T,
+            input.len(),
+        );
+    }
+    let mut output = vec![T::default(); frames * out_channel_count];
+    mixer.mix(
+        frames,
+        output.as_mut_ptr() as *mut c_void,
+        output.len() * mem::size_of::<T>(),
+    );
+    output
+}
+
+#[test]
+fn test_mix_wide_formats_to_silence_channel() {
+    let channels = vec![Channel::FrontLeft, Channel::FrontRight, Channel::Silence];
+    // The samples of the same channels are copied bit-exact, up to the limits of the formats.
+    let input = [I24::new(-(1 << 23)), I24::new((1 << 23) - 1)];
+    assert_eq!(
+        mix_stereo(StreamFormat::S24LE, &input, channels.clone()),
+        [input[0], input[1], I24::default()]
+    );
+    let input = [I24In32::new(-(1 << 23)), I24In32::new(12_345)];
+    assert_eq!(
+        mix_stereo(StreamFormat::S24In32LE, &input, channels.clone()),
+        [input[0], input[1], I24In32::default()]
+    );
+    let input = [i32::MIN, i32::MAX];
+    assert_eq!(
+        mix_stereo(StreamFormat::S32LE, &input, channels.clone()),
+        [i32::MIN, i32::MAX, 0]
+    );
+    let input = [-1.0_f64, 0.123_456_789_012_345_6];
+    assert_eq!(
+        mix_stereo(StreamFormat::Float64LE, &input, channels),
+        [-1.0, 0.123_456_789_012_345_6, 0.0]
+    );
+}
+
+#[test]
+fn test_downmix_wide_formats() {
+    let mono = vec![Channel::FrontCenter];
+    // The integer coefficients are normalized so the mix doesn't overflow.
+    let input = [I24::new(1_000), I24::new(3_000)];
+    assert_eq!(
+        mix_stereo(StreamFormat::S24LE, &input, mono.clone()),
+        [I24::new(2_000)]
+    );
+    let input = [I24In32::new((1 << 23) - 1), I24In32::new((1 << 23) - 1)];
+    assert_eq!(
+        mix_stereo(StreamFormat::S24In32LE, &input, mono.clone()),
+        [I24In32::new((1 << 23) - 1)]
+    );
+    let input = [-2_000_000_000_i32, -1_000_000_000];
+    assert_eq!(
+        mix_stereo(StreamFormat::S32LE, &input, mono.clone()),
+        [-1_500_000_000]
+    );
+    // The float coefficients aren't, and have the precision of the ones of f32.
+    let c = f64::from(std::f64::consts::FRAC_1_SQRT_2 as f32);
+    let input = [0.5_f64, 0.25];
+    assert_eq!(
+        mix_stereo(StreamFormat::Float64LE, &input, mono),
+        [0.0 + c * 0.5 + c * 0.25]
+    );
+}
+
+#[test]
+fn test_route_to_channel_map() {
+    // The stereo input goes to the 3rd and 4th channels of 8, bit-exact, and the others are
+    // silent.
+    let input = [i16::MIN, i16::MAX, 1, -1];
+    assert_eq!(
+        route_stereo(StreamFormat::S16LE, &input, 8, &[2, 3]),
+        [
+            0,
+            0,
+            i16::MIN,
+            i16::MAX,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            -1,
+            0,
+            0,
+            0,
+            0
+        ]
+    );
+    let input = [0.123_456_79_f32, -1.0];
+    assert_eq!(
+        route_stereo(StreamFormat::Float32LE, &input, 4, &[3, 0]),
+        [-1.0, 0.0, 0.0, 0.123_456_79]
+    );
+    let input = [I24::new(-(1 << 23)), I24::new((1 << 23) - 1)];
+    assert_eq!(
+        route_stereo(StreamFormat::S24LE, &input, 3, &[1, 2]),
+        [I24::default(), input[0], input[1]]
+    );
+    let input = [i32::MIN, i32::MAX];
+    assert_eq!(
+        route_stereo(StreamFormat::S32LE, &input, 2, &[1, 0]),
+        [i32::MAX, i32::MIN]
+    );
+    let input = [-1.0_f64, 0.123_456_789_012_345_6];
+    assert_eq!(
+        route_stereo(StreamFormat::Float64LE, &input, 3, &[0, 2]),
+        [-1.0, 0.0, 0.123_456_789_012_345_6]
+    );
+}
+
+#[test]
+fn test_route_to_missing_channel() {
+    // The device has fewer channels than the one the map was validated against.
+    let map = ChannelMap::new(&[2, 4], 2, 8).unwrap();
+    assert!(Mixer::with_channel_map(StreamFormat::Float32LE, 4, &map).is_none());
+    assert!(Mixer::with_channel_map(StreamFormat::Float32LE, 5, &map).is_some());
+}
+
+#[test]
+fn test_input_mixer() {
+    let c = std::f64::consts::FRAC_1_SQRT_2 as f32;
+    // The stereo channels of the device are down-mixed into the mono stream.
+    let mixer = Mixer::for_input(
+        StreamFormat::Float32LE,
+        2,
+        vec![Channel::FrontLeft, Channel::FrontRight],
+        1,
+        ChannelLayout::MONO,
+    );
+    let output = run_mixer(mixer, &[0.5_f32, 0.25], 2, 1);
+    assert!((output[0] - c * 0.75).abs() < 1e-6);
+
+    // The mono channel of the device is up-mixed into the stereo stream.
+    let mixer = Mixer::for_input(
+        StreamFormat::Float32LE,
+        1,
+        vec![Channel::FrontCenter],
+        2,
+        ChannelLayout::STEREO,
+    );
+    let output = run_mixer(mixer, &[0.5_f32], 1, 2);
+    assert!((output[0] - c * 0.5).abs() < 1e-6);
+    assert_eq!(output[1], output[0]);
+
+    // The device channels without a layout are in the default order.
+    let mixer = Mixer::for_input(StreamFormat::S16LE, 2, Vec::new(), 2, ChannelLayout::STEREO);
+    assert_eq!(run_mixer(mixer, &[100_i16, -100], 2, 2), [100, -100]);
+}
@@ -11,15 +11,26 @@ extern crate ringbuf;
 
 mod aggregate_device;
 mod auto_release;
+mod buffer_list;
 mod buffer_manager;
+mod channel_map;
 mod device_property;
+mod drift;
+mod error;
+mod gain;
 mod mixer;
+mod planar;
+mod position;
 mod resampler;
+mod sample;
+mod stats;
 mod utils;
 
 use self::aggregate_device::*;
 use self::auto_release::*;
+use self::buffer_list::*;
 use self::buffer_manager::*;
+use self::channel_map::*;
 use self::coreaudio_sys_utils::aggregate_device::*;
 use self::coreaudio_sys_utils::audio_object::*;
 use self::coreaudio_sys_utils::audio_unit::*;
@@ -28,24 +39,38 @@ use self::coreaudio_sys_utils::dispatch::*;
 use self::coreaudio_sys_utils::string::*;
 use self::coreaudio_sys_utils::sys::*;
 use self::device_property::*;
+use self::drift::*;
+use self::error::*;
+use self::gain::*;
 use self::mixer::*;
+use self::planar::*;
+use self::position::*;
 use self::resampler::*;
+use self::stats::*;
 use self::utils::*;
 use atomic;
 use cubeb_backend::{
     ffi, Context, ContextOps, DeviceCollectionRef, DeviceId, DeviceRef, DeviceType, Error, Ops,
-    Result, SampleFormat, State, Stream, StreamOps, StreamParams, StreamParamsRef, StreamPrefs,
+    Result, State, Stream, StreamOps, StreamParams, StreamParamsRef, StreamPrefs,
 };
 use mach::mach_time::{mach_absolute_time, mach_timebase_info};
 use std::cmp;
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
 use std::slice;
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+pub use self::buffer_manager::DEFAULT_INPUT_BUFFER_MARGIN;
+pub use self::gain::DEFAULT_GAIN_RAMP;
+pub use self::position::StreamTimestamp;
+pub use self::sample::StreamFormat;
+pub use self::stats::{GlitchStats, HistogramSnapshot, ReinitReason, StreamStats};
+
 const NO_ERR: OSStatus = 0;
 
 const AU_OUT_BUS: AudioUnitElement = 0;
@@ -100,6 +125,9 @@ fn make_sized_audio_channel_layout(sz: usize) -> AutoRelease<AudioChannelLayout>
 struct device_info {
     id: AudioDeviceID,
     flags: device_flags,
+    // The UID of the device selected by the user, which is stable across reboots or replugs,
+    // unlike the id. The stream switches back to the device when it comes back.
+    uid: Option<String>,
 }
 
 impl Default for device_info {
@@ -107,7 +135,147 @@ impl Default for device_info {
         Self {
             id: kAudioObjectUnknown,
             flags: device_flags::DEV_UNKNOWN,
+            uid: None,
+        }
+    }
+}
+
+// The HAL and the AudioUnits that a context, and the streams it creates, run on. The default one
+// runs on CoreAudio.
+#[derive(Clone)]
+struct Platform {
+    hardware: Arc<dyn HardwareAbstraction>,
+    units: Arc<dyn AudioUnitAbstraction>,
+}
+
+impl Platform {
+    fn new(hardware: Arc<dyn HardwareAbstraction>, units: Arc<dyn AudioUnitAbstraction>) -> Self {
+        Self { hardware, units }
+    }
+
+    fn hardware(&self) -> &dyn HardwareAbstraction {
+        self.hardware.as_ref()
+    }
+
+    fn units(&self) -> &dyn AudioUnitAbstraction {
+        self.units.as_ref()
+    }
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Self::new(Arc::new(CoreAudioHardware), Arc::new(CoreAudioUnits))
+    }
+}
+
+impl fmt::Debug for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Platform").finish()
+    }
+}
+
+// The device a side of a stream runs on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamDevice {
+    // The system default device.
+    Default,
+    // The device with the AudioObjectID, the `devid` in the cubeb device info.
+    Id(AudioDeviceID),
+    // The device with the persistent UID, the `device_id` in the cubeb device info.
+    Uid(String),
+}
+
+impl StreamDevice {
+    fn from_devid(devid: DeviceId) -> Self {
+        if devid.is_null() {
+            StreamDevice::Default
+        } else {
+            StreamDevice::Id(devid as AudioDeviceID)
+        }
+    }
+
+    // Get the id of the device, or kAudioObjectUnknown for the default device, and its UID.
+    fn resolve(
+        &self,
+        hardware: &dyn HardwareAbstraction,
+    ) -> Result<(AudioDeviceID, Option<String>)> {
+        match self {
+            StreamDevice::Default => Ok((kAudioObjectUnknown, None)),
+            StreamDevice::Id(id) => {
+                let uid = get_device_global_uid(hardware, *id)
+                    .ok()
+                    .map(|uid| uid.into_string());
+                Ok((*id, uid))
+            }
+            StreamDevice::Uid(uid) => match find_device_by_uid(hardware, uid) {
+                Some(id) => Ok((id, Some(uid.clone()))),
+                None => {
+                    cubeb_log!("Could not find the device with uid {}", uid);
+                    Err(Error::device_unavailable())
+                }
+            },
+        }
+    }
+}
+
+// The settings of a stream created by `AudioUnitContext::stream_init_with_options`.
+pub struct StreamOptions<'a> {
+    pub stream_name: Option<&'a CStr>,
+    pub input_device: StreamDevice,
+    pub input_stream_params: Option<&'a StreamParamsRef>,
+    pub output_device: StreamDevice,
+    pub output_stream_params: Option<&'a StreamParamsRef>,
+    pub latency_frames: u32,
+    // The length of the output fade-in and fade-out.
+    pub gain_ramp: Duration,
+    // The input buffered by the duplex streams on top of the callbacks.
+    pub input_buffer_margin: Duration,
+    // The format of the data exchanged with the data callback, instead of the format of the
+    // stream parameters, for the formats cubeb doesn't have.
+    pub sample_format: Option<StreamFormat>,
+    // The data callback gets and fills one buffer per channel. Its input and output buffers are
+    // arrays of pointers to the buffers of the channels.
+    pub planar: bool,
+    // The 0-based device channels captured in the channels of the input stream, in order,
+    // instead of the first channels of the device.
+    pub input_channel_map: Option<&'a [u32]>,
+    // The 0-based device channels playing the channels of the output stream, in order, instead
+    // of mixing the channels by their layouts. The other device channels are silent.
+    pub output_channel_map: Option<&'a [u32]>,
+    pub data_callback: ffi::cubeb_data_callback,
+    pub state_callback: ffi::cubeb_state_callback,
+    pub user_ptr: *mut c_void,
+}
+
+// How reinit picked the device of each side of the stream, reported to the device changed
+// callback through `AudioUnitStream::reinit_choice`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReinitChoice {
+    // The stream follows the system default device.
+    FollowDefault = 0,
+    // The device selected by the user is still alive, so the stream keeps running on it.
+    KeepSelected = 1,
+    // The device selected by the user is gone, so the stream falls back to the default device.
+    FallBackToDefault = 2,
+}
+
+// The device to run on after reinit, or kAudioObjectUnknown for the default device. The device
+// selected by the user is looked up by its UID since its id may change after it's replugged.
+fn choose_reinit_device(
+    hardware: &dyn HardwareAbstraction,
+    device: &device_info,
+) -> (AudioDeviceID, ReinitChoice) {
+    let selected = match device.uid.as_ref() {
+        Some(uid) => find_device_by_uid(hardware, uid),
+        None if device.flags.contains(device_flags::DEV_SELECTED_DEFAULT) => {
+            return (kAudioObjectUnknown, ReinitChoice::FollowDefault);
         }
+        None => Some(device.id).filter(|id| get_device_is_alive(hardware, *id).unwrap_or(false)),
+    };
+    match selected {
+        Some(id) => (id, ReinitChoice::KeepSelected),
+        None => (kAudioObjectUnknown, ReinitChoice::FallBackToDefault),
     }
 }
 
@@ -165,7 +333,7 @@ impl Into<mixer::Channel> for CAChannelLabel {
     }
 }
 
-fn set_notification_runloop() {
+fn set_notification_runloop(hardware: &dyn HardwareAbstraction) {
     let address = AudioObjectPropertyAddress {
         mSelector: kAudioHardwarePropertyRunLoop,
         mScope: kAudioObjectPropertyScopeGlobal,
@@ -176,8 +344,13 @@ fn set_notification_runloop() {
     // Otherwise HAL may use main thread to fire notifications.
     let run_loop: CFRunLoopRef = ptr::null_mut();
     let size = mem::size_of::<CFRunLoopRef>();
-    let status =
-        audio_object_set_property_data(kAudioObjectSystemObject, &address, size, &run_loop);
+    let status = audio_object_set_property_data(
+        hardware,
+        kAudioObjectSystemObject,
+        &address,
+        size,
+        &run_loop,
+    );
     if status != NO_ERR {
         cubeb_log!("Could not make global CoreAudio notifications use their own thread.");
     }
@@ -190,7 +363,11 @@ fn clamp_latency(latency_frames: u32) -> u32 {
     )
 }
 
-fn create_device_info(id: AudioDeviceID, devtype: DeviceType) -> Result<device_info> {
+fn create_device_info(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioDeviceID,
+    devtype: DeviceType,
+) -> Result<device_info> {
     assert_ne!(id, kAudioObjectSystemObject);
 
     let mut info = device_info {
@@ -200,9 +377,10 @@ fn create_device_info(id: AudioDeviceID, devtype: DeviceType) -> Result<device_i
             DeviceType::OUTPUT => device_flags::DEV_OUTPUT,
             _ => panic!("Only accept input or output type"),
         },
+        uid: None,
     };
 
-    let default_device_id = audiounit_get_default_device_id(devtype);
+    let default_device_id = audiounit_get_default_device_id(hardware, devtype);
     if default_device_id == kAudioObjectUnknown {
         cubeb_log!("Could not find default audio device for {:?}", devtype);
         return Err(Error::error());
@@ -222,40 +400,79 @@ fn create_device_info(id: AudioDeviceID, devtype: DeviceType) -> Result<device_i
     Ok(info)
 }
 
-fn create_stream_description(stream_params: &StreamParams) -> Result<AudioStreamBasicDescription> {
+// The input of a loopback stream captures the output device `id`, or the default output device
+// if `id` is unknown.
+fn create_input_device_info(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioDeviceID,
+    params: &StreamParams,
+) -> Result<device_info> {
+    if !params.prefs().contains(StreamPrefs::LOOPBACK) {
+        return create_device_info(hardware, id, DeviceType::INPUT);
+    }
+    let mut info = create_device_info(hardware, id, DeviceType::OUTPUT)?;
+    info.flags.remove(device_flags::DEV_OUTPUT);
+    info.flags.insert(device_flags::DEV_INPUT);
+    Ok(info)
+}
+
+// The description of the data of `stream_params` in `format`, which overrides the cubeb format of
+// the parameters.
+fn create_stream_description(
+    format: StreamFormat,
+    stream_params: &StreamParams,
+) -> Result<AudioStreamBasicDescription> {
     assert!(stream_params.rate() > 0);
     assert!(stream_params.channels() > 0);
 
     let mut desc = AudioStreamBasicDescription::default();
 
-    match stream_params.format() {
-        SampleFormat::S16LE => {
+    match format {
+        StreamFormat::S16LE => {
             desc.mBitsPerChannel = 16;
-            desc.mFormatFlags = kAudioFormatFlagIsSignedInteger;
+            desc.mFormatFlags = kAudioFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked;
         }
-        SampleFormat::S16BE => {
+        StreamFormat::S16BE => {
             desc.mBitsPerChannel = 16;
-            desc.mFormatFlags = kAudioFormatFlagIsSignedInteger | kAudioFormatFlagIsBigEndian;
+            desc.mFormatFlags = kAudioFormatFlagIsSignedInteger
+                | kAudioFormatFlagIsBigEndian
+                | kLinearPCMFormatFlagIsPacked;
+        }
+        StreamFormat::S24LE => {
+            desc.mBitsPerChannel = 24;
+            desc.mFormatFlags = kAudioFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked;
+        }
+        StreamFormat::S24In32LE => {
+            // The samples don't fill their 4 bytes, so they're not packed, and they're in the
+            // low bytes since they're not aligned high.
+            desc.mBitsPerChannel = 24;
+            desc.mFormatFlags = kAudioFormatFlagIsSignedInteger;
+        }
+        StreamFormat::S32LE => {
+            desc.mBitsPerChannel = 32;
+            desc.mFormatFlags = kAudioFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked;
         }
-        SampleFormat::Float32LE => {
+        StreamFormat::Float32LE => {
             desc.mBitsPerChannel = 32;
-            desc.mFormatFlags = kAudioFormatFlagIsFloat;
+            desc.mFormatFlags = kAudioFormatFlagIsFloat | kLinearPCMFormatFlagIsPacked;
         }
-        SampleFormat::Float32BE => {
+        StreamFormat::Float32BE => {
             desc.mBitsPerChannel = 32;
-            desc.mFormatFlags = kAudioFormatFlagIsFloat | kAudioFormatFlagIsBigEndian;
+            desc.mFormatFlags = kAudioFormatFlagIsFloat
+                | kAudioFormatFlagIsBigEndian
+                | kLinearPCMFormatFlagIsPacked;
         }
-        _ => {
-            return Err(Error::invalid_format());
+        StreamFormat::Float64LE => {
+            desc.mBitsPerChannel = 64;
+            desc.mFormatFlags = kAudioFormatFlagIsFloat | kLinearPCMFormatFlagIsPacked;
         }
     }
 
     desc.mFormatID = kAudioFormatLinearPCM;
-    desc.mFormatFlags |= kLinearPCMFormatFlagIsPacked;
     desc.mSampleRate = f64::from(stream_params.rate());
     desc.mChannelsPerFrame = stream_params.channels();
 
-    desc.mBytesPerFrame = (desc.mBitsPerChannel / 8) * desc.mChannelsPerFrame;
+    desc.mBytesPerFrame = format.sample_size() as u32 * desc.mChannelsPerFrame;
     desc.mFramesPerPacket = 1;
     desc.mBytesPerPacket = desc.mBytesPerFrame * desc.mFramesPerPacket;
 
@@ -264,9 +481,10 @@ fn create_stream_description(stream_params: &StreamParams) -> Result<AudioStream
     Ok(desc)
 }
 
-fn set_volume(unit: AudioUnit, volume: f32) -> Result<()> {
+fn set_volume(units: &dyn AudioUnitAbstraction, unit: AudioUnit, volume: f32) -> Result<()> {
     assert!(!unit.is_null());
     let r = audio_unit_set_parameter(
+        units,
         unit,
         kHALOutputParam_Volume,
         kAudioUnitScope_Global,
@@ -282,10 +500,33 @@ fn set_volume(unit: AudioUnit, volume: f32) -> Result<()> {
     }
 }
 
-fn get_volume(unit: AudioUnit) -> Result<f32> {
+// The latency of the AudioUnit, in frames at `rate`, or 0 if it's unknown.
+fn get_audiounit_latency(units: &dyn AudioUnitAbstraction, unit: AudioUnit, rate: f64) -> u32 {
+    assert!(!unit.is_null());
+    let mut latency_s: f64 = 0.0;
+    let mut size = mem::size_of_val(&latency_s);
+    let r = audio_unit_get_property(
+        units,
+        unit,
+        kAudioUnitProperty_Latency,
+        kAudioUnitScope_Global,
+        0,
+        &mut latency_s,
+        &mut size,
+    );
+    if r == NO_ERR {
+        (latency_s * rate) as u32
+    } else {
+        cubeb_log!("AudioUnitGetProperty/kAudioUnitProperty_Latency rv={}", r);
+        0
+    }
+}
+
+fn get_volume(units: &dyn AudioUnitAbstraction, unit: AudioUnit) -> Result<f32> {
     assert!(!unit.is_null());
     let mut volume: f32 = 0.0;
     let r = audio_unit_get_parameter(
+        units,
         unit,
         kHALOutputParam_Volume,
         kAudioUnitScope_Global,
@@ -313,6 +554,12 @@ fn minimum_resampling_input_frames(
     (input_rate * output_frames as f64 / output_rate).ceil() as usize
 }
 
+fn audiounit_make_buffers_silent(buffers: &mut [AudioBuffer]) {
+    for buffer in buffers.iter_mut() {
+        audiounit_make_silent(buffer);
+    }
+}
+
 fn audiounit_make_silent(io_data: &mut AudioBuffer) {
     assert!(!io_data.mData.is_null());
     let bytes = unsafe {
@@ -342,24 +589,22 @@ extern "C" fn audiounit_input_callback(
     assert_eq!(bus, AU_IN_BUS);
 
     assert!(!user_ptr.is_null());
-    let stm = unsafe { &mut *(user_ptr as *mut AudioUnitStream) };
+    let stm = unsafe { &mut *(user_ptr as *mut CoreStreamData) };
 
-    if stm.shutdown.load(Ordering::SeqCst) {
-        cubeb_log!("({:p}) input shutdown", stm as *const AudioUnitStream);
+    if stm.shared.shutdown.load(Ordering::SeqCst) {
+        cubeb_log!("({:p}) input shutdown", stm.shared.stm_ptr);
         return NO_ERR;
     }
 
-    let handler = |stm: &mut AudioUnitStream,
+    let handler = |stm: &mut CoreStreamData,
                    flags: *mut AudioUnitRenderActionFlags,
                    tstamp: *const AudioTimeStamp,
                    bus: u32,
                    input_frames: u32|
      -> ErrorHandle {
-        let input_buffer_manager = stm.core_stream_data.input_buffer_manager.as_mut().unwrap();
-        assert_eq!(
-            stm.core_stream_data.stm_ptr,
-            user_ptr as *const AudioUnitStream
-        );
+        let has_output = stm.has_output();
+        let input = stm.input.as_mut().unwrap();
+        let input_buffer_manager = &mut input.side.buffer_manager;
 
         // `flags` and `tstamp` must be non-null so they can be casted into the references.
         assert!(!flags.is_null());
@@ -367,32 +612,32 @@ extern "C" fn audiounit_input_callback(
         assert!(!tstamp.is_null());
         let tstamp = unsafe { &(*tstamp) };
 
-        // Create the AudioBufferList to store input.
-        let mut input_buffer_list = AudioBufferList::default();
-        input_buffer_list.mBuffers[0].mDataByteSize =
-            stm.core_stream_data.input_desc.mBytesPerFrame * input_frames;
-        input_buffer_list.mBuffers[0].mData = ptr::null_mut();
-        input_buffer_list.mBuffers[0].mNumberChannels =
-            stm.core_stream_data.input_desc.mChannelsPerFrame;
-        input_buffer_list.mNumberBuffers = 1;
+        // Prepare the AudioBufferList to store input, whose buffers are provided by the
+        // AudioUnit.
+        let rendered_channels = input.side.rendered_channels;
+        let buffers = input.side.rendered_buffers;
+        input.side.buffer_list.prepare(
+            buffers,
+            rendered_channels / buffers as u32,
+            input.format.sample_size(),
+            input_frames,
+        );
 
-        debug_assert!(!stm.core_stream_data.input_unit.is_null());
+        debug_assert!(!input.unit.is_null());
         let status = audio_unit_render(
-            stm.core_stream_data.input_unit,
+            input.platform.units(),
+            input.unit,
             flags,
             tstamp,
             bus,
             input_frames,
-            &mut input_buffer_list,
+            unsafe { &mut *input.side.buffer_list.as_mut_ptr() },
         );
-        if (status != NO_ERR)
-            && (status != kAudioUnitErr_CannotDoInCurrentContext
-                || stm.core_stream_data.output_unit.is_null())
-        {
+        if (status != NO_ERR) && (status != kAudioUnitErr_CannotDoInCurrentContext || !has_output) {
             return ErrorHandle::Return(status);
         }
         let handle = if status == kAudioUnitErr_CannotDoInCurrentContext {
-            assert!(!stm.core_stream_data.output_unit.is_null());
+            assert!(has_output);
             // kAudioUnitErr_CannotDoInCurrentContext is returned when using a BT
             // headset and the profile is changed from A2DP to HFP/HSP. The previous
             // output device is no longer valid and must be reset.
@@ -400,83 +645,202 @@ extern "C" fn audiounit_input_callback(
             // resumed once reinit has completed.
             cubeb_logv!(
                 "({:p}) input: reinit pending feeding silence instead",
-                stm.core_stream_data.stm_ptr
+                stm.shared.stm_ptr
             );
-            let elements =
-                (input_frames * stm.core_stream_data.input_desc.mChannelsPerFrame) as usize;
-            input_buffer_manager.push_silent_data(elements);
+            let elements = (input_frames * input.desc.mChannelsPerFrame) as usize;
+            if input_buffer_manager.push_silent_data(elements) < elements {
+                stm.shared.glitches.add_input_overrun();
+            }
+            stm.shared
+                .glitches
+                .add_silence_frames(input_frames as usize);
             ErrorHandle::Reinit
+        } else if stm.direct_planar {
+            assert_eq!(status, NO_ERR);
+            // The buffers of the channels go as they are to the data callback below, so they're
+            // attenuated in place.
+            let gain = if stm.shared.input_muted.load(Ordering::SeqCst) {
+                0.0
+            } else {
+                stm.shared.input_gain.load(atomic::Ordering::SeqCst)
+            };
+            for buffer in input.side.buffer_list.buffers_mut() {
+                apply_gain(input.format, buffer.mData, input_frames as usize, gain);
+            }
+            ErrorHandle::Return(status)
         } else {
             assert_eq!(status, NO_ERR);
+            let elements = (input_frames * input.desc.mChannelsPerFrame) as usize;
+            let sample_size = input.format.sample_size();
+            let rendered_size = (input_frames * rendered_channels) as usize * sample_size;
+            let rendered = input.side.buffer_list.buffers_mut();
+            let data = if rendered.len() == 1 {
+                rendered[0].mData
+            } else {
+                // Gather the buffers of the device into the interleaved input. The buffers of the
+                // callback have room for the frames of the AudioUnit since the setup.
+                let gathered = &mut input.side.gathered_input;
+                gathered.resize(rendered_size, 0);
+                gather_buffers(
+                    rendered,
+                    gathered,
+                    rendered_channels as usize,
+                    sample_size,
+                    input_frames as usize,
+                );
+                gathered.as_mut_ptr() as *mut c_void
+            };
+            // The channels of the data, which are the rendered ones until they're picked or
+            // mixed.
+            let mut channels = rendered_channels as usize;
+            let data = if input.side.rendered_map.is_empty() {
+                data
+            } else {
+                // Keep the mapped channels of the device.
+                channels = input.side.rendered_map.len();
+                let mapped = &mut input.side.mapped_input;
+                mapped.resize(input_frames as usize * channels * sample_size, 0);
+                extract_channels(
+                    unsafe { slice::from_raw_parts(data as *const u8, rendered_size) },
+                    mapped,
+                    rendered_channels as usize,
+                    &input.side.rendered_map,
+                    sample_size,
+                    input_frames as usize,
+                );
+                mapped.as_mut_ptr() as *mut c_void
+            };
+            let data = if let Some(mixer) = input.side.mixer.as_mut() {
+                // Mix the channels of the device into the channels of the stream.
+                mixer.update_buffer_size(input_frames as usize);
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        data as *const u8,
+                        mixer.get_buffer_mut_ptr(),
+                        input_frames as usize * channels * sample_size,
+                    );
+                }
+                let mixed = &mut input.side.mixed_input;
+                mixed.resize(elements * sample_size, 0);
+                mixer.mix(
+                    input_frames as usize,
+                    mixed.as_mut_ptr() as *mut c_void,
+                    mixed.len(),
+                );
+                mixed.as_mut_ptr() as *mut c_void
+            } else {
+                data
+            };
+            // Attenuate or mute the captured samples.
+            let gain = if stm.shared.input_muted.load(Ordering::SeqCst) {
+                0.0
+            } else {
+                stm.shared.input_gain.load(atomic::Ordering::SeqCst)
+            };
+            apply_gain(input.format, data, elements, gain);
             // Copy input data in linear buffer.
-            let elements =
-                (input_frames * stm.core_stream_data.input_desc.mChannelsPerFrame) as usize;
-            input_buffer_manager.push_data(input_buffer_list.mBuffers[0].mData, elements);
+            if input_buffer_manager.push_data(data, elements) < elements {
+                stm.shared.glitches.add_input_overrun();
+            }
             ErrorHandle::Return(status)
         };
 
+        // The total input latency is the timestamp difference + the frames waiting in the
+        // buffer and the resampler + the stream latency + the hardware latency.
+        let buffered_frames =
+            input_buffer_manager.available_samples() / input.desc.mChannelsPerFrame as usize;
+        let resampler_frames =
+            stm.resampler.latency() as f64 * input.hw_rate / f64::from(input.stream_params.rate());
+        let pending_frames = buffered_frames as u64
+            + resampler_frames as u64
+            + u64::from(stm.current_input_latency_frames.load(Ordering::SeqCst));
+        let input_latency_frames =
+            compute_input_latency(tstamp.mHostTime, input.hw_rate, pending_frames);
+        stm.shared
+            .total_input_latency_frames
+            .store(input_latency_frames, Ordering::SeqCst);
+        stm.shared.stats.set_input_buffered_frames(buffered_frames);
+
         // Advance input frame counter.
         stm.frames_read
             .fetch_add(input_frames as usize, atomic::Ordering::SeqCst);
 
         cubeb_logv!(
-            "({:p}) input: buffers {}, size {}, channels {}, rendered frames {}, total frames {}.",
-            stm.core_stream_data.stm_ptr,
-            input_buffer_list.mNumberBuffers,
-            input_buffer_list.mBuffers[0].mDataByteSize,
-            input_buffer_list.mBuffers[0].mNumberChannels,
+            "({:p}) input: buffers {}, channels {}, rendered frames {}, total frames {}, latency {} frames.",
+            stm.shared.stm_ptr,
+            input.side.buffer_list.buffers_mut().len(),
+            rendered_channels,
             input_frames,
-            input_buffer_manager.available_samples()
-                / stm.core_stream_data.input_desc.mChannelsPerFrame as usize
+            buffered_frames,
+            input_latency_frames
         );
 
         // Full Duplex. We'll call data_callback in the AudioUnit output callback.
-        if !stm.core_stream_data.output_unit.is_null() {
+        if has_output {
+            return handle;
+        }
+
+        // Input only, at the stream rate. Call the planar user callback with the rendered buffers.
+        if stm.direct_planar {
+            let outframes = stm.planar.as_mut().unwrap().call_with_buffers(
+                stm.shared.stm_ptr as *mut ffi::cubeb_stream,
+                Some(input.side.buffer_list.buffers_mut()),
+                None,
+                i64::from(input_frames),
+            );
+            if outframes < i64::from(input_frames) {
+                stm.shared.draining.store(true, Ordering::SeqCst);
+            }
             return handle;
         }
 
         // Input only. Call the user callback through resampler.
         // Resampler will deliver input buffer in the correct rate.
-        let mut total_input_frames = (input_buffer_manager.available_samples()
-            / stm.core_stream_data.input_desc.mChannelsPerFrame as usize)
-            as i64;
+        let input_channels = input.desc.mChannelsPerFrame as usize;
+        let mut total_input_frames =
+            (input_buffer_manager.available_samples() / input_channels) as i64;
         assert!(input_frames as i64 <= total_input_frames);
-        let input_buffer = input_buffer_manager.get_linear_data(total_input_frames as usize);
-        let outframes = stm.core_stream_data.resampler.fill(
-            input_buffer,
-            &mut total_input_frames,
-            ptr::null_mut(),
-            0,
-        );
+        let input_buffer =
+            input_buffer_manager.get_linear_data(total_input_frames as usize * input_channels);
+        let outframes =
+            stm.resampler
+                .fill(input_buffer, &mut total_input_frames, ptr::null_mut(), 0);
         if outframes < total_input_frames {
-            stm.draining.store(true, Ordering::SeqCst);
+            stm.shared.draining.store(true, Ordering::SeqCst);
         }
 
         handle
     };
 
     // If the stream is drained, do nothing.
-    let handle = if !stm.draining.load(Ordering::SeqCst) {
-        handler(stm, flags, tstamp, bus, input_frames)
+    let handle = if !stm.shared.draining.load(Ordering::SeqCst) {
+        let start = host_time_to_ns(unsafe { mach_absolute_time() });
+        let handle = handler(stm, flags, tstamp, bus, input_frames);
+        let elapsed = host_time_to_ns(unsafe { mach_absolute_time() }) - start;
+        let rate = stm.input.as_ref().unwrap().hw_rate;
+        stm.shared
+            .glitches
+            .add_callback_time(elapsed, input_frames, rate);
+        stm.shared.stats.input.record(input_frames, elapsed);
+        handle
     } else {
         ErrorHandle::Return(NO_ERR)
     };
 
     // If the input (input-only stream) or the output is drained (duplex stream),
     // cancel this callback.
-    if stm.draining.load(Ordering::SeqCst) {
-        let r = stop_audiounit(stm.core_stream_data.input_unit);
-        assert!(r.is_ok());
+    if stm.shared.draining.load(Ordering::SeqCst) {
+        stm.input.as_ref().unwrap().stop();
         // Only fire state-changed callback for input-only stream.
         // The state-changed callback for the duplex stream is fired in the output callback.
-        if stm.core_stream_data.output_unit.is_null() {
-            stm.notify_state_changed(State::Drained);
+        if !stm.has_output() {
+            stm.shared.notify_state_changed(State::Drained);
         }
     }
 
     match handle {
         ErrorHandle::Reinit => {
-            stm.reinit_async();
+            stm.shared.reinit_async(ReinitReason::RenderError);
             NO_ERR
         }
         ErrorHandle::Return(s) => s,
@@ -490,7 +854,22 @@ fn host_time_to_ns(host_time: u64) -> u64 {
     rv as u64
 }
 
-fn compute_output_latency(stm: &AudioUnitStream, host_time: u64) -> u32 {
+// The time since the first frame in the callback was captured, plus the `pending_frames` after
+// it, in frames of the input device.
+fn compute_input_latency(host_time: u64, hw_rate: f64, pending_frames: u64) -> u32 {
+    let now = host_time_to_ns(unsafe { mach_absolute_time() });
+    let audio_input_time = host_time_to_ns(host_time);
+    let input_latency_ns = if audio_input_time > now {
+        0
+    } else {
+        now - audio_input_time
+    };
+
+    const NS2S: u64 = 1_000_000_000;
+    (input_latency_ns * hw_rate as u64 / NS2S + pending_frames) as u32
+}
+
+fn compute_output_latency(stm: &CoreStreamData, host_time: u64) -> u32 {
     let now = host_time_to_ns(unsafe { mach_absolute_time() });
     let audio_output_time = host_time_to_ns(host_time);
     let output_latency_ns = if audio_output_time < now {
@@ -502,7 +881,7 @@ fn compute_output_latency(stm: &AudioUnitStream, host_time: u64) -> u32 {
     const NS2S: u64 = 1_000_000_000;
     // The total output latency is the timestamp difference + the stream latency +
     // the hardware latency.
-    let out_hw_rate = stm.core_stream_data.output_hw_rate as u64;
+    let out_hw_rate = stm.output.as_ref().unwrap().hw_rate as u64;
     (output_latency_ns * out_hw_rate / NS2S
         + stm.current_latency_frames.load(Ordering::SeqCst) as u64) as u32
 }
@@ -519,53 +898,74 @@ extern "C" fn audiounit_output_callback(
     assert!(!out_buffer_list.is_null());
 
     assert!(!user_ptr.is_null());
-    let stm = unsafe { &mut *(user_ptr as *mut AudioUnitStream) };
+    let stm = unsafe { &mut *(user_ptr as *mut CoreStreamData) };
 
     let out_buffer_list_ref = unsafe { &mut (*out_buffer_list) };
-    assert_eq!(out_buffer_list_ref.mNumberBuffers, 1);
     let mut buffers = unsafe {
         let ptr = out_buffer_list_ref.mBuffers.as_mut_ptr();
         let len = out_buffer_list_ref.mNumberBuffers as usize;
         slice::from_raw_parts_mut(ptr, len)
     };
 
-    let output_latency_frames = compute_output_latency(&stm, unsafe { (*tstamp).mHostTime });
+    let host_time = unsafe { (*tstamp).mHostTime };
+    let output_latency_frames = compute_output_latency(&stm, host_time);
 
-    stm.total_output_latency_frames
+    stm.shared
+        .total_output_latency_frames
         .store(output_latency_frames, Ordering::SeqCst);
+    stm.shared.stats.set_output_latency(output_latency_frames);
 
     cubeb_logv!(
         "({:p}) output: buffers {}, size {}, channels {}, frames {}.",
-        stm as *const AudioUnitStream,
+        stm.shared.stm_ptr,
         buffers.len(),
-        buffers[0].mDataByteSize,
-        buffers[0].mNumberChannels,
+        buffers.iter().map(|b| b.mDataByteSize).sum::<u32>(),
+        buffers.iter().map(|b| b.mNumberChannels).sum::<u32>(),
         output_frames
     );
 
-    if stm.shutdown.load(Ordering::SeqCst) {
-        cubeb_log!("({:p}) output shutdown.", stm as *const AudioUnitStream);
-        audiounit_make_silent(&mut buffers[0]);
+    if stm.shared.shutdown.load(Ordering::SeqCst) {
+        cubeb_log!("({:p}) output shutdown.", stm.shared.stm_ptr);
+        audiounit_make_buffers_silent(buffers);
         return NO_ERR;
     }
 
-    if stm.draining.load(Ordering::SeqCst) {
+    if stm.shared.draining.load(Ordering::SeqCst) {
         // Cancel the output callback only. For duplex stream,
         // the input callback will be cancelled in its own callback.
-        let r = stop_audiounit(stm.core_stream_data.output_unit);
-        assert!(r.is_ok());
-        stm.notify_state_changed(State::Drained);
-        audiounit_make_silent(&mut buffers[0]);
+        stm.output.as_ref().unwrap().stop();
+        stm.shared.notify_state_changed(State::Drained);
+        audiounit_make_buffers_silent(buffers);
         return NO_ERR;
     }
 
-    let handler = |stm: &mut AudioUnitStream,
+    let handler = |stm: &mut CoreStreamData,
+                   host_time: u64,
                    output_frames: u32,
                    buffers: &mut [AudioBuffer]|
      -> (OSStatus, Option<State>) {
+        let output = stm.output.as_mut().unwrap();
+        // The planar user callback fills the buffers of the channels of an output-only stream.
+        let direct = stm.direct_planar;
+
+        // The data of the several buffers of the device is scattered from the interleaved data,
+        // which has room for the frames of the AudioUnit since the setup.
+        let scattered = !direct && buffers.len() != 1;
+        if scattered {
+            output
+                .side
+                .scattered_output
+                .resize((output.desc.mBytesPerFrame * output_frames) as usize, 0);
+        }
+        let device_buffer = if scattered {
+            output.side.scattered_output.as_mut_ptr() as *mut c_void
+        } else {
+            buffers[0].mData
+        };
+
         // Get output buffer
-        let output_buffer = match stm.core_stream_data.mixer.as_mut() {
-            None => buffers[0].mData,
+        let output_buffer = match output.side.mixer.as_mut() {
+            None => device_buffer,
             Some(mixer) => {
                 // If remixing needs to occur, we can't directly work in our final
                 // destination buffer as data may be overwritten or too small to start with.
@@ -580,127 +980,283 @@ extern "C" fn audiounit_output_callback(
             .fetch_add(output_frames as usize, Ordering::SeqCst);
 
         // Also get the input buffer if the stream is duplex
-        let (input_buffer, mut input_frames) = if !stm.core_stream_data.input_unit.is_null() {
-            let input_buffer_manager = stm.core_stream_data.input_buffer_manager.as_mut().unwrap();
-            assert_ne!(stm.core_stream_data.input_desc.mChannelsPerFrame, 0);
-            let input_channels = stm.core_stream_data.input_desc.mChannelsPerFrame as usize;
+        let (input_buffer, mut input_frames) = if let Some(input) = stm.input.as_mut() {
+            let input_buffer_manager = &mut input.side.buffer_manager;
+            assert_ne!(input.desc.mChannelsPerFrame, 0);
+            let input_channels = input.desc.mChannelsPerFrame as usize;
             // If the output callback came first and this is a duplex stream, we need to
             // fill in some additional silence in the resampler.
             // Otherwise, if we had more than expected callbacks in a row, or we're
             // currently switching, we add some silence as well to compensate for the
             // fact that we're lacking some input data.
             let input_frames_needed = minimum_resampling_input_frames(
-                stm.core_stream_data.input_hw_rate,
-                f64::from(stm.core_stream_data.output_stream_params.rate()),
+                input.hw_rate,
+                f64::from(output.stream_params.rate()),
                 output_frames as usize,
             );
             let buffered_input_frames = input_buffer_manager.available_samples() / input_channels;
-            // Else if the input has buffered a lot already because the output started late, we
-            // need to trim the input buffer
-            if prev_frames_written == 0 && buffered_input_frames > input_frames_needed as usize {
-                input_buffer_manager.trim(input_frames_needed * input_channels);
-                let popped_samples =
-                    ((buffered_input_frames - input_frames_needed) * input_channels) as usize;
-                stm.frames_read.fetch_sub(popped_samples, Ordering::SeqCst);
-
-                cubeb_log!("Dropping {} frames in input buffer.", popped_samples);
-            }
+            if let Some(compensator) = input.side.drift_compensator.as_mut() {
+                // The input runs on its own clock. Keep about one input callback of frames in
+                // reserve, and consume the input faster or slower than its nominal rate to
+                // follow the drift between the clocks.
+                let target_frames = input_frames_needed + stm.shared.latency_frames as usize;
+                if prev_frames_written == 0 && buffered_input_frames > target_frames {
+                    input_buffer_manager.trim(target_frames * input_channels);
+                    let popped_frames = buffered_input_frames - target_frames;
+                    stm.frames_read.fetch_sub(popped_frames, Ordering::SeqCst);
+                    stm.shared.glitches.add_dropped_frames(popped_frames);
+                    cubeb_log!("Dropping {} frames in input buffer.", popped_frames);
+                } else if prev_frames_written == 0 && buffered_input_frames < target_frames {
+                    let silent_frames_to_push = target_frames - buffered_input_frames;
+                    input_buffer_manager.push_silent_data(silent_frames_to_push * input_channels);
+                    stm.frames_read
+                        .fetch_add(silent_frames_to_push, Ordering::SeqCst);
+                    stm.shared
+                        .glitches
+                        .add_silence_frames(silent_frames_to_push);
+                    cubeb_log!(
+                        "({:p}) Pushed {} frames of input silence to fill the reserve.",
+                        stm.shared.stm_ptr,
+                        silent_frames_to_push
+                    );
+                }
+                let buffered_input_frames =
+                    input_buffer_manager.available_samples() / input_channels;
+                let ratio =
+                    compensator.update(buffered_input_frames, target_frames, input_frames_needed);
+                let frames_to_pull = compensator.input_frames(input_frames_needed);
+                if frames_to_pull > buffered_input_frames {
+                    // The missing frames are filled with silence.
+                    stm.shared
+                        .glitches
+                        .add_silence_frames(frames_to_pull - buffered_input_frames);
+                    cubeb_log!(
+                        "({:p}) Input buffer underrun: {} frames buffered, {} frames needed.",
+                        stm.shared.stm_ptr,
+                        buffered_input_frames,
+                        frames_to_pull
+                    );
+                }
+                stm.shared.stats.set_drift_ratio(ratio);
+                let data = input_buffer_manager.get_linear_data(frames_to_pull * input_channels);
+                (
+                    compensator.process(data, frames_to_pull, input_frames_needed),
+                    input_frames_needed as i64,
+                )
+            } else {
+                // Else if the input has buffered a lot already because the output started late, we
+                // need to trim the input buffer
+                if prev_frames_written == 0 && buffered_input_frames > input_frames_needed as usize
+                {
+                    input_buffer_manager.trim(input_frames_needed * input_channels);
+                    let popped_samples =
+                        ((buffered_input_frames - input_frames_needed) * input_channels) as usize;
+                    stm.frames_read.fetch_sub(popped_samples, Ordering::SeqCst);
+                    stm.shared
+                        .glitches
+                        .add_dropped_frames(buffered_input_frames - input_frames_needed);
+
+                    cubeb_log!("Dropping {} frames in input buffer.", popped_samples);
+                }
 
-            if input_frames_needed > buffered_input_frames
-                && (stm.switching_device.load(Ordering::SeqCst)
-                    || stm.frames_read.load(Ordering::SeqCst) == 0)
-            {
-                let silent_frames_to_push = input_frames_needed - buffered_input_frames;
-                let silent_samples_to_push = silent_frames_to_push * input_channels;
-                input_buffer_manager.push_silent_data(silent_samples_to_push);
-                stm.frames_read
-                    .fetch_add(input_frames_needed, Ordering::SeqCst);
-                cubeb_log!(
-                    "({:p}) Missing Frames: {} pushed {} frames of input silence.",
-                    stm.core_stream_data.stm_ptr,
-                    if stm.frames_read.load(Ordering::SeqCst) == 0 {
+                // The resampler can't run short of input, so the frames missing after a late
+                // input callback are filled with silence too.
+                let input_frames = if input_frames_needed > buffered_input_frames {
+                    let reason = if stm.shared.switching_device.load(Ordering::SeqCst) {
+                        "device switching,"
+                    } else if stm.frames_read.load(Ordering::SeqCst) == 0 {
                         "input hasn't started,"
                     } else {
-                        assert!(stm.switching_device.load(Ordering::SeqCst));
-                        "device switching,"
-                    },
-                    silent_frames_to_push
-                );
+                        "input is late,"
+                    };
+                    let silent_frames_to_push = input_frames_needed - buffered_input_frames;
+                    let silent_samples_to_push = silent_frames_to_push * input_channels;
+                    input_buffer_manager.push_silent_data(silent_samples_to_push);
+                    stm.frames_read
+                        .fetch_add(input_frames_needed, Ordering::SeqCst);
+                    stm.shared
+                        .glitches
+                        .add_silence_frames(silent_frames_to_push);
+                    cubeb_log!(
+                        "({:p}) Missing Frames: {} pushed {} frames of input silence.",
+                        stm.shared.stm_ptr,
+                        reason,
+                        silent_frames_to_push
+                    );
+                    input_frames_needed
+                } else {
+                    buffered_input_frames
+                };
+
+                let input_samples_needed = input_frames * input_channels;
+                (
+                    input_buffer_manager.get_linear_data(input_samples_needed),
+                    input_frames as i64,
+                )
             }
-
-            let input_samples_needed = buffered_input_frames * input_channels;
-            (
-                input_buffer_manager.get_linear_data(input_samples_needed),
-                buffered_input_frames as i64,
-            )
         } else {
             (ptr::null_mut::<c_void>(), 0)
         };
 
-        let outframes = stm.core_stream_data.resampler.fill(
-            input_buffer,
-            if input_buffer.is_null() {
-                ptr::null_mut()
-            } else {
-                &mut input_frames
-            },
-            output_buffer,
-            i64::from(output_frames),
-        );
+        if let Some(input) = stm.input.as_ref() {
+            stm.shared.stats.set_input_buffered_frames(
+                input.side.buffer_manager.available_samples()
+                    / input.desc.mChannelsPerFrame as usize,
+            );
+        }
+
+        let outframes = if direct {
+            stm.planar.as_mut().unwrap().call_with_buffers(
+                stm.shared.stm_ptr as *mut ffi::cubeb_stream,
+                None,
+                Some(buffers),
+                i64::from(output_frames),
+            )
+        } else {
+            stm.resampler.fill(
+                input_buffer,
+                if input_buffer.is_null() {
+                    ptr::null_mut()
+                } else {
+                    &mut input_frames
+                },
+                output_buffer,
+                i64::from(output_frames),
+            )
+        };
 
         if outframes < 0 || outframes > i64::from(output_frames) {
-            stm.shutdown.store(true, Ordering::SeqCst);
-            stm.core_stream_data.stop_audiounits();
-            audiounit_make_silent(&mut buffers[0]);
+            stm.shared.shutdown.store(true, Ordering::SeqCst);
+            stm.stop_audiounits();
+            audiounit_make_buffers_silent(buffers);
             return (NO_ERR, Some(State::Error));
         }
 
-        stm.draining
+        if outframes < i64::from(output_frames) {
+            stm.shared.glitches.add_output_underrun();
+        }
+        stm.shared
+            .draining
             .store(outframes < i64::from(output_frames), Ordering::SeqCst);
-        stm.frames_played
-            .store(stm.frames_queued, atomic::Ordering::SeqCst);
-        stm.frames_queued += outframes as u64;
+        let frames_queued = stm.shared.frames_queued.load(Ordering::SeqCst);
+        stm.shared
+            .frames_played
+            .store(frames_queued, atomic::Ordering::SeqCst);
+        // The first frame of the buffer is presented after the stream and hardware latency,
+        // from the time the buffer reaches the device.
+        const NS2S: u64 = 1_000_000_000;
+        let current_latency_ns = u64::from(stm.current_latency_frames.load(Ordering::SeqCst))
+            * NS2S
+            / output.hw_rate as u64;
+        stm.shared.position_clock.update(
+            StreamTimestamp {
+                position: frames_queued,
+                host_time_ns: host_time_to_ns(host_time) + current_latency_ns,
+            },
+            frames_queued + outframes as u64,
+            output.stream_params.rate(),
+        );
+        stm.shared
+            .frames_queued
+            .fetch_add(outframes as u64, Ordering::SeqCst);
 
         // Post process output samples.
-        if stm.draining.load(Ordering::SeqCst) {
+        if stm.shared.draining.load(Ordering::SeqCst) {
             // Clear missing frames (silence)
-            let frames_to_bytes = |frames: usize| -> usize {
-                let sample_size =
-                    cubeb_sample_size(stm.core_stream_data.output_stream_params.format());
-                let channel_count = stm.core_stream_data.output_stream_params.channels() as usize;
-                frames * sample_size * channel_count
+            let sample_size = output.format.sample_size();
+            let channel_count = if direct {
+                1
+            } else {
+                output.stream_params.channels() as usize
             };
-            let out_bytes = unsafe {
-                slice::from_raw_parts_mut(
-                    output_buffer as *mut u8,
-                    frames_to_bytes(output_frames as usize),
-                )
+            let frames_to_bytes = |frames: usize| -> usize { frames * sample_size * channel_count };
+            let clear = |buffer: *mut c_void| {
+                let out_bytes = unsafe {
+                    slice::from_raw_parts_mut(
+                        buffer as *mut u8,
+                        frames_to_bytes(output_frames as usize),
+                    )
+                };
+                let start = frames_to_bytes(outframes as usize);
+                for byte in out_bytes.iter_mut().skip(start) {
+                    *byte = 0;
+                }
             };
-            let start = frames_to_bytes(outframes as usize);
-            for byte in out_bytes.iter_mut().skip(start) {
-                *byte = 0;
+            if direct {
+                // Each buffer holds one channel.
+                for buffer in buffers.iter() {
+                    clear(buffer.mData);
+                }
+            } else {
+                clear(output_buffer);
             }
         }
 
+        // Fade in after starting or switching devices, and fade out before stopping or
+        // switching devices.
+        let fading_out = stm.shared.fading_out.load(Ordering::SeqCst);
+        let ramp_frames = gain_ramp_frames(stm.shared.gain_ramp, output.stream_params.rate());
+        let ramp = &mut output.side.gain_ramp;
+        if fading_out {
+            ramp.fade_out(ramp_frames);
+        } else {
+            ramp.fade_in(ramp_frames);
+        }
+        if direct {
+            ramp.apply_to_planes(
+                output.format,
+                buffers.iter().map(|buffer| buffer.mData),
+                output_frames as usize,
+            );
+        } else {
+            ramp.apply_to_buffer(
+                output.format,
+                output_buffer,
+                output.stream_params.channels() as usize,
+                output_frames as usize,
+            );
+        }
+        if fading_out && ramp.is_silent() && !stm.shared.faded_out.swap(true, Ordering::SeqCst) {
+            // Only the first silent buffer takes the lock, to wake up `fade_out_output`.
+            let (ref lock, ref cvar) = stm.shared.fade_out_signal;
+            let _guard = lock.lock().unwrap();
+            cvar.notify_all();
+        }
+
         // Mixing
-        if stm.core_stream_data.mixer.is_some() {
-            assert!(
-                buffers[0].mDataByteSize
-                    >= stm.core_stream_data.output_desc.mBytesPerFrame * output_frames
+        let device_buffer_size = (output.desc.mBytesPerFrame * output_frames) as usize;
+        if output.side.mixer.is_some() {
+            assert!(scattered || buffers[0].mDataByteSize as usize >= device_buffer_size);
+            output.side.mixer.as_mut().unwrap().mix(
+                output_frames as usize,
+                device_buffer,
+                device_buffer_size,
             );
-            stm.core_stream_data.mixer.as_mut().unwrap().mix(
+        }
+
+        if scattered {
+            scatter_buffers(
+                &output.side.scattered_output,
+                buffers,
+                output.desc.mChannelsPerFrame as usize,
+                output.format.sample_size(),
                 output_frames as usize,
-                buffers[0].mData,
-                buffers[0].mDataByteSize as usize,
             );
         }
 
         (NO_ERR, None)
     };
 
-    let (status, notification) = handler(stm, output_frames, &mut buffers);
+    let start = host_time_to_ns(unsafe { mach_absolute_time() });
+    let (status, notification) = handler(stm, host_time, output_frames, &mut buffers);
+    let elapsed = host_time_to_ns(unsafe { mach_absolute_time() }) - start;
+    let rate = stm.output.as_ref().unwrap().hw_rate;
+    stm.shared
+        .glitches
+        .add_callback_time(elapsed, output_frames, rate);
+    stm.shared.stats.output.record(output_frames, elapsed);
     if let Some(state) = notification {
-        stm.notify_state_changed(state);
+        stm.shared.notify_state_changed(state);
     }
     status
 }
@@ -713,10 +1269,10 @@ extern "C" fn audiounit_property_listener_callback(
 ) -> OSStatus {
     use self::coreaudio_sys_utils::sys;
 
-    let stm = unsafe { &mut *(user as *mut AudioUnitStream) };
+    let stm = unsafe { &mut *(user as *mut CoreStreamData) };
     let addrs = unsafe { slice::from_raw_parts(addresses, address_count as usize) };
     let property_selector = PropertySelector::new(addrs[0].mSelector);
-    if stm.switching_device.load(Ordering::SeqCst) {
+    if stm.shared.switching_device.load(Ordering::SeqCst) {
         cubeb_log!(
             "Switching is already taking place. Skip Event {} for id={}",
             property_selector,
@@ -724,13 +1280,14 @@ extern "C" fn audiounit_property_listener_callback(
         );
         return NO_ERR;
     }
-    stm.switching_device.store(true, Ordering::SeqCst);
+    stm.shared.switching_device.store(true, Ordering::SeqCst);
 
     cubeb_log!(
         "({:p}) Audio device changed, {} events.",
-        stm as *const AudioUnitStream,
+        stm.shared.stm_ptr,
         address_count
     );
+    let mut reason = ReinitReason::NoReinit;
     for (i, addr) in addrs.iter().enumerate() {
         match addr.mSelector {
             sys::kAudioHardwarePropertyDefaultOutputDevice => {
@@ -739,6 +1296,7 @@ extern "C" fn audiounit_property_listener_callback(
                     i,
                     id
                 );
+                reason = ReinitReason::DefaultOutputChanged;
             }
             sys::kAudioHardwarePropertyDefaultInputDevice => {
                 cubeb_log!(
@@ -746,6 +1304,7 @@ extern "C" fn audiounit_property_listener_callback(
                     i,
                     id
                 );
+                reason = ReinitReason::DefaultInputChanged;
             }
             sys::kAudioDevicePropertyDeviceIsAlive => {
                 cubeb_log!(
@@ -753,16 +1312,12 @@ extern "C" fn audiounit_property_listener_callback(
                     i,
                     id
                 );
-                // If this is the default input device ignore the event,
-                // kAudioHardwarePropertyDefaultInputDevice will take care of the switch
-                if stm
-                    .core_stream_data
-                    .input_device
-                    .flags
-                    .contains(device_flags::DEV_SYSTEM_DEFAULT)
-                {
-                    cubeb_log!("It's the default input device, ignore the event");
-                    stm.switching_device.store(false, Ordering::SeqCst);
+                reason = ReinitReason::DeviceRemoved;
+                // If the stream follows the default device, ignore the event,
+                // kAudioHardwarePropertyDefault{Input,Output}Device will take care of the switch
+                if stm.follows_default_device(id) {
+                    cubeb_log!("It's the default device, ignore the event");
+                    stm.shared.switching_device.store(false, Ordering::SeqCst);
                     return NO_ERR;
                 }
             }
@@ -772,6 +1327,21 @@ extern "C" fn audiounit_property_listener_callback(
                     i,
                     id
                 );
+                reason = ReinitReason::DataSourceChanged;
+            }
+            sys::kAudioHardwarePropertyDevices => {
+                cubeb_log!(
+                    "Event[{}] - mSelector == kAudioHardwarePropertyDevices for id={}",
+                    i,
+                    id
+                );
+                // Only switch back when a device opened by UID is plugged in again.
+                if !stm.selected_device_is_back() {
+                    cubeb_log!("No selected device is back, ignore the event");
+                    stm.shared.switching_device.store(false, Ordering::SeqCst);
+                    return NO_ERR;
+                }
+                reason = ReinitReason::SelectedDeviceBack;
             }
             _ => {
                 cubeb_log!(
@@ -779,27 +1349,22 @@ extern "C" fn audiounit_property_listener_callback(
                     i,
                     addr.mSelector
                 );
-                stm.switching_device.store(false, Ordering::SeqCst);
+                stm.shared.switching_device.store(false, Ordering::SeqCst);
                 return NO_ERR;
             }
         }
     }
 
-    for _addr in addrs.iter() {
-        let callback = stm.device_changed_callback.lock().unwrap();
-        if let Some(device_changed_callback) = *callback {
-            unsafe {
-                device_changed_callback(stm.user_ptr);
-            }
-        }
-    }
-
-    stm.reinit_async();
+    // The device changed callback is fired once the stream runs on the new devices.
+    stm.shared.reinit_async(reason);
 
     NO_ERR
 }
 
-fn audiounit_get_default_device_id(devtype: DeviceType) -> AudioObjectID {
+fn audiounit_get_default_device_id(
+    hardware: &dyn HardwareAbstraction,
+    devtype: DeviceType,
+) -> AudioObjectID {
     let address = get_property_address(
         match devtype {
             DeviceType::INPUT => Property::HardwareDefaultInputDevice,
@@ -811,8 +1376,13 @@ fn audiounit_get_default_device_id(devtype: DeviceType) -> AudioObjectID {
 
     let mut devid: AudioDeviceID = kAudioObjectUnknown;
     let mut size = mem::size_of::<AudioDeviceID>();
-    if audio_object_get_property_data(kAudioObjectSystemObject, &address, &mut size, &mut devid)
-        != NO_ERR
+    if audio_object_get_property_data(
+        hardware,
+        kAudioObjectSystemObject,
+        &address,
+        &mut size,
+        &mut devid,
+    ) != NO_ERR
     {
         return kAudioObjectUnknown;
     }
@@ -846,10 +1416,14 @@ fn audiounit_convert_channel_layout(layout: &AudioChannelLayout) -> Vec<mixer::C
     channels
 }
 
-fn audiounit_get_preferred_channel_layout(output_unit: AudioUnit) -> Vec<mixer::Channel> {
+fn audiounit_get_preferred_channel_layout(
+    units: &dyn AudioUnitAbstraction,
+    output_unit: AudioUnit,
+) -> Vec<mixer::Channel> {
     let mut rv = NO_ERR;
     let mut size: usize = 0;
     rv = audio_unit_get_property_info(
+        units,
         output_unit,
         kAudioDevicePropertyPreferredChannelLayout,
         kAudioUnitScope_Output,
@@ -868,6 +1442,7 @@ fn audiounit_get_preferred_channel_layout(output_unit: AudioUnit) -> Vec<mixer::
 
     let mut layout = make_sized_audio_channel_layout(size);
     rv = audio_unit_get_property(
+        units,
         output_unit,
         kAudioDevicePropertyPreferredChannelLayout,
         kAudioUnitScope_Output,
@@ -886,12 +1461,36 @@ fn audiounit_get_preferred_channel_layout(output_unit: AudioUnit) -> Vec<mixer::
     audiounit_convert_channel_layout(layout.as_ref())
 }
 
+// The preferred layout of the channels of the device in the scope of `devtype`, empty if the
+// device has none.
+fn get_device_channel_layout(
+    hardware: &dyn HardwareAbstraction,
+    devid: AudioDeviceID,
+    devtype: DeviceType,
+) -> Vec<mixer::Channel> {
+    match get_device_preferred_channel_layout(hardware, devid, devtype) {
+        Ok(layout) => audiounit_convert_channel_layout(layout.as_ref()),
+        Err(e) => {
+            cubeb_log!(
+                "Cannot get the preferred channel layout of device {}. Error: {}",
+                devid,
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
 // This is for output AudioUnit only. Calling this by input-only AudioUnit is prone
 // to crash intermittently.
-fn audiounit_get_current_channel_layout(output_unit: AudioUnit) -> Vec<mixer::Channel> {
+fn audiounit_get_current_channel_layout(
+    units: &dyn AudioUnitAbstraction,
+    output_unit: AudioUnit,
+) -> Vec<mixer::Channel> {
     let mut rv = NO_ERR;
     let mut size: usize = 0;
     rv = audio_unit_get_property_info(
+        units,
         output_unit,
         kAudioUnitProperty_AudioChannelLayout,
         kAudioUnitScope_Output,
@@ -905,12 +1504,13 @@ fn audiounit_get_current_channel_layout(output_unit: AudioUnit) -> Vec<mixer::Ch
             rv
         );
         // This property isn't known before macOS 10.12, attempt another method.
-        return audiounit_get_preferred_channel_layout(output_unit);
+        return audiounit_get_preferred_channel_layout(units, output_unit);
     }
     debug_assert!(size > 0);
 
     let mut layout = make_sized_audio_channel_layout(size);
     rv = audio_unit_get_property(
+        units,
         output_unit,
         kAudioUnitProperty_AudioChannelLayout,
         kAudioUnitScope_Output,
@@ -929,27 +1529,54 @@ fn audiounit_get_current_channel_layout(output_unit: AudioUnit) -> Vec<mixer::Ch
     audiounit_convert_channel_layout(layout.as_ref())
 }
 
-fn start_audiounit(unit: AudioUnit) -> Result<()> {
-    let status = audio_output_unit_start(unit);
-    if status == NO_ERR {
-        Ok(())
-    } else {
-        cubeb_log!("Cannot start audiounit @ {:p}. Error: {}", unit, status);
-        Err(Error::error())
-    }
+fn start_audiounit(
+    units: &dyn AudioUnitAbstraction,
+    unit: AudioUnit,
+) -> std::result::Result<(), BackendError> {
+    BackendError::check(
+        Operation::StartAudioUnit,
+        audio_output_unit_start(units, unit),
+    )
+    .map_err(|e| {
+        cubeb_log!("Cannot start audiounit @ {:p}. {}", unit, e);
+        e
+    })
 }
 
-fn stop_audiounit(unit: AudioUnit) -> Result<()> {
-    let status = audio_output_unit_stop(unit);
-    if status == NO_ERR {
-        Ok(())
-    } else {
-        cubeb_log!("Cannot stop audiounit @ {:p}. Error: {}", unit, status);
-        Err(Error::error())
-    }
+fn stop_audiounit(
+    units: &dyn AudioUnitAbstraction,
+    unit: AudioUnit,
+) -> std::result::Result<(), BackendError> {
+    BackendError::check(
+        Operation::StopAudioUnit,
+        audio_output_unit_stop(units, unit),
+    )
+    .map_err(|e| {
+        cubeb_log!("Cannot stop audiounit @ {:p}. {}", unit, e);
+        e
+    })
+}
+
+fn audiounit_is_running(units: &dyn AudioUnitAbstraction, unit: AudioUnit) -> bool {
+    assert!(!unit.is_null());
+    let mut running: u32 = 0;
+    let mut size = mem::size_of::<u32>();
+    let status = audio_unit_get_property(
+        units,
+        unit,
+        kAudioOutputUnitProperty_IsRunning,
+        kAudioUnitScope_Global,
+        0,
+        &mut running,
+        &mut size,
+    );
+    status == NO_ERR && running != 0
 }
 
-fn create_audiounit(device: &device_info) -> Result<AudioUnit> {
+fn create_audiounit(
+    units: &dyn AudioUnitAbstraction,
+    device: &device_info,
+) -> std::result::Result<AudioUnit, BackendError> {
     assert!(device
         .flags
         .intersects(device_flags::DEV_INPUT | device_flags::DEV_OUTPUT));
@@ -957,7 +1584,8 @@ fn create_audiounit(device: &device_info) -> Result<AudioUnit> {
         .flags
         .contains(device_flags::DEV_INPUT | device_flags::DEV_OUTPUT));
 
-    let unit = create_default_audiounit(device.flags)?;
+    let unit =
+        create_default_audiounit(units, device.flags).map_err(|e| e.with_device(device.id))?;
     if device
         .flags
         .contains(device_flags::DEV_SYSTEM_DEFAULT | device_flags::DEV_OUTPUT)
@@ -967,41 +1595,28 @@ fn create_audiounit(device: &device_info) -> Result<AudioUnit> {
 
     if device.flags.contains(device_flags::DEV_INPUT) {
         // Input only.
-        enable_audiounit_scope(unit, DeviceType::INPUT, true).map_err(|e| {
-            cubeb_log!("Fail to enable audiounit input scope. Error: {}", e);
-            Error::error()
-        })?;
-        enable_audiounit_scope(unit, DeviceType::OUTPUT, false).map_err(|e| {
-            cubeb_log!("Fail to disable audiounit output scope. Error: {}", e);
-            Error::error()
-        })?;
+        enable_audiounit_scope(units, unit, DeviceType::INPUT, true)
+            .map_err(|e| BackendError::new(Operation::EnableIO, e).with_device(device.id))?;
+        enable_audiounit_scope(units, unit, DeviceType::OUTPUT, false)
+            .map_err(|e| BackendError::new(Operation::EnableIO, e).with_device(device.id))?;
     }
 
     if device.flags.contains(device_flags::DEV_OUTPUT) {
         // Output only.
-        enable_audiounit_scope(unit, DeviceType::OUTPUT, true).map_err(|e| {
-            cubeb_log!("Fail to enable audiounit output scope. Error: {}", e);
-            Error::error()
-        })?;
-        enable_audiounit_scope(unit, DeviceType::INPUT, false).map_err(|e| {
-            cubeb_log!("Fail to disable audiounit input scope. Error: {}", e);
-            Error::error()
-        })?;
+        enable_audiounit_scope(units, unit, DeviceType::OUTPUT, true)
+            .map_err(|e| BackendError::new(Operation::EnableIO, e).with_device(device.id))?;
+        enable_audiounit_scope(units, unit, DeviceType::INPUT, false)
+            .map_err(|e| BackendError::new(Operation::EnableIO, e).with_device(device.id))?;
     }
 
-    set_device_to_audiounit(unit, device.id).map_err(|e| {
-        cubeb_log!(
-            "Fail to set device {} to the created audiounit. Error: {}",
-            device.id,
-            e
-        );
-        Error::error()
-    })?;
+    set_device_to_audiounit(units, unit, device.id)
+        .map_err(|e| BackendError::new(Operation::SetDevice, e).with_device(device.id))?;
 
     Ok(unit)
 }
 
 fn enable_audiounit_scope(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     devtype: DeviceType,
     enable_io: bool,
@@ -1018,6 +1633,7 @@ fn enable_audiounit_scope(
         ),
     };
     let status = audio_unit_set_property(
+        units,
         unit,
         kAudioOutputUnitProperty_EnableIO,
         scope,
@@ -1033,12 +1649,14 @@ fn enable_audiounit_scope(
 }
 
 fn set_device_to_audiounit(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     device_id: AudioObjectID,
 ) -> std::result::Result<(), OSStatus> {
     assert!(!unit.is_null());
 
     let status = audio_unit_set_property(
+        units,
         unit,
         kAudioOutputUnitProperty_CurrentDevice,
         kAudioUnitScope_Global,
@@ -1053,9 +1671,12 @@ fn set_device_to_audiounit(
     }
 }
 
-fn create_default_audiounit(flags: device_flags) -> Result<AudioUnit> {
+fn create_default_audiounit(
+    units: &dyn AudioUnitAbstraction,
+    flags: device_flags,
+) -> std::result::Result<AudioUnit, BackendError> {
     let desc = get_audiounit_description(flags);
-    create_audiounit_by_description(desc)
+    create_audiounit_by_description(units, desc)
 }
 
 fn get_audiounit_description(flags: device_flags) -> AudioComponentDescription {
@@ -1081,24 +1702,25 @@ fn get_audiounit_description(flags: device_flags) -> AudioComponentDescription {
     }
 }
 
-fn create_audiounit_by_description(desc: AudioComponentDescription) -> Result<AudioUnit> {
-    let comp = unsafe { AudioComponentFindNext(ptr::null_mut(), &desc) };
-    if comp.is_null() {
-        cubeb_log!("Could not find matching audio hardware.");
-        return Err(Error::error());
-    }
+fn create_audiounit_by_description(
+    units: &dyn AudioUnitAbstraction,
+    desc: AudioComponentDescription,
+) -> std::result::Result<AudioUnit, BackendError> {
     let mut unit: AudioUnit = ptr::null_mut();
-    let status = unsafe { AudioComponentInstanceNew(comp, &mut unit) };
-    if status == NO_ERR {
-        assert!(!unit.is_null());
-        Ok(unit)
-    } else {
-        cubeb_log!("Fail to get a new AudioUnit. Error: {}", status);
-        Err(Error::error())
+    let status = audio_unit_new(units, &desc, &mut unit);
+    if status == AUDIO_COMPONENT_NOT_FOUND {
+        cubeb_log!("Could not find matching audio hardware.");
     }
+    BackendError::check(Operation::CreateAudioUnit, status)?;
+    assert!(!unit.is_null());
+    Ok(unit)
 }
 
-fn get_buffer_size(unit: AudioUnit, devtype: DeviceType) -> std::result::Result<u32, OSStatus> {
+fn get_buffer_size(
+    units: &dyn AudioUnitAbstraction,
+    unit: AudioUnit,
+    devtype: DeviceType,
+) -> std::result::Result<u32, OSStatus> {
     assert!(!unit.is_null());
     let (scope, element) = match devtype {
         DeviceType::INPUT => (kAudioUnitScope_Output, AU_IN_BUS),
@@ -1111,6 +1733,7 @@ fn get_buffer_size(unit: AudioUnit, devtype: DeviceType) -> std::result::Result<
     let mut frames: u32 = 0;
     let mut size = mem::size_of::<u32>();
     let status = audio_unit_get_property(
+        units,
         unit,
         kAudioDevicePropertyBufferFrameSize,
         scope,
@@ -1127,6 +1750,7 @@ fn get_buffer_size(unit: AudioUnit, devtype: DeviceType) -> std::result::Result<
 }
 
 fn set_buffer_size(
+    units: &dyn AudioUnitAbstraction,
     unit: AudioUnit,
     devtype: DeviceType,
     frames: u32,
@@ -1141,6 +1765,7 @@ fn set_buffer_size(
         ),
     };
     let status = audio_unit_set_property(
+        units,
         unit,
         kAudioDevicePropertyBufferFrameSize,
         scope,
@@ -1156,19 +1781,17 @@ fn set_buffer_size(
 }
 
 #[allow(clippy::mutex_atomic)] // The mutex needs to be fed into Condvar::wait_timeout.
-fn set_buffer_size_sync(unit: AudioUnit, devtype: DeviceType, frames: u32) -> Result<()> {
-    let current_frames = get_buffer_size(unit, devtype).map_err(|e| {
+fn set_buffer_size_sync(
+    units: &dyn AudioUnitAbstraction,
+    unit: AudioUnit,
+    devtype: DeviceType,
+    frames: u32,
+) -> std::result::Result<(), BackendError> {
+    let current_frames = get_buffer_size(units, unit, devtype)
+        .map_err(|e| BackendError::new(Operation::GetBufferSize, e))?;
+    if frames == current_frames {
         cubeb_log!(
-            "Cannot get buffer size of AudioUnit {:?} for {:?}. Error: {}",
-            unit,
-            devtype,
-            e
-        );
-        Error::error()
-    })?;
-    if frames == current_frames {
-        cubeb_log!(
-            "The buffer frame size of AudioUnit {:?} for {:?} is already {}",
+            "The buffer frame size of AudioUnit {:?} for {:?} is already {}",
             unit,
             devtype,
             frames
@@ -1183,6 +1806,7 @@ fn set_buffer_size_sync(unit: AudioUnit, devtype: DeviceType, frames: u32) -> Re
 
     assert_eq!(
         audio_unit_add_property_listener(
+            units,
             unit,
             kAudioDevicePropertyBufferFrameSize,
             buffer_size_changed_callback,
@@ -1194,6 +1818,7 @@ fn set_buffer_size_sync(unit: AudioUnit, devtype: DeviceType, frames: u32) -> Re
     let _teardown = finally(|| {
         assert_eq!(
             audio_unit_remove_property_listener_with_user_data(
+                units,
                 unit,
                 kAudioDevicePropertyBufferFrameSize,
                 buffer_size_changed_callback,
@@ -1203,15 +1828,8 @@ fn set_buffer_size_sync(unit: AudioUnit, devtype: DeviceType, frames: u32) -> Re
         );
     });
 
-    set_buffer_size(unit, devtype, frames).map_err(|e| {
-        cubeb_log!(
-            "Fail to set buffer size for AudioUnit {:?} for {:?}. Error: {}",
-            unit,
-            devtype,
-            e
-        );
-        Error::error()
-    })?;
+    set_buffer_size(units, unit, devtype, frames)
+        .map_err(|e| BackendError::new(Operation::SetBufferSize, e))?;
 
     let &(ref lock, ref cvar) = &*pair;
     let changed = lock.lock().unwrap();
@@ -1225,19 +1843,12 @@ fn set_buffer_size_sync(unit: AudioUnit, devtype: DeviceType, frames: u32) -> Re
             );
         }
         if !*chg {
-            return Err(Error::error());
+            return Err(BackendError::new(Operation::WaitForBufferSize, NO_ERR));
         }
     }
 
-    let new_frames = get_buffer_size(unit, devtype).map_err(|e| {
-        cubeb_log!(
-            "Cannot get new buffer size of AudioUnit {:?} for {:?}. Error: {}",
-            unit,
-            devtype,
-            e
-        );
-        Error::error()
-    })?;
+    let new_frames = get_buffer_size(units, unit, devtype)
+        .map_err(|e| BackendError::new(Operation::GetBufferSize, e))?;
     cubeb_log!(
         "The new buffer frames size of AudioUnit {:?} for {:?} is {}",
         unit,
@@ -1287,24 +1898,35 @@ fn convert_uint32_into_string(data: u32) -> CString {
     CString::new(buffer).unwrap_or(empty)
 }
 
-fn audiounit_get_default_datasource_string(devtype: DeviceType) -> Result<CString> {
-    let id = audiounit_get_default_device_id(devtype);
+fn audiounit_get_default_datasource_string(
+    hardware: &dyn HardwareAbstraction,
+    devtype: DeviceType,
+) -> Result<CString> {
+    let id = audiounit_get_default_device_id(hardware, devtype);
     if id == kAudioObjectUnknown {
         return Err(Error::error());
     }
-    let data = get_device_source(id, devtype).unwrap_or(0);
+    let data = get_device_source(hardware, id, devtype).unwrap_or(0);
     Ok(convert_uint32_into_string(data))
 }
 
-fn is_device_a_type_of(devid: AudioObjectID, devtype: DeviceType) -> bool {
+fn is_device_a_type_of(
+    hardware: &dyn HardwareAbstraction,
+    devid: AudioObjectID,
+    devtype: DeviceType,
+) -> bool {
     assert_ne!(devid, kAudioObjectUnknown);
-    get_channel_count(devid, devtype).unwrap_or(0) > 0
+    get_channel_count(hardware, devid, devtype).unwrap_or(0) > 0
 }
 
-fn get_channel_count(devid: AudioObjectID, devtype: DeviceType) -> Result<u32> {
+fn get_channel_count(
+    hardware: &dyn HardwareAbstraction,
+    devid: AudioObjectID,
+    devtype: DeviceType,
+) -> Result<u32> {
     assert_ne!(devid, kAudioObjectUnknown);
 
-    let buffers = get_device_stream_configuration(devid, devtype).map_err(|e| {
+    let buffers = get_device_stream_configuration(hardware, devid, devtype).map_err(|e| {
         cubeb_log!("Cannot get the stream configuration. Error: {}", e);
         Error::error()
     })?;
@@ -1317,10 +1939,11 @@ fn get_channel_count(devid: AudioObjectID, devtype: DeviceType) -> Result<u32> {
 }
 
 fn get_range_of_sample_rates(
+    hardware: &dyn HardwareAbstraction,
     devid: AudioObjectID,
     devtype: DeviceType,
 ) -> std::result::Result<(f64, f64), String> {
-    let result = get_ranges_of_device_sample_rate(devid, devtype);
+    let result = get_ranges_of_device_sample_rate(hardware, devid, devtype);
     if let Err(e) = result {
         return Err(format!("status {}", e));
     }
@@ -1340,8 +1963,12 @@ fn get_range_of_sample_rates(
     Ok((min, max))
 }
 
-fn get_presentation_latency(devid: AudioObjectID, devtype: DeviceType) -> u32 {
-    let device_latency = match get_device_latency(devid, devtype) {
+fn get_presentation_latency(
+    hardware: &dyn HardwareAbstraction,
+    devid: AudioObjectID,
+    devtype: DeviceType,
+) -> u32 {
+    let device_latency = match get_device_latency(hardware, devid, devtype) {
         Ok(latency) => latency,
         Err(e) => {
             cubeb_log!(
@@ -1354,7 +1981,7 @@ fn get_presentation_latency(devid: AudioObjectID, devtype: DeviceType) -> u32 {
         }
     };
 
-    let stream_latency = get_device_streams(devid, devtype).and_then(|streams| {
+    let stream_latency = get_device_streams(hardware, devid, devtype).and_then(|streams| {
         if streams.is_empty() {
             cubeb_log!(
                 "No any stream on device {} in {:?} scope!",
@@ -1363,7 +1990,7 @@ fn get_presentation_latency(devid: AudioObjectID, devtype: DeviceType) -> u32 {
             );
             Ok(0) // default stream latency
         } else {
-            get_stream_latency(streams[0], devtype)
+            get_stream_latency(hardware, streams[0], devtype)
         }
     }).map_err(|e| {
         cubeb_log!(
@@ -1379,17 +2006,18 @@ fn get_presentation_latency(devid: AudioObjectID, devtype: DeviceType) -> u32 {
 }
 
 fn get_device_group_id(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<CString, OSStatus> {
-    match get_device_transport_type(id, devtype) {
+    match get_device_transport_type(hardware, id, devtype) {
         // If the device type is "bltn" (builtin)
         Ok(0x626C_746E) => {
             cubeb_log!(
                 "transport type is {:?}",
                 convert_uint32_into_string(0x626C_746E)
             );
-            match get_device_source(id, devtype) {
+            match get_device_source(hardware, id, devtype) {
                 Ok(source) => {
                     let msg = format!("source is {:?}", convert_uint32_into_string(source));
                     match source {
@@ -1434,27 +2062,43 @@ fn get_device_group_id(
 
     // Some devices (e.g. AirPods) might only set the model-uid in the global scope.
     // The query might fail if the scope is input-only or output-only.
-    get_device_model_uid(id, devtype)
-        .or_else(|_| get_device_model_uid(id, DeviceType::INPUT | DeviceType::OUTPUT))
+    get_device_model_uid(hardware, id, devtype)
+        .or_else(|_| get_device_model_uid(hardware, id, DeviceType::INPUT | DeviceType::OUTPUT))
         .map(|uid| uid.into_cstring())
 }
 
 fn get_device_label(
+    hardware: &dyn HardwareAbstraction,
     id: AudioDeviceID,
     devtype: DeviceType,
 ) -> std::result::Result<StringRef, OSStatus> {
-    get_device_source_name(id, devtype).or_else(|_| get_device_name(id, devtype))
+    get_device_source_name(hardware, id, devtype)
+        .or_else(|_| get_device_name(hardware, id, devtype))
+}
+
+fn get_device_global_uid(
+    hardware: &dyn HardwareAbstraction,
+    id: AudioDeviceID,
+) -> std::result::Result<StringRef, OSStatus> {
+    get_device_uid(hardware, id, DeviceType::INPUT | DeviceType::OUTPUT)
 }
 
-fn get_device_global_uid(id: AudioDeviceID) -> std::result::Result<StringRef, OSStatus> {
-    get_device_uid(id, DeviceType::INPUT | DeviceType::OUTPUT)
+// Find the alive device with the UID.
+fn find_device_by_uid(hardware: &dyn HardwareAbstraction, uid: &str) -> Option<AudioDeviceID> {
+    audiounit_get_devices(hardware).into_iter().find(|&id| {
+        id != kAudioObjectUnknown
+            && get_device_global_uid(hardware, id)
+                .map_or(false, |device_uid| device_uid.into_string() == uid)
+            && get_device_is_alive(hardware, id).unwrap_or(false)
+    })
 }
 
 fn create_cubeb_device_info(
+    hardware: &dyn HardwareAbstraction,
     devid: AudioObjectID,
     devtype: DeviceType,
 ) -> Result<ffi::cubeb_device_info> {
-    let channels = get_channel_count(devid, devtype)?;
+    let channels = get_channel_count(hardware, devid, devtype)?;
     if channels == 0 {
         // Invalid type for this device.
         return Err(Error::error());
@@ -1469,7 +2113,7 @@ fn create_cubeb_device_info(
     );
     dev_info.devid = devid as ffi::cubeb_devid;
 
-    match get_device_uid(devid, devtype) {
+    match get_device_uid(hardware, devid, devtype) {
         Ok(uid) => {
             let c_string = uid.into_cstring();
             dev_info.device_id = c_string.into_raw();
@@ -1484,7 +2128,7 @@ fn create_cubeb_device_info(
         }
     }
 
-    match get_device_group_id(devid, devtype) {
+    match get_device_group_id(hardware, devid, devtype) {
         Ok(group_id) => {
             dev_info.group_id = group_id.into_raw();
         }
@@ -1498,7 +2142,7 @@ fn create_cubeb_device_info(
         }
     }
 
-    let label = match get_device_label(devid, devtype) {
+    let label = match get_device_label(hardware, devid, devtype) {
         Ok(label) => label.into_cstring(),
         Err(e) => {
             cubeb_log!(
@@ -1512,7 +2156,7 @@ fn create_cubeb_device_info(
     };
     dev_info.friendly_name = label.into_raw();
 
-    match get_device_manufacturer(devid, devtype) {
+    match get_device_manufacturer(hardware, devid, devtype) {
         Ok(vendor) => {
             let vendor = vendor.into_cstring();
             dev_info.vendor_name = vendor.into_raw();
@@ -1534,7 +2178,7 @@ fn create_cubeb_device_info(
     };
 
     dev_info.state = ffi::CUBEB_DEVICE_STATE_ENABLED;
-    dev_info.preferred = if devid == audiounit_get_default_device_id(devtype) {
+    dev_info.preferred = if devid == audiounit_get_default_device_id(hardware, devtype) {
         ffi::CUBEB_DEVICE_PREF_ALL
     } else {
         ffi::CUBEB_DEVICE_PREF_NONE
@@ -1543,7 +2187,7 @@ fn create_cubeb_device_info(
     dev_info.format = ffi::CUBEB_DEVICE_FMT_ALL;
     dev_info.default_format = ffi::CUBEB_DEVICE_FMT_F32NE;
 
-    match get_device_sample_rate(devid, devtype) {
+    match get_device_sample_rate(hardware, devid, devtype) {
         Ok(rate) => {
             dev_info.default_rate = rate as u32;
         }
@@ -1557,7 +2201,7 @@ fn create_cubeb_device_info(
         }
     }
 
-    match get_range_of_sample_rates(devid, devtype) {
+    match get_range_of_sample_rates(hardware, devid, devtype) {
         Ok((min, max)) => {
             dev_info.min_rate = min as u32;
             dev_info.max_rate = max as u32;
@@ -1572,9 +2216,11 @@ fn create_cubeb_device_info(
         }
     }
 
-    let latency = get_presentation_latency(devid, devtype);
+    let latency = get_presentation_latency(hardware, devid, devtype);
 
-    let (latency_low, latency_high) = match get_device_buffer_frame_size_range(devid, devtype) {
+    let (latency_low, latency_high) = match get_device_buffer_frame_size_range(
+        hardware, devid, devtype,
+    ) {
         Ok(range) => (
             latency + range.mMinimum as u32,
             latency + range.mMaximum as u32,
@@ -1633,20 +2279,43 @@ fn destroy_cubeb_device_info(device: &mut ffi::cubeb_device_info) {
     }
 }
 
-fn audiounit_get_devices() -> Vec<AudioObjectID> {
+fn validate_channel_map(
+    devtype: DeviceType,
+    map: &[u32],
+    channels: u32,
+    device_channels: u32,
+) -> Result<ChannelMap> {
+    ChannelMap::new(map, channels, device_channels).ok_or_else(|| {
+        cubeb_log!(
+            "Invalid {:?} channel map {:?} for {} channels of a device of {}.",
+            devtype,
+            map,
+            channels,
+            device_channels
+        );
+        Error::invalid_parameter()
+    })
+}
+
+fn audiounit_get_devices(hardware: &dyn HardwareAbstraction) -> Vec<AudioObjectID> {
     let mut size: usize = 0;
     let address = get_property_address(
         Property::HardwareDevices,
         DeviceType::INPUT | DeviceType::OUTPUT,
     );
-    let mut ret =
-        audio_object_get_property_data_size(kAudioObjectSystemObject, &address, &mut size);
+    let mut ret = audio_object_get_property_data_size(
+        hardware,
+        kAudioObjectSystemObject,
+        &address,
+        &mut size,
+    );
     if ret != NO_ERR {
         return Vec::new();
     }
     // Total number of input and output devices.
     let mut devices: Vec<AudioObjectID> = allocate_array_by_size(size);
     ret = audio_object_get_property_data(
+        hardware,
         kAudioObjectSystemObject,
         &address,
         &mut size,
@@ -1658,17 +2327,20 @@ fn audiounit_get_devices() -> Vec<AudioObjectID> {
     devices
 }
 
-fn audiounit_get_devices_of_type(devtype: DeviceType) -> Vec<AudioObjectID> {
+fn audiounit_get_devices_of_type(
+    hardware: &dyn HardwareAbstraction,
+    devtype: DeviceType,
+) -> Vec<AudioObjectID> {
     assert!(devtype.intersects(DeviceType::INPUT | DeviceType::OUTPUT));
 
-    let mut devices = audiounit_get_devices();
+    let mut devices = audiounit_get_devices(hardware);
 
     // Remove the aggregate device from the list of devices (if any).
     devices.retain(|&device| {
         // TODO: (bug 1628411) Figure out when `device` is `kAudioObjectUnknown`.
         if device == kAudioObjectUnknown {
             false
-        } else if let Ok(uid) = get_device_global_uid(device) {
+        } else if let Ok(uid) = get_device_global_uid(hardware, device) {
             let uid = uid.into_string();
             !uid.contains(PRIVATE_AGGREGATE_DEVICE_NAME)
         } else {
@@ -1685,13 +2357,14 @@ fn audiounit_get_devices_of_type(devtype: DeviceType) -> Vec<AudioObjectID> {
 
     let mut devices_in_scope = Vec::new();
     for device in devices {
-        let label = match get_device_label(device, DeviceType::OUTPUT | DeviceType::INPUT) {
+        let label = match get_device_label(hardware, device, DeviceType::OUTPUT | DeviceType::INPUT)
+        {
             Ok(label) => label.into_string(),
             Err(e) => format!("Unknown(error: {})", e),
         };
         let info = format!("{} ({})", device, label);
 
-        if let Ok(channels) = get_channel_count(device, devtype) {
+        if let Ok(channels) = get_channel_count(hardware, device, devtype) {
             cubeb_log!("device {} has {} {:?}-channels", info, channels, devtype);
             if channels > 0 {
                 devices_in_scope.push(device);
@@ -1727,7 +2400,8 @@ extern "C" fn audiounit_collection_changed_callback(
             return;
         }
         if devices.input.changed_callback.is_some() {
-            let input_devices = audiounit_get_devices_of_type(DeviceType::INPUT);
+            let input_devices =
+                audiounit_get_devices_of_type(ctx_guard.platform.hardware(), DeviceType::INPUT);
             if devices.input.update_devices(input_devices) {
                 unsafe {
                     devices.input.changed_callback.unwrap()(
@@ -1738,7 +2412,8 @@ extern "C" fn audiounit_collection_changed_callback(
             }
         }
         if devices.output.changed_callback.is_some() {
-            let output_devices = audiounit_get_devices_of_type(DeviceType::OUTPUT);
+            let output_devices =
+                audiounit_get_devices_of_type(ctx_guard.platform.hardware(), DeviceType::OUTPUT);
             if devices.output.update_devices(output_devices) {
                 unsafe {
                     devices.output.changed_callback.unwrap()(
@@ -1868,15 +2543,22 @@ pub struct AudioUnitContext {
     serial_queue: Queue,
     latency_controller: Mutex<LatencyController>,
     devices: Mutex<SharedDevices>,
+    // The HAL and the AudioUnits of the context and its streams.
+    platform: Platform,
 }
 
 impl AudioUnitContext {
     fn new() -> Self {
+        Self::with_platform(Platform::default())
+    }
+
+    fn with_platform(platform: Platform) -> Self {
         Self {
             _ops: &OPS as *const _,
             serial_queue: Queue::new(DISPATCH_QUEUE_LABEL),
             latency_controller: Mutex::new(LatencyController::default()),
             devices: Mutex::new(SharedDevices::default()),
+            platform,
         }
     }
 
@@ -1921,6 +2603,7 @@ impl AudioUnitContext {
                 DeviceType::INPUT | DeviceType::OUTPUT,
             );
             let ret = audio_object_add_property_listener(
+                self.platform.hardware(),
                 kAudioObjectSystemObject,
                 &address,
                 audiounit_collection_changed_callback,
@@ -1942,7 +2625,7 @@ impl AudioUnitContext {
             devices.input.set(
                 collection_changed_callback,
                 user_ptr,
-                audiounit_get_devices_of_type(DeviceType::INPUT),
+                audiounit_get_devices_of_type(self.platform.hardware(), DeviceType::INPUT),
             );
         }
 
@@ -1952,7 +2635,7 @@ impl AudioUnitContext {
             devices.output.set(
                 collection_changed_callback,
                 user_ptr,
-                audiounit_get_devices_of_type(DeviceType::OUTPUT),
+                audiounit_get_devices_of_type(self.platform.hardware(), DeviceType::OUTPUT),
             );
         }
 
@@ -1985,6 +2668,7 @@ impl AudioUnitContext {
         );
         // Note: unregister a non registered cb is not a problem, not checking.
         let r = audio_object_remove_property_listener(
+            self.platform.hardware(),
             kAudioObjectSystemObject,
             &address,
             audiounit_collection_changed_callback,
@@ -2001,12 +2685,134 @@ impl AudioUnitContext {
             Err(Error::error())
         }
     }
+
+    // Create a stream like `stream_init`, but the devices can be selected by their UIDs.
+    pub fn stream_init_with_options(&mut self, options: StreamOptions) -> Result<Stream> {
+        let platform = self.platform.clone();
+        let hardware = platform.hardware();
+        let latency_frames = options.latency_frames;
+        // The devices selected by their UIDs must be available.
+        let input_device = options.input_device.resolve(hardware)?;
+        let output_device = options.output_device.resolve(hardware)?;
+
+        // Latency cannot change if another stream is operating in parallel. In this case
+        // latency is set to the other stream value.
+        let global_latency_frames = self
+            .update_latency_by_adding_stream(latency_frames)
+            .unwrap();
+        if global_latency_frames != latency_frames {
+            cubeb_log!(
+                "Use global latency {} instead of the requested latency {}.",
+                global_latency_frames,
+                latency_frames
+            );
+        }
+
+        let in_stm_settings = if let Some(params) = options.input_stream_params {
+            let stm_params = StreamParams::from(unsafe { *params.as_ptr() });
+            let mut in_device = create_input_device_info(hardware, input_device.0, &stm_params)
+                .map_err(|e| {
+                    cubeb_log!("Fail to create device info for input.");
+                    e
+                })?;
+            in_device.uid = input_device.1;
+            Some((stm_params, in_device))
+        } else {
+            None
+        };
+
+        let out_stm_settings = if let Some(params) = options.output_stream_params {
+            let mut out_device = create_device_info(hardware, output_device.0, DeviceType::OUTPUT)
+                .map_err(|e| {
+                    cubeb_log!("Fail to create device info for output.");
+                    e
+                })?;
+            out_device.uid = output_device.1;
+            let stm_params = StreamParams::from(unsafe { *params.as_ptr() });
+            Some((stm_params, out_device))
+        } else {
+            None
+        };
+
+        let mut boxed_stream = Box::new(AudioUnitStream::new(
+            self,
+            options.user_ptr,
+            options.data_callback,
+            options.state_callback,
+            global_latency_frames,
+        ));
+
+        let stm_ptr = boxed_stream.as_ref() as *const AudioUnitStream as *const ffi::cubeb_stream;
+        {
+            // The inner stream isn't created yet, so nothing else holds the shared state.
+            let shared = Arc::get_mut(&mut boxed_stream.shared).unwrap();
+            shared.stm_ptr = stm_ptr;
+            // Rename the task queue to be an unique label.
+            let queue_label = format!("{}.{:p}", DISPATCH_QUEUE_LABEL, stm_ptr);
+            shared.queue = Queue::new(queue_label.as_str());
+            shared.gain_ramp = options.gain_ramp;
+            shared.input_buffer_margin = options.input_buffer_margin;
+            shared.planar = options.planar;
+        }
+
+        boxed_stream.core_stream_data = Box::new(CoreStreamData::new(
+            boxed_stream.shared.clone(),
+            platform.clone(),
+            in_stm_settings,
+            out_stm_settings,
+        ));
+        if let Some(format) = options.sample_format {
+            boxed_stream.core_stream_data.set_format(format);
+        }
+        // The maps are validated here only. The setup after a reinit just checks that the new
+        // devices still have the mapped channels.
+        if let (Some(map), Some(input)) = (
+            options.input_channel_map,
+            boxed_stream.core_stream_data.input.as_ref(),
+        ) {
+            let map = validate_channel_map(
+                DeviceType::INPUT,
+                map,
+                input.stream_params.channels(),
+                input.device_channel_count()?,
+            )?;
+            boxed_stream.core_stream_data.set_input_channel_map(map);
+        }
+        if let (Some(map), Some(output)) = (
+            options.output_channel_map,
+            boxed_stream.core_stream_data.output.as_ref(),
+        ) {
+            let map = validate_channel_map(
+                DeviceType::OUTPUT,
+                map,
+                output.stream_params.channels(),
+                get_channel_count(hardware, output.device.id, DeviceType::OUTPUT)?,
+            )?;
+            boxed_stream.core_stream_data.set_output_channel_map(map);
+        }
+
+        if let Err(r) = boxed_stream.core_stream_data.setup() {
+            cubeb_log!(
+                "({:p}) Could not setup the audiounit stream.",
+                boxed_stream.as_ref()
+            );
+            return Err(r);
+        }
+
+        let cubeb_stream = unsafe { Stream::from_ptr(Box::into_raw(boxed_stream) as *mut _) };
+        cubeb_log!(
+            "({:p}) Cubeb stream {:?} init successful.",
+            &cubeb_stream as *const Stream,
+            options.stream_name
+        );
+        Ok(cubeb_stream)
+    }
 }
 
 impl ContextOps for AudioUnitContext {
     fn init(_context_name: Option<&CStr>) -> Result<Context> {
-        set_notification_runloop();
         let ctx = Box::new(AudioUnitContext::new());
+        set_notification_runloop(ctx.platform.hardware());
         Ok(unsafe { Context::from_ptr(Box::into_raw(ctx) as *mut _) })
     }
 
@@ -2019,18 +2825,19 @@ impl ContextOps for AudioUnitContext {
     }
     #[cfg(not(target_os = "ios"))]
     fn max_channel_count(&mut self) -> Result<u32> {
-        let device = audiounit_get_default_device_id(DeviceType::OUTPUT);
+        let device = audiounit_get_default_device_id(self.platform.hardware(), DeviceType::OUTPUT);
         if device == kAudioObjectUnknown {
             return Err(Error::error());
         }
 
-        let format = get_device_stream_format(device, DeviceType::OUTPUT).map_err(|e| {
-            cubeb_log!(
-                "Cannot get the stream format of the default output device. Error: {}",
-                e
-            );
-            Error::error()
-        })?;
+        let format = get_device_stream_format(self.platform.hardware(), device, DeviceType::OUTPUT)
+            .map_err(|e| {
+                cubeb_log!(
+                    "Cannot get the stream format of the default output device. Error: {}",
+                    e
+                );
+                Error::error()
+            })?;
         Ok(format.mChannelsPerFrame)
     }
     #[cfg(target_os = "ios")]
@@ -2039,17 +2846,21 @@ impl ContextOps for AudioUnitContext {
     }
     #[cfg(not(target_os = "ios"))]
     fn min_latency(&mut self, _params: StreamParams) -> Result<u32> {
-        let device = audiounit_get_default_device_id(DeviceType::OUTPUT);
+        let device = audiounit_get_default_device_id(self.platform.hardware(), DeviceType::OUTPUT);
         if device == kAudioObjectUnknown {
             cubeb_log!("Could not get default output device id.");
             return Err(Error::error());
         }
 
-        let range =
-            get_device_buffer_frame_size_range(device, DeviceType::OUTPUT).map_err(|e| {
-                cubeb_log!("Could not get acceptable latency range. Error: {}", e);
-                Error::error()
-            })?;
+        let range = get_device_buffer_frame_size_range(
+            self.platform.hardware(),
+            device,
+            DeviceType::OUTPUT,
+        )
+        .map_err(|e| {
+            cubeb_log!("Could not get acceptable latency range. Error: {}", e);
+            Error::error()
+        })?;
 
         Ok(cmp::max(range.mMinimum as u32, SAFE_MIN_LATENCY_FRAMES))
     }
@@ -2059,17 +2870,18 @@ impl ContextOps for AudioUnitContext {
     }
     #[cfg(not(target_os = "ios"))]
     fn preferred_sample_rate(&mut self) -> Result<u32> {
-        let device = audiounit_get_default_device_id(DeviceType::OUTPUT);
+        let device = audiounit_get_default_device_id(self.platform.hardware(), DeviceType::OUTPUT);
         if device == kAudioObjectUnknown {
             return Err(Error::error());
         }
-        let rate = get_device_sample_rate(device, DeviceType::OUTPUT).map_err(|e| {
-            cubeb_log!(
-                "Cannot get the sample rate of the default output device. Error: {}",
-                e
-            );
-            Error::error()
-        })?;
+        let rate = get_device_sample_rate(self.platform.hardware(), device, DeviceType::OUTPUT)
+            .map_err(|e| {
+                cubeb_log!(
+                    "Cannot get the sample rate of the default output device. Error: {}",
+                    e
+                );
+                Error::error()
+            })?;
         Ok(rate as u32)
     }
     fn enumerate_devices(
@@ -2083,9 +2895,11 @@ impl ContextOps for AudioUnitContext {
             if !devtype.contains(*dev_type) {
                 continue;
             }
-            let devices = audiounit_get_devices_of_type(*dev_type);
+            let devices = audiounit_get_devices_of_type(self.platform.hardware(), *dev_type);
             for device in devices {
-                if let Ok(info) = create_cubeb_device_info(device, *dev_type) {
+                if let Ok(info) =
+                    create_cubeb_device_info(self.platform.hardware(), device, *dev_type)
+                {
                     if !is_aggregate_device(&info) {
                         device_infos.push(info);
                     }
@@ -2120,7 +2934,7 @@ impl ContextOps for AudioUnitContext {
     }
     fn stream_init(
         &mut self,
-        _stream_name: Option<&CStr>,
+        stream_name: Option<&CStr>,
         input_device: DeviceId,
         input_stream_params: Option<&StreamParamsRef>,
         output_device: DeviceId,
@@ -2136,72 +2950,23 @@ impl ContextOps for AudioUnitContext {
             return Err(Error::invalid_parameter());
         }
 
-        // Latency cannot change if another stream is operating in parallel. In this case
-        // latency is set to the other stream value.
-        let global_latency_frames = self
-            .update_latency_by_adding_stream(latency_frames)
-            .unwrap();
-        if global_latency_frames != latency_frames {
-            cubeb_log!(
-                "Use global latency {} instead of the requested latency {}.",
-                global_latency_frames,
-                latency_frames
-            );
-        }
-
-        let in_stm_settings = if let Some(params) = input_stream_params {
-            let in_device = create_device_info(input_device as AudioDeviceID, DeviceType::INPUT)
-                .map_err(|e| {
-                    cubeb_log!("Fail to create device info for input.");
-                    e
-                })?;
-            let stm_params = StreamParams::from(unsafe { *params.as_ptr() });
-            Some((stm_params, in_device))
-        } else {
-            None
-        };
-
-        let out_stm_settings = if let Some(params) = output_stream_params {
-            let out_device = create_device_info(output_device as AudioDeviceID, DeviceType::OUTPUT)
-                .map_err(|e| {
-                    cubeb_log!("Fail to create device info for output.");
-                    e
-                })?;
-            let stm_params = StreamParams::from(unsafe { *params.as_ptr() });
-            Some((stm_params, out_device))
-        } else {
-            None
-        };
-
-        let mut boxed_stream = Box::new(AudioUnitStream::new(
-            self,
-            user_ptr,
+        self.stream_init_with_options(StreamOptions {
+            stream_name,
+            input_device: StreamDevice::from_devid(input_device),
+            input_stream_params,
+            output_device: StreamDevice::from_devid(output_device),
+            output_stream_params,
+            latency_frames,
+            gain_ramp: DEFAULT_GAIN_RAMP,
+            input_buffer_margin: DEFAULT_INPUT_BUFFER_MARGIN,
+            sample_format: None,
+            planar: false,
+            input_channel_map: None,
+            output_channel_map: None,
             data_callback,
             state_callback,
-            global_latency_frames,
-        ));
-
-        // Rename the task queue to be an unique label.
-        let queue_label = format!("{}.{:p}", DISPATCH_QUEUE_LABEL, boxed_stream.as_ref());
-        boxed_stream.queue = Queue::new(queue_label.as_str());
-
-        boxed_stream.core_stream_data =
-            CoreStreamData::new(boxed_stream.as_ref(), in_stm_settings, out_stm_settings);
-
-        if let Err(r) = boxed_stream.core_stream_data.setup() {
-            cubeb_log!(
-                "({:p}) Could not setup the audiounit stream.",
-                boxed_stream.as_ref()
-            );
-            return Err(r);
-        }
-
-        let cubeb_stream = unsafe { Stream::from_ptr(Box::into_raw(boxed_stream) as *mut _) };
-        cubeb_log!(
-            "({:p}) Cubeb stream init successful.",
-            &cubeb_stream as *const Stream
-        );
-        Ok(cubeb_stream)
+            user_ptr,
+        })
     }
     fn register_device_collection_changed(
         &mut self,
@@ -2249,512 +3014,1068 @@ impl Drop for AudioUnitContext {
 unsafe impl Send for AudioUnitContext {}
 unsafe impl Sync for AudioUnitContext {}
 
+// StreamHalf: The data of the stream in one direction.
+// ------------------------------------------------------------------------------------------------
 #[derive(Debug)]
-struct CoreStreamData<'ctx> {
-    stm_ptr: *const AudioUnitStream<'ctx>,
-    aggregate_device: AggregateDevice,
-    mixer: Option<Mixer>,
-    resampler: Resampler,
+struct StreamHalf<T> {
     // Stream creation parameters.
-    input_stream_params: StreamParams,
-    output_stream_params: StreamParams,
-    // Format descriptions.
-    input_desc: AudioStreamBasicDescription,
-    output_desc: AudioStreamBasicDescription,
-    // I/O AudioUnits.
-    input_unit: AudioUnit,
-    output_unit: AudioUnit,
-    // Info of the I/O devices.
-    input_device: device_info,
-    output_device: device_info,
-    // Sample rates of the I/O devices.
-    input_hw_rate: f64,
-    output_hw_rate: f64,
-    // Channel layout of the output AudioUnit.
-    device_layout: Vec<mixer::Channel>,
-    input_buffer_manager: Option<BufferManager>,
-    // Listeners indicating what system events are monitored.
-    default_input_listener: Option<device_property_listener>,
-    default_output_listener: Option<device_property_listener>,
-    input_alive_listener: Option<device_property_listener>,
-    input_source_listener: Option<device_property_listener>,
-    output_source_listener: Option<device_property_listener>,
+    stream_params: StreamParams,
+    // The format of the samples exchanged with the data callback.
+    format: StreamFormat,
+    // Info of the device.
+    device: device_info,
+    // Format description of the data exchanged with the AudioUnit. The input AudioUnit renders
+    // the `rendered_channels` channels of the device instead when they're mapped or mixed into
+    // the channels of the stream, e.g. both channels of a stereo microphone for a mono stream.
+    desc: AudioStreamBasicDescription,
+    // The AudioUnit. It's null before the half is set up and after it's closed.
+    unit: AudioUnit,
+    // Sample rate of the device.
+    hw_rate: f64,
+    // The AudioUnit renders or takes one buffer per channel.
+    planar: bool,
+    // The HAL and the AudioUnits the half runs on.
+    platform: Platform,
+    // The data only used in this direction.
+    side: T,
 }
 
-impl<'ctx> Default for CoreStreamData<'ctx> {
-    fn default() -> Self {
-        Self {
-            stm_ptr: ptr::null(),
-            aggregate_device: AggregateDevice::default(),
-            mixer: None,
-            resampler: Resampler::default(),
-            input_stream_params: StreamParams::from(ffi::cubeb_stream_params {
-                format: ffi::CUBEB_SAMPLE_FLOAT32NE,
-                rate: 0,
-                channels: 0,
-                layout: ffi::CUBEB_LAYOUT_UNDEFINED,
-                prefs: ffi::CUBEB_STREAM_PREF_NONE,
-            }),
-            output_stream_params: StreamParams::from(ffi::cubeb_stream_params {
-                format: ffi::CUBEB_SAMPLE_FLOAT32NE,
-                rate: 0,
-                channels: 0,
-                layout: ffi::CUBEB_LAYOUT_UNDEFINED,
-                prefs: ffi::CUBEB_STREAM_PREF_NONE,
-            }),
-            input_desc: AudioStreamBasicDescription::default(),
-            output_desc: AudioStreamBasicDescription::default(),
-            input_unit: ptr::null_mut(),
-            output_unit: ptr::null_mut(),
-            input_device: device_info::default(),
-            output_device: device_info::default(),
-            input_hw_rate: 0_f64,
-            output_hw_rate: 0_f64,
-            device_layout: Vec::new(),
-            input_buffer_manager: None,
-            default_input_listener: None,
-            default_output_listener: None,
-            input_alive_listener: None,
-            input_source_listener: None,
-            output_source_listener: None,
-        }
-    }
+#[derive(Debug)]
+struct InputSide {
+    buffer_manager: InputBuffer,
+    // The list rendered by the input AudioUnit, and the input gathered from its buffers when it
+    // has several of them. Only used in the input callback.
+    buffer_list: BufferList,
+    gathered_input: Vec<u8>,
+    // The device channels captured in the channels of the stream, if they're not its first
+    // channels. The AudioUnit then renders its `rendered_channels` channels, and the input
+    // callback extracts the channels of `rendered_map`, which is the map shifted past the
+    // channels of the other devices of the aggregate device, into `mapped_input`. The map
+    // also picks the channels of the device to mix out of the aggregate device.
+    channel_map: Option<ChannelMap>,
+    rendered_channels: u32,
+    // The buffers of the rendered channels, which is one per channel for the planar streams.
+    rendered_buffers: usize,
+    rendered_map: Vec<u32>,
+    mapped_input: Vec<u8>,
+    // Mixes the channels of the device, in `device_layout`, into the channels of the stream
+    // when they don't match, into `mixed_input`. Only used in the input callback.
+    mixer: Option<Mixer>,
+    device_layout: Vec<mixer::Channel>,
+    mixed_input: Vec<u8>,
+    // Set when the input and the output run on different clocks. Only used in the render
+    // callback.
+    drift_compensator: Option<DriftCompensator>,
 }
 
-impl<'ctx> CoreStreamData<'ctx> {
-    fn new(
-        stm: &AudioUnitStream<'ctx>,
-        input_stream_settings: Option<(StreamParams, device_info)>,
-        output_stream_settings: Option<(StreamParams, device_info)>,
-    ) -> Self {
-        fn get_default_sttream_params() -> StreamParams {
-            StreamParams::from(ffi::cubeb_stream_params {
-                format: ffi::CUBEB_SAMPLE_FLOAT32NE,
-                rate: 0,
-                channels: 0,
-                layout: ffi::CUBEB_LAYOUT_UNDEFINED,
-                prefs: ffi::CUBEB_STREAM_PREF_NONE,
-            })
-        }
-        let (in_stm_params, in_dev) =
-            input_stream_settings.unwrap_or((get_default_sttream_params(), device_info::default()));
-        let (out_stm_params, out_dev) = output_stream_settings
-            .unwrap_or((get_default_sttream_params(), device_info::default()));
-        Self {
-            stm_ptr: stm,
-            aggregate_device: AggregateDevice::default(),
-            mixer: None,
-            resampler: Resampler::default(),
-            input_stream_params: in_stm_params,
-            output_stream_params: out_stm_params,
-            input_desc: AudioStreamBasicDescription::default(),
-            output_desc: AudioStreamBasicDescription::default(),
-            input_unit: ptr::null_mut(),
-            output_unit: ptr::null_mut(),
-            input_device: in_dev,
-            output_device: out_dev,
-            input_hw_rate: 0_f64,
-            output_hw_rate: 0_f64,
-            device_layout: Vec::new(),
-            input_buffer_manager: None,
-            default_input_listener: None,
-            default_output_listener: None,
-            input_alive_listener: None,
-            input_source_listener: None,
-            output_source_listener: None,
-        }
-    }
+#[derive(Debug)]
+struct OutputSide {
+    mixer: Option<Mixer>,
+    // Channel layout of the output AudioUnit.
+    device_layout: Vec<mixer::Channel>,
+    // The fades of the data in the stream format. Only used in the render callback.
+    gain_ramp: GainRamp,
+    // The output scattered to the buffers of the render callback when it has several of them.
+    scattered_output: Vec<u8>,
+    // The device channels playing the channels of the stream, instead of the ones its layout is
+    // mixed into.
+    channel_map: Option<ChannelMap>,
+}
 
-    fn start_audiounits(&self) -> Result<()> {
-        // Only allowed to be called after the stream is initialized
-        // and before the stream is destroyed.
-        debug_assert!(!self.input_unit.is_null() || !self.output_unit.is_null());
+type InputHalf = StreamHalf<InputSide>;
+type OutputHalf = StreamHalf<OutputSide>;
 
-        if !self.input_unit.is_null() {
-            start_audiounit(self.input_unit)?;
-        }
-        if !self.output_unit.is_null() {
-            start_audiounit(self.output_unit)?;
+impl<T> StreamHalf<T> {
+    fn start(&self) -> Result<()> {
+        if self.unit.is_null() {
+            return Ok(());
         }
+        start_audiounit(self.platform.units(), self.unit)?;
         Ok(())
     }
 
-    fn stop_audiounits(&self) {
-        if !self.input_unit.is_null() {
-            let r = stop_audiounit(self.input_unit);
-            assert!(r.is_ok());
-        }
-        if !self.output_unit.is_null() {
-            let r = stop_audiounit(self.output_unit);
+    fn stop(&self) {
+        if !self.unit.is_null() {
+            let r = stop_audiounit(self.platform.units(), self.unit);
             assert!(r.is_ok());
         }
     }
 
-    fn has_input(&self) -> bool {
-        self.input_stream_params.rate() > 0
+    fn close(&mut self) {
+        if !self.unit.is_null() {
+            let units = self.platform.units();
+            audio_unit_uninitialize(units, self.unit);
+            dispose_audio_unit(units, self.unit);
+            self.unit = ptr::null_mut();
+        }
     }
+}
 
-    fn has_output(&self) -> bool {
-        self.output_stream_params.rate() > 0
-    }
+impl InputHalf {
+    fn new(platform: Platform, stream_params: StreamParams, device: device_info) -> Self {
+        let format = StreamFormat::from(stream_params.format());
+        // The buffer is sized by `CoreStreamData::setup` once the hardware rates are known.
+        let buffer_manager = InputBuffer::new(format, 0);
+        Self {
+            stream_params,
+            format,
+            device,
+            desc: AudioStreamBasicDescription::default(),
+            unit: ptr::null_mut(),
+            hw_rate: 0_f64,
+            planar: false,
+            platform,
+            side: InputSide {
+                buffer_manager,
+                buffer_list: BufferList::new(1),
+                gathered_input: Vec::new(),
+                channel_map: None,
+                rendered_channels: 0,
+                rendered_buffers: 1,
+                rendered_map: Vec::new(),
+                mapped_input: Vec::new(),
+                mixer: None,
+                device_layout: Vec::new(),
+                mixed_input: Vec::new(),
+                drift_compensator: None,
+            },
+        }
+    }
+
+    fn is_loopback(&self) -> bool {
+        self.stream_params.prefs().contains(StreamPrefs::LOOPBACK)
+    }
+
+    // The scope of the device captured by this half. The loopback input captures the output of
+    // the device.
+    fn device_type(&self) -> DeviceType {
+        if self.is_loopback() {
+            DeviceType::OUTPUT
+        } else {
+            DeviceType::INPUT
+        }
+    }
+
+    // The number of channels captured from the device of this half.
+    fn device_channel_count(&self) -> Result<u32> {
+        get_channel_count(self.platform.hardware(), self.device.id, self.device_type())
+    }
+
+    // Create the input AudioUnit on `device`, which is the device of this half or the aggregate
+    // device including it, and register `user_ptr` for the input callback.
+    fn setup(
+        &mut self,
+        device: &device_info,
+        latency_frames: u32,
+        user_ptr: *mut c_void,
+    ) -> std::result::Result<(), BackendError> {
+        cubeb_log!(
+            "({:p}) Initialize input by device info: {:?}",
+            user_ptr,
+            device
+        );
+
+        self.unit = create_audiounit(self.platform.units(), device)?;
+
+        cubeb_log!(
+            "({:p}) Opening input side: rate {}, channels {}, format {:?}, layout {:?}, prefs {:?}, latency in frames {}.",
+            user_ptr,
+            self.stream_params.rate(),
+            self.stream_params.channels(),
+            self.format,
+            self.stream_params.layout(),
+            self.stream_params.prefs(),
+            latency_frames
+        );
+
+        // Get input device sample rate.
+        let mut input_hw_desc = AudioStreamBasicDescription::default();
+        let mut size = mem::size_of::<AudioStreamBasicDescription>();
+        let r = audio_unit_get_property(
+            self.platform.units(),
+            self.unit,
+            kAudioUnitProperty_StreamFormat,
+            kAudioUnitScope_Input,
+            AU_IN_BUS,
+            &mut input_hw_desc,
+            &mut size,
+        );
+        BackendError::check(Operation::GetStreamFormat, r)?;
+        cubeb_log!(
+            "({:p}) Input hardware description: {:?}",
+            user_ptr,
+            input_hw_desc
+        );
+        self.hw_rate = input_hw_desc.mSampleRate;
+
+        // Set format description according to the input params.
+        self.desc = create_stream_description(self.format, &self.stream_params)
+            .map_err(|_| BackendError::new(Operation::CreateStreamDescription, NO_ERR))?;
+
+        let mut src_desc = self.desc;
+        // The aggregate device has the input channels of the output device first. The device may
+        // have fewer channels after reinit.
+        let rendered_channels = input_hw_desc.mChannelsPerFrame;
+        let device_channels = if device.id == self.device.id {
+            rendered_channels
+        } else {
+            cmp::min(self.device_channel_count().unwrap_or(0), rendered_channels)
+        };
+        let first_channel = rendered_channels - device_channels;
+        self.side.rendered_map.clear();
+        self.side.mixer = None;
+        if let Some(map) = self.side.channel_map.as_ref() {
+            // Render all the channels of the device, to pick the mapped ones.
+            if !map.fits(device_channels) {
+                cubeb_log!(
+                    "({:p}) Input channel map {:?} doesn't fit the {} channels of the device.",
+                    user_ptr,
+                    map,
+                    device_channels
+                );
+                return Err(BackendError::new(Operation::CheckInputChannels, NO_ERR));
+            }
+            self.side.rendered_map = map.iter().map(|c| c + first_channel).collect();
+            src_desc.mChannelsPerFrame = rendered_channels;
+        } else {
+            if device_channels == 0 {
+                return Err(BackendError::new(Operation::CheckInputChannels, NO_ERR));
+            }
+            // Read the layout from the device, since the layout of an input-only AudioUnit isn't
+            // reliable. It's the layout of this half's device, even in an aggregate device.
+            let mut device_layout = get_device_channel_layout(
+                self.platform.hardware(),
+                self.device.id,
+                self.device_type(),
+            );
+            if device_layout.len() != device_channels as usize {
+                device_layout.clear();
+            }
+            self.side.device_layout = device_layout;
+            // The channels of an undefined layout, of the stream or of the device, are taken in
+            // order.
+            let stream_layout = mixer::get_channel_order(self.stream_params.layout());
+            if device_channels != self.stream_params.channels()
+                || (!stream_layout.is_empty()
+                    && !self.side.device_layout.is_empty()
+                    && self.side.device_layout != stream_layout)
+            {
+                cubeb_log!("Incompatible input channel layouts detected, setting up remixer");
+                // Render the channels of the device, and mix them into the ones of the stream
+                // in the input callback.
+                if first_channel > 0 {
+                    self.side.rendered_map = (first_channel..rendered_channels).collect();
+                }
+                src_desc.mChannelsPerFrame = rendered_channels;
+                self.side.mixer = Some(Mixer::for_input(
+                    self.format,
+                    device_channels as usize,
+                    self.side.device_layout.clone(),
+                    self.stream_params.channels() as usize,
+                    self.stream_params.layout(),
+                ));
+            }
+        }
+        src_desc.mBytesPerFrame = self.format.sample_size() as u32 * src_desc.mChannelsPerFrame;
+        src_desc.mBytesPerPacket = src_desc.mBytesPerFrame * src_desc.mFramesPerPacket;
+        if self.planar {
+            src_desc = non_interleaved(&src_desc);
+        }
+        self.side.rendered_channels = src_desc.mChannelsPerFrame;
+        self.side.rendered_buffers = buffer_count(&src_desc);
+        // Room for a buffer per channel at most.
+        self.side.buffer_list = BufferList::new(cmp::max(src_desc.mChannelsPerFrame as usize, 1));
+        // The AudioUnit renders `latency_frames` frames at most, so the buffers of the input
+        // callback don't grow on the audio thread.
+        let frames = latency_frames as usize;
+        let sample_size = self.format.sample_size();
+        self.side.gathered_input = if self.side.rendered_buffers > 1 {
+            vec![0; frames * self.side.rendered_channels as usize * sample_size]
+        } else {
+            Vec::new()
+        };
+        self.side.mapped_input = vec![0; frames * self.side.rendered_map.len() * sample_size];
+        self.side.mixed_input = match self.side.mixer.as_mut() {
+            Some(mixer) => {
+                mixer.update_buffer_size(frames);
+                vec![0; frames * self.stream_params.channels() as usize * sample_size]
+            }
+            None => Vec::new(),
+        };
+
+        // Use latency to set buffer size
+        assert_ne!(latency_frames, 0);
+        set_buffer_size_sync(
+            self.platform.units(),
+            self.unit,
+            DeviceType::INPUT,
+            latency_frames,
+        )?;
+
+        // Input AudioUnit must be configured with device's sample rate.
+        // we will resample inside input callback.
+        src_desc.mSampleRate = self.hw_rate;
+        let r = audio_unit_set_property(
+            self.platform.units(),
+            self.unit,
+            kAudioUnitProperty_StreamFormat,
+            kAudioUnitScope_Output,
+            AU_IN_BUS,
+            &src_desc,
+            mem::size_of::<AudioStreamBasicDescription>(),
+        );
+        BackendError::check(Operation::SetStreamFormat, r)?;
+
+        // Frames per buffer in the input callback.
+        let r = audio_unit_set_property(
+            self.platform.units(),
+            self.unit,
+            kAudioUnitProperty_MaximumFramesPerSlice,
+            kAudioUnitScope_Global,
+            AU_IN_BUS,
+            &latency_frames,
+            mem::size_of::<u32>(),
+        );
+        BackendError::check(Operation::SetMaxFramesPerSlice, r)?;
+
+        let aurcbs_in = AURenderCallbackStruct {
+            inputProc: Some(audiounit_input_callback),
+            inputProcRefCon: user_ptr,
+        };
+
+        let r = audio_unit_set_property(
+            self.platform.units(),
+            self.unit,
+            kAudioOutputUnitProperty_SetInputCallback,
+            kAudioUnitScope_Global,
+            AU_OUT_BUS,
+            &aurcbs_in,
+            mem::size_of_val(&aurcbs_in),
+        );
+        BackendError::check(Operation::SetCallback, r)?;
+
+        cubeb_log!(
+            "({:p}) Input audiounit init with device {} successfully.",
+            user_ptr,
+            device.id
+        );
+        Ok(())
+    }
+}
+
+impl OutputHalf {
+    fn new(platform: Platform, stream_params: StreamParams, device: device_info) -> Self {
+        Self {
+            format: StreamFormat::from(stream_params.format()),
+            stream_params,
+            device,
+            desc: AudioStreamBasicDescription::default(),
+            unit: ptr::null_mut(),
+            hw_rate: 0_f64,
+            planar: false,
+            platform,
+            side: OutputSide {
+                mixer: None,
+                device_layout: Vec::new(),
+                gain_ramp: GainRamp::default(),
+                scattered_output: Vec::new(),
+                channel_map: None,
+            },
+        }
+    }
+
+    // Create the output AudioUnit on `device`, which is the device of this half or the aggregate
+    // device including it, and register `user_ptr` for the render callback.
+    fn setup(
+        &mut self,
+        device: &device_info,
+        latency_frames: u32,
+        user_ptr: *mut c_void,
+    ) -> std::result::Result<(), BackendError> {
+        cubeb_log!(
+            "({:p}) Initialize output by device info: {:?}",
+            user_ptr,
+            device
+        );
+
+        self.unit = create_audiounit(self.platform.units(), device)?;
+
+        cubeb_log!(
+            "({:p}) Opening output side: rate {}, channels {}, format {:?}, layout {:?}, prefs {:?}, latency in frames {}.",
+            user_ptr,
+            self.stream_params.rate(),
+            self.stream_params.channels(),
+            self.format,
+            self.stream_params.layout(),
+            self.stream_params.prefs(),
+            latency_frames
+        );
+
+        self.desc = create_stream_description(self.format, &self.stream_params)
+            .map_err(|_| BackendError::new(Operation::CreateStreamDescription, NO_ERR))?;
+
+        // Get output device sample rate.
+        let mut output_hw_desc = AudioStreamBasicDescription::default();
+        let mut size = mem::size_of::<AudioStreamBasicDescription>();
+        let r = audio_unit_get_property(
+            self.platform.units(),
+            self.unit,
+            kAudioUnitProperty_StreamFormat,
+            kAudioUnitScope_Output,
+            AU_OUT_BUS,
+            &mut output_hw_desc,
+            &mut size,
+        );
+        BackendError::check(Operation::GetStreamFormat, r)?;
+        cubeb_log!(
+            "({:p}) Output hardware description: {:?}",
+            user_ptr,
+            output_hw_desc
+        );
+        self.hw_rate = output_hw_desc.mSampleRate;
+        let hw_channels = output_hw_desc.mChannelsPerFrame;
+        if hw_channels == 0 {
+            return Err(BackendError::new(Operation::CheckOutputChannels, NO_ERR));
+        }
+
+        self.side.device_layout =
+            audiounit_get_current_channel_layout(self.platform.units(), self.unit);
+
+        self.side.mixer = if let Some(map) = self.side.channel_map.as_ref() {
+            // The device may have fewer channels after reinit.
+            let mixer = Mixer::with_channel_map(self.format, hw_channels as usize, map)
+                .ok_or_else(|| {
+                    cubeb_log!(
+                        "({:p}) Output channel map {:?} doesn't fit the {} channels of the device.",
+                        user_ptr,
+                        map,
+                        hw_channels
+                    );
+                    BackendError::new(Operation::CheckOutputChannels, NO_ERR)
+                })?;
+            Some(mixer)
+        } else if hw_channels != self.stream_params.channels()
+            || self.side.device_layout != mixer::get_channel_order(self.stream_params.layout())
+        {
+            cubeb_log!("Incompatible channel layouts detected, setting up remixer");
+            Some(Mixer::new(
+                self.format,
+                self.stream_params.channels() as usize,
+                self.stream_params.layout(),
+                hw_channels as usize,
+                self.side.device_layout.clone(),
+            ))
+        } else {
+            None
+        };
+        if self.side.mixer.is_some() {
+            // We will be remixing the data before it reaches the output device.
+            // We need to adjust the number of channels and other
+            // AudioStreamDescription details.
+            self.desc.mChannelsPerFrame = hw_channels;
+            self.desc.mBytesPerFrame = self.format.sample_size() as u32 * hw_channels;
+            self.desc.mBytesPerPacket = self.desc.mBytesPerFrame * self.desc.mFramesPerPacket;
+        }
+
+        // The data is interleaved until the render callback scatters it into the buffers of the
+        // planar streams.
+        let device_desc = if self.planar {
+            non_interleaved(&self.desc)
+        } else {
+            self.desc
+        };
+        let r = audio_unit_set_property(
+            self.platform.units(),
+            self.unit,
+            kAudioUnitProperty_StreamFormat,
+            kAudioUnitScope_Input,
+            AU_OUT_BUS,
+            &device_desc,
+            mem::size_of::<AudioStreamBasicDescription>(),
+        );
+        BackendError::check(Operation::SetStreamFormat, r)?;
+
+        // The render callback takes `latency_frames` frames at most, so its buffers don't grow on
+        // the audio thread.
+        let frames = latency_frames as usize;
+        self.side.scattered_output = if buffer_count(&device_desc) > 1 {
+            vec![0; frames * self.desc.mBytesPerFrame as usize]
+        } else {
+            Vec::new()
+        };
+        if let Some(mixer) = self.side.mixer.as_mut() {
+            mixer.update_buffer_size(frames);
+        }
+
+        // Use latency to set buffer size
+        assert_ne!(latency_frames, 0);
+        set_buffer_size_sync(
+            self.platform.units(),
+            self.unit,
+            DeviceType::OUTPUT,
+            latency_frames,
+        )?;
+
+        // Frames per buffer in the input callback.
+        let r = audio_unit_set_property(
+            self.platform.units(),
+            self.unit,
+            kAudioUnitProperty_MaximumFramesPerSlice,
+            kAudioUnitScope_Global,
+            AU_OUT_BUS,
+            &latency_frames,
+            mem::size_of::<u32>(),
+        );
+        BackendError::check(Operation::SetMaxFramesPerSlice, r)?;
+
+        let aurcbs_out = AURenderCallbackStruct {
+            inputProc: Some(audiounit_output_callback),
+            inputProcRefCon: user_ptr,
+        };
+        let r = audio_unit_set_property(
+            self.platform.units(),
+            self.unit,
+            kAudioUnitProperty_SetRenderCallback,
+            kAudioUnitScope_Global,
+            AU_OUT_BUS,
+            &aurcbs_out,
+            mem::size_of_val(&aurcbs_out),
+        );
+        BackendError::check(Operation::SetCallback, r)?;
+
+        cubeb_log!(
+            "({:p}) Output audiounit init with device {} successfully.",
+            user_ptr,
+            device.id
+        );
+        Ok(())
+    }
+}
+
+// SharedStreamData: The state of the stream kept across its inner streams.
+// ------------------------------------------------------------------------------------------------
+// The outer stream, which implements the cubeb interface, and its inner stream, which runs on the
+// devices, share this state. The inner stream is replaced by a new one on reinit, and the new one
+// carries on with the same callbacks, position and fades.
+#[derive(Debug)]
+struct SharedStreamData {
+    // The outer stream. It's only passed to the user callbacks, and only dereferenced by the
+    // tasks on `queue`, which run before the outer stream is destroyed.
+    stm_ptr: *const ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    // Task queue for the stream.
+    queue: Queue,
+    data_callback: ffi::cubeb_data_callback,
+    state_callback: ffi::cubeb_state_callback,
+    // Frame counters
+    frames_played: AtomicU64,
+    frames_queued: AtomicU64,
+    // The position and its presentation time sampled in the render callback.
+    position_clock: PositionClock,
+    glitches: GlitchCounters,
+    stats: StatsCollector,
+    shutdown: AtomicBool,
+    draining: AtomicBool,
+    // The software gain, and mute, of the input samples.
+    input_gain: atomic::Atomic<f32>,
+    input_muted: AtomicBool,
+    // The length of the output fades.
+    gain_ramp: Duration,
+    // The safety margin of the input buffer.
+    input_buffer_margin: Duration,
+    // The data callback takes one buffer per channel.
+    planar: bool,
+    // Set to fade out the output, and the render callback sets `faded_out` once it's silent,
+    // and signals it.
+    fading_out: AtomicBool,
+    faded_out: AtomicBool,
+    fade_out_signal: (Mutex<()>, Condvar),
+    reinit_pending: AtomicBool,
+    // Latency requested by the user.
+    latency_frames: u32,
+    total_output_latency_frames: AtomicU32,
+    // The total input latency computed in the input callback.
+    total_input_latency_frames: AtomicU32,
+    // This is true if a device change callback is currently running.
+    switching_device: AtomicBool,
+}
+
+impl SharedStreamData {
+    fn new(
+        user_ptr: *mut c_void,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        latency_frames: u32,
+    ) -> Self {
+        Self {
+            stm_ptr: ptr::null(),
+            user_ptr,
+            queue: Queue::new(DISPATCH_QUEUE_LABEL),
+            data_callback,
+            state_callback,
+            frames_played: AtomicU64::new(0),
+            frames_queued: AtomicU64::new(0),
+            position_clock: PositionClock::default(),
+            glitches: GlitchCounters::default(),
+            stats: StatsCollector::default(),
+            shutdown: AtomicBool::new(true),
+            draining: AtomicBool::new(false),
+            input_gain: atomic::Atomic::new(1.0),
+            input_muted: AtomicBool::new(false),
+            gain_ramp: DEFAULT_GAIN_RAMP,
+            input_buffer_margin: DEFAULT_INPUT_BUFFER_MARGIN,
+            planar: false,
+            fading_out: AtomicBool::new(false),
+            faded_out: AtomicBool::new(false),
+            fade_out_signal: (Mutex::new(()), Condvar::new()),
+            reinit_pending: AtomicBool::new(false),
+            latency_frames,
+            total_output_latency_frames: AtomicU32::new(0),
+            total_input_latency_frames: AtomicU32::new(0),
+            switching_device: AtomicBool::new(false),
+        }
+    }
+
+    fn notify_state_changed(&self, state: State) {
+        if self.state_callback.is_none() {
+            return;
+        }
+        let callback = self.state_callback.unwrap();
+        unsafe {
+            callback(
+                self.stm_ptr as *mut ffi::cubeb_stream,
+                self.user_ptr,
+                state.into(),
+            );
+        }
+    }
+
+    // Reinit the outer stream on its queue. The inner stream calling this may be replaced by the
+    // time the task runs.
+    fn reinit_async(&self, reason: ReinitReason) {
+        if self.reinit_pending.swap(true, Ordering::SeqCst) {
+            // A reinit task is already pending, nothing more to do.
+            cubeb_log!(
+                "({:p}) re-init stream task already pending, cancelling request",
+                self.stm_ptr
+            );
+            return;
+        }
+        self.stats.add_reinit(reason);
+
+        // The task can't borrow the stream, so it gets its address. The stream outlives the task
+        // since it's destroyed by a final task on the same queue.
+        let stm_address = self.stm_ptr as usize;
+        // Use a new thread, through the queue, to avoid deadlock when calling
+        // Get/SetProperties method from inside notify callback
+        self.queue.run_async(move || {
+            let stm = unsafe { &mut *(stm_address as *mut AudioUnitStream) };
+            let stm_ptr = stm as *const AudioUnitStream;
+            if stm.destroy_pending.load(Ordering::SeqCst) {
+                cubeb_log!(
+                    "({:p}) stream pending destroy, cancelling reinit task",
+                    stm_ptr
+                );
+                return;
+            }
+
+            if stm.reinit(reason).is_err() {
+                stm.core_stream_data.close();
+                stm.shared.notify_state_changed(State::Error);
+                cubeb_log!(
+                    "({:p}) Could not reopen the stream after switching.",
+                    stm_ptr
+                );
+            }
+            stm.shared.switching_device.store(false, Ordering::SeqCst);
+            stm.shared.reinit_pending.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+unsafe impl Send for SharedStreamData {}
+unsafe impl Sync for SharedStreamData {}
+
+// The inner stream. Its address is the data of its AudioUnit callbacks and its listeners, so it's
+// boxed before it's set up.
+#[derive(Debug)]
+struct CoreStreamData {
+    shared: Arc<SharedStreamData>,
+    aggregate_device: AggregateDevice,
+    resampler: Resampler,
+    // The adapter between the resampler and the planar data callback, called by the resampler.
+    planar: Option<Box<PlanarCallback>>,
+    // The callbacks pass the buffers of the AudioUnit to the planar data callback, without the
+    // resampler, since the stream of one direction has nothing to convert.
+    direct_planar: bool,
+    input: Option<InputHalf>,
+    output: Option<OutputHalf>,
+    // Listeners indicating what system events are monitored.
+    default_input_listener: Option<device_property_listener>,
+    default_output_listener: Option<device_property_listener>,
+    input_alive_listener: Option<device_property_listener>,
+    output_alive_listener: Option<device_property_listener>,
+    input_source_listener: Option<device_property_listener>,
+    output_source_listener: Option<device_property_listener>,
+    devices_listener: Option<device_property_listener>,
+    // The HAL and the AudioUnits the stream runs on.
+    platform: Platform,
+    // How many frames got read from the input since the stream started (includes
+    // padded silence)
+    frames_read: AtomicUsize,
+    // How many frames got written to the output device since the stream started
+    frames_written: AtomicUsize,
+    // The device and AudioUnit latency of the output and the input.
+    current_latency_frames: AtomicU32,
+    current_input_latency_frames: AtomicU32,
+}
+
+impl Default for CoreStreamData {
+    fn default() -> Self {
+        Self {
+            shared: Arc::new(SharedStreamData::new(ptr::null_mut(), None, None, 0)),
+            aggregate_device: AggregateDevice::default(),
+            resampler: Resampler::default(),
+            planar: None,
+            direct_planar: false,
+            input: None,
+            output: None,
+            default_input_listener: None,
+            default_output_listener: None,
+            input_alive_listener: None,
+            output_alive_listener: None,
+            input_source_listener: None,
+            output_source_listener: None,
+            devices_listener: None,
+            platform: Platform::default(),
+            frames_read: AtomicUsize::new(0),
+            frames_written: AtomicUsize::new(0),
+            current_latency_frames: AtomicU32::new(0),
+            current_input_latency_frames: AtomicU32::new(0),
+        }
+    }
+}
+
+impl CoreStreamData {
+    fn new(
+        shared: Arc<SharedStreamData>,
+        platform: Platform,
+        input_stream_settings: Option<(StreamParams, device_info)>,
+        output_stream_settings: Option<(StreamParams, device_info)>,
+    ) -> Self {
+        Self {
+            shared,
+            aggregate_device: AggregateDevice::default(),
+            resampler: Resampler::default(),
+            planar: None,
+            direct_planar: false,
+            input: input_stream_settings
+                .map(|(params, device)| InputHalf::new(platform.clone(), params, device)),
+            output: output_stream_settings
+                .map(|(params, device)| OutputHalf::new(platform.clone(), params, device)),
+            default_input_listener: None,
+            default_output_listener: None,
+            input_alive_listener: None,
+            output_alive_listener: None,
+            input_source_listener: None,
+            output_source_listener: None,
+            devices_listener: None,
+            platform,
+            frames_read: AtomicUsize::new(0),
+            frames_written: AtomicUsize::new(0),
+            current_latency_frames: AtomicU32::new(0),
+            current_input_latency_frames: AtomicU32::new(0),
+        }
+    }
+
+    // Exchange the data with the data callback in `format` instead of the format of the stream
+    // parameters.
+    fn set_format(&mut self, format: StreamFormat) {
+        if let Some(input) = self.input.as_mut() {
+            input.format = format;
+        }
+        if let Some(output) = self.output.as_mut() {
+            output.format = format;
+        }
+    }
+
+    // Capture the device channels of `map` in the channels of the input, instead of the first
+    // channels of the device.
+    fn set_input_channel_map(&mut self, map: ChannelMap) {
+        if let Some(input) = self.input.as_mut() {
+            input.side.channel_map = Some(map);
+        }
+    }
+
+    // Play the channels of the output on the device channels of `map`, instead of mixing them
+    // into the channels of the device.
+    fn set_output_channel_map(&mut self, map: ChannelMap) {
+        if let Some(output) = self.output.as_mut() {
+            output.side.channel_map = Some(map);
+        }
+    }
+
+    fn start_audiounits(&self) -> Result<()> {
+        // Only allowed to be called after the stream is initialized
+        // and before the stream is destroyed.
+        debug_assert!(self.has_input() || self.has_output());
+
+        if let Some(input) = self.input.as_ref() {
+            input.start()?;
+        }
+        if let Some(output) = self.output.as_ref() {
+            output.start()?;
+        }
+        Ok(())
+    }
+
+    fn stop_audiounits(&self) {
+        if let Some(input) = self.input.as_ref() {
+            input.stop();
+        }
+        if let Some(output) = self.output.as_ref() {
+            output.stop();
+        }
+    }
+
+    fn has_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    fn has_output(&self) -> bool {
+        self.output.is_some()
+    }
+
+    fn is_loopback(&self) -> bool {
+        self.input
+            .as_ref()
+            .map_or(false, |input| input.is_loopback())
+    }
+
+    // Whether all the sides running on the device follow the default device.
+    fn follows_default_device(&self, id: AudioObjectID) -> bool {
+        let devices: Vec<&device_info> = self
+            .input
+            .as_ref()
+            .map(|input| &input.device)
+            .into_iter()
+            .chain(self.output.as_ref().map(|output| &output.device))
+            .filter(|device| device.id == id)
+            .collect();
+        !devices.is_empty()
+            && devices
+                .iter()
+                .all(|device| device.flags.contains(device_flags::DEV_SELECTED_DEFAULT))
+    }
+
+    // The sides opened by UID run on the default device while their device is gone.
+    fn waiting_devices(&self) -> Vec<&device_info> {
+        self.input
+            .as_ref()
+            .map(|input| &input.device)
+            .into_iter()
+            .chain(self.output.as_ref().map(|output| &output.device))
+            .filter(|device| {
+                device.uid.is_some() && device.flags.contains(device_flags::DEV_SELECTED_DEFAULT)
+            })
+            .collect()
+    }
+
+    // Whether a device the stream is waiting for is available again.
+    fn selected_device_is_back(&self) -> bool {
+        self.waiting_devices().iter().any(|device| {
+            find_device_by_uid(self.platform.hardware(), device.uid.as_ref().unwrap()).is_some()
+        })
+    }
 
     fn should_use_aggregate_device(&self) -> bool {
         // Only using aggregate device when the input is a mic-only device and the output is a
         // speaker-only device. Otherwise, the mic on the output device may become the main
         // microphone of the aggregate device for this duplex stream.
-        self.has_input()
-            && self.has_output()
-            && self.input_device.id != kAudioObjectUnknown
-            && self.input_device.flags.contains(device_flags::DEV_INPUT)
-            && self.output_device.id != kAudioObjectUnknown
-            && self.output_device.flags.contains(device_flags::DEV_OUTPUT)
-            && self.input_device.id != self.output_device.id
-            && !is_device_a_type_of(self.input_device.id, DeviceType::OUTPUT)
-            && !is_device_a_type_of(self.output_device.id, DeviceType::INPUT)
-    }
-
-    #[allow(clippy::cognitive_complexity)] // TODO: Refactoring.
+        let (input_device, output_device) = match (self.input.as_ref(), self.output.as_ref()) {
+            (Some(input), Some(output)) => (&input.device, &output.device),
+            _ => return false,
+        };
+        // The loopback input has its own aggregate device.
+        if self.is_loopback() {
+            return false;
+        }
+        input_device.id != kAudioObjectUnknown
+            && input_device.flags.contains(device_flags::DEV_INPUT)
+            && output_device.id != kAudioObjectUnknown
+            && output_device.flags.contains(device_flags::DEV_OUTPUT)
+            && input_device.id != output_device.id
+            && !is_device_a_type_of(
+                self.platform.hardware(),
+                input_device.id,
+                DeviceType::OUTPUT,
+            )
+            && !is_device_a_type_of(
+                self.platform.hardware(),
+                output_device.id,
+                DeviceType::INPUT,
+            )
+    }
+
+    fn add_device_listener(&self, listener: &device_property_listener) -> OSStatus {
+        audio_object_add_property_listener(
+            self.platform.hardware(),
+            listener.device,
+            &listener.property,
+            listener.listener,
+            self as *const Self as *mut c_void,
+        )
+    }
+
+    fn remove_device_listener(&self, listener: &device_property_listener) -> OSStatus {
+        audio_object_remove_property_listener(
+            self.platform.hardware(),
+            listener.device,
+            &listener.property,
+            listener.listener,
+            self as *const Self as *mut c_void,
+        )
+    }
+
     fn setup(&mut self) -> Result<()> {
-        if self
-            .input_stream_params
-            .prefs()
-            .contains(StreamPrefs::LOOPBACK)
-            || self
-                .output_stream_params
-                .prefs()
-                .contains(StreamPrefs::LOOPBACK)
-        {
-            cubeb_log!("({:p}) Loopback not supported for audiounit.", self.stm_ptr);
+        if self.output.as_ref().map_or(false, |output| {
+            output.stream_params.prefs().contains(StreamPrefs::LOOPBACK)
+        }) {
+            cubeb_log!(
+                "({:p}) Loopback is only supported for the input.",
+                self.shared.stm_ptr
+            );
             return Err(Error::not_supported());
         }
 
-        let mut in_dev_info = self.input_device.clone();
-        let mut out_dev_info = self.output_device.clone();
+        let mut in_dev_info = self.input.as_ref().map(|i| i.device.clone());
+        let mut out_dev_info = self.output.as_ref().map(|o| o.device.clone());
+
+        if self.is_loopback() {
+            // Capture the output device by the input of an aggregate device tapping it.
+            let in_dev = in_dev_info.as_mut().unwrap();
+            let device =
+                AggregateDevice::new_loopback(&self.platform, in_dev.id).map_err(|status| {
+                    let e = BackendError::new(Operation::CreateAggregateDevice, status)
+                        .with_device(in_dev.id);
+                    cubeb_log!("({:p}) Loopback setup failed. {}", self.shared.stm_ptr, e);
+                    e
+                })?;
+            in_dev.id = device.get_device_id();
+            in_dev.flags = device_flags::DEV_INPUT;
+            self.aggregate_device = device;
+            cubeb_log!(
+                "({:p}) Use aggregate device {} for loopback.",
+                self.shared.stm_ptr,
+                self.aggregate_device.get_device_id()
+            );
+        }
 
         if self.should_use_aggregate_device() {
-            match AggregateDevice::new(in_dev_info.id, out_dev_info.id) {
+            let in_dev = in_dev_info.as_mut().unwrap();
+            let out_dev = out_dev_info.as_mut().unwrap();
+            match AggregateDevice::new(&self.platform, in_dev.id, out_dev.id) {
                 Ok(device) => {
-                    in_dev_info.id = device.get_device_id();
-                    out_dev_info.id = device.get_device_id();
-                    in_dev_info.flags = device_flags::DEV_INPUT;
-                    out_dev_info.flags = device_flags::DEV_OUTPUT;
+                    in_dev.id = device.get_device_id();
+                    out_dev.id = device.get_device_id();
+                    in_dev.flags = device_flags::DEV_INPUT;
+                    out_dev.flags = device_flags::DEV_OUTPUT;
                     self.aggregate_device = device;
                     cubeb_log!(
                         "({:p}) Use aggregate device {} for input and output.",
-                        self.stm_ptr,
+                        self.shared.stm_ptr,
                         self.aggregate_device.get_device_id()
                     );
                 }
                 Err(status) => {
                     cubeb_log!(
-                        "({:p}) Create aggregate devices failed. Error: {}.\
-                         Use assigned devices directly instead.",
-                        self.stm_ptr,
-                        status
+                        "({:p}) {}. Use assigned devices directly instead.",
+                        self.shared.stm_ptr,
+                        BackendError::new(Operation::CreateAggregateDevice, status)
                     );
                 }
             }
         }
 
-        assert!(!self.stm_ptr.is_null());
-        let stream = unsafe { &(*self.stm_ptr) };
+        let stream = self.shared.clone();
+        let user_ptr = self as *mut Self as *mut c_void;
 
         // Configure I/O stream
-        if self.has_input() {
-            cubeb_log!(
-                "({:p}) Initialize input by device info: {:?}",
-                self.stm_ptr,
-                in_dev_info
-            );
-
-            self.input_unit = create_audiounit(&in_dev_info).map_err(|e| {
-                cubeb_log!("({:p}) AudioUnit creation for input failed.", self.stm_ptr);
-                e
-            })?;
-
-            cubeb_log!(
-                "({:p}) Opening input side: rate {}, channels {}, format {:?}, layout {:?}, prefs {:?}, latency in frames {}.",
-                self.stm_ptr,
-                self.input_stream_params.rate(),
-                self.input_stream_params.channels(),
-                self.input_stream_params.format(),
-                self.input_stream_params.layout(),
-                self.input_stream_params.prefs(),
-                stream.latency_frames
-            );
-
-            // Get input device sample rate.
-            let mut input_hw_desc = AudioStreamBasicDescription::default();
-            let mut size = mem::size_of::<AudioStreamBasicDescription>();
-            let r = audio_unit_get_property(
-                self.input_unit,
-                kAudioUnitProperty_StreamFormat,
-                kAudioUnitScope_Input,
-                AU_IN_BUS,
-                &mut input_hw_desc,
-                &mut size,
-            );
-            if r != NO_ERR {
-                cubeb_log!(
-                    "AudioUnitGetProperty/input/kAudioUnitProperty_StreamFormat rv={}",
-                    r
-                );
-                return Err(Error::error());
-            }
-            cubeb_log!(
-                "({:p}) Input hardware description: {:?}",
-                self.stm_ptr,
-                input_hw_desc
-            );
-            self.input_hw_rate = input_hw_desc.mSampleRate;
-
-            // Set format description according to the input params.
-            self.input_desc =
-                create_stream_description(&self.input_stream_params).map_err(|e| {
-                    cubeb_log!(
-                        "({:p}) Setting format description for input failed.",
-                        self.stm_ptr
-                    );
+        if let Some(input) = self.input.as_mut() {
+            let device = in_dev_info.as_ref().unwrap();
+            input.planar = stream.planar;
+            input
+                .setup(device, stream.latency_frames, user_ptr)
+                .map_err(|e| {
+                    let e = e.with_device(device.id);
+                    cubeb_log!("({:p}) Input setup failed. {}", stream.stm_ptr, e);
                     e
                 })?;
-
-            // Use latency to set buffer size
-            assert_ne!(stream.latency_frames, 0);
-            if let Err(r) =
-                set_buffer_size_sync(self.input_unit, DeviceType::INPUT, stream.latency_frames)
-            {
-                cubeb_log!("({:p}) Error in change input buffer size.", self.stm_ptr);
-                return Err(r);
-            }
-
-            let mut src_desc = self.input_desc;
-            // Input AudioUnit must be configured with device's sample rate.
-            // we will resample inside input callback.
-            src_desc.mSampleRate = self.input_hw_rate;
-            let r = audio_unit_set_property(
-                self.input_unit,
-                kAudioUnitProperty_StreamFormat,
-                kAudioUnitScope_Output,
-                AU_IN_BUS,
-                &src_desc,
-                mem::size_of::<AudioStreamBasicDescription>(),
-            );
-            if r != NO_ERR {
-                cubeb_log!(
-                    "AudioUnitSetProperty/input/kAudioUnitProperty_StreamFormat rv={}",
-                    r
-                );
-                return Err(Error::error());
-            }
-
-            // Frames per buffer in the input callback.
-            let r = audio_unit_set_property(
-                self.input_unit,
-                kAudioUnitProperty_MaximumFramesPerSlice,
-                kAudioUnitScope_Global,
-                AU_IN_BUS,
-                &stream.latency_frames,
-                mem::size_of::<u32>(),
-            );
-            if r != NO_ERR {
-                cubeb_log!(
-                    "AudioUnitSetProperty/input/kAudioUnitProperty_MaximumFramesPerSlice rv={}",
-                    r
-                );
-                return Err(Error::error());
-            }
-
-            self.input_buffer_manager = Some(BufferManager::new(self.input_stream_params.format()));
-
-            let aurcbs_in = AURenderCallbackStruct {
-                inputProc: Some(audiounit_input_callback),
-                inputProcRefCon: self.stm_ptr as *mut c_void,
-            };
-
-            let r = audio_unit_set_property(
-                self.input_unit,
-                kAudioOutputUnitProperty_SetInputCallback,
-                kAudioUnitScope_Global,
-                AU_OUT_BUS,
-                &aurcbs_in,
-                mem::size_of_val(&aurcbs_in),
-            );
-            if r != NO_ERR {
-                cubeb_log!(
-                    "AudioUnitSetProperty/input/kAudioOutputUnitProperty_SetInputCallback rv={}",
-                    r
-                );
-                return Err(Error::error());
-            }
-
-            stream.frames_read.store(0, Ordering::SeqCst);
-
-            cubeb_log!(
-                "({:p}) Input audiounit init with device {} successfully.",
-                self.stm_ptr,
-                in_dev_info.id
-            );
         }
 
-        if self.has_output() {
-            cubeb_log!(
-                "({:p}) Initialize output by device info: {:?}",
-                self.stm_ptr,
-                out_dev_info
-            );
-
-            self.output_unit = create_audiounit(&out_dev_info).map_err(|e| {
-                cubeb_log!("({:p}) AudioUnit creation for output failed.", self.stm_ptr);
-                e
-            })?;
-
-            cubeb_log!(
-                "({:p}) Opening output side: rate {}, channels {}, format {:?}, layout {:?}, prefs {:?}, latency in frames {}.",
-                self.stm_ptr,
-                self.output_stream_params.rate(),
-                self.output_stream_params.channels(),
-                self.output_stream_params.format(),
-                self.output_stream_params.layout(),
-                self.output_stream_params.prefs(),
-                stream.latency_frames
-            );
-
-            self.output_desc =
-                create_stream_description(&self.output_stream_params).map_err(|e| {
-                    cubeb_log!(
-                        "({:p}) Could not initialize the audio stream description.",
-                        self.stm_ptr
-                    );
-                    e
-                })?;
-
-            // Get output device sample rate.
-            let mut output_hw_desc = AudioStreamBasicDescription::default();
-            let mut size = mem::size_of::<AudioStreamBasicDescription>();
-            let r = audio_unit_get_property(
-                self.output_unit,
-                kAudioUnitProperty_StreamFormat,
-                kAudioUnitScope_Output,
-                AU_OUT_BUS,
-                &mut output_hw_desc,
-                &mut size,
-            );
-            if r != NO_ERR {
+        // The input and the output of a duplex stream on different devices, without an aggregate
+        // device, are driven by different clocks.
+        let separate_clocks = match (in_dev_info.as_ref(), out_dev_info.as_ref()) {
+            (Some(in_dev), Some(out_dev)) => in_dev.id != out_dev.id && !self.is_loopback(),
+            _ => false,
+        };
+        let output_rate = self
+            .output
+            .as_ref()
+            .map(|output| f64::from(output.stream_params.rate()));
+        if let Some(input) = self.input.as_mut() {
+            input.side.drift_compensator = if separate_clocks {
                 cubeb_log!(
-                    "AudioUnitGetProperty/output/kAudioUnitProperty_StreamFormat rv={}",
-                    r
+                    "({:p}) Compensate the drift between the input and output clocks.",
+                    stream.stm_ptr
                 );
-                return Err(Error::error());
-            }
-            cubeb_log!(
-                "({:p}) Output hardware description: {:?}",
-                self.stm_ptr,
-                output_hw_desc
-            );
-            self.output_hw_rate = output_hw_desc.mSampleRate;
-            let hw_channels = output_hw_desc.mChannelsPerFrame;
-            if hw_channels == 0 {
-                cubeb_log!(
-                    "({:p}) Output hardware has no output channel! Bail out.",
-                    self.stm_ptr
+                // The output callbacks take `latency_frames` frames at most, which need this
+                // many input frames at the input rate.
+                let max_frames = minimum_resampling_input_frames(
+                    input.hw_rate,
+                    output_rate.unwrap(),
+                    stream.latency_frames as usize,
                 );
-                return Err(Error::device_unavailable());
-            }
-
-            self.device_layout = audiounit_get_current_channel_layout(self.output_unit);
-
-            self.mixer = if hw_channels != self.output_stream_params.channels()
-                || self.device_layout
-                    != mixer::get_channel_order(self.output_stream_params.layout())
-            {
-                cubeb_log!("Incompatible channel layouts detected, setting up remixer");
-                // We will be remixing the data before it reaches the output device.
-                // We need to adjust the number of channels and other
-                // AudioStreamDescription details.
-                self.output_desc.mChannelsPerFrame = hw_channels;
-                self.output_desc.mBytesPerFrame =
-                    (self.output_desc.mBitsPerChannel / 8) * self.output_desc.mChannelsPerFrame;
-                self.output_desc.mBytesPerPacket =
-                    self.output_desc.mBytesPerFrame * self.output_desc.mFramesPerPacket;
-                Some(Mixer::new(
-                    self.output_stream_params.format(),
-                    self.output_stream_params.channels() as usize,
-                    self.output_stream_params.layout(),
-                    hw_channels as usize,
-                    self.device_layout.clone(),
+                Some(DriftCompensator::new(
+                    input.format,
+                    input.desc.mChannelsPerFrame as usize,
+                    input.hw_rate,
+                    max_frames,
                 ))
             } else {
                 None
             };
+        }
 
-            let r = audio_unit_set_property(
-                self.output_unit,
-                kAudioUnitProperty_StreamFormat,
-                kAudioUnitScope_Input,
-                AU_OUT_BUS,
-                &self.output_desc,
-                mem::size_of::<AudioStreamBasicDescription>(),
-            );
-            if r != NO_ERR {
-                cubeb_log!(
-                    "AudioUnitSetProperty/output/kAudioUnitProperty_StreamFormat rv={}",
-                    r
-                );
-                return Err(Error::error());
-            }
-
-            // Use latency to set buffer size
-            assert_ne!(stream.latency_frames, 0);
-            if let Err(r) =
-                set_buffer_size_sync(self.output_unit, DeviceType::OUTPUT, stream.latency_frames)
-            {
-                cubeb_log!("({:p}) Error in change output buffer size.", self.stm_ptr);
-                return Err(r);
-            }
-
-            // Frames per buffer in the input callback.
-            let r = audio_unit_set_property(
-                self.output_unit,
-                kAudioUnitProperty_MaximumFramesPerSlice,
-                kAudioUnitScope_Global,
-                AU_OUT_BUS,
-                &stream.latency_frames,
-                mem::size_of::<u32>(),
-            );
-            if r != NO_ERR {
-                cubeb_log!(
-                    "AudioUnitSetProperty/output/kAudioUnitProperty_MaximumFramesPerSlice rv={}",
-                    r
-                );
-                return Err(Error::error());
-            }
+        if let Some(output) = self.output.as_mut() {
+            let device = out_dev_info.as_ref().unwrap();
+            output.planar = stream.planar;
+            output
+                .setup(device, stream.latency_frames, user_ptr)
+                .map_err(|e| {
+                    let e = e.with_device(device.id);
+                    cubeb_log!("({:p}) Output setup failed. {}", stream.stm_ptr, e);
+                    e
+                })?;
+        }
 
-            let aurcbs_out = AURenderCallbackStruct {
-                inputProc: Some(audiounit_output_callback),
-                inputProcRefCon: self.stm_ptr as *mut c_void,
-            };
-            let r = audio_unit_set_property(
-                self.output_unit,
-                kAudioUnitProperty_SetRenderCallback,
-                kAudioUnitScope_Global,
-                AU_OUT_BUS,
-                &aurcbs_out,
-                mem::size_of_val(&aurcbs_out),
+        if let Some(input) = self.input.as_mut() {
+            let output_rate = self
+                .output
+                .as_ref()
+                .map_or(input.hw_rate, |output| output.hw_rate);
+            let capacity = input_buffer_capacity(
+                input.desc.mChannelsPerFrame,
+                input.hw_rate,
+                output_rate,
+                stream.latency_frames,
+                stream.input_buffer_margin,
             );
-            if r != NO_ERR {
-                cubeb_log!(
-                    "AudioUnitSetProperty/output/kAudioUnitProperty_SetRenderCallback rv={}",
-                    r
-                );
-                return Err(Error::error());
-            }
-
-            stream.frames_written.store(0, Ordering::SeqCst);
-
+            input.side.buffer_manager = InputBuffer::new(input.format, capacity);
             cubeb_log!(
-                "({:p}) Output audiounit init with device {} successfully.",
-                self.stm_ptr,
-                out_dev_info.id
+                "({:p}) Input buffer of {} samples.",
+                stream.stm_ptr,
+                input.side.buffer_manager.capacity()
             );
         }
 
@@ -2762,78 +4083,158 @@ impl<'ctx> CoreStreamData<'ctx> {
         // reliable only in the capture device sample rate.
         // Resampler will convert it to the user sample rate
         // and deliver it to the callback.
-        let target_sample_rate = if self.has_input() {
-            self.input_stream_params.rate()
-        } else {
-            assert!(self.has_output());
-            self.output_stream_params.rate()
+        let target_sample_rate = match (self.input.as_ref(), self.output.as_ref()) {
+            (Some(input), _) => input.stream_params.rate(),
+            (None, Some(output)) => output.stream_params.rate(),
+            (None, None) => panic!("The stream has neither input nor output"),
         };
 
-        let resampler_input_params = if self.has_input() {
-            let mut params = unsafe { *(self.input_stream_params.as_ptr()) };
-            params.rate = self.input_hw_rate as u32;
-            Some(params)
-        } else {
-            None
+        let resampler_input_params = self.input.as_ref().map(|input| {
+            let mut params = unsafe { *(input.stream_params.as_ptr()) };
+            params.rate = input.hw_rate as u32;
+            params
+        });
+        let resampler_output_params = self
+            .output
+            .as_ref()
+            .map(|output| unsafe { *(output.stream_params.as_ptr()) });
+
+        let format = match (self.input.as_ref(), self.output.as_ref()) {
+            (Some(input), _) => input.format,
+            (None, Some(output)) => output.format,
+            (None, None) => panic!("The stream has neither input nor output"),
         };
-        let resampler_output_params = if self.has_output() {
-            let params = unsafe { *(self.output_stream_params.as_ptr()) };
-            Some(params)
+        // The buffers of the AudioUnit have the channels of the stream of one direction, at its
+        // rate, unless they're mixed or mapped.
+        self.direct_planar = stream.planar
+            && match (self.input.as_ref(), self.output.as_ref()) {
+                (Some(input), None) => {
+                    input.side.mixer.is_none()
+                        && input.side.rendered_map.is_empty()
+                        && input.side.rendered_buffers == input.stream_params.channels() as usize
+                        && approx_eq!(f64, input.hw_rate, f64::from(input.stream_params.rate()))
+                }
+                (None, Some(output)) => output.side.mixer.is_none(),
+                _ => false,
+            };
+        // The resampler calls the planar data callback through the adapter, with the frames of
+        // about a callback of the AudioUnits, or more if the input is resampled to a higher rate.
+        let (data_callback, callback_user_ptr): (ffi::cubeb_data_callback, *mut c_void) =
+            if stream.planar {
+                let latency_frames = f64::from(stream.latency_frames);
+                let callback_frames = self.input.as_ref().map_or(latency_frames, |input| {
+                    latency_frames * f64::from(input.stream_params.rate()) / input.hw_rate
+                });
+                let mut planar = Box::new(PlanarCallback::new(
+                    stream.data_callback,
+                    stream.user_ptr,
+                    format.sample_size(),
+                    self.input
+                        .as_ref()
+                        .map_or(0, |input| input.stream_params.channels() as usize),
+                    self.output
+                        .as_ref()
+                        .map_or(0, |output| output.stream_params.channels() as usize),
+                    callback_frames.max(latency_frames).ceil() as usize,
+                ));
+                let planar_ptr = planar.as_mut() as *mut PlanarCallback as *mut c_void;
+                self.planar = Some(planar);
+                (Some(planar_data_callback), planar_ptr)
+            } else {
+                (stream.data_callback, stream.user_ptr)
+            };
+
+        self.resampler = if format.to_cubeb_format().is_some() {
+            Resampler::new(
+                self.shared.stm_ptr as *mut ffi::cubeb_stream,
+                resampler_input_params,
+                resampler_output_params,
+                target_sample_rate,
+                data_callback,
+                callback_user_ptr,
+            )
         } else {
-            None
+            // The cubeb resampler can't convert the rate of this format, so the input must be
+            // captured at the stream rate. The output AudioUnit converts the output rate.
+            if let Some(input) = self.input.as_ref() {
+                if !approx_eq!(f64, input.hw_rate, f64::from(input.stream_params.rate())) {
+                    cubeb_log!(
+                        "({:p}) The input rate {} of format {:?} must be the device rate {}.",
+                        self.shared.stm_ptr,
+                        input.stream_params.rate(),
+                        format,
+                        input.hw_rate
+                    );
+                    return Err(Error::invalid_format());
+                }
+            }
+            Resampler::passthrough(
+                self.shared.stm_ptr as *mut ffi::cubeb_stream,
+                self.input
+                    .as_ref()
+                    .map_or(0, |input| input.desc.mBytesPerFrame as usize),
+                data_callback,
+                callback_user_ptr,
+            )
         };
 
-        self.resampler = Resampler::new(
-            self.stm_ptr as *mut ffi::cubeb_stream,
-            resampler_input_params,
-            resampler_output_params,
-            target_sample_rate,
-            stream.data_callback,
-            stream.user_ptr,
-        );
+        if let Some(input) = self.input.as_ref() {
+            BackendError::check(
+                Operation::InitializeAudioUnit,
+                audio_unit_initialize(self.platform.units(), input.unit),
+            )
+            .map_err(|e| {
+                let e = e.with_device(input.device.id);
+                cubeb_log!("({:p}) Input setup failed. {}", self.shared.stm_ptr, e);
+                e
+            })?;
 
-        if !self.input_unit.is_null() {
-            let r = audio_unit_initialize(self.input_unit);
-            if r != NO_ERR {
-                cubeb_log!("AudioUnitInitialize/input rv={}", r);
-                return Err(Error::error());
-            }
+            let device_latency = get_presentation_latency(
+                self.platform.hardware(),
+                input.device.id,
+                input.device_type(),
+            );
+            let unit_latency =
+                get_audiounit_latency(self.platform.units(), input.unit, input.desc.mSampleRate);
+            self.current_input_latency_frames
+                .store(device_latency + unit_latency, Ordering::SeqCst);
+            cubeb_log!(
+                "({:p}) Input latency: device {} frames, audiounit {} frames.",
+                self.shared.stm_ptr,
+                device_latency,
+                unit_latency
+            );
         }
 
-        if !self.output_unit.is_null() {
-            let r = audio_unit_initialize(self.output_unit);
-            if r != NO_ERR {
-                cubeb_log!("AudioUnitInitialize/output rv={}", r);
-                return Err(Error::error());
-            }
+        if let Some(output) = self.output.as_ref() {
+            BackendError::check(
+                Operation::InitializeAudioUnit,
+                audio_unit_initialize(self.platform.units(), output.unit),
+            )
+            .map_err(|e| {
+                let e = e.with_device(output.device.id);
+                cubeb_log!("({:p}) Output setup failed. {}", self.shared.stm_ptr, e);
+                e
+            })?;
 
-            stream.current_latency_frames.store(
-                get_presentation_latency(self.output_device.id, DeviceType::OUTPUT),
+            self.current_latency_frames.store(
+                get_presentation_latency(
+                    self.platform.hardware(),
+                    output.device.id,
+                    DeviceType::OUTPUT,
+                ) + get_audiounit_latency(
+                    self.platform.units(),
+                    output.unit,
+                    output.desc.mSampleRate,
+                ),
                 Ordering::SeqCst,
             );
-
-            let mut unit_s: f64 = 0.0;
-            let mut size = mem::size_of_val(&unit_s);
-            if audio_unit_get_property(
-                self.output_unit,
-                kAudioUnitProperty_Latency,
-                kAudioUnitScope_Global,
-                0,
-                &mut unit_s,
-                &mut size,
-            ) == NO_ERR
-            {
-                stream.current_latency_frames.fetch_add(
-                    (unit_s * self.output_desc.mSampleRate) as u32,
-                    Ordering::SeqCst,
-                );
-            }
         }
 
         if let Err(r) = self.install_system_changed_callback() {
             cubeb_log!(
                 "({:p}) Could not install the device change callback.",
-                self.stm_ptr
+                self.shared.stm_ptr
             );
             return Err(r);
         }
@@ -2841,7 +4242,7 @@ impl<'ctx> CoreStreamData<'ctx> {
         if let Err(r) = self.install_device_changed_callback() {
             cubeb_log!(
                 "({:p}) Could not install all device change callback.",
-                self.stm_ptr
+                self.shared.stm_ptr
             );
             return Err(r);
         }
@@ -2850,92 +4251,137 @@ impl<'ctx> CoreStreamData<'ctx> {
     }
 
     fn close(&mut self) {
-        if !self.input_unit.is_null() {
-            audio_unit_uninitialize(self.input_unit);
-            dispose_audio_unit(self.input_unit);
-            self.input_unit = ptr::null_mut();
+        if let Some(input) = self.input.as_mut() {
+            input.close();
+            input.side.mixer = None;
         }
 
-        if !self.output_unit.is_null() {
-            audio_unit_uninitialize(self.output_unit);
-            dispose_audio_unit(self.output_unit);
-            self.output_unit = ptr::null_mut();
+        if let Some(output) = self.output.as_mut() {
+            output.close();
+            output.side.mixer = None;
         }
 
         self.resampler.destroy();
-        self.mixer = None;
+        self.planar = None;
+        self.direct_planar = false;
         self.aggregate_device = AggregateDevice::default();
 
         if self.uninstall_system_changed_callback().is_err() {
             cubeb_log!(
                 "({:p}) Could not uninstall the system changed callback",
-                self.stm_ptr
+                self.shared.stm_ptr
             );
         }
 
         if self.uninstall_device_changed_callback().is_err() {
             cubeb_log!(
                 "({:p}) Could not uninstall all device change listeners",
-                self.stm_ptr
+                self.shared.stm_ptr
             );
         }
     }
 
     fn install_device_changed_callback(&mut self) -> Result<()> {
-        assert!(!self.stm_ptr.is_null());
-        let stm = unsafe { &(*self.stm_ptr) };
-
-        if !self.output_unit.is_null() {
+        if let Some(output_device) = self.output.as_ref().map(|output| output.device.id) {
             // This event will notify us when the data source on the same device changes,
             // for example when the user plugs in a normal (non-usb) headset in the
             // headphone jack.
-            assert_ne!(self.output_device.id, kAudioObjectUnknown);
-            assert_ne!(self.output_device.id, kAudioObjectSystemObject);
+            assert_ne!(output_device, kAudioObjectUnknown);
+            assert_ne!(output_device, kAudioObjectSystemObject);
 
             self.output_source_listener = Some(device_property_listener::new(
-                self.output_device.id,
+                output_device,
                 get_property_address(Property::DeviceSource, DeviceType::OUTPUT),
                 audiounit_property_listener_callback,
             ));
-            let rv = stm.add_device_listener(self.output_source_listener.as_ref().unwrap());
+            let rv = self.add_device_listener(self.output_source_listener.as_ref().unwrap());
             if rv != NO_ERR {
                 self.output_source_listener = None;
-                cubeb_log!("AudioObjectAddPropertyListener/output/kAudioDevicePropertyDataSource rv={}, device id={}", rv, self.output_device.id);
-                return Err(Error::error());
+                let e =
+                    BackendError::new(Operation::InstallListener, rv).with_device(output_device);
+                cubeb_log!(
+                    "({:p}) {} for kAudioDevicePropertyDataSource.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
+            }
+
+            // Event to notify when the output is going away. The input registers the same
+            // listener below if it runs on the same device.
+            if self
+                .input
+                .as_ref()
+                .map_or(true, |input| input.device.id != output_device)
+            {
+                self.output_alive_listener = Some(device_property_listener::new(
+                    output_device,
+                    get_property_address(
+                        Property::DeviceIsAlive,
+                        DeviceType::INPUT | DeviceType::OUTPUT,
+                    ),
+                    audiounit_property_listener_callback,
+                ));
+                let rv = self.add_device_listener(self.output_alive_listener.as_ref().unwrap());
+                if rv != NO_ERR {
+                    self.output_alive_listener = None;
+                    let e = BackendError::new(Operation::InstallListener, rv)
+                        .with_device(output_device);
+                    cubeb_log!(
+                        "({:p}) {} for kAudioDevicePropertyDeviceIsAlive.",
+                        self.shared.stm_ptr,
+                        e
+                    );
+                    return Err(e.into());
+                }
             }
         }
 
-        if !self.input_unit.is_null() {
+        if let Some((input_device, source_type)) = self
+            .input
+            .as_ref()
+            .map(|input| (input.device.id, input.device_type()))
+        {
             // This event will notify us when the data source on the input device changes.
-            assert_ne!(self.input_device.id, kAudioObjectUnknown);
-            assert_ne!(self.input_device.id, kAudioObjectSystemObject);
+            assert_ne!(input_device, kAudioObjectUnknown);
+            assert_ne!(input_device, kAudioObjectSystemObject);
 
             self.input_source_listener = Some(device_property_listener::new(
-                self.input_device.id,
-                get_property_address(Property::DeviceSource, DeviceType::INPUT),
+                input_device,
+                get_property_address(Property::DeviceSource, source_type),
                 audiounit_property_listener_callback,
             ));
-            let rv = stm.add_device_listener(self.input_source_listener.as_ref().unwrap());
+            let rv = self.add_device_listener(self.input_source_listener.as_ref().unwrap());
             if rv != NO_ERR {
                 self.input_source_listener = None;
-                cubeb_log!("AudioObjectAddPropertyListener/input/kAudioDevicePropertyDataSource rv={}, device id={}", rv, self.input_device.id);
-                return Err(Error::error());
+                let e = BackendError::new(Operation::InstallListener, rv).with_device(input_device);
+                cubeb_log!(
+                    "({:p}) {} for kAudioDevicePropertyDataSource.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
             }
 
             // Event to notify when the input is going away.
             self.input_alive_listener = Some(device_property_listener::new(
-                self.input_device.id,
+                input_device,
                 get_property_address(
                     Property::DeviceIsAlive,
                     DeviceType::INPUT | DeviceType::OUTPUT,
                 ),
                 audiounit_property_listener_callback,
             ));
-            let rv = stm.add_device_listener(self.input_alive_listener.as_ref().unwrap());
+            let rv = self.add_device_listener(self.input_alive_listener.as_ref().unwrap());
             if rv != NO_ERR {
                 self.input_alive_listener = None;
-                cubeb_log!("AudioObjectAddPropertyListener/input/kAudioDevicePropertyDeviceIsAlive rv={}, device id ={}", rv, self.input_device.id);
-                return Err(Error::error());
+                let e = BackendError::new(Operation::InstallListener, rv).with_device(input_device);
+                cubeb_log!(
+                    "({:p}) {} for kAudioDevicePropertyDeviceIsAlive.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
             }
         }
 
@@ -2943,10 +4389,20 @@ impl<'ctx> CoreStreamData<'ctx> {
     }
 
     fn install_system_changed_callback(&mut self) -> Result<()> {
-        assert!(!self.stm_ptr.is_null());
-        let stm = unsafe { &(*self.stm_ptr) };
-
-        if !self.output_unit.is_null() {
+        // The sides running on a device selected by the user don't follow the default device.
+        let follows_default =
+            |device: &device_info| device.flags.contains(device_flags::DEV_SELECTED_DEFAULT);
+        let input_follows_default = self
+            .input
+            .as_ref()
+            .map_or(false, |input| follows_default(&input.device));
+        let output_follows_default = self
+            .output
+            .as_ref()
+            .map_or(false, |output| follows_default(&output.device));
+
+        // The loopback input follows the default output device too.
+        if output_follows_default || (self.is_loopback() && input_follows_default) {
             // This event will notify us when the default audio device changes,
             // for example when the user plugs in a USB headset and the system chooses it
             // automatically as the default, or when another device is chosen in the
@@ -2959,15 +4415,45 @@ impl<'ctx> CoreStreamData<'ctx> {
                 ),
                 audiounit_property_listener_callback,
             ));
-            let r = stm.add_device_listener(self.default_output_listener.as_ref().unwrap());
+            let r = self.add_device_listener(self.default_output_listener.as_ref().unwrap());
             if r != NO_ERR {
                 self.default_output_listener = None;
-                cubeb_log!("AudioObjectAddPropertyListener/output/kAudioHardwarePropertyDefaultOutputDevice rv={}", r);
-                return Err(Error::error());
+                let e = BackendError::new(Operation::InstallListener, r)
+                    .with_device(kAudioObjectSystemObject);
+                cubeb_log!(
+                    "({:p}) {} for kAudioHardwarePropertyDefaultOutputDevice.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
+            }
+        }
+
+        if !self.waiting_devices().is_empty() {
+            // This event will notify us when the devices opened by UID are plugged back in.
+            self.devices_listener = Some(device_property_listener::new(
+                kAudioObjectSystemObject,
+                get_property_address(
+                    Property::HardwareDevices,
+                    DeviceType::INPUT | DeviceType::OUTPUT,
+                ),
+                audiounit_property_listener_callback,
+            ));
+            let r = self.add_device_listener(self.devices_listener.as_ref().unwrap());
+            if r != NO_ERR {
+                self.devices_listener = None;
+                let e = BackendError::new(Operation::InstallListener, r)
+                    .with_device(kAudioObjectSystemObject);
+                cubeb_log!(
+                    "({:p}) {} for kAudioHardwarePropertyDevices.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
             }
         }
 
-        if !self.input_unit.is_null() {
+        if input_follows_default && !self.is_loopback() {
             // This event will notify us when the default input device changes.
             self.default_input_listener = Some(device_property_listener::new(
                 kAudioObjectSystemObject,
@@ -2977,11 +4463,17 @@ impl<'ctx> CoreStreamData<'ctx> {
                 ),
                 audiounit_property_listener_callback,
             ));
-            let r = stm.add_device_listener(self.default_input_listener.as_ref().unwrap());
+            let r = self.add_device_listener(self.default_input_listener.as_ref().unwrap());
             if r != NO_ERR {
                 self.default_input_listener = None;
-                cubeb_log!("AudioObjectAddPropertyListener/input/kAudioHardwarePropertyDefaultInputDevice rv={}", r);
-                return Err(Error::error());
+                let e = BackendError::new(Operation::InstallListener, r)
+                    .with_device(kAudioObjectSystemObject);
+                cubeb_log!(
+                    "({:p}) {} for kAudioHardwarePropertyDefaultInputDevice.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
             }
         }
 
@@ -2989,81 +4481,123 @@ impl<'ctx> CoreStreamData<'ctx> {
     }
 
     fn uninstall_device_changed_callback(&mut self) -> Result<()> {
-        if self.stm_ptr.is_null() {
-            assert!(
-                self.output_source_listener.is_none()
-                    && self.input_source_listener.is_none()
-                    && self.input_alive_listener.is_none()
-            );
-            return Ok(());
-        }
-
-        let stm = unsafe { &(*self.stm_ptr) };
-
         // Failing to uninstall listeners is not a fatal error.
         let mut r = Ok(());
 
         if self.output_source_listener.is_some() {
-            let rv = stm.remove_device_listener(self.output_source_listener.as_ref().unwrap());
+            let rv = self.remove_device_listener(self.output_source_listener.as_ref().unwrap());
             if rv != NO_ERR {
-                cubeb_log!("AudioObjectRemovePropertyListener/output/kAudioDevicePropertyDataSource rv={}, device id={}", rv, self.output_device.id);
-                r = Err(Error::error());
+                let e = BackendError::new(Operation::UninstallListener, rv)
+                    .with_device(self.output_source_listener.as_ref().unwrap().device);
+                cubeb_log!(
+                    "({:p}) {} for kAudioDevicePropertyDataSource.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                r = Err(e.into());
             }
             self.output_source_listener = None;
         }
 
         if self.input_source_listener.is_some() {
-            let rv = stm.remove_device_listener(self.input_source_listener.as_ref().unwrap());
+            let rv = self.remove_device_listener(self.input_source_listener.as_ref().unwrap());
             if rv != NO_ERR {
-                cubeb_log!("AudioObjectRemovePropertyListener/input/kAudioDevicePropertyDataSource rv={}, device id={}", rv, self.input_device.id);
-                r = Err(Error::error());
+                let e = BackendError::new(Operation::UninstallListener, rv)
+                    .with_device(self.input_source_listener.as_ref().unwrap().device);
+                cubeb_log!(
+                    "({:p}) {} for kAudioDevicePropertyDataSource.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                r = Err(e.into());
             }
             self.input_source_listener = None;
         }
 
         if self.input_alive_listener.is_some() {
-            let rv = stm.remove_device_listener(self.input_alive_listener.as_ref().unwrap());
+            let rv = self.remove_device_listener(self.input_alive_listener.as_ref().unwrap());
             if rv != NO_ERR {
-                cubeb_log!("AudioObjectRemovePropertyListener/input/kAudioDevicePropertyDeviceIsAlive rv={}, device id={}", rv, self.input_device.id);
-                r = Err(Error::error());
+                let e = BackendError::new(Operation::UninstallListener, rv)
+                    .with_device(self.input_alive_listener.as_ref().unwrap().device);
+                cubeb_log!(
+                    "({:p}) {} for kAudioDevicePropertyDeviceIsAlive.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                r = Err(e.into());
             }
             self.input_alive_listener = None;
         }
 
+        if self.output_alive_listener.is_some() {
+            let rv = self.remove_device_listener(self.output_alive_listener.as_ref().unwrap());
+            if rv != NO_ERR {
+                let e = BackendError::new(Operation::UninstallListener, rv)
+                    .with_device(self.output_alive_listener.as_ref().unwrap().device);
+                cubeb_log!(
+                    "({:p}) {} for kAudioDevicePropertyDeviceIsAlive.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                r = Err(e.into());
+            }
+            self.output_alive_listener = None;
+        }
+
         r
     }
 
     fn uninstall_system_changed_callback(&mut self) -> Result<()> {
-        if self.stm_ptr.is_null() {
-            assert!(
-                self.default_output_listener.is_none() && self.default_input_listener.is_none()
-            );
-            return Ok(());
-        }
-
-        let stm = unsafe { &(*self.stm_ptr) };
-
         if self.default_output_listener.is_some() {
-            let r = stm.remove_device_listener(self.default_output_listener.as_ref().unwrap());
+            let r = self.remove_device_listener(self.default_output_listener.as_ref().unwrap());
             if r != NO_ERR {
-                return Err(Error::error());
+                let e = BackendError::new(Operation::UninstallListener, r)
+                    .with_device(kAudioObjectSystemObject);
+                cubeb_log!(
+                    "({:p}) {} for kAudioHardwarePropertyDefaultOutputDevice.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
             }
             self.default_output_listener = None;
         }
 
         if self.default_input_listener.is_some() {
-            let r = stm.remove_device_listener(self.default_input_listener.as_ref().unwrap());
+            let r = self.remove_device_listener(self.default_input_listener.as_ref().unwrap());
             if r != NO_ERR {
-                return Err(Error::error());
+                let e = BackendError::new(Operation::UninstallListener, r)
+                    .with_device(kAudioObjectSystemObject);
+                cubeb_log!(
+                    "({:p}) {} for kAudioHardwarePropertyDefaultInputDevice.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
             }
             self.default_input_listener = None;
         }
 
+        if self.devices_listener.is_some() {
+            let r = self.remove_device_listener(self.devices_listener.as_ref().unwrap());
+            if r != NO_ERR {
+                let e = BackendError::new(Operation::UninstallListener, r)
+                    .with_device(kAudioObjectSystemObject);
+                cubeb_log!(
+                    "({:p}) {} for kAudioHardwarePropertyDevices.",
+                    self.shared.stm_ptr,
+                    e
+                );
+                return Err(e.into());
+            }
+            self.devices_listener = None;
+        }
+
         Ok(())
     }
 }
 
-impl<'ctx> Drop for CoreStreamData<'ctx> {
+impl Drop for CoreStreamData {
     fn drop(&mut self) {
         self.stop_audiounits();
         self.close();
@@ -3075,36 +4609,21 @@ impl<'ctx> Drop for CoreStreamData<'ctx> {
 // #[repr(C)] is used to prevent any padding from being added in the beginning of the AudioUnitStream.
 #[repr(C)]
 #[derive(Debug)]
-// Allow exposing this private struct in public interfaces when running tests.
-#[cfg_attr(test, allow(private_in_public))]
-struct AudioUnitStream<'ctx> {
+pub struct AudioUnitStream<'ctx> {
     context: &'ctx mut AudioUnitContext,
     user_ptr: *mut c_void,
-    // Task queue for the stream.
-    queue: Queue,
-
-    data_callback: ffi::cubeb_data_callback,
-    state_callback: ffi::cubeb_state_callback,
+    // The state shared with the inner stream.
+    shared: Arc<SharedStreamData>,
     device_changed_callback: Mutex<ffi::cubeb_device_changed_callback>,
-    // Frame counters
-    frames_played: AtomicU64,
-    frames_queued: u64,
-    // How many frames got read from the input since the stream started (includes
-    // padded silence)
-    frames_read: AtomicUsize,
-    // How many frames got written to the output device since the stream started
-    frames_written: AtomicUsize,
-    shutdown: AtomicBool,
-    draining: AtomicBool,
-    reinit_pending: AtomicBool,
+    // The last position reported, which must not go backward after switching devices.
+    prev_position: u64,
+    // The next reinit moves the stream onto the system default devices.
+    reinit_to_default: AtomicBool,
+    // The (input, output) devices picked by the last reinit.
+    reinit_choices: Mutex<(Option<ReinitChoice>, Option<ReinitChoice>)>,
     destroy_pending: AtomicBool,
-    // Latency requested by the user.
-    latency_frames: u32,
-    current_latency_frames: AtomicU32,
-    total_output_latency_frames: AtomicU32,
-    // This is true if a device change callback is currently running.
-    switching_device: AtomicBool,
-    core_stream_data: CoreStreamData<'ctx>,
+    // The inner stream running on the devices. It's replaced by a new one on reinit.
+    core_stream_data: Box<CoreStreamData>,
 }
 
 impl<'ctx> AudioUnitStream<'ctx> {
@@ -3118,202 +4637,462 @@ impl<'ctx> AudioUnitStream<'ctx> {
         AudioUnitStream {
             context,
             user_ptr,
-            queue: Queue::new(DISPATCH_QUEUE_LABEL),
-            data_callback,
-            state_callback,
+            shared: Arc::new(SharedStreamData::new(
+                user_ptr,
+                data_callback,
+                state_callback,
+                latency_frames,
+            )),
             device_changed_callback: Mutex::new(None),
-            frames_played: AtomicU64::new(0),
-            frames_queued: 0,
-            frames_read: AtomicUsize::new(0),
-            frames_written: AtomicUsize::new(0),
-            shutdown: AtomicBool::new(true),
-            draining: AtomicBool::new(false),
-            reinit_pending: AtomicBool::new(false),
+            prev_position: 0,
+            reinit_to_default: AtomicBool::new(false),
+            reinit_choices: Mutex::new((None, None)),
             destroy_pending: AtomicBool::new(false),
-            latency_frames,
-            current_latency_frames: AtomicU32::new(0),
-            total_output_latency_frames: AtomicU32::new(0),
-            switching_device: AtomicBool::new(false),
-            core_stream_data: CoreStreamData::default(),
+            core_stream_data: Box::default(),
         }
     }
 
-    fn add_device_listener(&self, listener: &device_property_listener) -> OSStatus {
-        audio_object_add_property_listener(
-            listener.device,
-            &listener.property,
-            listener.listener,
-            self as *const Self as *mut c_void,
-        )
+    fn notify_device_changed(&self) {
+        let callback = self.device_changed_callback.lock().unwrap();
+        if let Some(device_changed_callback) = *callback {
+            unsafe {
+                device_changed_callback(self.user_ptr);
+            }
+        }
     }
 
-    fn remove_device_listener(&self, listener: &device_property_listener) -> OSStatus {
-        audio_object_remove_property_listener(
-            listener.device,
-            &listener.property,
-            listener.listener,
-            self as *const Self as *mut c_void,
-        )
+    // How the last reinit picked the device of the `devtype` side of the stream. It's set before
+    // the device changed callback is called, so the callback can read it.
+    pub fn reinit_choice(&self, devtype: DeviceType) -> Result<ReinitChoice> {
+        let choices = self.reinit_choices.lock().unwrap();
+        let choice = match devtype {
+            DeviceType::INPUT => choices.0,
+            DeviceType::OUTPUT => choices.1,
+            _ => return Err(Error::invalid_parameter()),
+        };
+        // The stream has no such side, or wasn't reinitialized yet.
+        choice.ok_or_else(Error::error)
     }
 
-    fn notify_state_changed(&self, state: State) {
-        if self.state_callback.is_none() {
-            return;
+    // Scale the captured samples by `gain` in [0.0, 1.0].
+    pub fn set_input_gain(&self, gain: f32) -> Result<()> {
+        if !self.core_stream_data.has_input() {
+            return Err(Error::error());
         }
-        let callback = self.state_callback.unwrap();
-        unsafe {
-            callback(
-                self as *const AudioUnitStream as *mut ffi::cubeb_stream,
-                self.user_ptr,
-                state.into(),
+        if !(0.0..=1.0).contains(&gain) {
+            return Err(Error::invalid_parameter());
+        }
+        self.shared.input_gain.store(gain, atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn input_gain(&self) -> Result<f32> {
+        if !self.core_stream_data.has_input() {
+            return Err(Error::error());
+        }
+        Ok(self.shared.input_gain.load(atomic::Ordering::SeqCst))
+    }
+
+    // Replace the captured samples by silence, whatever the input gain is.
+    pub fn set_input_mute(&self, mute: bool) -> Result<()> {
+        if !self.core_stream_data.has_input() {
+            return Err(Error::error());
+        }
+        self.shared.input_muted.store(mute, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn input_mute(&self) -> Result<bool> {
+        if !self.core_stream_data.has_input() {
+            return Err(Error::error());
+        }
+        Ok(self.shared.input_muted.load(Ordering::SeqCst))
+    }
+
+    // The glitches since the stream is created or the last `reset_glitch_stats`.
+    pub fn glitch_stats(&self) -> GlitchStats {
+        self.shared.glitches.snapshot()
+    }
+
+    // The glitches, the callback timings, the buffering and the reinits of the stream.
+    pub fn stats(&self) -> StreamStats {
+        self.shared.stats.snapshot(self.shared.glitches.snapshot())
+    }
+
+    // Reset the glitch counters, and return the glitches counted until now.
+    pub fn reset_glitch_stats(&self) -> GlitchStats {
+        self.shared.glitches.reset()
+    }
+
+    // The position and the host time of its presentation, sampled in the last render callback.
+    pub fn timestamp(&self) -> Result<StreamTimestamp> {
+        if !self.core_stream_data.has_output() {
+            return Err(Error::error());
+        }
+        Ok(self.shared.position_clock.read().0)
+    }
+
+    // The position presented now, extrapolated from the last timestamp by the host clock so it
+    // moves between the render callbacks.
+    pub fn interpolated_position(&self) -> Result<u64> {
+        if !self.core_stream_data.has_output() {
+            return Err(Error::error());
+        }
+        let now = host_time_to_ns(unsafe { mach_absolute_time() });
+        Ok(self.shared.position_clock.position_at(now))
+    }
+
+    // The latency of the captured data reaching the data callback, in frames of the input device.
+    #[cfg(target_os = "ios")]
+    pub fn input_latency(&self) -> Result<u32> {
+        Err(not_supported())
+    }
+    #[cfg(not(target_os = "ios"))]
+    pub fn input_latency(&self) -> Result<u32> {
+        if !self.core_stream_data.has_input() {
+            return Err(Error::error());
+        }
+        Ok(self
+            .shared
+            .total_input_latency_frames
+            .load(Ordering::SeqCst))
+    }
+
+    // The device capturing the input, whose volume can be set.
+    fn input_volume_device(&self) -> Result<AudioDeviceID> {
+        let input = match self.core_stream_data.input.as_ref() {
+            Some(input) => input,
+            None => return Err(Error::error()),
+        };
+        // The loopback input captures an output device.
+        if self.core_stream_data.is_loopback() {
+            return Err(Error::not_supported());
+        }
+        let address = get_property_address(Property::DeviceVolume, DeviceType::INPUT);
+        if !audio_object_has_property(
+            self.core_stream_data.platform.hardware(),
+            input.device.id,
+            &address,
+        ) {
+            cubeb_log!(
+                "({:p}) Device {} has no input volume.",
+                self as *const AudioUnitStream,
+                input.device.id
             );
+            return Err(Error::not_supported());
+        }
+        Ok(input.device.id)
+    }
+
+    // Set the hardware volume, in [0.0, 1.0], of the input device. It affects all the
+    // applications capturing the device.
+    pub fn set_input_device_volume(&self, volume: f32) -> Result<()> {
+        if !(0.0..=1.0).contains(&volume) {
+            return Err(Error::invalid_parameter());
         }
+        let device = self.input_volume_device()?;
+        set_device_volume(
+            self.core_stream_data.platform.hardware(),
+            device,
+            DeviceType::INPUT,
+            volume,
+        )
+        .map_err(|status| {
+            let e = BackendError::new(Operation::SetDeviceVolume, status).with_device(device);
+            cubeb_log!("({:p}) {}", self as *const AudioUnitStream, e);
+            e.into()
+        })
+    }
+
+    pub fn input_device_volume(&self) -> Result<f32> {
+        let device = self.input_volume_device()?;
+        get_device_volume(
+            self.core_stream_data.platform.hardware(),
+            device,
+            DeviceType::INPUT,
+        )
+        .map_err(|status| {
+            let e = BackendError::new(Operation::GetDeviceVolume, status).with_device(device);
+            cubeb_log!("({:p}) {}", self as *const AudioUnitStream, e);
+            e.into()
+        })
     }
 
-    fn reinit(&mut self) -> Result<()> {
+    fn reinit(&mut self, reason: ReinitReason) -> Result<()> {
         // Call stop_audiounits to avoid potential data race. If there is a running data callback,
         // which locks a mutex inside CoreAudio framework, then this call will block the current
         // thread until the callback is finished since this call asks to lock a mutex inside
         // CoreAudio framework that is used by the data callback.
-        if !self.shutdown.load(Ordering::SeqCst) {
+        if !self.shared.shutdown.load(Ordering::SeqCst) {
+            // A removed device renders nothing more to fade out.
+            if reason != ReinitReason::DeviceRemoved {
+                self.fade_out_output();
+            }
             self.core_stream_data.stop_audiounits();
+            // The restarted stream fades in.
+            self.reset_gain_ramp();
         }
 
-        debug_assert!(
-            !self.core_stream_data.input_unit.is_null()
-                || !self.core_stream_data.output_unit.is_null()
-        );
-        let vol_rv = if self.core_stream_data.output_unit.is_null() {
-            Err(Error::error())
-        } else {
-            get_volume(self.core_stream_data.output_unit)
+        debug_assert!(self.core_stream_data.has_input() || self.core_stream_data.has_output());
+        let vol_rv = match self.core_stream_data.output.as_ref() {
+            Some(output) if !output.unit.is_null() => {
+                get_volume(self.context.platform.units(), output.unit)
+            }
+            _ => Err(Error::error()),
         };
 
-        let has_input = !self.core_stream_data.input_unit.is_null();
-        let input_device = if has_input {
-            self.core_stream_data.input_device.id
-        } else {
-            kAudioObjectUnknown
+        // Keep the devices selected by the user while they are alive.
+        let to_default = self.reinit_to_default.swap(false, Ordering::SeqCst);
+        let choose = |device: &device_info| {
+            if to_default {
+                (kAudioObjectUnknown, ReinitChoice::FollowDefault)
+            } else {
+                choose_reinit_device(self.context.platform.hardware(), device)
+            }
+        };
+        let mut input_choice = self
+            .core_stream_data
+            .input
+            .as_ref()
+            .map(|input| choose(&input.device));
+        let mut output_choice = self
+            .core_stream_data
+            .output
+            .as_ref()
+            .map(|output| choose(&output.device));
+        let selected = |choice: &Option<(AudioDeviceID, ReinitChoice)>| {
+            choice.map_or(false, |(id, _)| id != kAudioObjectUnknown)
         };
-
-        self.core_stream_data.close();
 
         // Reinit occurs in one of the following case:
         // - When the device is not alive any more
         // - When the default system device change.
         // - The bluetooth device changed from A2DP to/from HFP/HSP profile
-        // We first attempt to re-use the same device id, should that fail we will
-        // default to the (potentially new) default device.
-        if has_input {
-            self.core_stream_data.input_device = create_device_info(input_device, DeviceType::INPUT).map_err(|e| {
+        // We first attempt to re-use the selected devices, should that fail we will
+        // default to the (potentially new) default devices.
+        let mut created = self.create_core_stream_data(
+            input_choice.map_or(kAudioObjectUnknown, |(id, _)| id),
+            output_choice.map_or(kAudioObjectUnknown, |(id, _)| id),
+            !to_default,
+        );
+        if created.is_err() && (selected(&input_choice) || selected(&output_choice)) {
+            cubeb_log!(
+                "({:p}) Stream reinit failed. Retry with the default devices.",
+                self as *const AudioUnitStream
+            );
+            let fall_back = Some((kAudioObjectUnknown, ReinitChoice::FallBackToDefault));
+            if selected(&input_choice) {
+                input_choice = fall_back;
+            }
+            if selected(&output_choice) {
+                output_choice = fall_back;
+            }
+            created =
+                self.create_core_stream_data(kAudioObjectUnknown, kAudioObjectUnknown, !to_default);
+        }
+
+        match created {
+            Ok(core_stream_data) => {
+                // Dropping the previous inner stream releases its units and devices.
+                drop(mem::replace(&mut self.core_stream_data, core_stream_data));
+            }
+            Err(e) => {
+                cubeb_log!(
+                    "({:p}) Stream reinit failed. Roll back to the previous stream.",
+                    self as *const AudioUnitStream
+                );
+                return self.roll_back_reinit().map_err(|_| e);
+            }
+        }
+
+        if let Ok(volume) = vol_rv {
+            set_volume(
+                self.context.platform.units(),
+                self.core_stream_data.output.as_ref().unwrap().unit,
+                volume,
+            );
+        }
+
+        let choices = (
+            input_choice.map(|(_, choice)| choice),
+            output_choice.map(|(_, choice)| choice),
+        );
+        cubeb_log!(
+            "({:p}) Stream reinit with (input, output) devices: {:?}.",
+            self as *const AudioUnitStream,
+            choices
+        );
+        *self.reinit_choices.lock().unwrap() = choices;
+
+        // If the stream was running, start it again.
+        if !self.shared.shutdown.load(Ordering::SeqCst) {
+            self.core_stream_data.start_audiounits().map_err(|e| {
                 cubeb_log!(
-                    "({:p}) Create input device info failed. This can happen when last media device is unplugged",
-                    self.core_stream_data.stm_ptr
+                    "({:p}) Start audiounit failed.",
+                    self as *const AudioUnitStream
                 );
                 e
             })?;
         }
 
-        // Always use the default output on reinit. This is not correct in every
-        // case but it is sufficient for Firefox and prevent reinit from reporting
-        // failures. It will change soon when reinit mechanism will be updated.
-        self.core_stream_data.output_device = create_device_info(kAudioObjectUnknown, DeviceType::OUTPUT).map_err(|e| {
-            cubeb_log!(
-                "({:p}) Create output device info failed. This can happen when last media device is unplugged",
-                self.core_stream_data.stm_ptr
-            );
-            e
-        })?;
+        // Report the switch requested by a device event or by the user. The reinit caused by
+        // a render error keeps the same devices.
+        if self.shared.switching_device.load(Ordering::SeqCst) || to_default {
+            self.notify_device_changed();
+        }
 
-        if self.core_stream_data.setup().is_err() {
-            cubeb_log!(
-                "({:p}) Stream reinit failed.",
-                self.core_stream_data.stm_ptr
-            );
-            if has_input && input_device != kAudioObjectUnknown {
-                // Attempt to re-use the same device-id failed, so attempt again with
-                // default input device.
-                self.core_stream_data.input_device = create_device_info(kAudioObjectUnknown, DeviceType::INPUT).map_err(|e| {
+        Ok(())
+    }
+
+    // Create and set up a new inner stream with the same settings as the current one, running on
+    // `input_device` and `output_device`. The default devices are used for kAudioObjectUnknown.
+    // The new stream keeps waiting for the devices selected by the user if `keep_uids` is set.
+    fn create_core_stream_data(
+        &self,
+        input_device: AudioDeviceID,
+        output_device: AudioDeviceID,
+        keep_uids: bool,
+    ) -> Result<Box<CoreStreamData>> {
+        let input_settings = match self.core_stream_data.input.as_ref() {
+            Some(input) => {
+                let params = StreamParams::from(unsafe { *input.stream_params.as_ptr() });
+                let mut device = create_input_device_info(self.context.platform.hardware(), input_device, &params).map_err(|e| {
                     cubeb_log!(
                         "({:p}) Create input device info failed. This can happen when last media device is unplugged",
-                        self.core_stream_data.stm_ptr
+                        self as *const AudioUnitStream
                     );
                     e
                 })?;
-                self.core_stream_data.setup().map_err(|e| {
+                if keep_uids {
+                    device.uid = input.device.uid.clone();
+                }
+                Some((params, device))
+            }
+            None => None,
+        };
+
+        let output_settings = match self.core_stream_data.output.as_ref() {
+            Some(output) => {
+                let mut device = create_device_info(self.context.platform.hardware(), output_device, DeviceType::OUTPUT).map_err(|e| {
                     cubeb_log!(
-                        "({:p}) Second stream reinit failed.",
-                        self.core_stream_data.stm_ptr
+                        "({:p}) Create output device info failed. This can happen when last media device is unplugged",
+                        self as *const AudioUnitStream
                     );
                     e
                 })?;
+                if keep_uids {
+                    device.uid = output.device.uid.clone();
+                }
+                let params = StreamParams::from(unsafe { *output.stream_params.as_ptr() });
+                Some((params, device))
             }
-        }
+            None => None,
+        };
 
-        if let Ok(volume) = vol_rv {
-            set_volume(self.core_stream_data.output_unit, volume);
+        // The partially set up inner stream is closed when it's dropped. It's boxed first since
+        // its address is the data of its callbacks and listeners.
+        let mut core_stream_data = Box::new(CoreStreamData::new(
+            self.shared.clone(),
+            self.context.platform.clone(),
+            input_settings,
+            output_settings,
+        ));
+        // The data callback keeps its format, which may not be the one of the stream parameters.
+        let format = match (
+            self.core_stream_data.input.as_ref(),
+            self.core_stream_data.output.as_ref(),
+        ) {
+            (Some(input), _) => Some(input.format),
+            (None, Some(output)) => Some(output.format),
+            (None, None) => None,
+        };
+        if let Some(format) = format {
+            core_stream_data.set_format(format);
         }
-
-        // If the stream was running, start it again.
-        if !self.shutdown.load(Ordering::SeqCst) {
-            self.core_stream_data.start_audiounits().map_err(|e| {
-                cubeb_log!(
-                    "({:p}) Start audiounit failed.",
-                    self.core_stream_data.stm_ptr
-                );
-                e
-            })?;
+        // The sides keep their channel maps too.
+        if let Some(map) = self
+            .core_stream_data
+            .input
+            .as_ref()
+            .and_then(|input| input.side.channel_map.clone())
+        {
+            core_stream_data.set_input_channel_map(map);
         }
-
-        Ok(())
+        if let Some(map) = self
+            .core_stream_data
+            .output
+            .as_ref()
+            .and_then(|output| output.side.channel_map.clone())
+        {
+            core_stream_data.set_output_channel_map(map);
+        }
+        core_stream_data.setup()?;
+        Ok(core_stream_data)
     }
 
-    fn reinit_async(&mut self) {
-        if self.reinit_pending.swap(true, Ordering::SeqCst) {
-            // A reinit task is already pending, nothing more to do.
-            cubeb_log!(
-                "({:p}) re-init stream task already pending, cancelling request",
-                self as *const AudioUnitStream
-            );
+    // Fade out the output and wait until the silence is rendered, so stopping the AudioUnits
+    // doesn't cut the audio abruptly. The wait is bounded since the render callback may never
+    // come, for example when the device is gone.
+    fn fade_out_output(&self) {
+        let (rate, unit) = match self.core_stream_data.output.as_ref() {
+            Some(output) => (output.stream_params.rate(), output.unit),
+            None => return,
+        };
+        if self.shared.shutdown.load(Ordering::SeqCst)
+            || self.shared.draining.load(Ordering::SeqCst)
+        {
+            return;
+        }
+        // Nothing is rendered, so there is nothing to fade out.
+        if unit.is_null() || !audiounit_is_running(self.core_stream_data.platform.units(), unit) {
+            return;
+        }
+        if self.shared.fading_out.load(Ordering::SeqCst)
+            && self.shared.faded_out.load(Ordering::SeqCst)
+        {
             return;
         }
 
-        let queue = self.queue.clone();
-        let mutexed_stm = Arc::new(Mutex::new(self));
-        let also_mutexed_stm = Arc::clone(&mutexed_stm);
-        // Use a new thread, through the queue, to avoid deadlock when calling
-        // Get/SetProperties method from inside notify callback
-        queue.run_async(move || {
-            let mut stm_guard = also_mutexed_stm.lock().unwrap();
-            let stm_ptr = *stm_guard as *const AudioUnitStream;
-            if stm_guard.destroy_pending.load(Ordering::SeqCst) {
+        let (ref lock, ref cvar) = self.shared.fade_out_signal;
+        let mut guard = lock.lock().unwrap();
+        self.shared.faded_out.store(false, Ordering::SeqCst);
+        self.shared.fading_out.store(true, Ordering::SeqCst);
+        // Give the callbacks time to render the ramp in the largest buffers.
+        let buffers = Duration::from_millis(
+            u64::from(2 * SAFE_MAX_LATENCY_FRAMES) * 1000 / u64::from(cmp::max(rate, 1)),
+        );
+        let deadline = Instant::now() + self.shared.gain_ramp + buffers;
+        while !self.shared.faded_out.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            if now >= deadline {
                 cubeb_log!(
-                    "({:p}) stream pending destroy, cancelling reinit task",
-                    stm_ptr
+                    "({:p}) Timed out waiting for the fade-out.",
+                    self as *const AudioUnitStream
                 );
                 return;
             }
+            guard = cvar.wait_timeout(guard, deadline - now).unwrap().0;
+        }
+    }
 
-            if stm_guard.reinit().is_err() {
-                stm_guard.core_stream_data.close();
-                stm_guard.notify_state_changed(State::Error);
-                cubeb_log!(
-                    "({:p}) Could not reopen the stream after switching.",
-                    stm_ptr
-                );
-            }
-            stm_guard.switching_device.store(false, Ordering::SeqCst);
-            stm_guard.reinit_pending.store(false, Ordering::SeqCst);
-        });
+    // Start the output from silence. Only called when the AudioUnits are stopped.
+    fn reset_gain_ramp(&mut self) {
+        self.shared.fading_out.store(false, Ordering::SeqCst);
+        if let Some(output) = self.core_stream_data.output.as_mut() {
+            output.side.gain_ramp = GainRamp::default();
+        }
     }
 
-    fn destroy_internal(&mut self) {
-        self.core_stream_data.close();
-        assert!(self.context.active_streams() >= 1);
-        self.context.update_latency_by_removing_stream();
+    // Resume the current inner stream after failing to replace it. It kept its listeners, its
+    // counters and its latencies, which the failed inner stream never touched.
+    fn roll_back_reinit(&mut self) -> Result<()> {
+        if !self.shared.shutdown.load(Ordering::SeqCst) {
+            self.core_stream_data.start_audiounits()?;
+        }
+        Ok(())
     }
 
-    fn destroy(&mut self) {
+    fn uninstall_listeners(&mut self) {
         if self
             .core_stream_data
             .uninstall_system_changed_callback()
@@ -3335,11 +5114,21 @@ impl<'ctx> AudioUnitStream<'ctx> {
                 self as *const AudioUnitStream
             );
         }
+    }
+
+    fn destroy_internal(&mut self) {
+        self.core_stream_data.close();
+        assert!(self.context.active_streams() >= 1);
+        self.context.update_latency_by_removing_stream();
+    }
+
+    fn destroy(&mut self) {
+        self.uninstall_listeners();
 
         // Execute the stream destroy work.
         self.destroy_pending.store(true, Ordering::SeqCst);
 
-        let queue = self.queue.clone();
+        let queue = self.shared.queue.clone();
 
         let stream_ptr = self as *const AudioUnitStream;
         // Execute close in serial queue to avoid collision
@@ -3349,9 +5138,9 @@ impl<'ctx> AudioUnitStream<'ctx> {
             // which locks a mutex inside CoreAudio framework, then this call will block the current
             // thread until the callback is finished since this call asks to lock a mutex inside
             // CoreAudio framework that is used by the data callback.
-            if !self.shutdown.load(Ordering::SeqCst) {
+            if !self.shared.shutdown.load(Ordering::SeqCst) {
                 self.core_stream_data.stop_audiounits();
-                self.shutdown.store(true, Ordering::SeqCst);
+                self.shared.shutdown.store(true, Ordering::SeqCst);
             }
 
             self.destroy_internal();
@@ -3369,14 +5158,14 @@ impl<'ctx> Drop for AudioUnitStream<'ctx> {
 
 impl<'ctx> StreamOps for AudioUnitStream<'ctx> {
     fn start(&mut self) -> Result<()> {
-        self.shutdown.store(false, Ordering::SeqCst);
-        self.draining.store(false, Ordering::SeqCst);
+        self.shared.shutdown.store(false, Ordering::SeqCst);
+        self.shared.draining.store(false, Ordering::SeqCst);
 
         // Execute start in serial queue to avoid racing with destroy or reinit.
         let mut result = Err(Error::error());
         let started = &mut result;
         let stream = &self;
-        self.queue.run_sync(move || {
+        self.shared.queue.run_sync(move || {
             *started = stream.core_stream_data.start_audiounits();
         });
 
@@ -3384,7 +5173,7 @@ impl<'ctx> StreamOps for AudioUnitStream<'ctx> {
             return result;
         }
 
-        self.notify_state_changed(State::Started);
+        self.shared.notify_state_changed(State::Started);
 
         cubeb_log!(
             "Cubeb stream ({:p}) started successfully.",
@@ -3393,15 +5182,18 @@ impl<'ctx> StreamOps for AudioUnitStream<'ctx> {
         Ok(())
     }
     fn stop(&mut self) -> Result<()> {
-        self.shutdown.store(true, Ordering::SeqCst);
+        self.fade_out_output();
+        self.shared.shutdown.store(true, Ordering::SeqCst);
 
         // Execute stop in serial queue to avoid racing with destroy or reinit.
-        let stream = &self;
-        self.queue.run_sync(move || {
+        let queue = self.shared.queue.clone();
+        let stream = &mut *self;
+        queue.run_sync(move || {
             stream.core_stream_data.stop_audiounits();
+            stream.reset_gain_ramp();
         });
 
-        self.notify_state_changed(State::Stopped);
+        self.shared.notify_state_changed(State::Stopped);
 
         cubeb_log!(
             "Cubeb stream ({:p}) stopped successfully.",
@@ -3410,17 +5202,31 @@ impl<'ctx> StreamOps for AudioUnitStream<'ctx> {
         Ok(())
     }
     fn reset_default_device(&mut self) -> Result<()> {
-        Err(Error::not_supported())
+        // The flag makes the next reinit move both sides to the system default devices, even if
+        // the selected devices are alive. A queued reinit picks up the flag when it runs.
+        self.reinit_to_default.store(true, Ordering::SeqCst);
+        self.shared.reinit_async(ReinitReason::ResetDefaultDevice);
+        cubeb_log!(
+            "Cubeb stream ({:p}) is moving to the default devices.",
+            self as *const AudioUnitStream
+        );
+        Ok(())
     }
     fn position(&mut self) -> Result<u64> {
-        let current_latency_frames = u64::from(self.current_latency_frames.load(Ordering::SeqCst));
-        let frames_played = self.frames_played.load(Ordering::SeqCst);
+        let current_latency_frames = u64::from(
+            self.core_stream_data
+                .current_latency_frames
+                .load(Ordering::SeqCst),
+        );
+        let frames_played = self.shared.frames_played.load(Ordering::SeqCst);
         let position = if current_latency_frames > frames_played {
             0
         } else {
             frames_played - current_latency_frames
         };
-        Ok(position)
+        // The latency of the new device may be higher after switching devices.
+        self.prev_position = cmp::max(self.prev_position, position);
+        Ok(self.prev_position)
     }
     #[cfg(target_os = "ios")]
     fn latency(&mut self) -> Result<u32> {
@@ -3428,10 +5234,16 @@ impl<'ctx> StreamOps for AudioUnitStream<'ctx> {
     }
     #[cfg(not(target_os = "ios"))]
     fn latency(&mut self) -> Result<u32> {
-        Ok(self.total_output_latency_frames.load(Ordering::SeqCst))
+        Ok(self
+            .shared
+            .total_output_latency_frames
+            .load(Ordering::SeqCst))
     }
     fn set_volume(&mut self, volume: f32) -> Result<()> {
-        set_volume(self.core_stream_data.output_unit, volume)
+        match self.core_stream_data.output.as_ref() {
+            Some(output) => set_volume(output.platform.units(), output.unit, volume),
+            None => Err(Error::error()),
+        }
     }
     #[cfg(target_os = "ios")]
     fn current_device(&mut self) -> Result<&DeviceRef> {
@@ -3439,8 +5251,14 @@ impl<'ctx> StreamOps for AudioUnitStream<'ctx> {
     }
     #[cfg(not(target_os = "ios"))]
     fn current_device(&mut self) -> Result<&DeviceRef> {
-        let input_name = audiounit_get_default_datasource_string(DeviceType::INPUT);
-        let output_name = audiounit_get_default_datasource_string(DeviceType::OUTPUT);
+        let input_name = audiounit_get_default_datasource_string(
+            self.context.platform.hardware(),
+            DeviceType::INPUT,
+        );
+        let output_name = audiounit_get_default_datasource_string(
+            self.context.platform.hardware(),
+            DeviceType::OUTPUT,
+        );
         if input_name.is_err() && output_name.is_err() {
             return Err(Error::error());
         }
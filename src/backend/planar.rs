@@ -0,0 +1,237 @@
+use super::coreaudio_sys_utils::sys::AudioBuffer;
+use cubeb_backend::ffi;
+use std::cmp;
+use std::os::raw::{c_long, c_void};
+use std::ptr;
+use std::slice;
+
+// Exchange the data with the data callback taking one buffer per channel. The AudioUnits of the
+// planar streams render and take one buffer per channel too, which are passed as they are to the
+// data callback by `call_with_buffers` when the stream has nothing to convert. Otherwise the
+// resampler and the mixer keep the interleaved data, which is deinterleaved for the data callback,
+// and its output interleaved. The data callback gets arrays of pointers to the channel buffers as
+// its input and output buffers.
+#[derive(Debug)]
+pub struct PlanarCallback {
+    data_callback: ffi::cubeb_data_callback,
+    user_ptr: *mut c_void,
+    sample_size: usize,
+    input_channels: usize,
+    output_channels: usize,
+    // The frames of each channel in `input` and `output`. The longer calls are split.
+    frames: usize,
+    // The channels, one after the other, of the data exchanged with the data callback.
+    input: Vec<u8>,
+    output: Vec<u8>,
+    input_planes: Vec<*const c_void>,
+    output_planes: Vec<*mut c_void>,
+}
+
+impl PlanarCallback {
+    // The buffers are allocated for the calls of up to `frames` frames, so they're not
+    // reallocated on the audio threads.
+    pub fn new(
+        data_callback: ffi::cubeb_data_callback,
+        user_ptr: *mut c_void,
+        sample_size: usize,
+        input_channels: usize,
+        output_channels: usize,
+        frames: usize,
+    ) -> Self {
+        assert!(data_callback.is_some());
+        assert!(sample_size > 0);
+        assert!(frames > 0);
+        let channel_size = frames * sample_size;
+        Self {
+            data_callback,
+            user_ptr,
+            sample_size,
+            input_channels,
+            output_channels,
+            frames,
+            input: vec![0; channel_size * input_channels],
+            output: vec![0; channel_size * output_channels],
+            input_planes: vec![ptr::null(); input_channels],
+            output_planes: vec![ptr::null_mut(); output_channels],
+        }
+    }
+
+    // Call the data callback with the buffers of the AudioUnits, each holding one channel of
+    // `nframes` frames.
+    pub fn call_with_buffers(
+        &mut self,
+        stream: *mut ffi::cubeb_stream,
+        input_buffers: Option<&[AudioBuffer]>,
+        output_buffers: Option<&[AudioBuffer]>,
+        nframes: c_long,
+    ) -> c_long {
+        if let Some(buffers) = input_buffers.as_ref() {
+            assert_eq!(buffers.len(), self.input_channels);
+            for (plane, buffer) in self.input_planes.iter_mut().zip(buffers.iter()) {
+                *plane = buffer.mData as *const c_void;
+            }
+        }
+        if let Some(buffers) = output_buffers.as_ref() {
+            assert_eq!(buffers.len(), self.output_channels);
+            for (plane, buffer) in self.output_planes.iter_mut().zip(buffers.iter()) {
+                *plane = buffer.mData;
+            }
+        }
+        unsafe {
+            self.data_callback.unwrap()(
+                stream,
+                self.user_ptr,
+                if input_buffers.is_some() {
+                    self.input_planes.as_ptr() as *const c_void
+                } else {
+                    ptr::null()
+                },
+                if output_buffers.is_some() {
+                    self.output_planes.as_mut_ptr() as *mut c_void
+                } else {
+                    ptr::null_mut()
+                },
+                nframes,
+            )
+        }
+    }
+
+    // Call the data callback with the interleaved data, in calls of up to `frames` frames, until
+    // it writes fewer frames than asked.
+    fn call(
+        &mut self,
+        stream: *mut ffi::cubeb_stream,
+        input_buffer: *const c_void,
+        output_buffer: *mut c_void,
+        nframes: c_long,
+    ) -> c_long {
+        assert!(nframes >= 0);
+        let frames = nframes as usize;
+        let input_frame_size = self.input_channels * self.sample_size;
+        let output_frame_size = self.output_channels * self.sample_size;
+        let mut done = 0;
+        loop {
+            let chunk = cmp::min(frames - done, self.frames);
+            let input = if input_buffer.is_null() {
+                ptr::null()
+            } else {
+                unsafe { (input_buffer as *const u8).add(done * input_frame_size) as *const c_void }
+            };
+            let output = if output_buffer.is_null() {
+                ptr::null_mut()
+            } else {
+                unsafe { (output_buffer as *mut u8).add(done * output_frame_size) as *mut c_void }
+            };
+            let written = self.call_interleaved(stream, input, output, chunk);
+            if written < 0 {
+                return written;
+            }
+            done += cmp::min(written as usize, chunk);
+            if (written as usize) < chunk || done == frames {
+                return done as c_long;
+            }
+        }
+    }
+
+    fn call_interleaved(
+        &mut self,
+        stream: *mut ffi::cubeb_stream,
+        input_buffer: *const c_void,
+        output_buffer: *mut c_void,
+        frames: usize,
+    ) -> c_long {
+        debug_assert!(frames <= self.frames);
+        let channel_size = self.frames * self.sample_size;
+        let sample_size = self.sample_size;
+        for (i, plane) in self.input_planes.iter_mut().enumerate() {
+            *plane = self.input[i * channel_size..].as_ptr() as *const c_void;
+        }
+        for (i, plane) in self.output_planes.iter_mut().enumerate() {
+            *plane = self.output[i * channel_size..].as_mut_ptr() as *mut c_void;
+        }
+
+        if !input_buffer.is_null() {
+            let input = unsafe {
+                slice::from_raw_parts(
+                    input_buffer as *const u8,
+                    frames * sample_size * self.input_channels,
+                )
+            };
+            deinterleave(input, &mut self.input, self.input_channels, sample_size);
+        }
+
+        let written = unsafe {
+            self.data_callback.unwrap()(
+                stream,
+                self.user_ptr,
+                if input_buffer.is_null() {
+                    ptr::null()
+                } else {
+                    self.input_planes.as_ptr() as *const c_void
+                },
+                if output_buffer.is_null() {
+                    ptr::null_mut()
+                } else {
+                    self.output_planes.as_mut_ptr() as *mut c_void
+                },
+                frames as c_long,
+            )
+        };
+
+        if !output_buffer.is_null() && written > 0 {
+            let written_frames = cmp::min(written as usize, frames);
+            let output = unsafe {
+                slice::from_raw_parts_mut(
+                    output_buffer as *mut u8,
+                    written_frames * self.output_channels * sample_size,
+                )
+            };
+            interleave(&self.output, output, self.output_channels, sample_size);
+        }
+        written
+    }
+}
+
+// The data callback given to the resampler in place of the one of the user, whose `user_ptr` is
+// the `PlanarCallback`.
+pub extern "C" fn planar_data_callback(
+    stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: c_long,
+) -> c_long {
+    assert!(!user_ptr.is_null());
+    let planar = unsafe { &mut *(user_ptr as *mut PlanarCallback) };
+    planar.call(stream, input_buffer, output_buffer, nframes)
+}
+
+// Copy the interleaved `frames` into `channels` channels of the same length in `planes`. The
+// frames may be shorter than the planes.
+pub fn deinterleave(frames: &[u8], planes: &mut [u8], channels: usize, sample_size: usize) {
+    if channels == 0 {
+        return;
+    }
+    let channel_size = planes.len() / channels;
+    for (i, frame) in frames.chunks(channels * sample_size).enumerate() {
+        for (channel, sample) in frame.chunks(sample_size).enumerate() {
+            let start = channel * channel_size + i * sample_size;
+            planes[start..start + sample_size].copy_from_slice(sample);
+        }
+    }
+}
+
+// Copy the `channels` channels of the same length in `planes` into the interleaved `frames`. The
+// frames may be shorter than the planes.
+pub fn interleave(planes: &[u8], frames: &mut [u8], channels: usize, sample_size: usize) {
+    if channels == 0 {
+        return;
+    }
+    let channel_size = planes.len() / channels;
+    for (i, frame) in frames.chunks_mut(channels * sample_size).enumerate() {
+        for (channel, sample) in frame.chunks_mut(sample_size).enumerate() {
+            let start = channel * channel_size + i * sample_size;
+            sample.copy_from_slice(&planes[start..start + sample_size]);
+        }
+    }
+}
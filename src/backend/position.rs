@@ -0,0 +1,84 @@
+use std::cmp;
+use std::hint;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+const NS_PER_SECOND: u64 = 1_000_000_000;
+
+// The stream position, in frames of the stream rate, and the host time, in nanoseconds, when
+// the frame at the position is presented by the device.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamTimestamp {
+    pub position: u64,
+    pub host_time_ns: u64,
+}
+
+impl StreamTimestamp {
+    // The position at `now_ns`, extrapolated from the timestamp at `rate`. The position never
+    // goes past `limit`, the end of the frames queued so far, so it stops moving when the
+    // stream stops.
+    pub fn position_at(&self, now_ns: u64, rate: u32, limit: u64) -> u64 {
+        let frames = |ns: u64| ns * u64::from(rate) / NS_PER_SECOND;
+        let position = if now_ns >= self.host_time_ns {
+            self.position + frames(now_ns - self.host_time_ns)
+        } else {
+            self.position
+                .saturating_sub(frames(self.host_time_ns - now_ns))
+        };
+        cmp::min(position, limit)
+    }
+}
+
+// The timestamp sampled in the render callback, with the end of the queued frames and the
+// stream rate, for the interpolation. The render callback is the only writer, so the readers
+// take a consistent snapshot by a sequence lock without blocking the callback: the sequence is
+// odd while the values are being written, and the readers retry if the sequence is odd or has
+// changed while they read.
+#[derive(Debug, Default)]
+pub struct PositionClock {
+    sequence: AtomicUsize,
+    position: AtomicU64,
+    host_time_ns: AtomicU64,
+    queued: AtomicU64,
+    rate: AtomicU32,
+}
+
+impl PositionClock {
+    pub fn update(&self, timestamp: StreamTimestamp, queued: u64, rate: u32) {
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.position.store(timestamp.position, Ordering::SeqCst);
+        self.host_time_ns
+            .store(timestamp.host_time_ns, Ordering::SeqCst);
+        self.queued.store(queued, Ordering::SeqCst);
+        self.rate.store(rate, Ordering::SeqCst);
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // The last timestamp, the end of the queued frames and the stream rate.
+    pub fn read(&self) -> (StreamTimestamp, u64, u32) {
+        loop {
+            let sequence = self.sequence.load(Ordering::SeqCst);
+            if sequence % 2 != 0 {
+                hint::spin_loop();
+                continue;
+            }
+            let timestamp = StreamTimestamp {
+                position: self.position.load(Ordering::SeqCst),
+                host_time_ns: self.host_time_ns.load(Ordering::SeqCst),
+            };
+            let queued = self.queued.load(Ordering::SeqCst);
+            let rate = self.rate.load(Ordering::SeqCst);
+            if self.sequence.load(Ordering::SeqCst) == sequence {
+                return (timestamp, queued, rate);
+            }
+        }
+    }
+
+    // The position at `now_ns`, or 0 before the first render callback.
+    pub fn position_at(&self, now_ns: u64) -> u64 {
+        let (timestamp, queued, rate) = self.read();
+        if rate == 0 {
+            return 0;
+        }
+        timestamp.position_at(now_ns, rate, queued)
+    }
+}
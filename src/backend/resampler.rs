@@ -2,9 +2,83 @@ use super::auto_release::*;
 use cubeb_backend::ffi;
 use std::os::raw::{c_long, c_uint, c_void};
 use std::ptr;
+use std::slice;
 
+// Pass the data as it is to the data callback, for the sample formats the cubeb resampler
+// doesn't have. The input of the duplex streams is queued so each callback gets as many input
+// frames as output frames, like with the cubeb resampler.
 #[derive(Debug)]
-pub struct Resampler(AutoRelease<ffi::cubeb_resampler>);
+struct Passthrough {
+    stream: *mut ffi::cubeb_stream,
+    data_callback: ffi::cubeb_data_callback,
+    user_ptr: *mut c_void,
+    input_frame_size: usize,
+    input: Vec<u8>,
+}
+
+impl Passthrough {
+    fn fill(
+        &mut self,
+        input_buffer: *mut c_void,
+        input_frame_count: *mut c_long,
+        output_buffer: *mut c_void,
+        output_frames_needed: c_long,
+    ) -> c_long {
+        let data_callback = self.data_callback.unwrap();
+        if output_buffer.is_null() {
+            assert!(!input_buffer.is_null() && !input_frame_count.is_null());
+            return unsafe {
+                data_callback(
+                    self.stream,
+                    self.user_ptr,
+                    input_buffer,
+                    ptr::null_mut(),
+                    *input_frame_count,
+                )
+            };
+        }
+        if input_buffer.is_null() {
+            return unsafe {
+                data_callback(
+                    self.stream,
+                    self.user_ptr,
+                    ptr::null(),
+                    output_buffer,
+                    output_frames_needed,
+                )
+            };
+        }
+        assert!(!input_frame_count.is_null());
+        let input_bytes = unsafe { *input_frame_count } as usize * self.input_frame_size;
+        if input_bytes > 0 {
+            self.input.extend_from_slice(unsafe {
+                slice::from_raw_parts(input_buffer as *const u8, input_bytes)
+            });
+        }
+        // The missing input frames are silent.
+        let needed_bytes = output_frames_needed as usize * self.input_frame_size;
+        if self.input.len() < needed_bytes {
+            self.input.resize(needed_bytes, 0);
+        }
+        let frames = unsafe {
+            data_callback(
+                self.stream,
+                self.user_ptr,
+                self.input.as_ptr() as *const c_void,
+                output_buffer,
+                output_frames_needed,
+            )
+        };
+        self.input.drain(..needed_bytes);
+        frames
+    }
+}
+
+#[derive(Debug)]
+pub struct Resampler {
+    resampler: AutoRelease<ffi::cubeb_resampler>,
+    passthrough: Option<Passthrough>,
+}
 
 impl Resampler {
     pub fn new(
@@ -38,7 +112,31 @@ impl Resampler {
         };
         assert!(!raw_resampler.is_null(), "Failed to create resampler");
         let resampler = AutoRelease::new(raw_resampler, ffi::cubeb_resampler_destroy);
-        Self(resampler)
+        Self {
+            resampler,
+            passthrough: None,
+        }
+    }
+
+    // A resampler passing the data as it is, whose input frames have `input_frame_size` bytes.
+    // The input and the output must be at the stream rate.
+    pub fn passthrough(
+        stream: *mut ffi::cubeb_stream,
+        input_frame_size: usize,
+        data_callback: ffi::cubeb_data_callback,
+        user_ptr: *mut c_void,
+    ) -> Self {
+        assert!(data_callback.is_some());
+        Self {
+            resampler: AutoRelease::new(ptr::null_mut(), ffi::cubeb_resampler_destroy),
+            passthrough: Some(Passthrough {
+                stream,
+                data_callback,
+                user_ptr,
+                input_frame_size,
+                input: Vec::new(),
+            }),
+        }
     }
 
     pub fn fill(
@@ -48,9 +146,17 @@ impl Resampler {
         output_buffer: *mut c_void,
         output_frames_needed: c_long,
     ) -> c_long {
+        if let Some(passthrough) = self.passthrough.as_mut() {
+            return passthrough.fill(
+                input_buffer,
+                input_frame_count,
+                output_buffer,
+                output_frames_needed,
+            );
+        }
         unsafe {
             ffi::cubeb_resampler_fill(
-                self.0.as_mut(),
+                self.resampler.as_mut(),
                 input_buffer,
                 input_frame_count,
                 output_buffer,
@@ -59,10 +165,19 @@ impl Resampler {
         }
     }
 
+    // The delay of the resampled data, in frames at the stream rate.
+    pub fn latency(&mut self) -> c_long {
+        if self.resampler.as_ptr().is_null() {
+            return 0;
+        }
+        unsafe { ffi::cubeb_resampler_latency(self.resampler.as_mut()) }
+    }
+
     pub fn destroy(&mut self) {
-        if !self.0.as_ptr().is_null() {
-            self.0.reset(ptr::null_mut());
+        if !self.resampler.as_ptr().is_null() {
+            self.resampler.reset(ptr::null_mut());
         }
+        self.passthrough = None;
     }
 }
 
@@ -74,9 +189,9 @@ impl Drop for Resampler {
 
 impl Default for Resampler {
     fn default() -> Self {
-        Self(AutoRelease::new(
-            ptr::null_mut(),
-            ffi::cubeb_resampler_destroy,
-        ))
+        Self {
+            resampler: AutoRelease::new(ptr::null_mut(), ffi::cubeb_resampler_destroy),
+            passthrough: None,
+        }
     }
 }
@@ -0,0 +1,263 @@
+use cubeb_backend::SampleFormat;
+use std::convert::TryFrom;
+use std::fmt;
+
+const I24_MIN: i32 = -(1 << 23);
+const I24_MAX: i32 = (1 << 23) - 1;
+
+// The formats of the samples exchanged with the data callback. The first ones are the formats of
+// cubeb, with the same values, and the others are only set by `StreamOptions::sample_format`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamFormat {
+    S16LE = 0,
+    S16BE = 1,
+    Float32LE = 2,
+    Float32BE = 3,
+    // 24-bit integers packed in 3 bytes.
+    S24LE = 4,
+    // 24-bit integers in the low 3 bytes of 4 bytes, sign-extended.
+    S24In32LE = 5,
+    S32LE = 6,
+    Float64LE = 7,
+}
+
+impl StreamFormat {
+    pub fn sample_size(self) -> usize {
+        match self {
+            StreamFormat::S16LE | StreamFormat::S16BE => 2,
+            StreamFormat::S24LE => 3,
+            StreamFormat::Float32LE
+            | StreamFormat::Float32BE
+            | StreamFormat::S24In32LE
+            | StreamFormat::S32LE => 4,
+            StreamFormat::Float64LE => 8,
+        }
+    }
+
+    // The format of cubeb, for its resampler, or None if cubeb doesn't have it.
+    pub fn to_cubeb_format(self) -> Option<SampleFormat> {
+        match self {
+            StreamFormat::S16LE => Some(SampleFormat::S16LE),
+            StreamFormat::S16BE => Some(SampleFormat::S16BE),
+            StreamFormat::Float32LE => Some(SampleFormat::Float32LE),
+            StreamFormat::Float32BE => Some(SampleFormat::Float32BE),
+            _ => None,
+        }
+    }
+}
+
+// The format of a raw value from the C API, which may not be one of the formats.
+impl TryFrom<u32> for StreamFormat {
+    type Error = ();
+    fn try_from(value: u32) -> Result<Self, ()> {
+        match value {
+            0 => Ok(StreamFormat::S16LE),
+            1 => Ok(StreamFormat::S16BE),
+            2 => Ok(StreamFormat::Float32LE),
+            3 => Ok(StreamFormat::Float32BE),
+            4 => Ok(StreamFormat::S24LE),
+            5 => Ok(StreamFormat::S24In32LE),
+            6 => Ok(StreamFormat::S32LE),
+            7 => Ok(StreamFormat::Float64LE),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<SampleFormat> for StreamFormat {
+    fn from(format: SampleFormat) -> Self {
+        match format {
+            SampleFormat::S16LE => StreamFormat::S16LE,
+            SampleFormat::S16BE => StreamFormat::S16BE,
+            SampleFormat::Float32LE => StreamFormat::Float32LE,
+            SampleFormat::Float32BE => StreamFormat::Float32BE,
+            SampleFormat::S16NE if cfg!(target_endian = "big") => StreamFormat::S16BE,
+            SampleFormat::S16NE => StreamFormat::S16LE,
+            SampleFormat::Float32NE if cfg!(target_endian = "big") => StreamFormat::Float32BE,
+            SampleFormat::Float32NE => StreamFormat::Float32LE,
+        }
+    }
+}
+
+// The types of the samples stored by the backend. The default value is the silence.
+pub trait Sample: Copy + Default + fmt::Debug + Send + 'static {
+    // The floating point samples are in [-1.0, 1.0], and the integer ones use their whole range.
+    const IS_FLOAT: bool;
+    fn to_f64(self) -> f64;
+    // The integer samples are rounded and clamped to their range.
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Sample for i16 {
+    const IS_FLOAT: bool = false;
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+    fn from_f64(value: f64) -> Self {
+        value.round() as i16
+    }
+}
+
+impl Sample for i32 {
+    const IS_FLOAT: bool = false;
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+    fn from_f64(value: f64) -> Self {
+        value.round() as i32
+    }
+}
+
+impl Sample for f32 {
+    const IS_FLOAT: bool = true;
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl Sample for f64 {
+    const IS_FLOAT: bool = true;
+    fn to_f64(self) -> f64 {
+        self
+    }
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+fn clamp_i24(value: f64) -> i32 {
+    (value.round() as i32).max(I24_MIN).min(I24_MAX)
+}
+
+// A little-endian 24-bit integer packed in 3 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct I24([u8; 3]);
+
+impl I24 {
+    // The bits of `value` above the 24 low ones are dropped.
+    pub fn new(value: i32) -> Self {
+        let bytes = value.to_le_bytes();
+        I24([bytes[0], bytes[1], bytes[2]])
+    }
+
+    pub fn get(self) -> i32 {
+        // Sign-extended by the arithmetic shift.
+        i32::from_le_bytes([0, self.0[0], self.0[1], self.0[2]]) >> 8
+    }
+}
+
+impl Sample for I24 {
+    const IS_FLOAT: bool = false;
+    fn to_f64(self) -> f64 {
+        f64::from(self.get())
+    }
+    fn from_f64(value: f64) -> Self {
+        I24::new(clamp_i24(value))
+    }
+}
+
+// A 24-bit integer in the low bits of a 32-bit integer.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct I24In32(i32);
+
+impl I24In32 {
+    // The bits of `value` above the 24 low ones are replaced by its sign.
+    pub fn new(value: i32) -> Self {
+        I24In32((value << 8) >> 8)
+    }
+
+    pub fn get(self) -> i32 {
+        self.0
+    }
+}
+
+impl Sample for I24In32 {
+    const IS_FLOAT: bool = false;
+    fn to_f64(self) -> f64 {
+        f64::from(self.get())
+    }
+    fn from_f64(value: f64) -> Self {
+        I24In32::new(clamp_i24(value))
+    }
+}
+
+#[test]
+fn test_stream_format_sample_size() {
+    use std::mem;
+    let pairs = [
+        (SampleFormat::S16LE, mem::size_of::<i16>()),
+        (SampleFormat::S16BE, mem::size_of::<i16>()),
+        (SampleFormat::S16NE, mem::size_of::<i16>()),
+        (SampleFormat::Float32LE, mem::size_of::<f32>()),
+        (SampleFormat::Float32BE, mem::size_of::<f32>()),
+        (SampleFormat::Float32NE, mem::size_of::<f32>()),
+    ];
+    for (format, size) in pairs.iter() {
+        let format = StreamFormat::from(*format);
+        assert_eq!(format.sample_size(), *size);
+        assert_eq!(
+            format.to_cubeb_format().map(StreamFormat::from),
+            Some(format)
+        );
+    }
+
+    let pairs = [
+        (StreamFormat::S24LE, mem::size_of::<I24>()),
+        (StreamFormat::S24In32LE, mem::size_of::<I24In32>()),
+        (StreamFormat::S32LE, mem::size_of::<i32>()),
+        (StreamFormat::Float64LE, mem::size_of::<f64>()),
+    ];
+    for (format, size) in pairs.iter() {
+        assert_eq!(format.sample_size(), *size);
+        assert_eq!(format.to_cubeb_format(), None);
+    }
+}
+
+#[test]
+fn test_i24_bytes() {
+    for value in [0, 1, -1, 0x12_3456, I24_MIN, I24_MAX].iter() {
+        let sample = I24::new(*value);
+        assert_eq!(
+            sample.0,
+            [
+                value.to_le_bytes()[0],
+                value.to_le_bytes()[1],
+                value.to_le_bytes()[2]
+            ]
+        );
+        assert_eq!(sample.get(), *value);
+        assert_eq!(I24In32::new(*value).get(), *value);
+    }
+    assert_eq!(I24::new(-1).0, [0xff, 0xff, 0xff]);
+    assert_eq!(I24::new(I24_MIN).0, [0x00, 0x00, 0x80]);
+    // The high bits are dropped.
+    assert_eq!(I24::new(0x7f80_0000).get(), I24_MIN);
+    assert_eq!(I24In32::new(0x7f80_0000).get(), I24_MIN);
+    assert_eq!(I24In32::new(0x00ff_ffff).get(), -1);
+}
+
+#[test]
+fn test_sample_from_f64() {
+    assert_eq!(I24::from_f64(1e10).get(), I24_MAX);
+    assert_eq!(I24::from_f64(-1e10).get(), I24_MIN);
+    assert_eq!(I24::from_f64(-2.5).get(), -3);
+    assert_eq!(I24In32::from_f64(8_388_607.4).get(), I24_MAX);
+    assert_eq!(I24In32::from_f64(-8_388_608.6).get(), I24_MIN);
+    assert_eq!(i32::from_f64(1e10), i32::MAX);
+    assert_eq!(i32::from_f64(-1e10), i32::MIN);
+    assert_eq!(i32::from_f64(1.5), 2);
+    assert_eq!(i16::from_f64(40_000.0), i16::MAX);
+    assert_eq!(
+        f64::from_f64(0.123_456_789_012_345_6),
+        0.123_456_789_012_345_6
+    );
+    for value in [I24_MIN, -1, 0, 1, I24_MAX].iter() {
+        assert_eq!(I24::from_f64(I24::new(*value).to_f64()).get(), *value);
+    }
+}
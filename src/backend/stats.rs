@@ -0,0 +1,329 @@
+use std::cmp;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+// The glitches of a stream since it's created or the counters are reset.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GlitchStats {
+    // The times the input data didn't fit in the input buffer, and was partly lost.
+    pub input_overruns: u64,
+    // The frames of silence inserted in the input data when it's missing.
+    pub silence_frames: u64,
+    // The input frames dropped to catch up with the output.
+    pub dropped_frames: u64,
+    // The render callbacks getting fewer frames than asked from the data callback, padded with
+    // silence.
+    pub output_underruns: u64,
+    // The callbacks taking longer than the duration of their frames.
+    pub deadline_misses: u64,
+}
+
+// The counters of `GlitchStats`, updated by the callbacks and read from any thread.
+#[derive(Debug, Default)]
+pub struct GlitchCounters {
+    input_overruns: AtomicU64,
+    silence_frames: AtomicU64,
+    dropped_frames: AtomicU64,
+    output_underruns: AtomicU64,
+    deadline_misses: AtomicU64,
+}
+
+impl GlitchCounters {
+    pub fn add_input_overrun(&self) {
+        self.input_overruns.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn add_silence_frames(&self, frames: usize) {
+        self.silence_frames
+            .fetch_add(frames as u64, Ordering::SeqCst);
+    }
+
+    pub fn add_dropped_frames(&self, frames: usize) {
+        self.dropped_frames
+            .fetch_add(frames as u64, Ordering::SeqCst);
+    }
+
+    pub fn add_output_underrun(&self) {
+        self.output_underruns.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Count a deadline miss if the callback for `frames` frames at `rate` took `elapsed_ns`
+    // nanoseconds, longer than the time the frames last.
+    pub fn add_callback_time(&self, elapsed_ns: u64, frames: u32, rate: f64) {
+        if rate <= 0.0 {
+            return;
+        }
+        let deadline_ns = f64::from(frames) * 1_000_000_000.0 / rate;
+        if elapsed_ns as f64 > deadline_ns {
+            self.deadline_misses.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    pub fn snapshot(&self) -> GlitchStats {
+        GlitchStats {
+            input_overruns: self.input_overruns.load(Ordering::SeqCst),
+            silence_frames: self.silence_frames.load(Ordering::SeqCst),
+            dropped_frames: self.dropped_frames.load(Ordering::SeqCst),
+            output_underruns: self.output_underruns.load(Ordering::SeqCst),
+            deadline_misses: self.deadline_misses.load(Ordering::SeqCst),
+        }
+    }
+
+    // Reset the counters, and return their values before the reset. No count is lost between
+    // the read and the reset.
+    pub fn reset(&self) -> GlitchStats {
+        GlitchStats {
+            input_overruns: self.input_overruns.swap(0, Ordering::SeqCst),
+            silence_frames: self.silence_frames.swap(0, Ordering::SeqCst),
+            dropped_frames: self.dropped_frames.swap(0, Ordering::SeqCst),
+            output_underruns: self.output_underruns.swap(0, Ordering::SeqCst),
+            deadline_misses: self.deadline_misses.swap(0, Ordering::SeqCst),
+        }
+    }
+}
+
+// A histogram of the values recorded by the callbacks, without locking. The bucket `i > 0` counts
+// the values in [2^(i-1), 2^i), and the bucket 0 counts the zeros.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    pub fn bucket(value: u64) -> usize {
+        let bits = (64 - value.leading_zeros()) as usize;
+        cmp::min(bits, HISTOGRAM_BUCKETS - 1)
+    }
+
+    pub fn record(&self, value: u64) {
+        self.buckets[Self::bucket(value)].fetch_add(1, Ordering::SeqCst);
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.sum.fetch_add(value, Ordering::SeqCst);
+        self.max.fetch_max(value, Ordering::SeqCst);
+    }
+
+    // The values recorded while taking the snapshot may be partly counted.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut buckets = [0; HISTOGRAM_BUCKETS];
+        for (bucket, count) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *bucket = count.load(Ordering::SeqCst);
+        }
+        HistogramSnapshot {
+            buckets,
+            count: self.count.load(Ordering::SeqCst),
+            sum: self.sum.load(Ordering::SeqCst),
+            max: self.max.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HistogramSnapshot {
+    pub buckets: [u64; HISTOGRAM_BUCKETS],
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+}
+
+impl HistogramSnapshot {
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum as f64 / self.count as f64)
+        }
+    }
+
+    // The upper bound of the bucket containing the `percentile`th percentile, capped by the
+    // largest value.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (percentile / 100.0 * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = if i == 0 { 0 } else { (1_u64 << i) - 1 };
+                return Some(cmp::min(upper, self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
+
+// Why the stream was reinitialized.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReinitReason {
+    NoReinit = 0,
+    DefaultInputChanged = 1,
+    DefaultOutputChanged = 2,
+    DeviceRemoved = 3,
+    DataSourceChanged = 4,
+    SelectedDeviceBack = 5,
+    RenderError = 6,
+    ResetDefaultDevice = 7,
+}
+
+impl Default for ReinitReason {
+    fn default() -> Self {
+        ReinitReason::NoReinit
+    }
+}
+
+impl ReinitReason {
+    fn from_raw(raw: u32) -> Self {
+        use self::ReinitReason::*;
+        [
+            DefaultInputChanged,
+            DefaultOutputChanged,
+            DeviceRemoved,
+            DataSourceChanged,
+            SelectedDeviceBack,
+            RenderError,
+            ResetDefaultDevice,
+        ]
+        .iter()
+        .cloned()
+        .find(|reason| *reason as u32 == raw)
+        .unwrap_or(NoReinit)
+    }
+}
+
+// The frames and the duration, in microseconds, of the callbacks of one direction.
+#[derive(Debug, Default)]
+pub struct CallbackStats {
+    frames: Histogram,
+    duration_us: Histogram,
+}
+
+impl CallbackStats {
+    pub fn record(&self, frames: u32, elapsed_ns: u64) {
+        self.frames.record(u64::from(frames));
+        self.duration_us.record(elapsed_ns / 1_000);
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamStats {
+    pub glitches: GlitchStats,
+    pub input_callback_frames: HistogramSnapshot,
+    pub input_callback_duration_us: HistogramSnapshot,
+    pub output_callback_frames: HistogramSnapshot,
+    pub output_callback_duration_us: HistogramSnapshot,
+    // The input frames waiting for the output in the duplex streams, after the last callback.
+    pub input_buffered_frames: usize,
+    // The last output latency, and its range since the stream is created, or 0 before the first
+    // render callback.
+    pub output_latency_frames: u32,
+    pub min_output_latency_frames: u32,
+    pub max_output_latency_frames: u32,
+    // The last ratio of the input rate to its nominal rate, and its range since the stream is
+    // created, for the duplex streams compensating the drift between their clocks, or 0.
+    pub drift_ratio: f64,
+    pub min_drift_ratio: f64,
+    pub max_drift_ratio: f64,
+    pub reinits: u64,
+    pub last_reinit_reason: ReinitReason,
+}
+
+// The data of `StreamStats` besides the glitches, collected by the callbacks without locking.
+#[derive(Debug)]
+pub struct StatsCollector {
+    pub input: CallbackStats,
+    pub output: CallbackStats,
+    input_buffered_frames: AtomicUsize,
+    output_latency_frames: AtomicU32,
+    min_output_latency_frames: AtomicU32,
+    max_output_latency_frames: AtomicU32,
+    // The bits of the positive ratios, which are ordered like the ratios.
+    drift_ratio: AtomicU64,
+    min_drift_ratio: AtomicU64,
+    max_drift_ratio: AtomicU64,
+    reinits: AtomicU64,
+    last_reinit_reason: AtomicU32,
+}
+
+impl Default for StatsCollector {
+    fn default() -> Self {
+        Self {
+            input: CallbackStats::default(),
+            output: CallbackStats::default(),
+            input_buffered_frames: AtomicUsize::new(0),
+            output_latency_frames: AtomicU32::new(0),
+            min_output_latency_frames: AtomicU32::new(u32::max_value()),
+            max_output_latency_frames: AtomicU32::new(0),
+            drift_ratio: AtomicU64::new(0),
+            min_drift_ratio: AtomicU64::new(u64::max_value()),
+            max_drift_ratio: AtomicU64::new(0),
+            reinits: AtomicU64::new(0),
+            last_reinit_reason: AtomicU32::new(0),
+        }
+    }
+}
+
+impl StatsCollector {
+    pub fn set_input_buffered_frames(&self, frames: usize) {
+        self.input_buffered_frames.store(frames, Ordering::SeqCst);
+    }
+
+    pub fn set_output_latency(&self, frames: u32) {
+        self.output_latency_frames.store(frames, Ordering::SeqCst);
+        self.min_output_latency_frames
+            .fetch_min(frames, Ordering::SeqCst);
+        self.max_output_latency_frames
+            .fetch_max(frames, Ordering::SeqCst);
+    }
+
+    pub fn set_drift_ratio(&self, ratio: f64) {
+        debug_assert!(ratio > 0.0);
+        let bits = ratio.to_bits();
+        self.drift_ratio.store(bits, Ordering::SeqCst);
+        self.min_drift_ratio.fetch_min(bits, Ordering::SeqCst);
+        self.max_drift_ratio.fetch_max(bits, Ordering::SeqCst);
+    }
+
+    pub fn add_reinit(&self, reason: ReinitReason) {
+        self.reinits.fetch_add(1, Ordering::SeqCst);
+        self.last_reinit_reason
+            .store(reason as u32, Ordering::SeqCst);
+    }
+
+    pub fn snapshot(&self, glitches: GlitchStats) -> StreamStats {
+        let max_output_latency_frames = self.max_output_latency_frames.load(Ordering::SeqCst);
+        let min_output_latency_frames = cmp::min(
+            self.min_output_latency_frames.load(Ordering::SeqCst),
+            max_output_latency_frames,
+        );
+        let max_drift_ratio = self.max_drift_ratio.load(Ordering::SeqCst);
+        let min_drift_ratio =
+            cmp::min(self.min_drift_ratio.load(Ordering::SeqCst), max_drift_ratio);
+        StreamStats {
+            glitches,
+            input_callback_frames: self.input.frames.snapshot(),
+            input_callback_duration_us: self.input.duration_us.snapshot(),
+            output_callback_frames: self.output.frames.snapshot(),
+            output_callback_duration_us: self.output.duration_us.snapshot(),
+            input_buffered_frames: self.input_buffered_frames.load(Ordering::SeqCst),
+            output_latency_frames: self.output_latency_frames.load(Ordering::SeqCst),
+            min_output_latency_frames,
+            max_output_latency_frames,
+            drift_ratio: f64::from_bits(self.drift_ratio.load(Ordering::SeqCst)),
+            min_drift_ratio: f64::from_bits(min_drift_ratio),
+            max_drift_ratio: f64::from_bits(max_drift_ratio),
+            reinits: self.reinits.load(Ordering::SeqCst),
+            last_reinit_reason: ReinitReason::from_raw(
+                self.last_reinit_reason.load(Ordering::SeqCst),
+            ),
+        }
+    }
+}
@@ -18,10 +18,13 @@ fn test_aggregate_set_sub_devices_for_an_unknown_aggregate_device() {
 
     let default_input = default_input.unwrap();
     let default_output = default_output.unwrap();
-    assert!(
-        AggregateDevice::set_sub_devices(kAudioObjectUnknown, default_input, default_output)
-            .is_err()
-    );
+    assert!(AggregateDevice::set_sub_devices(
+        &CoreAudioHardware,
+        kAudioObjectUnknown,
+        default_input,
+        default_output
+    )
+    .is_err());
 }
 
 #[test]
@@ -29,6 +32,7 @@ fn test_aggregate_set_sub_devices_for_an_unknown_aggregate_device() {
 fn test_aggregate_set_sub_devices_for_unknown_devices() {
     // If aggregate device id is kAudioObjectUnknown, we are unable to set device list.
     assert!(AggregateDevice::set_sub_devices(
+        &CoreAudioHardware,
         kAudioObjectUnknown,
         kAudioObjectUnknown,
         kAudioObjectUnknown
@@ -44,11 +48,11 @@ fn test_aggregate_set_sub_devices_for_unknown_devices() {
 fn test_aggregate_get_sub_devices() {
     let devices = test_get_all_devices();
     for device in devices {
-        // `AggregateDevice::get_sub_devices(device)` will return a single-element vector
+        // `AggregateDevice::get_sub_devices(&CoreAudioHardware, device)` will return a single-element vector
         // containing `device` itself if it's not an aggregate device. This test assumes devices
         // is not an empty aggregate device (Test will panic when calling get_sub_devices with
         // an empty aggregate device).
-        let sub_devices = AggregateDevice::get_sub_devices(device).unwrap();
+        let sub_devices = AggregateDevice::get_sub_devices(&CoreAudioHardware, device).unwrap();
         // TODO: If the device is a blank aggregate device, then the assertion fails!
         assert!(!sub_devices.is_empty());
     }
@@ -57,7 +61,8 @@ fn test_aggregate_get_sub_devices() {
 #[test]
 #[should_panic]
 fn test_aggregate_get_sub_devices_for_a_unknown_device() {
-    let devices = AggregateDevice::get_sub_devices(kAudioObjectUnknown).unwrap();
+    let devices =
+        AggregateDevice::get_sub_devices(&CoreAudioHardware, kAudioObjectUnknown).unwrap();
     assert!(devices.is_empty());
 }
 
@@ -66,7 +71,7 @@ fn test_aggregate_get_sub_devices_for_a_unknown_device() {
 #[test]
 #[should_panic]
 fn test_aggregate_set_master_device_for_an_unknown_aggregate_device() {
-    assert!(AggregateDevice::set_master_device(kAudioObjectUnknown).is_err());
+    assert!(AggregateDevice::set_master_device(&CoreAudioHardware, kAudioObjectUnknown).is_err());
 }
 
 // AggregateDevice::activate_clock_drift_compensation
@@ -74,7 +79,11 @@ fn test_aggregate_set_master_device_for_an_unknown_aggregate_device() {
 #[test]
 #[should_panic]
 fn test_aggregate_activate_clock_drift_compensation_for_an_unknown_aggregate_device() {
-    assert!(AggregateDevice::activate_clock_drift_compensation(kAudioObjectUnknown).is_err());
+    assert!(AggregateDevice::activate_clock_drift_compensation(
+        &CoreAudioHardware,
+        kAudioObjectUnknown
+    )
+    .is_err());
 }
 
 // AggregateDevice::destroy_device
@@ -82,14 +91,21 @@ fn test_aggregate_activate_clock_drift_compensation_for_an_unknown_aggregate_dev
 #[test]
 #[should_panic]
 fn test_aggregate_destroy_device_for_unknown_plugin_and_aggregate_devices() {
-    assert!(AggregateDevice::destroy_device(kAudioObjectUnknown, kAudioObjectUnknown).is_err())
+    assert!(AggregateDevice::destroy_device(
+        &CoreAudioHardware,
+        kAudioObjectUnknown,
+        kAudioObjectUnknown
+    )
+    .is_err())
 }
 
 #[test]
 #[should_panic]
 fn test_aggregate_destroy_aggregate_device_for_a_unknown_aggregate_device() {
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    assert!(AggregateDevice::destroy_device(plugin, kAudioObjectUnknown).is_err());
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    assert!(
+        AggregateDevice::destroy_device(&CoreAudioHardware, plugin, kAudioObjectUnknown).is_err()
+    );
 }
 
 // Default Ignored Tests
@@ -106,13 +122,15 @@ fn test_aggregate_destroy_aggregate_device_for_a_unknown_aggregate_device() {
 #[ignore]
 fn test_aggregate_create_blank_device() {
     // TODO: Test this when there is no available devices.
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
     let devices = test_get_all_devices();
     let device = devices.into_iter().find(|dev| dev == &device).unwrap();
-    let uid = get_device_global_uid(device).unwrap().into_string();
+    let uid = get_device_global_uid(&CoreAudioHardware, device)
+        .unwrap()
+        .into_string();
     assert!(uid.contains(PRIVATE_AGGREGATE_DEVICE_NAME));
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 // AggregateDevice::get_sub_devices
@@ -122,14 +140,14 @@ fn test_aggregate_create_blank_device() {
 #[should_panic]
 fn test_aggregate_get_sub_devices_for_blank_aggregate_devices() {
     // TODO: Test this when there is no available devices.
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
     // There is no sub device in a blank aggregate device!
     // AggregateDevice::get_sub_devices guarantees returning a non-empty devices vector, so
     // the following call will panic!
-    let sub_devices = AggregateDevice::get_sub_devices(device).unwrap();
+    let sub_devices = AggregateDevice::get_sub_devices(&CoreAudioHardware, device).unwrap();
     assert!(sub_devices.is_empty());
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 // AggregateDevice::set_sub_devices_sync
@@ -147,13 +165,21 @@ fn test_aggregate_set_sub_devices() {
     let input_device = input_device.unwrap();
     let output_device = output_device.unwrap();
 
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
-    assert!(AggregateDevice::set_sub_devices_sync(device, input_device, output_device).is_ok());
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
+    assert!(AggregateDevice::set_sub_devices_sync(
+        &CoreAudioHardware,
+        device,
+        input_device,
+        output_device
+    )
+    .is_ok());
 
-    let sub_devices = AggregateDevice::get_sub_devices(device).unwrap();
-    let input_sub_devices = AggregateDevice::get_sub_devices(input_device).unwrap();
-    let output_sub_devices = AggregateDevice::get_sub_devices(output_device).unwrap();
+    let sub_devices = AggregateDevice::get_sub_devices(&CoreAudioHardware, device).unwrap();
+    let input_sub_devices =
+        AggregateDevice::get_sub_devices(&CoreAudioHardware, input_device).unwrap();
+    let output_sub_devices =
+        AggregateDevice::get_sub_devices(&CoreAudioHardware, output_device).unwrap();
 
     // TODO: There may be overlapping devices between input_sub_devices and output_sub_devices,
     //       but now AggregateDevice::set_sub_devices will add them directly.
@@ -179,7 +205,7 @@ fn test_aggregate_set_sub_devices() {
         assert!(onwed_device_uids.contains(uid));
     }
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 #[test]
@@ -192,12 +218,18 @@ fn test_aggregate_set_sub_devices_for_unknown_input_devices() {
     }
     let output_device = output_device.unwrap();
 
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
 
-    assert!(AggregateDevice::set_sub_devices(device, kAudioObjectUnknown, output_device).is_err());
+    assert!(AggregateDevice::set_sub_devices(
+        &CoreAudioHardware,
+        device,
+        kAudioObjectUnknown,
+        output_device
+    )
+    .is_err());
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 #[test]
@@ -210,18 +242,28 @@ fn test_aggregate_set_sub_devices_for_unknown_output_devices() {
     }
     let input_device = input_device.unwrap();
 
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
 
-    assert!(AggregateDevice::set_sub_devices(device, input_device, kAudioObjectUnknown).is_err());
+    assert!(AggregateDevice::set_sub_devices(
+        &CoreAudioHardware,
+        device,
+        input_device,
+        kAudioObjectUnknown
+    )
+    .is_err());
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 fn get_device_uids(devices: &Vec<AudioObjectID>) -> Vec<String> {
     devices
         .iter()
-        .map(|device| get_device_global_uid(*device).unwrap().into_string())
+        .map(|device| {
+            get_device_global_uid(&CoreAudioHardware, *device)
+                .unwrap()
+                .into_string()
+        })
         .collect()
 }
 
@@ -240,19 +282,25 @@ fn test_aggregate_set_master_device() {
     let input_device = input_device.unwrap();
     let output_device = output_device.unwrap();
 
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
-    assert!(AggregateDevice::set_sub_devices_sync(device, input_device, output_device).is_ok());
-    assert!(AggregateDevice::set_master_device(device).is_ok());
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
+    assert!(AggregateDevice::set_sub_devices_sync(
+        &CoreAudioHardware,
+        device,
+        input_device,
+        output_device
+    )
+    .is_ok());
+    assert!(AggregateDevice::set_master_device(&CoreAudioHardware, device).is_ok());
 
     // Check if master is set to the first sub device of the default output device.
     // TODO: What if the output device in the aggregate device is not the default output device?
     let first_output_sub_device_uid =
-        get_device_uid(AggregateDevice::get_sub_devices(device).unwrap()[0]);
+        get_device_uid(AggregateDevice::get_sub_devices(&CoreAudioHardware, device).unwrap()[0]);
     let master_device_uid = test_get_master_device(device);
     assert_eq!(first_output_sub_device_uid, master_device_uid);
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 #[test]
@@ -264,9 +312,9 @@ fn test_aggregate_set_master_device_for_a_blank_aggregate_device() {
         return;
     }
 
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
-    assert!(AggregateDevice::set_master_device(device).is_ok());
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
+    assert!(AggregateDevice::set_master_device(&CoreAudioHardware, device).is_ok());
 
     // TODO: it's really weird the aggregate device actually own nothing
     //       but its master device can be set successfully!
@@ -276,11 +324,13 @@ fn test_aggregate_set_master_device_for_a_blank_aggregate_device() {
     // The CFStringRef of the master device returned from `test_get_master_device` is actually
     // non-null.
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 fn get_device_uid(id: AudioObjectID) -> String {
-    get_device_global_uid(id).unwrap().into_string()
+    get_device_global_uid(&CoreAudioHardware, id)
+        .unwrap()
+        .into_string()
 }
 
 // AggregateDevice::activate_clock_drift_compensation
@@ -298,11 +348,17 @@ fn test_aggregate_activate_clock_drift_compensation() {
     let input_device = input_device.unwrap();
     let output_device = output_device.unwrap();
 
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
-    assert!(AggregateDevice::set_sub_devices_sync(device, input_device, output_device).is_ok());
-    assert!(AggregateDevice::set_master_device(device).is_ok());
-    assert!(AggregateDevice::activate_clock_drift_compensation(device).is_ok());
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
+    assert!(AggregateDevice::set_sub_devices_sync(
+        &CoreAudioHardware,
+        device,
+        input_device,
+        output_device
+    )
+    .is_ok());
+    assert!(AggregateDevice::set_master_device(&CoreAudioHardware, device).is_ok());
+    assert!(AggregateDevice::activate_clock_drift_compensation(&CoreAudioHardware, device).is_ok());
 
     // Check the compensations.
     let devices = test_get_all_onwed_devices(device);
@@ -314,7 +370,7 @@ fn test_aggregate_activate_clock_drift_compensation() {
         assert_eq!(*compensation, if i == 0 { 0 } else { DRIFT_COMPENSATION });
     }
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 #[test]
@@ -331,21 +387,28 @@ fn test_aggregate_activate_clock_drift_compensation_for_an_aggregate_device_with
     let input_device = input_device.unwrap();
     let output_device = output_device.unwrap();
 
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
-    assert!(AggregateDevice::set_sub_devices_sync(device, input_device, output_device).is_ok());
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
+    assert!(AggregateDevice::set_sub_devices_sync(
+        &CoreAudioHardware,
+        device,
+        input_device,
+        output_device
+    )
+    .is_ok());
 
     // TODO: Is the master device the first output sub device by default if we
     //       don't set that ? Is it because we add the output sub device list
     //       before the input's one ? (See implementation of
     //       AggregateDevice::set_sub_devices).
-    let first_output_sub_device_uid =
-        get_device_uid(AggregateDevice::get_sub_devices(output_device).unwrap()[0]);
+    let first_output_sub_device_uid = get_device_uid(
+        AggregateDevice::get_sub_devices(&CoreAudioHardware, output_device).unwrap()[0],
+    );
     let master_device_uid = test_get_master_device(device);
     assert_eq!(first_output_sub_device_uid, master_device_uid);
 
     // Compensate the drift directly without setting master device.
-    assert!(AggregateDevice::activate_clock_drift_compensation(device).is_ok());
+    assert!(AggregateDevice::activate_clock_drift_compensation(&CoreAudioHardware, device).is_ok());
 
     // Check the compensations.
     let devices = test_get_all_onwed_devices(device);
@@ -357,25 +420,27 @@ fn test_aggregate_activate_clock_drift_compensation_for_an_aggregate_device_with
         assert_eq!(*compensation, if i == 0 { 0 } else { DRIFT_COMPENSATION });
     }
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 #[test]
 #[should_panic]
 #[ignore]
 fn test_aggregate_activate_clock_drift_compensation_for_a_blank_aggregate_device() {
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
 
-    let sub_devices = AggregateDevice::get_sub_devices(device).unwrap();
+    let sub_devices = AggregateDevice::get_sub_devices(&CoreAudioHardware, device).unwrap();
     assert!(sub_devices.is_empty());
     let onwed_devices = test_get_all_onwed_devices(device);
     assert!(onwed_devices.is_empty());
 
     // Get a panic since no sub devices to be set compensation.
-    assert!(AggregateDevice::activate_clock_drift_compensation(device).is_err());
+    assert!(
+        AggregateDevice::activate_clock_drift_compensation(&CoreAudioHardware, device).is_err()
+    );
 
-    assert!(AggregateDevice::destroy_device(plugin, device).is_ok());
+    assert!(AggregateDevice::destroy_device(&CoreAudioHardware, plugin, device).is_ok());
 }
 
 fn get_drift_compensations(devices: &Vec<AudioObjectID>) -> Vec<u32> {
@@ -395,7 +460,9 @@ fn get_drift_compensations(devices: &Vec<AudioObjectID>) -> Vec<u32> {
 #[ignore]
 #[should_panic]
 fn test_aggregate_destroy_aggregate_device_for_a_unknown_plugin_device() {
-    let plugin = AggregateDevice::get_system_plugin_id().unwrap();
-    let device = AggregateDevice::create_blank_device_sync(plugin).unwrap();
-    assert!(AggregateDevice::destroy_device(kAudioObjectUnknown, device).is_err());
+    let plugin = AggregateDevice::get_system_plugin_id(&CoreAudioHardware).unwrap();
+    let device = AggregateDevice::create_blank_device_sync(&CoreAudioHardware, plugin).unwrap();
+    assert!(
+        AggregateDevice::destroy_device(&CoreAudioHardware, kAudioObjectUnknown, device).is_err()
+    );
 }
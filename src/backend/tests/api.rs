@@ -149,7 +149,8 @@ fn test_minimum_resampling_input_frames_equal_input_output_rate() {
 #[test]
 fn test_create_device_info_from_unknown_input_device() {
     if let Some(default_device_id) = test_get_default_device(Scope::Input) {
-        let default_device = create_device_info(kAudioObjectUnknown, DeviceType::INPUT).unwrap();
+        let default_device =
+            create_device_info(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).unwrap();
         assert_eq!(default_device.id, default_device_id);
         assert_eq!(
             default_device.flags,
@@ -165,7 +166,9 @@ fn test_create_device_info_from_unknown_input_device() {
 #[test]
 fn test_create_device_info_from_unknown_output_device() {
     if let Some(default_device_id) = test_get_default_device(Scope::Output) {
-        let default_device = create_device_info(kAudioObjectUnknown, DeviceType::OUTPUT).unwrap();
+        let default_device =
+            create_device_info(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::OUTPUT)
+                .unwrap();
         assert_eq!(default_device.id, default_device_id);
         assert_eq!(
             default_device.flags,
@@ -181,13 +184,21 @@ fn test_create_device_info_from_unknown_output_device() {
 #[test]
 #[should_panic]
 fn test_set_device_info_to_system_input_device() {
-    let _device = create_device_info(kAudioObjectSystemObject, DeviceType::INPUT);
+    let _device = create_device_info(
+        &CoreAudioHardware,
+        kAudioObjectSystemObject,
+        DeviceType::INPUT,
+    );
 }
 
 #[test]
 #[should_panic]
 fn test_set_device_info_to_system_output_device() {
-    let _device = create_device_info(kAudioObjectSystemObject, DeviceType::OUTPUT);
+    let _device = create_device_info(
+        &CoreAudioHardware,
+        kAudioObjectSystemObject,
+        DeviceType::OUTPUT,
+    );
 }
 
 // FIXIT: Is it ok to set input device to a nonexistent device ?
@@ -196,7 +207,7 @@ fn test_set_device_info_to_system_output_device() {
 #[should_panic]
 fn test_set_device_info_to_nonexistent_input_device() {
     let nonexistent_id = std::u32::MAX;
-    let _device = create_device_info(nonexistent_id, DeviceType::INPUT);
+    let _device = create_device_info(&CoreAudioHardware, nonexistent_id, DeviceType::INPUT);
 }
 
 // FIXIT: Is it ok to set output device to a nonexistent device ?
@@ -205,7 +216,7 @@ fn test_set_device_info_to_nonexistent_input_device() {
 #[should_panic]
 fn test_set_device_info_to_nonexistent_output_device() {
     let nonexistent_id = std::u32::MAX;
-    let _device = create_device_info(nonexistent_id, DeviceType::OUTPUT);
+    let _device = create_device_info(&CoreAudioHardware, nonexistent_id, DeviceType::OUTPUT);
 }
 
 // add_listener (for default output device)
@@ -232,7 +243,7 @@ fn test_add_listener_unknown_device() {
             callback,
         );
         assert_eq!(
-            stream.add_device_listener(&listener),
+            stream.core_stream_data.add_device_listener(&listener),
             kAudioHardwareBadObjectError as OSStatus
         );
     });
@@ -261,8 +272,14 @@ fn test_add_listener_then_remove_system_device() {
             ),
             callback,
         );
-        assert_eq!(stream.add_device_listener(&listener), NO_ERR);
-        assert_eq!(stream.remove_device_listener(&listener), NO_ERR);
+        assert_eq!(
+            stream.core_stream_data.add_device_listener(&listener),
+            NO_ERR
+        );
+        assert_eq!(
+            stream.core_stream_data.remove_device_listener(&listener),
+            NO_ERR
+        );
     });
 }
 
@@ -287,7 +304,10 @@ fn test_remove_listener_without_adding_any_listener_before_system_device() {
             ),
             callback,
         );
-        assert_eq!(stream.remove_device_listener(&listener), NO_ERR);
+        assert_eq!(
+            stream.core_stream_data.remove_device_listener(&listener),
+            NO_ERR
+        );
     });
 }
 
@@ -313,7 +333,7 @@ fn test_remove_listener_unknown_device() {
             callback,
         );
         assert_eq!(
-            stream.remove_device_listener(&listener),
+            stream.core_stream_data.remove_device_listener(&listener),
             kAudioHardwareBadObjectError as OSStatus
         );
     });
@@ -325,14 +345,14 @@ fn test_remove_listener_unknown_device() {
 fn test_get_default_device_id() {
     if test_get_default_device(Scope::Input).is_some() {
         assert_ne!(
-            audiounit_get_default_device_id(DeviceType::INPUT),
+            audiounit_get_default_device_id(&CoreAudioHardware, DeviceType::INPUT),
             kAudioObjectUnknown,
         );
     }
 
     if test_get_default_device(Scope::Output).is_some() {
         assert_ne!(
-            audiounit_get_default_device_id(DeviceType::OUTPUT),
+            audiounit_get_default_device_id(&CoreAudioHardware, DeviceType::OUTPUT),
             kAudioObjectUnknown,
         );
     }
@@ -342,7 +362,7 @@ fn test_get_default_device_id() {
 #[should_panic]
 fn test_get_default_device_id_with_unknown_type() {
     assert_eq!(
-        audiounit_get_default_device_id(DeviceType::UNKNOWN),
+        audiounit_get_default_device_id(&CoreAudioHardware, DeviceType::UNKNOWN),
         kAudioObjectUnknown,
     );
 }
@@ -351,7 +371,7 @@ fn test_get_default_device_id_with_unknown_type() {
 #[should_panic]
 fn test_get_default_device_id_with_inout_type() {
     assert_eq!(
-        audiounit_get_default_device_id(DeviceType::INPUT | DeviceType::OUTPUT),
+        audiounit_get_default_device_id(&CoreAudioHardware, DeviceType::INPUT | DeviceType::OUTPUT),
         kAudioObjectUnknown,
     );
 }
@@ -743,7 +763,7 @@ fn test_get_preferred_channel_layout_output() {
     let unit = unit.unwrap();
     if let Some(layout) = devices_layouts.get(source.as_str()) {
         assert_eq!(
-            &audiounit_get_preferred_channel_layout(unit.get_inner()),
+            &audiounit_get_preferred_channel_layout(&CoreAudioUnits, unit.get_inner()),
             layout
         );
     } else {
@@ -779,7 +799,7 @@ fn test_get_current_channel_layout_output() {
     let unit = unit.unwrap();
     if let Some(layout) = devices_layouts.get(source.as_str()) {
         assert_eq!(
-            audiounit_get_current_channel_layout(unit.get_inner()),
+            audiounit_get_current_channel_layout(&CoreAudioUnits, unit.get_inner()),
             *layout
         );
     } else {
@@ -822,7 +842,8 @@ fn test_create_stream_description() {
         raw.layout = ffi::CUBEB_LAYOUT_UNDEFINED;
         raw.prefs = ffi::CUBEB_STREAM_PREF_NONE;
         let params = StreamParams::from(raw);
-        let description = create_stream_description(&params).unwrap();
+        let description =
+            create_stream_description(StreamFormat::from(params.format()), &params).unwrap();
         assert_eq!(description.mFormatID, kAudioFormatLinearPCM);
         assert_eq!(
             description.mFormatFlags,
@@ -837,6 +858,95 @@ fn test_create_stream_description() {
     }
 }
 
+#[test]
+fn test_create_stream_description_of_all_formats() {
+    fn bytes_of(description: &AudioStreamBasicDescription) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                description as *const AudioStreamBasicDescription as *const u8,
+                mem::size_of::<AudioStreamBasicDescription>(),
+            )
+        }
+    }
+
+    let mut raw = ffi::cubeb_stream_params::default();
+    raw.format = ffi::CUBEB_SAMPLE_FLOAT32NE;
+    raw.rate = 48_000;
+    raw.channels = 3;
+    raw.layout = ffi::CUBEB_LAYOUT_UNDEFINED;
+    raw.prefs = ffi::CUBEB_STREAM_PREF_NONE;
+    let params = StreamParams::from(raw);
+    // The format of the params is overridden by the stream format.
+    for (format, bits, bytes, flags) in [
+        (
+            StreamFormat::S16LE,
+            16_u32,
+            2_u32,
+            kAudioFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked,
+        ),
+        (
+            StreamFormat::S16BE,
+            16,
+            2,
+            kAudioFormatFlagIsSignedInteger
+                | kAudioFormatFlagIsBigEndian
+                | kLinearPCMFormatFlagIsPacked,
+        ),
+        (
+            StreamFormat::Float32LE,
+            32,
+            4,
+            kAudioFormatFlagIsFloat | kLinearPCMFormatFlagIsPacked,
+        ),
+        (
+            StreamFormat::Float32BE,
+            32,
+            4,
+            kAudioFormatFlagIsFloat | kAudioFormatFlagIsBigEndian | kLinearPCMFormatFlagIsPacked,
+        ),
+        (
+            StreamFormat::S24LE,
+            24,
+            3,
+            kAudioFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked,
+        ),
+        (
+            StreamFormat::S24In32LE,
+            24,
+            4,
+            kAudioFormatFlagIsSignedInteger,
+        ),
+        (
+            StreamFormat::S32LE,
+            32,
+            4,
+            kAudioFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked,
+        ),
+        (
+            StreamFormat::Float64LE,
+            64,
+            8,
+            kAudioFormatFlagIsFloat | kLinearPCMFormatFlagIsPacked,
+        ),
+    ]
+    .iter()
+    {
+        let expected = AudioStreamBasicDescription {
+            mSampleRate: 48_000.0,
+            mFormatID: kAudioFormatLinearPCM,
+            mFormatFlags: *flags,
+            mBytesPerPacket: bytes * 3,
+            mFramesPerPacket: 1,
+            mBytesPerFrame: bytes * 3,
+            mChannelsPerFrame: 3,
+            mBitsPerChannel: *bits,
+            mReserved: 0,
+        };
+        let description = create_stream_description(*format, &params).unwrap();
+        assert_eq!(bytes_of(&description), bytes_of(&expected), "{:?}", format);
+    }
+}
+
 // create_default_audiounit
 // ------------------------------------
 #[test]
@@ -852,7 +962,7 @@ fn test_create_default_audiounit() {
     ];
 
     for flags in flags_list.iter() {
-        let unit = create_default_audiounit(*flags).unwrap();
+        let unit = create_default_audiounit(&CoreAudioUnits, *flags).unwrap();
         assert!(!unit.is_null());
         // Destroy the AudioUnits
         unsafe {
@@ -870,10 +980,31 @@ fn test_enable_audiounit_scope() {
     // for the unit whose subtype is kAudioUnitSubType_HALOutput
     // even when there is no available input or output devices.
     if let Some(unit) = test_create_audiounit(ComponentSubType::HALOutput) {
-        assert!(enable_audiounit_scope(unit.get_inner(), DeviceType::OUTPUT, true).is_ok());
-        assert!(enable_audiounit_scope(unit.get_inner(), DeviceType::OUTPUT, false).is_ok());
-        assert!(enable_audiounit_scope(unit.get_inner(), DeviceType::INPUT, true).is_ok());
-        assert!(enable_audiounit_scope(unit.get_inner(), DeviceType::INPUT, false).is_ok());
+        assert!(enable_audiounit_scope(
+            &CoreAudioUnits,
+            unit.get_inner(),
+            DeviceType::OUTPUT,
+            true
+        )
+        .is_ok());
+        assert!(enable_audiounit_scope(
+            &CoreAudioUnits,
+            unit.get_inner(),
+            DeviceType::OUTPUT,
+            false
+        )
+        .is_ok());
+        assert!(
+            enable_audiounit_scope(&CoreAudioUnits, unit.get_inner(), DeviceType::INPUT, true)
+                .is_ok()
+        );
+        assert!(enable_audiounit_scope(
+            &CoreAudioUnits,
+            unit.get_inner(),
+            DeviceType::INPUT,
+            false
+        )
+        .is_ok());
     } else {
         println!("No audiounit to perform test.");
     }
@@ -883,19 +1014,23 @@ fn test_enable_audiounit_scope() {
 fn test_enable_audiounit_scope_for_default_output_unit() {
     if let Some(unit) = test_create_audiounit(ComponentSubType::DefaultOutput) {
         assert_eq!(
-            enable_audiounit_scope(unit.get_inner(), DeviceType::OUTPUT, true).unwrap_err(),
+            enable_audiounit_scope(&CoreAudioUnits, unit.get_inner(), DeviceType::OUTPUT, true)
+                .unwrap_err(),
             kAudioUnitErr_InvalidProperty
         );
         assert_eq!(
-            enable_audiounit_scope(unit.get_inner(), DeviceType::OUTPUT, false).unwrap_err(),
+            enable_audiounit_scope(&CoreAudioUnits, unit.get_inner(), DeviceType::OUTPUT, false)
+                .unwrap_err(),
             kAudioUnitErr_InvalidProperty
         );
         assert_eq!(
-            enable_audiounit_scope(unit.get_inner(), DeviceType::INPUT, true).unwrap_err(),
+            enable_audiounit_scope(&CoreAudioUnits, unit.get_inner(), DeviceType::INPUT, true)
+                .unwrap_err(),
             kAudioUnitErr_InvalidProperty
         );
         assert_eq!(
-            enable_audiounit_scope(unit.get_inner(), DeviceType::INPUT, false).unwrap_err(),
+            enable_audiounit_scope(&CoreAudioUnits, unit.get_inner(), DeviceType::INPUT, false)
+                .unwrap_err(),
             kAudioUnitErr_InvalidProperty
         );
     }
@@ -905,7 +1040,7 @@ fn test_enable_audiounit_scope_for_default_output_unit() {
 #[should_panic]
 fn test_enable_audiounit_scope_with_null_unit() {
     let unit: AudioUnit = ptr::null_mut();
-    assert!(enable_audiounit_scope(unit, DeviceType::INPUT, false).is_err());
+    assert!(enable_audiounit_scope(&CoreAudioUnits, unit, DeviceType::INPUT, false).is_err());
 }
 
 // create_audiounit
@@ -930,7 +1065,7 @@ fn test_for_create_audiounit() {
         if device.flags.contains(device_flags::DEV_OUTPUT) && default_output.is_some() {
             let device_id = default_output.clone().unwrap();
             device.id = device_id;
-            let unit = create_audiounit(&device).unwrap();
+            let unit = create_audiounit(&CoreAudioUnits, &device).unwrap();
             assert!(!unit.is_null());
             assert!(test_audiounit_scope_is_enabled(unit, Scope::Output));
 
@@ -964,7 +1099,7 @@ fn test_for_create_audiounit() {
         if device.flags.contains(device_flags::DEV_INPUT) && default_input.is_some() {
             let device_id = default_input.clone().unwrap();
             device.id = device_id;
-            let unit = create_audiounit(&device).unwrap();
+            let unit = create_audiounit(&CoreAudioUnits, &device).unwrap();
             assert!(!unit.is_null());
             assert!(test_audiounit_scope_is_enabled(unit, Scope::Input));
             // Destroy the audioUnit.
@@ -980,7 +1115,7 @@ fn test_for_create_audiounit() {
 #[should_panic]
 fn test_create_audiounit_with_unknown_scope() {
     let device = device_info::default();
-    let _unit = create_audiounit(&device);
+    let _unit = create_audiounit(&CoreAudioUnits, &device);
 }
 
 // clamp_latency
@@ -1022,9 +1157,13 @@ fn test_set_buffer_size_sync() {
         .unwrap();
         assert_ne!(buffer_frames, 0);
         buffer_frames *= 2;
-        assert!(
-            set_buffer_size_sync(unit.get_inner(), scope.clone().into(), buffer_frames).is_ok()
-        );
+        assert!(set_buffer_size_sync(
+            &CoreAudioUnits,
+            unit.get_inner(),
+            scope.clone().into(),
+            buffer_frames
+        )
+        .is_ok());
         let new_buffer_frames =
             test_audiounit_get_buffer_frame_size(unit.get_inner(), scope.clone(), prop_scope)
                 .unwrap();
@@ -1046,7 +1185,7 @@ fn test_set_buffer_size_sync_for_output_with_null_output_unit() {
 
 fn test_set_buffer_size_sync_by_scope_with_null_unit(scope: Scope) {
     let unit: AudioUnit = ptr::null_mut();
-    assert!(set_buffer_size_sync(unit, scope.into(), 2048).is_err());
+    assert!(set_buffer_size_sync(&CoreAudioUnits, unit, scope.into(), 2048).is_err());
 }
 
 // get_volume, set_volume
@@ -1055,8 +1194,11 @@ fn test_set_buffer_size_sync_by_scope_with_null_unit(scope: Scope) {
 fn test_stream_get_volume() {
     if let Some(unit) = test_get_default_audiounit(Scope::Output) {
         let expected_volume: f32 = 0.5;
-        set_volume(unit.get_inner(), expected_volume);
-        assert_eq!(expected_volume, get_volume(unit.get_inner()).unwrap());
+        set_volume(&CoreAudioUnits, unit.get_inner(), expected_volume);
+        assert_eq!(
+            expected_volume,
+            get_volume(&CoreAudioUnits, unit.get_inner()).unwrap()
+        );
     } else {
         println!("No output audiounit.");
     }
@@ -1083,7 +1225,7 @@ fn test_get_default_device_name() {
 
     fn test_get_default_device_name_in_scope(scope: Scope) {
         if let Some(name) = test_get_default_source_name(scope.clone()) {
-            let source = audiounit_get_default_datasource_string(scope.into())
+            let source = audiounit_get_default_datasource_string(&CoreAudioHardware, scope.into())
                 .unwrap()
                 .into_string()
                 .unwrap();
@@ -1103,7 +1245,11 @@ fn test_is_device_a_type_of() {
 
     fn test_is_device_in_scope(scope: Scope) {
         if let Some(device) = test_get_default_device(scope.clone()) {
-            assert!(is_device_a_type_of(device, scope.into()));
+            assert!(is_device_a_type_of(
+                &CoreAudioHardware,
+                device,
+                scope.into()
+            ));
         } else {
             println!("No device for {:?}.", scope);
         }
@@ -1119,7 +1265,9 @@ fn test_get_channel_count() {
 
     fn test_channel_count(scope: Scope) {
         if let Some(device) = test_get_default_device(scope.clone()) {
-            let channels = get_channel_count(device, DeviceType::from(scope.clone())).unwrap();
+            let channels =
+                get_channel_count(&CoreAudioHardware, device, DeviceType::from(scope.clone()))
+                    .unwrap();
             assert!(channels > 0);
             assert_eq!(
                 channels,
@@ -1139,7 +1287,7 @@ fn test_get_channel_count_of_input_for_a_output_only_deivce() {
         if test_device_in_scope(device, Scope::Input) {
             continue;
         }
-        let count = get_channel_count(device, DeviceType::INPUT).unwrap();
+        let count = get_channel_count(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         assert_eq!(count, 0);
     }
 }
@@ -1152,7 +1300,7 @@ fn test_get_channel_count_of_output_for_a_input_only_deivce() {
         if test_device_in_scope(device, Scope::Output) {
             continue;
         }
-        let count = get_channel_count(device, DeviceType::OUTPUT).unwrap();
+        let count = get_channel_count(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         assert_eq!(count, 0);
     }
 }
@@ -1161,7 +1309,7 @@ fn test_get_channel_count_of_output_for_a_input_only_deivce() {
 #[should_panic]
 fn test_get_channel_count_of_unknown_device() {
     assert_eq!(
-        get_channel_count(kAudioObjectUnknown, DeviceType::OUTPUT).unwrap_err(),
+        get_channel_count(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::OUTPUT).unwrap_err(),
         Error::error()
     );
 }
@@ -1175,7 +1323,12 @@ fn test_get_channel_count_of_inout_type() {
         if let Some(device) = test_get_default_device(scope.clone()) {
             assert_eq!(
                 // Get a kAudioHardwareUnknownPropertyError in get_channel_count actually.
-                get_channel_count(device, DeviceType::INPUT | DeviceType::OUTPUT).unwrap_err(),
+                get_channel_count(
+                    &CoreAudioHardware,
+                    device,
+                    DeviceType::INPUT | DeviceType::OUTPUT
+                )
+                .unwrap_err(),
                 Error::error()
             );
         } else {
@@ -1193,7 +1346,7 @@ fn test_get_channel_count_of_unknwon_type() {
     fn test_channel_count(scope: Scope) {
         if let Some(device) = test_get_default_device(scope.clone()) {
             assert_eq!(
-                get_channel_count(device, DeviceType::UNKNOWN).unwrap_err(),
+                get_channel_count(&CoreAudioHardware, device, DeviceType::UNKNOWN).unwrap_err(),
                 Error::error()
             );
         } else {
@@ -1229,7 +1382,7 @@ fn test_get_range_of_sample_rates() {
         ];
         let mut ranges = Vec::new();
         for scope in scopes.iter() {
-            ranges.push(get_range_of_sample_rates(id, *scope).unwrap());
+            ranges.push(get_range_of_sample_rates(&CoreAudioHardware, id, *scope).unwrap());
         }
         ranges
     }
@@ -1251,7 +1404,8 @@ fn test_get_device_presentation_latency() {
     fn test_get_device_presentation_latencies_in_scope(scope: Scope) {
         if let Some(device) = test_get_default_device(scope.clone()) {
             // TODO: The latencies very from devices to devices. Check nothing here.
-            let latency = get_presentation_latency(device, scope.clone().into());
+            let latency =
+                get_presentation_latency(&CoreAudioHardware, device, scope.clone().into());
             println!(
                 "present latency on the device {} in scope {:?}: {}",
                 device, scope, latency
@@ -1267,7 +1421,7 @@ fn test_get_device_presentation_latency() {
 #[test]
 fn test_get_device_group_id() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        match get_device_group_id(device, DeviceType::INPUT) {
+        match get_device_group_id(&CoreAudioHardware, device, DeviceType::INPUT) {
             Ok(id) => println!("input group id: {:?}", id),
             Err(e) => println!("No input group id. Error: {}", e),
         }
@@ -1276,7 +1430,7 @@ fn test_get_device_group_id() {
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        match get_device_group_id(device, DeviceType::OUTPUT) {
+        match get_device_group_id(&CoreAudioHardware, device, DeviceType::OUTPUT) {
             Ok(id) => println!("output group id: {:?}", id),
             Err(e) => println!("No output group id. Error: {}", e),
         }
@@ -1300,8 +1454,9 @@ fn test_get_same_group_id_for_builtin_device_pairs() {
     let mut group_ids = HashMap::<u32, String>::new();
     let input_devices = test_get_devices_in_scope(Scope::Input);
     for device in input_devices.iter() {
-        match get_device_source(*device, DeviceType::INPUT) {
-            Ok(source) => match get_device_group_id(*device, DeviceType::INPUT) {
+        match get_device_source(&CoreAudioHardware, *device, DeviceType::INPUT) {
+            Ok(source) => match get_device_group_id(&CoreAudioHardware, *device, DeviceType::INPUT)
+            {
                 Ok(id) => assert!(group_ids
                     .insert(source, id.into_string().unwrap())
                     .is_none()),
@@ -1312,13 +1467,15 @@ fn test_get_same_group_id_for_builtin_device_pairs() {
     }
     let output_devices = test_get_devices_in_scope(Scope::Output);
     for device in output_devices.iter() {
-        match get_device_source(*device, DeviceType::OUTPUT) {
-            Ok(source) => match get_device_group_id(*device, DeviceType::OUTPUT) {
-                Ok(id) => assert!(group_ids
-                    .insert(source, id.into_string().unwrap())
-                    .is_none()),
-                Err(e) => assert!(group_ids.insert(source, format!("Error {}", e)).is_none()),
-            },
+        match get_device_source(&CoreAudioHardware, *device, DeviceType::OUTPUT) {
+            Ok(source) => {
+                match get_device_group_id(&CoreAudioHardware, *device, DeviceType::OUTPUT) {
+                    Ok(id) => assert!(group_ids
+                        .insert(source, id.into_string().unwrap())
+                        .is_none()),
+                    Err(e) => assert!(group_ids.insert(source, format!("Error {}", e)).is_none()),
+                }
+            }
             _ => {} // do nothing when failing to get source.
         }
     }
@@ -1339,7 +1496,9 @@ fn test_get_same_group_id_for_builtin_device_pairs() {
 #[test]
 #[should_panic]
 fn test_get_device_group_id_by_unknown_device() {
-    assert!(get_device_group_id(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_group_id(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err()
+    );
 }
 
 // get_device_label
@@ -1347,14 +1506,14 @@ fn test_get_device_group_id_by_unknown_device() {
 #[test]
 fn test_get_device_label() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let name = get_device_label(device, DeviceType::INPUT).unwrap();
+        let name = get_device_label(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         println!("input device label: {}", name.into_string());
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let name = get_device_label(device, DeviceType::OUTPUT).unwrap();
+        let name = get_device_label(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         println!("output device label: {}", name.into_string());
     } else {
         println!("No output device.");
@@ -1364,7 +1523,7 @@ fn test_get_device_label() {
 #[test]
 #[should_panic]
 fn test_get_device_label_by_unknown_device() {
-    assert!(get_device_label(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(get_device_label(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err());
 }
 
 // get_device_global_uid
@@ -1373,14 +1532,14 @@ fn test_get_device_label_by_unknown_device() {
 fn test_get_device_global_uid() {
     // Input device.
     if let Some(input) = test_get_default_device(Scope::Input) {
-        let uid = get_device_global_uid(input).unwrap();
+        let uid = get_device_global_uid(&CoreAudioHardware, input).unwrap();
         let uid = uid.into_string();
         assert!(!uid.is_empty());
     }
 
     // Output device.
     if let Some(output) = test_get_default_device(Scope::Output) {
-        let uid = get_device_global_uid(output).unwrap();
+        let uid = get_device_global_uid(&CoreAudioHardware, output).unwrap();
         let uid = uid.into_string();
         assert!(!uid.is_empty());
     }
@@ -1390,7 +1549,7 @@ fn test_get_device_global_uid() {
 #[should_panic]
 fn test_get_device_global_uid_by_unknwon_device() {
     // Unknown device.
-    assert!(get_device_global_uid(kAudioObjectUnknown).is_err());
+    assert!(get_device_global_uid(&CoreAudioHardware, kAudioObjectUnknown).is_err());
 }
 
 // create_cubeb_device_info
@@ -1438,7 +1597,7 @@ fn test_create_cubeb_device_info() {
         let dev_types = [DeviceType::INPUT, DeviceType::OUTPUT];
         let mut results = VecDeque::new();
         for dev_type in dev_types.iter() {
-            results.push_back(create_cubeb_device_info(id, *dev_type));
+            results.push_back(create_cubeb_device_info(&CoreAudioHardware, id, *dev_type));
         }
         results
     }
@@ -1488,7 +1647,10 @@ fn test_create_cubeb_device_info() {
 #[test]
 #[should_panic]
 fn test_create_device_info_by_unknown_device() {
-    assert!(create_cubeb_device_info(kAudioObjectUnknown, DeviceType::OUTPUT).is_err());
+    assert!(
+        create_cubeb_device_info(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::OUTPUT)
+            .is_err()
+    );
 }
 
 #[test]
@@ -1499,7 +1661,9 @@ fn test_create_device_info_with_unknown_type() {
 
     fn test_create_device_info_with_unknown_type_by_scope(scope: Scope) {
         if let Some(device) = test_get_default_device(scope.clone()) {
-            assert!(create_cubeb_device_info(device, DeviceType::UNKNOWN).is_err());
+            assert!(
+                create_cubeb_device_info(&CoreAudioHardware, device, DeviceType::UNKNOWN).is_err()
+            );
         } else {
             panic!("Panic by default: No device for {:?}.", scope);
         }
@@ -1533,9 +1697,12 @@ fn test_create_device_from_hwdev_with_inout_type() {
     fn test_create_device_from_hwdev_with_inout_type_by_scope(scope: Scope) {
         if let Some(device) = test_get_default_device(scope.clone()) {
             // Get a kAudioHardwareUnknownPropertyError in get_channel_count actually.
-            assert!(
-                create_cubeb_device_info(device, DeviceType::INPUT | DeviceType::OUTPUT).is_err()
-            );
+            assert!(create_cubeb_device_info(
+                &CoreAudioHardware,
+                device,
+                DeviceType::INPUT | DeviceType::OUTPUT
+            )
+            .is_err());
         } else {
             println!("No device for {:?}.", scope);
         }
@@ -1565,9 +1732,10 @@ fn test_is_aggregate_device() {
 fn test_get_devices_of_type() {
     use std::collections::HashSet;
 
-    let all_devices = audiounit_get_devices_of_type(DeviceType::INPUT | DeviceType::OUTPUT);
-    let input_devices = audiounit_get_devices_of_type(DeviceType::INPUT);
-    let output_devices = audiounit_get_devices_of_type(DeviceType::OUTPUT);
+    let all_devices =
+        audiounit_get_devices_of_type(&CoreAudioHardware, DeviceType::INPUT | DeviceType::OUTPUT);
+    let input_devices = audiounit_get_devices_of_type(&CoreAudioHardware, DeviceType::INPUT);
+    let output_devices = audiounit_get_devices_of_type(&CoreAudioHardware, DeviceType::OUTPUT);
 
     let mut expected_all = test_get_all_devices();
     expected_all.sort();
@@ -1592,7 +1760,7 @@ fn test_get_devices_of_type() {
 #[test]
 #[should_panic]
 fn test_get_devices_of_type_unknown() {
-    let no_devs = audiounit_get_devices_of_type(DeviceType::UNKNOWN);
+    let no_devs = audiounit_get_devices_of_type(&CoreAudioHardware, DeviceType::UNKNOWN);
     assert!(no_devs.is_empty());
 }
 
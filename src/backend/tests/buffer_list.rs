@@ -0,0 +1,127 @@
+use super::*;
+
+fn audio_buffer(channels: u32, data: &mut [u8]) -> AudioBuffer {
+    AudioBuffer {
+        mNumberChannels: channels,
+        mDataByteSize: data.len() as u32,
+        mData: data.as_mut_ptr() as *mut c_void,
+    }
+}
+
+// gather_buffers and scatter_buffers
+// ------------------------------------
+#[test]
+fn test_scatter_and_gather_buffers() {
+    // 3 frames of 3 channels, one byte per sample.
+    let data: Vec<u8> = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+    let mut first = vec![0u8; 6];
+    let mut second = vec![0u8; 3];
+    let mut buffers = [audio_buffer(2, &mut first), audio_buffer(1, &mut second)];
+
+    scatter_buffers(&data, &mut buffers, 3, 1, 3);
+    assert_eq!(first, vec![1, 2, 4, 5, 7, 8]);
+    assert_eq!(second, vec![3, 6, 9]);
+
+    let mut gathered = vec![0xFFu8; 9];
+    gather_buffers(&buffers, &mut gathered, 3, 1, 3);
+    assert_eq!(gathered, data);
+}
+
+#[test]
+fn test_gather_buffers_with_missing_channels() {
+    // The buffers hold 1 of the 2 channels, and 2 of the 3 frames.
+    let mut left = vec![1u8, 0, 2, 0];
+    let buffers = [audio_buffer(1, &mut left)];
+    let mut data = vec![0xFFu8; 12];
+    gather_buffers(&buffers, &mut data, 2, 2, 3);
+    assert_eq!(data, vec![1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_gather_buffers_skips_null_buffers() {
+    let mut right = vec![3u8, 4];
+    let buffers = [
+        AudioBuffer {
+            mNumberChannels: 1,
+            mDataByteSize: 2,
+            mData: ptr::null_mut(),
+        },
+        audio_buffer(1, &mut right),
+    ];
+    let mut data = vec![0xFFu8; 4];
+    gather_buffers(&buffers, &mut data, 2, 1, 2);
+    assert_eq!(data, vec![0, 3, 0, 4]);
+}
+
+#[test]
+fn test_scatter_buffers_silences_extra_channels() {
+    // The 2 channels of the data go into buffers holding 3 channels.
+    let data: Vec<u8> = vec![1, 2, 3, 4];
+    let mut first = vec![0xFFu8; 2];
+    let mut second = vec![0xFFu8; 4];
+    let mut buffers = [audio_buffer(1, &mut first), audio_buffer(2, &mut second)];
+    scatter_buffers(&data, &mut buffers, 2, 1, 2);
+    assert_eq!(first, vec![1, 3]);
+    assert_eq!(second, vec![2, 0, 4, 0]);
+}
+
+// BufferList
+// ------------------------------------
+#[test]
+fn test_buffer_list_prepare() {
+    let mut list = BufferList::new(4);
+    list.prepare(4, 1, mem::size_of::<f32>(), 256);
+    assert_eq!(unsafe { (*list.as_mut_ptr()).mNumberBuffers }, 4);
+    let buffers = list.buffers_mut();
+    assert_eq!(buffers.len(), 4);
+    for buffer in buffers.iter() {
+        assert_eq!(buffer.mNumberChannels, 1);
+        assert_eq!(buffer.mDataByteSize, 256 * 4);
+        assert!(buffer.mData.is_null());
+    }
+
+    list.prepare(1, 4, mem::size_of::<f32>(), 128);
+    let buffers = list.buffers_mut();
+    assert_eq!(buffers.len(), 1);
+    assert_eq!(buffers[0].mNumberChannels, 4);
+    assert_eq!(buffers[0].mDataByteSize, 128 * 4 * 4);
+}
+
+#[test]
+#[should_panic]
+fn test_buffer_list_prepare_over_capacity() {
+    let mut list = BufferList::new(1);
+    list.prepare(2, 1, mem::size_of::<f32>(), 256);
+}
+
+#[test]
+fn test_buffer_count() {
+    let mut desc = AudioStreamBasicDescription {
+        mChannelsPerFrame: 3,
+        mFormatFlags: kAudioFormatFlagIsFloat,
+        ..Default::default()
+    };
+    assert_eq!(buffer_count(&desc), 1);
+    desc.mFormatFlags |= kAudioFormatFlagIsNonInterleaved;
+    assert_eq!(buffer_count(&desc), 3);
+}
+
+#[test]
+fn test_non_interleaved() {
+    let desc = AudioStreamBasicDescription {
+        mFormatFlags: kAudioFormatFlagIsFloat,
+        mBytesPerPacket: 12,
+        mFramesPerPacket: 1,
+        mBytesPerFrame: 12,
+        mChannelsPerFrame: 3,
+        mBitsPerChannel: 32,
+        ..Default::default()
+    };
+    let planar = non_interleaved(&desc);
+    assert_eq!(buffer_count(&planar), 3);
+    assert_eq!(planar.mBytesPerFrame, 4);
+    assert_eq!(planar.mBytesPerPacket, 4);
+    assert_eq!(planar.mChannelsPerFrame, 3);
+    // It's already one buffer per channel.
+    assert_eq!(non_interleaved(&planar).mBytesPerFrame, 4);
+}
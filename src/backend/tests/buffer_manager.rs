@@ -0,0 +1,168 @@
+use super::*;
+use crate::backend::sample::Sample;
+
+// input_buffer_capacity
+// ------------------------------------
+#[test]
+fn test_input_buffer_capacity() {
+    // Two callbacks of each side and 50 ms at 48 kHz.
+    assert_eq!(
+        input_buffer_capacity(1, 48_000.0, 48_000.0, 512, Duration::from_millis(50)),
+        4 * 512 + 2_400
+    );
+    // The capacity grows with the channels.
+    assert_eq!(
+        input_buffer_capacity(32, 48_000.0, 48_000.0, 512, Duration::from_millis(50)),
+        32 * (4 * 512 + 2_400)
+    );
+    // An output callback takes more input frames when the input rate is higher.
+    assert_eq!(
+        input_buffer_capacity(2, 96_000.0, 48_000.0, 256, Duration::from_millis(0)),
+        2 * (2 * (256 + 512))
+    );
+    assert_eq!(
+        input_buffer_capacity(1, 44_100.0, 48_000.0, 480, Duration::from_millis(0)),
+        2 * (480 + 441)
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_input_buffer_capacity_zero_input_rate() {
+    input_buffer_capacity(1, 0.0, 48_000.0, 512, DEFAULT_INPUT_BUFFER_MARGIN);
+}
+
+// BufferManager
+// ------------------------------------
+#[test]
+fn test_buffer_manager_push_and_pull() {
+    let mut manager = BufferManager::<f32>::new(8);
+    assert_eq!(manager.capacity(), 8);
+    let data: Vec<f32> = (1..=6).map(|i| i as f32).collect();
+    assert_eq!(manager.push_data(&data), 6);
+    // The silence fills the buffer up to its capacity.
+    assert_eq!(manager.push_silent_data(4), 2);
+    assert_eq!(manager.available_samples(), 8);
+
+    // The oldest samples are trimmed.
+    manager.trim(5);
+    assert_eq!(manager.available_samples(), 5);
+    // The missing samples are pulled as silence.
+    assert_eq!(manager.get_linear_data(6), &[4.0, 5.0, 6.0, 0.0, 0.0, 0.0]);
+    assert_eq!(manager.available_samples(), 0);
+}
+
+#[test]
+fn test_buffer_manager_large_silence() {
+    // The silence isn't limited by a fixed size.
+    let mut manager = BufferManager::<i16>::new(100_000);
+    assert_eq!(manager.push_silent_data(100_000), 100_000);
+    manager.trim(0);
+    assert_eq!(manager.available_samples(), 0);
+}
+
+// A xorshift generator, so the failures are reproducible by the seed.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+// Run random sequences of pushes, silences, pulls and trims on a `BufferManager`, and check it
+// behaves like a bounded queue.
+fn check_buffer_manager_as_queue<T: Sample + PartialEq>(sample: fn(u64) -> T) {
+    use std::collections::VecDeque;
+
+    for seed in 1..=20 {
+        let mut random = Random(seed);
+        let capacity = 1 + random.below(512);
+        let mut manager = BufferManager::<T>::new(capacity);
+        let mut model = VecDeque::new();
+        let mut next_value = 1;
+        for step in 0..1_000 {
+            let samples = random.below(2 * capacity);
+            match random.below(4) {
+                0 => {
+                    let data: Vec<T> = (next_value..next_value + samples as u64)
+                        .map(sample)
+                        .collect();
+                    next_value += samples as u64;
+                    let pushed = manager.push_data(&data);
+                    assert_eq!(pushed, cmp::min(samples, capacity - model.len()));
+                    model.extend(data[..pushed].iter().cloned());
+                }
+                1 => {
+                    let pushed = manager.push_silent_data(samples);
+                    assert_eq!(pushed, cmp::min(samples, capacity - model.len()));
+                    model.extend((0..pushed).map(|_| T::default()));
+                }
+                2 => {
+                    let pulled = manager.get_linear_data(samples).to_vec();
+                    assert_eq!(pulled.len(), samples);
+                    let read = cmp::min(samples, model.len());
+                    let expected: Vec<T> = model
+                        .drain(..read)
+                        .chain((read..samples).map(|_| T::default()))
+                        .collect();
+                    assert_eq!(pulled, expected, "seed {}, step {}", seed, step);
+                }
+                _ => {
+                    let final_size = random.below(model.len() + 1);
+                    manager.trim(final_size);
+                    let excess = model.len() - final_size;
+                    model.drain(..excess);
+                }
+            }
+            assert_eq!(manager.available_samples(), model.len());
+            assert!(manager.available_samples() <= manager.capacity());
+        }
+    }
+}
+
+#[test]
+fn test_buffer_manager_as_queue_i16() {
+    check_buffer_manager_as_queue::<i16>(|i| (i % 32_767) as i16 + 1);
+}
+
+#[test]
+fn test_buffer_manager_as_queue_f32() {
+    check_buffer_manager_as_queue::<f32>(|i| i as f32);
+}
+
+#[test]
+#[should_panic]
+fn test_buffer_manager_trim_beyond_buffered() {
+    let mut manager = BufferManager::<f32>::new(8);
+    manager.push_silent_data(2);
+    manager.trim(4);
+}
+
+// InputBuffer
+// ------------------------------------
+#[test]
+fn test_input_buffer_untyped_data() {
+    let mut buffer = InputBuffer::new(StreamFormat::S16LE, 4);
+    assert_eq!(buffer.capacity(), 4);
+    let data: Vec<i16> = vec![1, -1, 2, -2, 3, -3];
+    assert_eq!(buffer.push_data(data.as_ptr() as *const c_void, 6), 4);
+    buffer.trim(2);
+    let output = buffer.get_linear_data(4);
+    let output = unsafe { slice::from_raw_parts(output as *const i16, 4) };
+    assert_eq!(output, &[2, -2, 0, 0]);
+
+    let mut buffer = InputBuffer::new(StreamFormat::Float32LE, 4);
+    assert_eq!(buffer.push_data(ptr::null(), 0), 0);
+    assert_eq!(buffer.push_silent_data(8), 4);
+    assert_eq!(buffer.available_samples(), 4);
+}
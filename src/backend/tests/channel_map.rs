@@ -0,0 +1,47 @@
+use super::*;
+
+// is_valid_channel_map
+// ------------------------------------
+#[test]
+fn test_is_valid_channel_map() {
+    assert!(is_valid_channel_map(&[4, 5], 2, 16));
+    assert!(is_valid_channel_map(&[15, 0, 15], 3, 16));
+    // One item per channel of the stream.
+    assert!(!is_valid_channel_map(&[4], 2, 16));
+    assert!(!is_valid_channel_map(&[4, 5, 6], 2, 16));
+    // Only the channels of the device.
+    assert!(!is_valid_channel_map(&[4, 16], 2, 16));
+    assert!(!is_valid_channel_map(&[0], 1, 0));
+}
+
+// ChannelMap
+// ------------------------------------
+#[test]
+fn test_channel_map() {
+    assert!(ChannelMap::new(&[4], 2, 16).is_none());
+    assert!(ChannelMap::new(&[4, 16], 2, 16).is_none());
+    let map = ChannelMap::new(&[4, 9], 2, 16).unwrap();
+    assert_eq!(&*map, &[4, 9]);
+    // The device may have fewer channels later.
+    assert!(map.fits(10));
+    assert!(!map.fits(9));
+}
+
+// extract_channels
+// ------------------------------------
+#[test]
+fn test_extract_channels() {
+    // 2 frames of 4 channels of 2-byte samples.
+    let input: Vec<u8> = (0..16).collect();
+    let mut output = vec![0xFFu8; 8];
+    extract_channels(&input, &mut output, 4, &[3, 1], 2, 2);
+    assert_eq!(output, vec![6, 7, 2, 3, 14, 15, 10, 11]);
+}
+
+#[test]
+fn test_extract_repeated_channel() {
+    let input: Vec<u8> = vec![1, 2, 3, 4];
+    let mut output = vec![0u8; 6];
+    extract_channels(&input, &mut output, 2, &[1, 1, 0], 1, 2);
+    assert_eq!(output, vec![2, 2, 1, 4, 4, 3]);
+}
@@ -377,7 +377,11 @@ fn test_register_device_changed_callback_to_check_default_device_changed(stm_typ
             for _ in 0..input_count {
                 // While the stream is re-initializing for the default device switch,
                 // switching for the default device again will be ignored.
-                while stream.switching_device.load(atomic::Ordering::SeqCst) {}
+                while stream
+                    .shared
+                    .switching_device
+                    .load(atomic::Ordering::SeqCst)
+                {}
                 changed_watcher.prepare();
                 input_device_switcher.next();
                 changed_watcher.wait_for_change();
@@ -386,7 +390,11 @@ fn test_register_device_changed_callback_to_check_default_device_changed(stm_typ
             for _ in 0..output_count {
                 // While the stream is re-initializing for the default device switch,
                 // switching for the default device again will be ignored.
-                while stream.switching_device.load(atomic::Ordering::SeqCst) {}
+                while stream
+                    .shared
+                    .switching_device
+                    .load(atomic::Ordering::SeqCst)
+                {}
                 changed_watcher.prepare();
                 output_device_switcher.next();
                 changed_watcher.wait_for_change();
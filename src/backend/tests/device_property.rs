@@ -7,14 +7,14 @@ use super::*;
 fn test_get_device_uid() {
     // Input device.
     if let Some(input) = test_get_default_device(Scope::Input) {
-        let uid = get_device_uid(input, DeviceType::INPUT).unwrap();
+        let uid = get_device_uid(&CoreAudioHardware, input, DeviceType::INPUT).unwrap();
         let uid = uid.into_string();
         assert!(!uid.is_empty());
     }
 
     // Output device.
     if let Some(output) = test_get_default_device(Scope::Output) {
-        let uid = get_device_uid(output, DeviceType::OUTPUT).unwrap();
+        let uid = get_device_uid(&CoreAudioHardware, output, DeviceType::OUTPUT).unwrap();
         let uid = uid.into_string();
         assert!(!uid.is_empty());
     }
@@ -24,7 +24,7 @@ fn test_get_device_uid() {
 #[should_panic]
 fn test_get_device_uid_by_unknwon_device() {
     // Unknown device.
-    assert!(get_device_uid(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(get_device_uid(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err());
 }
 
 // get_device_model_uid
@@ -33,7 +33,7 @@ fn test_get_device_uid_by_unknwon_device() {
 #[test]
 fn test_get_device_model_uid() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        match get_device_model_uid(device, DeviceType::INPUT) {
+        match get_device_model_uid(&CoreAudioHardware, device, DeviceType::INPUT) {
             Ok(uid) => println!("input model uid: {}", uid.into_string()),
             Err(e) => println!("No input model uid. Error: {}", e),
         }
@@ -42,7 +42,7 @@ fn test_get_device_model_uid() {
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        match get_device_model_uid(device, DeviceType::OUTPUT) {
+        match get_device_model_uid(&CoreAudioHardware, device, DeviceType::OUTPUT) {
             Ok(uid) => println!("output model uid: {}", uid.into_string()),
             Err(e) => println!("No output model uid. Error: {}", e),
         }
@@ -54,7 +54,9 @@ fn test_get_device_model_uid() {
 #[test]
 #[should_panic]
 fn test_get_device_model_uid_by_unknown_device() {
-    assert!(get_device_model_uid(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_model_uid(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err()
+    );
 }
 
 // get_device_transport_type
@@ -62,7 +64,7 @@ fn test_get_device_model_uid_by_unknown_device() {
 #[test]
 fn test_get_device_transport_type() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        match get_device_transport_type(device, DeviceType::INPUT) {
+        match get_device_transport_type(&CoreAudioHardware, device, DeviceType::INPUT) {
             Ok(trans_type) => println!(
                 "input transport type: {:X}, {:?}",
                 trans_type,
@@ -75,7 +77,7 @@ fn test_get_device_transport_type() {
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        match get_device_transport_type(device, DeviceType::OUTPUT) {
+        match get_device_transport_type(&CoreAudioHardware, device, DeviceType::OUTPUT) {
             Ok(trans_type) => println!(
                 "output transport type: {:X}, {:?}",
                 trans_type,
@@ -91,7 +93,60 @@ fn test_get_device_transport_type() {
 #[test]
 #[should_panic]
 fn test_get_device_transport_type_by_unknown_device() {
-    assert!(get_device_transport_type(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_transport_type(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT)
+            .is_err()
+    );
+}
+
+// get_device_is_alive
+// ------------------------------------
+#[test]
+fn test_get_device_is_alive() {
+    if let Some(device) = test_get_default_device(Scope::Output) {
+        assert_eq!(get_device_is_alive(&CoreAudioHardware, device), Ok(true));
+    } else {
+        println!("No output device.");
+    }
+}
+
+#[test]
+#[should_panic]
+fn test_get_device_is_alive_by_unknown_device() {
+    assert!(get_device_is_alive(&CoreAudioHardware, kAudioObjectUnknown).is_err());
+}
+
+// get_device_volume
+// ------------------------------------
+// Not all the devices have a master volume.
+#[test]
+fn test_get_device_volume() {
+    if let Some(device) = test_get_default_device(Scope::Input) {
+        match get_device_volume(&CoreAudioHardware, device, DeviceType::INPUT) {
+            Ok(volume) => assert!((0.0..=1.0).contains(&volume)),
+            Err(e) => println!("No input volume. Error: {}", e),
+        }
+    } else {
+        println!("No input device.");
+    }
+}
+
+#[test]
+#[should_panic]
+fn test_get_device_volume_by_unknown_device() {
+    assert!(get_device_volume(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err());
+}
+
+#[test]
+#[should_panic]
+fn test_set_device_volume_by_unknown_device() {
+    assert!(set_device_volume(
+        &CoreAudioHardware,
+        kAudioObjectUnknown,
+        DeviceType::INPUT,
+        1.0
+    )
+    .is_err());
 }
 
 // get_device_source
@@ -100,7 +155,7 @@ fn test_get_device_transport_type_by_unknown_device() {
 #[test]
 fn test_get_device_source() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        match get_device_source(device, DeviceType::INPUT) {
+        match get_device_source(&CoreAudioHardware, device, DeviceType::INPUT) {
             Ok(source) => println!(
                 "input source: {:X}, {:?}",
                 source,
@@ -113,7 +168,7 @@ fn test_get_device_source() {
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        match get_device_source(device, DeviceType::OUTPUT) {
+        match get_device_source(&CoreAudioHardware, device, DeviceType::OUTPUT) {
             Ok(source) => println!(
                 "output source: {:X}, {:?}",
                 source,
@@ -129,7 +184,7 @@ fn test_get_device_source() {
 #[test]
 #[should_panic]
 fn test_get_device_source_by_unknown_device() {
-    assert!(get_device_source(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(get_device_source(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err());
 }
 
 // get_device_source_name
@@ -137,7 +192,7 @@ fn test_get_device_source_by_unknown_device() {
 #[test]
 fn test_get_device_source_name() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        match get_device_source_name(device, DeviceType::INPUT) {
+        match get_device_source_name(&CoreAudioHardware, device, DeviceType::INPUT) {
             Ok(name) => println!("input: {}", name.into_string()),
             Err(e) => println!("No input data source name. Error: {}", e),
         }
@@ -146,7 +201,7 @@ fn test_get_device_source_name() {
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        match get_device_source_name(device, DeviceType::OUTPUT) {
+        match get_device_source_name(&CoreAudioHardware, device, DeviceType::OUTPUT) {
             Ok(name) => println!("output: {}", name.into_string()),
             Err(e) => println!("No output data source name. Error: {}", e),
         }
@@ -158,7 +213,9 @@ fn test_get_device_source_name() {
 #[test]
 #[should_panic]
 fn test_get_device_source_name_by_unknown_device() {
-    assert!(get_device_source_name(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_source_name(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err()
+    );
 }
 
 // get_device_name
@@ -166,14 +223,14 @@ fn test_get_device_source_name_by_unknown_device() {
 #[test]
 fn test_get_device_name() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let name = get_device_name(device, DeviceType::INPUT).unwrap();
+        let name = get_device_name(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         println!("input device name: {}", name.into_string());
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let name = get_device_name(device, DeviceType::OUTPUT).unwrap();
+        let name = get_device_name(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         println!("output device name: {}", name.into_string());
     } else {
         println!("No output device.");
@@ -183,7 +240,7 @@ fn test_get_device_name() {
 #[test]
 #[should_panic]
 fn test_get_device_name_by_unknown_device() {
-    assert!(get_device_name(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(get_device_name(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err());
 }
 
 // get_device_manufacturer
@@ -193,7 +250,7 @@ fn test_get_device_manufacturer() {
     if let Some(device) = test_get_default_device(Scope::Input) {
         // Some devices like AirPods cannot get the vendor info so we print the error directly.
         // TODO: Replace `map` and `unwrap_or_else` by `map_or_else`
-        let name = get_device_manufacturer(device, DeviceType::INPUT)
+        let name = get_device_manufacturer(&CoreAudioHardware, device, DeviceType::INPUT)
             .map(|name| name.into_string())
             .unwrap_or_else(|e| format!("Error: {}", e));
         println!("input device vendor: {}", name);
@@ -204,7 +261,7 @@ fn test_get_device_manufacturer() {
     if let Some(device) = test_get_default_device(Scope::Output) {
         // Some devices like AirPods cannot get the vendor info so we print the error directly.
         // TODO: Replace `map` and `unwrap_or_else` by `map_or_else`
-        let name = get_device_manufacturer(device, DeviceType::OUTPUT)
+        let name = get_device_manufacturer(&CoreAudioHardware, device, DeviceType::OUTPUT)
             .map(|name| name.into_string())
             .unwrap_or_else(|e| format!("Error: {}", e));
         println!("output device vendor: {}", name);
@@ -216,7 +273,10 @@ fn test_get_device_manufacturer() {
 #[test]
 #[should_panic]
 fn test_get_device_manufacturer_by_unknown_device() {
-    assert!(get_device_manufacturer(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_manufacturer(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT)
+            .is_err()
+    );
 }
 
 // get_device_buffer_frame_size_range
@@ -224,7 +284,9 @@ fn test_get_device_manufacturer_by_unknown_device() {
 #[test]
 fn test_get_device_buffer_frame_size_range() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let range = get_device_buffer_frame_size_range(device, DeviceType::INPUT).unwrap();
+        let range =
+            get_device_buffer_frame_size_range(&CoreAudioHardware, device, DeviceType::INPUT)
+                .unwrap();
         println!(
             "range of input buffer frame size: {}-{}",
             range.mMinimum, range.mMaximum
@@ -234,7 +296,9 @@ fn test_get_device_buffer_frame_size_range() {
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let range = get_device_buffer_frame_size_range(device, DeviceType::OUTPUT).unwrap();
+        let range =
+            get_device_buffer_frame_size_range(&CoreAudioHardware, device, DeviceType::OUTPUT)
+                .unwrap();
         println!(
             "range of output buffer frame size: {}-{}",
             range.mMinimum, range.mMaximum
@@ -247,7 +311,12 @@ fn test_get_device_buffer_frame_size_range() {
 #[test]
 #[should_panic]
 fn test_get_device_buffer_frame_size_range_by_unknown_device() {
-    assert!(get_device_buffer_frame_size_range(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(get_device_buffer_frame_size_range(
+        &CoreAudioHardware,
+        kAudioObjectUnknown,
+        DeviceType::INPUT
+    )
+    .is_err());
 }
 
 // get_device_latency
@@ -255,14 +324,14 @@ fn test_get_device_buffer_frame_size_range_by_unknown_device() {
 #[test]
 fn test_get_device_latency() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let latency = get_device_latency(device, DeviceType::INPUT).unwrap();
+        let latency = get_device_latency(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         println!("latency of input device: {}", latency);
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let latency = get_device_latency(device, DeviceType::OUTPUT).unwrap();
+        let latency = get_device_latency(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         println!("latency of output device: {}", latency);
     } else {
         println!("No output device.");
@@ -272,7 +341,9 @@ fn test_get_device_latency() {
 #[test]
 #[should_panic]
 fn test_get_device_latency_by_unknown_device() {
-    assert!(get_device_latency(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_latency(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err()
+    );
 }
 
 // get_device_streams
@@ -280,14 +351,14 @@ fn test_get_device_latency_by_unknown_device() {
 #[test]
 fn test_get_device_streams() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let streams = get_device_streams(device, DeviceType::INPUT).unwrap();
+        let streams = get_device_streams(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         println!("streams on the input device: {:?}", streams);
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let streams = get_device_streams(device, DeviceType::OUTPUT).unwrap();
+        let streams = get_device_streams(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         println!("streams on the output device: {:?}", streams);
     } else {
         println!("No output device.");
@@ -297,7 +368,9 @@ fn test_get_device_streams() {
 #[test]
 #[should_panic]
 fn test_get_device_streams_by_unknown_device() {
-    assert!(get_device_streams(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_streams(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err()
+    );
 }
 
 // get_device_sample_rate
@@ -305,14 +378,14 @@ fn test_get_device_streams_by_unknown_device() {
 #[test]
 fn test_get_device_sample_rate() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let rate = get_device_sample_rate(device, DeviceType::INPUT).unwrap();
+        let rate = get_device_sample_rate(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         println!("input sample rate: {}", rate);
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let rate = get_device_sample_rate(device, DeviceType::OUTPUT).unwrap();
+        let rate = get_device_sample_rate(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         println!("output sample rate: {}", rate);
     } else {
         println!("No output device.");
@@ -322,7 +395,9 @@ fn test_get_device_sample_rate() {
 #[test]
 #[should_panic]
 fn test_get_device_sample_rate_by_unknown_device() {
-    assert!(get_device_sample_rate(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_sample_rate(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err()
+    );
 }
 
 // get_ranges_of_device_sample_rate
@@ -330,14 +405,18 @@ fn test_get_device_sample_rate_by_unknown_device() {
 #[test]
 fn test_get_ranges_of_device_sample_rate() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let ranges = get_ranges_of_device_sample_rate(device, DeviceType::INPUT).unwrap();
+        let ranges =
+            get_ranges_of_device_sample_rate(&CoreAudioHardware, device, DeviceType::INPUT)
+                .unwrap();
         println!("ranges of input sample rate: {:?}", ranges);
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let ranges = get_ranges_of_device_sample_rate(device, DeviceType::OUTPUT).unwrap();
+        let ranges =
+            get_ranges_of_device_sample_rate(&CoreAudioHardware, device, DeviceType::OUTPUT)
+                .unwrap();
         println!("ranges of output sample rate: {:?}", ranges);
     } else {
         println!("No output device.");
@@ -347,7 +426,12 @@ fn test_get_ranges_of_device_sample_rate() {
 #[test]
 #[should_panic]
 fn test_get_ranges_of_device_sample_rate_by_unknown_device() {
-    assert!(get_ranges_of_device_sample_rate(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(get_ranges_of_device_sample_rate(
+        &CoreAudioHardware,
+        kAudioObjectUnknown,
+        DeviceType::INPUT
+    )
+    .is_err());
 }
 
 // get_device_stream_format
@@ -355,14 +439,16 @@ fn test_get_ranges_of_device_sample_rate_by_unknown_device() {
 #[test]
 fn test_get_device_stream_format() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let format = get_device_stream_format(device, DeviceType::INPUT).unwrap();
+        let format =
+            get_device_stream_format(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         println!("input stream format: {:?}", format);
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let format = get_device_stream_format(device, DeviceType::OUTPUT).unwrap();
+        let format =
+            get_device_stream_format(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         println!("output stream format: {:?}", format);
     } else {
         println!("No output device.");
@@ -372,7 +458,10 @@ fn test_get_device_stream_format() {
 #[test]
 #[should_panic]
 fn test_get_device_stream_format_by_unknown_device() {
-    assert!(get_device_stream_format(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_device_stream_format(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT)
+            .is_err()
+    );
 }
 
 // get_device_stream_configuration
@@ -380,14 +469,17 @@ fn test_get_device_stream_format_by_unknown_device() {
 #[test]
 fn test_get_device_stream_configuration() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let buffers = get_device_stream_configuration(device, DeviceType::INPUT).unwrap();
+        let buffers =
+            get_device_stream_configuration(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         println!("input stream config: {:?}", buffers);
     } else {
         println!("No input device.");
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let buffers = get_device_stream_configuration(device, DeviceType::OUTPUT).unwrap();
+        let buffers =
+            get_device_stream_configuration(&CoreAudioHardware, device, DeviceType::OUTPUT)
+                .unwrap();
         println!("output stream config: {:?}", buffers);
     } else {
         println!("No output device.");
@@ -397,7 +489,50 @@ fn test_get_device_stream_configuration() {
 #[test]
 #[should_panic]
 fn test_get_device_stream_configuration_by_unknown_device() {
-    assert!(get_device_stream_configuration(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(get_device_stream_configuration(
+        &CoreAudioHardware,
+        kAudioObjectUnknown,
+        DeviceType::INPUT
+    )
+    .is_err());
+}
+
+// get_device_preferred_channel_layout
+// ------------------------------------
+#[test]
+fn test_get_device_preferred_channel_layout() {
+    if let Some(device) = test_get_default_device(Scope::Input) {
+        let layout =
+            get_device_preferred_channel_layout(&CoreAudioHardware, device, DeviceType::INPUT);
+        println!(
+            "input channel layout: {:?}",
+            layout.map(|layout| audiounit_convert_channel_layout(layout.as_ref()))
+        );
+    } else {
+        println!("No input device.");
+    }
+
+    if let Some(device) = test_get_default_device(Scope::Output) {
+        let layout =
+            get_device_preferred_channel_layout(&CoreAudioHardware, device, DeviceType::OUTPUT);
+        println!(
+            "output channel layout: {:?}",
+            layout.map(|layout| audiounit_convert_channel_layout(layout.as_ref()))
+        );
+    } else {
+        println!("No output device.");
+    }
+}
+
+#[test]
+#[should_panic]
+fn test_get_device_preferred_channel_layout_by_unknown_device() {
+    assert!(get_device_preferred_channel_layout(
+        &CoreAudioHardware,
+        kAudioObjectUnknown,
+        DeviceType::INPUT
+    )
+    .is_err());
 }
 
 // get_stream_latency
@@ -405,9 +540,10 @@ fn test_get_device_stream_configuration_by_unknown_device() {
 #[test]
 fn test_get_stream_latency() {
     if let Some(device) = test_get_default_device(Scope::Input) {
-        let streams = get_device_streams(device, DeviceType::INPUT).unwrap();
+        let streams = get_device_streams(&CoreAudioHardware, device, DeviceType::INPUT).unwrap();
         for stream in streams {
-            let latency = get_stream_latency(stream, DeviceType::INPUT).unwrap();
+            let latency =
+                get_stream_latency(&CoreAudioHardware, stream, DeviceType::INPUT).unwrap();
             println!("latency of the input stream {} is {}", stream, latency);
         }
     } else {
@@ -415,9 +551,10 @@ fn test_get_stream_latency() {
     }
 
     if let Some(device) = test_get_default_device(Scope::Output) {
-        let streams = get_device_streams(device, DeviceType::OUTPUT).unwrap();
+        let streams = get_device_streams(&CoreAudioHardware, device, DeviceType::OUTPUT).unwrap();
         for stream in streams {
-            let latency = get_stream_latency(stream, DeviceType::OUTPUT).unwrap();
+            let latency =
+                get_stream_latency(&CoreAudioHardware, stream, DeviceType::OUTPUT).unwrap();
             println!("latency of the output stream {} is {}", stream, latency);
         }
     } else {
@@ -428,5 +565,7 @@ fn test_get_stream_latency() {
 #[test]
 #[should_panic]
 fn test_get_stream_latency_by_unknown_device() {
-    assert!(get_stream_latency(kAudioObjectUnknown, DeviceType::INPUT).is_err());
+    assert!(
+        get_stream_latency(&CoreAudioHardware, kAudioObjectUnknown, DeviceType::INPUT).is_err()
+    );
 }
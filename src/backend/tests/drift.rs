@@ -0,0 +1,151 @@
+use super::*;
+
+// DriftEstimator
+// ------------------------------------
+#[test]
+fn test_drift_estimator_follows_level() {
+    let mut estimator = DriftEstimator::new(48_000.0);
+    assert_eq!(estimator.ratio(), 1.0);
+    // The input is consumed faster when the level is above the target, and slower when it's
+    // below the target.
+    assert!(estimator.update(1_024, 512, 512) > 1.0);
+    let mut estimator = DriftEstimator::new(48_000.0);
+    assert!(estimator.update(0, 512, 512) < 1.0);
+    // The correction is bounded.
+    let mut estimator = DriftEstimator::new(48_000.0);
+    for _ in 0..10_000 {
+        let ratio = estimator.update(4_096, 0, 512);
+        assert!(ratio > 1.0 && ratio <= 1.001);
+    }
+}
+
+const RATE: f64 = 48_000.0;
+const FRAMES: usize = 512;
+const TARGET: usize = 2 * FRAMES;
+
+// Run the input and the output of a duplex stream on two clocks, the input one `skew` faster
+// than the output one, for `seconds`, and return the fill levels of the input buffer observed by
+// the output callbacks and the ratios they used.
+fn run_clock_pair(skew: f64, seconds: f64) -> (Vec<usize>, Vec<f64>) {
+    let input_period = FRAMES as f64 / (RATE * (1.0 + skew));
+    let output_period = FRAMES as f64 / RATE;
+
+    let mut compensator = DriftCompensator::new(StreamFormat::Float32LE, 1, RATE, FRAMES);
+    let input = vec![0.0_f32; 4 * FRAMES];
+    let mut level = 0;
+    let mut levels = Vec::new();
+    let mut ratios = Vec::new();
+    let mut next_input = 0.0;
+    let mut next_output = output_period / 2.0;
+    while next_output < seconds {
+        if next_input < next_output {
+            level += FRAMES;
+            next_input += input_period;
+            continue;
+        }
+        // The first output callback fills the reserve with silence.
+        if levels.is_empty() {
+            level = TARGET;
+        }
+        ratios.push(compensator.update(level, TARGET, FRAMES));
+        levels.push(level);
+        let frames = compensator.input_frames(FRAMES);
+        assert!(frames <= level, "underrun after {} s", next_output);
+        compensator.process(input.as_ptr() as *const c_void, frames, FRAMES);
+        level -= frames;
+        next_output += output_period;
+    }
+    (levels, ratios)
+}
+
+#[test]
+fn test_drift_compensation_with_skewed_clocks() {
+    for skew in [200e-6, -200e-6].iter().cloned() {
+        let (levels, ratios) = run_clock_pair(skew, 300.0);
+        // The level only moves by whole input callbacks, when the callbacks change their order,
+        // so the ratio follows the skew on average, and the level stays around the target.
+        let settled = &ratios[ratios.len() / 2..];
+        let ratio = settled.iter().sum::<f64>() / settled.len() as f64;
+        assert!((ratio - 1.0 - skew).abs() < 50e-6, "ratio {}", ratio);
+        let settled = &levels[levels.len() / 2..];
+        let mean = settled.iter().sum::<usize>() as f64 / settled.len() as f64;
+        assert!((mean - TARGET as f64).abs() < 64.0, "mean level {}", mean);
+        assert!(settled.iter().all(|level| *level < 2_048));
+    }
+}
+
+// DriftCompensator
+// ------------------------------------
+#[test]
+fn test_drift_compensator_passthrough() {
+    let mut compensator = DriftCompensator::new(StreamFormat::S16LE, 2, 48_000.0, 4);
+    assert_eq!(compensator.ratio(), 1.0);
+    // The interpolation needs the frame after the last output frame.
+    assert_eq!(compensator.input_frames(4), 5);
+    let input: Vec<i16> = vec![1, -1, 2, -2, 3, -3, 4, -4, 5, -5];
+    let output = compensator.process(input.as_ptr() as *const c_void, 5, 4);
+    let output = unsafe { slice::from_raw_parts(output as *const i16, 8) };
+    assert_eq!(output, &[1, -1, 2, -2, 3, -3, 4, -4]);
+
+    // The last frame is kept for the next call.
+    assert_eq!(compensator.input_frames(2), 2);
+    let input: Vec<i16> = vec![6, -6, 7, -7];
+    let output = compensator.process(input.as_ptr() as *const c_void, 2, 2);
+    let output = unsafe { slice::from_raw_parts(output as *const i16, 4) };
+    assert_eq!(output, &[5, -5, 6, -6]);
+}
+
+#[test]
+fn test_drift_compensator_interpolates() {
+    let mut compensator = DriftCompensator::new(StreamFormat::Float32LE, 1, 48_000.0, 1_000);
+    // Consume the input faster than the nominal rate.
+    let ratio = compensator.update(48_000, 0, 48_000);
+    assert!(ratio > 1.0);
+    let frames = compensator.input_frames(1_000);
+    assert_eq!(frames, (999.0 * ratio) as usize + 2);
+    let input: Vec<f32> = (0..frames).map(|i| i as f32).collect();
+    let output = compensator.process(input.as_ptr() as *const c_void, frames, 1_000);
+    let output = unsafe { slice::from_raw_parts(output as *const f32, 1_000) };
+    for (i, sample) in output.iter().enumerate() {
+        assert!(approx_eq!(
+            f32,
+            *sample,
+            (i as f64 * ratio) as f32,
+            epsilon = 0.001
+        ));
+    }
+}
+
+#[test]
+fn test_drift_compensator_pads_missing_input() {
+    let mut compensator = DriftCompensator::new(StreamFormat::Float32LE, 1, 48_000.0, 4);
+    assert_eq!(compensator.input_frames(4), 5);
+    // The frames without the input after them are silent.
+    let input: Vec<f32> = vec![1.0, 2.0, 3.0];
+    let output = compensator.process(input.as_ptr() as *const c_void, 3, 4);
+    let output = unsafe { slice::from_raw_parts(output as *const f32, 4) };
+    assert_eq!(output, &[1.0, 2.0, 0.0, 0.0]);
+    // The buffers are allocated for the callbacks of up to `max_frames` frames.
+    let first = output.as_ptr();
+    let input: Vec<f32> = vec![4.0; 4];
+    let output = compensator.process(input.as_ptr() as *const c_void, 4, 4);
+    assert_eq!(output as *const f32, first);
+}
+
+#[test]
+fn test_drift_compensator_buffers_for_resampled_input() {
+    // The input device runs at twice the output rate, so a callback of the latency needs twice
+    // as many input frames, and more when the input is consumed faster.
+    let frames = minimum_resampling_input_frames(96_000.0, 48_000.0, 256);
+    assert_eq!(frames, 512);
+    let mut compensator = DriftCompensator::new(StreamFormat::Float32LE, 2, 96_000.0, frames);
+    let capacities = compensator.capacities();
+    let input = vec![1.0_f32; 2 * frames * 2];
+    for _ in 0..1_000 {
+        let ratio = compensator.update(96_000, 0, frames);
+        assert!(ratio > 1.0);
+        let input_frames = compensator.input_frames(frames);
+        compensator.process(input.as_ptr() as *const c_void, input_frames, frames);
+        assert_eq!(compensator.capacities(), capacities);
+    }
+}
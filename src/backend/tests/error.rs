@@ -0,0 +1,126 @@
+use super::*;
+
+// BackendError
+// ------------------------------------
+#[test]
+fn test_backend_error_check() {
+    assert!(BackendError::check(Operation::SetStreamFormat, NO_ERR).is_ok());
+
+    let error = BackendError::check(Operation::SetStreamFormat, kAudioUnitErr_FormatNotSupported)
+        .unwrap_err();
+    assert_eq!(error.operation(), Operation::SetStreamFormat);
+    assert_eq!(error.status(), kAudioUnitErr_FormatNotSupported);
+    assert_eq!(error.device(), kAudioObjectUnknown);
+}
+
+#[test]
+fn test_backend_error_with_device() {
+    let error = BackendError::new(Operation::CreateAudioUnit, kAudio_ParamError as OSStatus);
+    assert_eq!(error.device(), kAudioObjectUnknown);
+
+    // The device closest to the failure is kept.
+    let error = error.with_device(42).with_device(43);
+    assert_eq!(error.device(), 42);
+}
+
+#[test]
+fn test_backend_error_display() {
+    assert_eq!(
+        BackendError::new(Operation::WaitForBufferSize, NO_ERR).to_string(),
+        "Fail to wait for buffer size change"
+    );
+    assert_eq!(
+        BackendError::new(Operation::SetStreamFormat, kAudioUnitErr_FormatNotSupported)
+            .with_device(42)
+            .to_string(),
+        format!(
+            "Fail to set stream format on device 42. Error: {}",
+            kAudioUnitErr_FormatNotSupported
+        )
+    );
+    // "!dev"
+    assert_eq!(
+        BackendError::new(
+            Operation::InstallListener,
+            kAudioHardwareBadDeviceError as OSStatus
+        )
+        .to_string(),
+        format!(
+            "Fail to install listener. Error: {} ('!dev')",
+            kAudioHardwareBadDeviceError as OSStatus
+        )
+    );
+}
+
+#[test]
+fn test_backend_error_into_cubeb_error() {
+    let into_error = |operation, status| Error::from(BackendError::new(operation, status));
+
+    // The status decides the error if it's known.
+    assert_eq!(
+        into_error(
+            Operation::StartAudioUnit,
+            kAudioHardwareBadDeviceError as OSStatus
+        ),
+        Error::device_unavailable()
+    );
+    assert_eq!(
+        into_error(
+            Operation::SetBufferSize,
+            kAudioHardwareBadObjectError as OSStatus
+        ),
+        Error::device_unavailable()
+    );
+    assert_eq!(
+        into_error(Operation::SetStreamFormat, kAudioUnitErr_FormatNotSupported),
+        Error::invalid_format()
+    );
+    assert_eq!(
+        into_error(
+            Operation::GetStreamFormat,
+            kAudioHardwareUnknownPropertyError as OSStatus
+        ),
+        Error::not_supported()
+    );
+    assert_eq!(
+        into_error(Operation::SetCallback, kAudioUnitErr_InvalidProperty),
+        Error::not_supported()
+    );
+    assert_eq!(
+        into_error(Operation::SetBufferSize, kAudio_ParamError as OSStatus),
+        Error::invalid_parameter()
+    );
+
+    // A missing AudioComponent isn't about the device.
+    assert_eq!(
+        into_error(Operation::CreateAudioUnit, AUDIO_COMPONENT_NOT_FOUND),
+        Error::error()
+    );
+
+    // Otherwise the operation decides.
+    assert_eq!(
+        into_error(Operation::CreateAudioUnit, -1),
+        Error::device_unavailable()
+    );
+    assert_eq!(
+        into_error(Operation::CheckInputChannels, NO_ERR),
+        Error::device_unavailable()
+    );
+    assert_eq!(
+        into_error(Operation::CheckOutputChannels, NO_ERR),
+        Error::device_unavailable()
+    );
+    assert_eq!(
+        into_error(Operation::CreateStreamDescription, NO_ERR),
+        Error::invalid_format()
+    );
+    assert_eq!(
+        into_error(Operation::SetStreamFormat, -1),
+        Error::invalid_format()
+    );
+    assert_eq!(
+        into_error(Operation::WaitForBufferSize, NO_ERR),
+        Error::error()
+    );
+    assert_eq!(into_error(Operation::InstallListener, -1), Error::error());
+}
@@ -0,0 +1,936 @@
+use super::*;
+use std::collections::HashMap;
+
+// FakeHardware: An in-memory AudioObject system
+// ------------------------------------------------------------------------------------------------
+// Tests can build a set of virtual devices, and then run the code talking to the HAL without any
+// audio hardware by giving it the fake system instead of `CoreAudioHardware`.
+
+const FAKE_FIRST_OBJECT_ID: AudioObjectID = 100;
+
+#[derive(Clone, Debug)]
+pub enum FakeValue {
+    Data(Vec<u8>),
+    String(String),
+    // The table used by the translation properties like
+    // kAudioDevicePropertyDataSourceNameForIDCFString.
+    Names(Vec<(u32, String)>),
+    // A translation property mapping any input to the object, like
+    // kAudioHardwarePropertyPlugInForBundleID.
+    Translation(AudioObjectID),
+    // A CFArray of CFStrings, like kAudioAggregateDevicePropertyFullSubDeviceList.
+    Strings(Vec<String>),
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeDevice {
+    pub uid: String,
+    pub name: String,
+    pub manufacturer: String,
+    pub model_uid: Option<String>,
+    pub transport_type: u32,
+    pub input_channels: u32,
+    pub output_channels: u32,
+    pub sample_rate: f64,
+    pub sample_rate_range: (f64, f64),
+    pub buffer_frame_size_range: (f64, f64),
+    // (source id, source name)
+    pub input_source: Option<(u32, String)>,
+    pub output_source: Option<(u32, String)>,
+    // (device latency, stream latency)
+    pub input_latency: (u32, u32),
+    pub output_latency: (u32, u32),
+    // The labels of the preferred input channel layout. The device has no input layout if it's
+    // empty.
+    pub input_layout: Vec<AudioChannelLabel>,
+}
+
+impl FakeDevice {
+    pub fn new(uid: &str, input_channels: u32, output_channels: u32) -> Self {
+        Self {
+            uid: uid.to_string(),
+            name: format!("{} name", uid),
+            manufacturer: String::from("Fake Manufacturer"),
+            model_uid: Some(format!("{} model", uid)),
+            transport_type: kAudioDeviceTransportTypeVirtual,
+            input_channels,
+            output_channels,
+            sample_rate: 48_000.0,
+            sample_rate_range: (44_100.0, 96_000.0),
+            buffer_frame_size_range: (15.0, 4096.0),
+            input_source: None,
+            output_source: None,
+            input_latency: (0, 0),
+            output_latency: (0, 0),
+            input_layout: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Listener {
+    id: AudioObjectID,
+    selector: AudioObjectPropertySelector,
+    scope: AudioObjectPropertyScope,
+    element: AudioObjectPropertyElement,
+    listener: audio_object_property_listener_proc,
+    data: usize,
+}
+
+type PropertyKey = (
+    AudioObjectID,
+    AudioObjectPropertySelector,
+    AudioObjectPropertyScope,
+);
+
+struct FakeSystem {
+    properties: HashMap<PropertyKey, FakeValue>,
+    listeners: Vec<Listener>,
+    devices: Vec<AudioObjectID>,
+    next_id: AudioObjectID,
+    // The plugin creating the aggregate devices.
+    plugin: AudioObjectID,
+    aggregate_devices: Vec<AudioObjectID>,
+    // The process taps and the devices they capture.
+    taps: HashMap<AudioObjectID, AudioObjectID>,
+}
+
+impl FakeSystem {
+    fn find_key(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+    ) -> Option<PropertyKey> {
+        // The properties in global scope are visible from all the scopes.
+        let key = (id, address.mSelector, address.mScope);
+        let global_key = (id, address.mSelector, kAudioObjectPropertyScopeGlobal);
+        if self.properties.contains_key(&key) {
+            Some(key)
+        } else if self.properties.contains_key(&global_key) {
+            Some(global_key)
+        } else {
+            None
+        }
+    }
+
+    fn allocate_id(&mut self) -> AudioObjectID {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+pub struct FakeHardware {
+    system: Mutex<FakeSystem>,
+}
+
+impl FakeHardware {
+    pub fn new() -> Arc<Self> {
+        let hardware = Arc::new(Self {
+            system: Mutex::new(FakeSystem {
+                properties: HashMap::new(),
+                listeners: Vec::new(),
+                devices: Vec::new(),
+                next_id: FAKE_FIRST_OBJECT_ID,
+                plugin: kAudioObjectUnknown,
+                aggregate_devices: Vec::new(),
+                taps: HashMap::new(),
+            }),
+        });
+        let no_device: AudioObjectID = kAudioObjectUnknown;
+        for selector in &[
+            kAudioHardwarePropertyDefaultInputDevice,
+            kAudioHardwarePropertyDefaultOutputDevice,
+        ] {
+            hardware.insert(
+                kAudioObjectSystemObject,
+                *selector,
+                kAudioObjectPropertyScopeGlobal,
+                FakeValue::Data(bytes_of(&no_device)),
+            );
+        }
+        hardware.insert(
+            kAudioObjectSystemObject,
+            kAudioHardwarePropertyDevices,
+            kAudioObjectPropertyScopeGlobal,
+            FakeValue::Data(Vec::new()),
+        );
+
+        let plugin = {
+            let mut system = hardware.system.lock().unwrap();
+            system.plugin = system.allocate_id();
+            system.plugin
+        };
+        hardware.insert(
+            kAudioObjectSystemObject,
+            kAudioHardwarePropertyPlugInForBundleID,
+            kAudioObjectPropertyScopeGlobal,
+            FakeValue::Translation(plugin),
+        );
+        // The plugin creates or destroys the aggregate devices when these properties are read.
+        for selector in &[
+            kAudioPlugInCreateAggregateDevice,
+            kAudioPlugInDestroyAggregateDevice,
+        ] {
+            hardware.insert(
+                plugin,
+                *selector,
+                kAudioObjectPropertyScopeGlobal,
+                FakeValue::Data(bytes_of(&kAudioObjectUnknown)),
+            );
+        }
+        hardware
+    }
+
+    // The platform running on the fake system, with the CoreAudio AudioUnits.
+    pub fn platform(self: &Arc<Self>) -> Platform {
+        Platform::new(self.clone(), Arc::new(CoreAudioUnits))
+    }
+
+    pub fn add_device(&self, device: FakeDevice) -> AudioObjectID {
+        let (id, devices) = {
+            let mut system = self.system.lock().unwrap();
+            let id = system.allocate_id();
+            system.devices.push(id);
+            (id, system.devices.clone())
+        };
+
+        let global = kAudioObjectPropertyScopeGlobal;
+        self.insert(
+            id,
+            kAudioDevicePropertyDeviceUID,
+            global,
+            FakeValue::String(device.uid),
+        );
+        if let Some(model_uid) = device.model_uid {
+            self.insert(
+                id,
+                kAudioDevicePropertyModelUID,
+                global,
+                FakeValue::String(model_uid),
+            );
+        }
+        self.insert(
+            id,
+            kAudioObjectPropertyName,
+            global,
+            FakeValue::String(device.name),
+        );
+        self.insert(
+            id,
+            kAudioObjectPropertyManufacturer,
+            global,
+            FakeValue::String(device.manufacturer),
+        );
+        let is_alive: u32 = 1;
+        self.insert(
+            id,
+            kAudioDevicePropertyDeviceIsAlive,
+            global,
+            FakeValue::Data(bytes_of(&is_alive)),
+        );
+        self.insert(
+            id,
+            kAudioDevicePropertyTransportType,
+            global,
+            FakeValue::Data(bytes_of(&device.transport_type)),
+        );
+        self.insert(
+            id,
+            kAudioDevicePropertyNominalSampleRate,
+            global,
+            FakeValue::Data(bytes_of(&device.sample_rate)),
+        );
+        let rates = AudioValueRange {
+            mMinimum: device.sample_rate_range.0,
+            mMaximum: device.sample_rate_range.1,
+        };
+        self.insert(
+            id,
+            kAudioDevicePropertyAvailableNominalSampleRates,
+            global,
+            FakeValue::Data(bytes_of(&rates)),
+        );
+        let frames = AudioValueRange {
+            mMinimum: device.buffer_frame_size_range.0,
+            mMaximum: device.buffer_frame_size_range.1,
+        };
+        self.insert(
+            id,
+            kAudioDevicePropertyBufferFrameSizeRange,
+            global,
+            FakeValue::Data(bytes_of(&frames)),
+        );
+
+        let scopes = [
+            (
+                kAudioDevicePropertyScopeInput,
+                device.input_channels,
+                device.input_source,
+                device.input_latency,
+            ),
+            (
+                kAudioDevicePropertyScopeOutput,
+                device.output_channels,
+                device.output_source,
+                device.output_latency,
+            ),
+        ];
+        for (scope, channels, source, latency) in scopes.iter().cloned() {
+            self.insert_streams(id, scope, channels, device.sample_rate, source, latency);
+        }
+        if !device.input_layout.is_empty() {
+            self.insert(
+                id,
+                kAudioDevicePropertyPreferredChannelLayout,
+                kAudioDevicePropertyScopeInput,
+                FakeValue::Data(channel_layout_bytes(&device.input_layout)),
+            );
+        }
+
+        self.set_value(
+            kAudioObjectSystemObject,
+            kAudioHardwarePropertyDevices,
+            global,
+            FakeValue::Data(bytes_of_slice(&devices)),
+        );
+        id
+    }
+
+    // Insert the stream properties of the device in `scope`.
+    fn insert_streams(
+        &self,
+        id: AudioObjectID,
+        scope: AudioObjectPropertyScope,
+        channels: u32,
+        sample_rate: f64,
+        source: Option<(u32, String)>,
+        (device_latency, stream_latency): (u32, u32),
+    ) {
+        let global = kAudioObjectPropertyScopeGlobal;
+
+        let list = AudioBufferList {
+            mNumberBuffers: if channels > 0 { 1 } else { 0 },
+            mBuffers: [AudioBuffer {
+                mNumberChannels: channels,
+                mDataByteSize: 0,
+                mData: ptr::null_mut(),
+            }],
+        };
+        self.insert(
+            id,
+            kAudioDevicePropertyStreamConfiguration,
+            scope,
+            FakeValue::Data(bytes_of(&list)),
+        );
+        if channels == 0 {
+            self.insert(
+                id,
+                kAudioDevicePropertyStreams,
+                scope,
+                FakeValue::Data(Vec::new()),
+            );
+            return;
+        }
+
+        let stream = self.system.lock().unwrap().allocate_id();
+        self.insert(
+            id,
+            kAudioDevicePropertyStreams,
+            scope,
+            FakeValue::Data(bytes_of(&stream)),
+        );
+        self.insert(
+            stream,
+            kAudioStreamPropertyLatency,
+            global,
+            FakeValue::Data(bytes_of(&stream_latency)),
+        );
+        self.insert(
+            id,
+            kAudioDevicePropertyLatency,
+            scope,
+            FakeValue::Data(bytes_of(&device_latency)),
+        );
+        let format = AudioStreamBasicDescription {
+            mSampleRate: sample_rate,
+            mFormatID: kAudioFormatLinearPCM,
+            mFormatFlags: kAudioFormatFlagIsFloat | kAudioFormatFlagIsPacked,
+            mBytesPerPacket: 4 * channels,
+            mFramesPerPacket: 1,
+            mBytesPerFrame: 4 * channels,
+            mChannelsPerFrame: channels,
+            mBitsPerChannel: 32,
+            mReserved: 0,
+        };
+        self.insert(
+            id,
+            kAudioDevicePropertyStreamFormat,
+            scope,
+            FakeValue::Data(bytes_of(&format)),
+        );
+        if let Some((source, name)) = source {
+            self.insert(
+                id,
+                kAudioDevicePropertyDataSource,
+                scope,
+                FakeValue::Data(bytes_of(&source)),
+            );
+            self.insert(
+                id,
+                kAudioDevicePropertyDataSourceNameForIDCFString,
+                scope,
+                FakeValue::Names(vec![(source, name)]),
+            );
+        }
+    }
+
+    pub fn remove_device(&self, id: AudioObjectID) {
+        self.set_property(
+            id,
+            kAudioDevicePropertyDeviceIsAlive,
+            kAudioObjectPropertyScopeGlobal,
+            0u32,
+        );
+        let devices = {
+            let mut system = self.system.lock().unwrap();
+            system.devices.retain(|device| *device != id);
+            system.properties.retain(|key, _| key.0 != id);
+            system.devices.clone()
+        };
+        self.set_value(
+            kAudioObjectSystemObject,
+            kAudioHardwarePropertyDevices,
+            kAudioObjectPropertyScopeGlobal,
+            FakeValue::Data(bytes_of_slice(&devices)),
+        );
+        for devtype in &[DeviceType::INPUT, DeviceType::OUTPUT] {
+            if self.default_device(*devtype) == id {
+                self.set_default_device(kAudioObjectUnknown, *devtype);
+            }
+        }
+    }
+
+    pub fn default_device(&self, devtype: DeviceType) -> AudioObjectID {
+        let system = self.system.lock().unwrap();
+        let key = (
+            kAudioObjectSystemObject,
+            default_device_selector(devtype),
+            kAudioObjectPropertyScopeGlobal,
+        );
+        match system.properties.get(&key) {
+            Some(FakeValue::Data(bytes)) => unsafe { *(bytes.as_ptr() as *const AudioObjectID) },
+            _ => kAudioObjectUnknown,
+        }
+    }
+
+    pub fn set_default_device(&self, id: AudioObjectID, devtype: DeviceType) {
+        self.set_property(
+            kAudioObjectSystemObject,
+            default_device_selector(devtype),
+            kAudioObjectPropertyScopeGlobal,
+            id,
+        );
+    }
+
+    // Set the property and fire its listeners.
+    pub fn set_property<T: Copy>(
+        &self,
+        id: AudioObjectID,
+        selector: AudioObjectPropertySelector,
+        scope: AudioObjectPropertyScope,
+        value: T,
+    ) {
+        self.set_value(id, selector, scope, FakeValue::Data(bytes_of(&value)));
+    }
+
+    pub fn set_value(
+        &self,
+        id: AudioObjectID,
+        selector: AudioObjectPropertySelector,
+        scope: AudioObjectPropertyScope,
+        value: FakeValue,
+    ) {
+        self.insert(id, selector, scope, value);
+        self.notify(id, selector);
+    }
+
+    pub fn remove_property(
+        &self,
+        id: AudioObjectID,
+        selector: AudioObjectPropertySelector,
+        scope: AudioObjectPropertyScope,
+    ) {
+        let mut system = self.system.lock().unwrap();
+        system.properties.remove(&(id, selector, scope));
+    }
+
+    pub fn listener_count(
+        &self,
+        id: AudioObjectID,
+        selector: AudioObjectPropertySelector,
+    ) -> usize {
+        let system = self.system.lock().unwrap();
+        system
+            .listeners
+            .iter()
+            .filter(|l| l.id == id && l.selector == selector)
+            .count()
+    }
+
+    // The strings set to a CFArray property, like the sub devices of an aggregate device.
+    pub fn strings(&self, id: AudioObjectID, selector: AudioObjectPropertySelector) -> Vec<String> {
+        let system = self.system.lock().unwrap();
+        match system
+            .properties
+            .get(&(id, selector, kAudioObjectPropertyScopeGlobal))
+        {
+            Some(FakeValue::Strings(strings)) => strings.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn aggregate_devices(&self) -> Vec<AudioObjectID> {
+        self.system.lock().unwrap().aggregate_devices.clone()
+    }
+
+    // The process taps and the devices they capture.
+    pub fn taps(&self) -> Vec<(AudioObjectID, AudioObjectID)> {
+        let system = self.system.lock().unwrap();
+        let mut taps: Vec<(AudioObjectID, AudioObjectID)> = system
+            .taps
+            .iter()
+            .map(|(tap, device)| (*tap, *device))
+            .collect();
+        taps.sort();
+        taps
+    }
+
+    fn channels(&self, id: AudioObjectID, scope: AudioObjectPropertyScope) -> u32 {
+        let system = self.system.lock().unwrap();
+        match system
+            .properties
+            .get(&(id, kAudioDevicePropertyStreamConfiguration, scope))
+        {
+            Some(FakeValue::Data(bytes)) => {
+                let list = unsafe { &*(bytes.as_ptr() as *const AudioBufferList) };
+                if list.mNumberBuffers > 0 {
+                    list.mBuffers[0].mNumberChannels
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn string_value(
+        &self,
+        id: AudioObjectID,
+        selector: AudioObjectPropertySelector,
+    ) -> Option<String> {
+        let system = self.system.lock().unwrap();
+        match system
+            .properties
+            .get(&(id, selector, kAudioObjectPropertyScopeGlobal))
+        {
+            Some(FakeValue::String(string)) => Some(string.clone()),
+            _ => None,
+        }
+    }
+
+    fn find_device_by_uid(&self, uid: &str) -> Option<AudioObjectID> {
+        let devices = self.system.lock().unwrap().devices.clone();
+        devices.into_iter().find(|id| {
+            self.string_value(*id, kAudioDevicePropertyDeviceUID)
+                .as_deref()
+                == Some(uid)
+        })
+    }
+
+    // Called when kAudioPlugInCreateAggregateDevice is read with the description of the device.
+    fn create_aggregate_device(&self, description: &CFMutableDictRef) -> AudioObjectID {
+        let key = cfstringref_from_static_string(AGGREGATE_DEVICE_UID_KEY);
+        let uid = string_from_cfstringref(description.get_value(key) as CFStringRef);
+        unsafe {
+            CFRelease(key as *const c_void);
+        }
+
+        let id = self.add_device(FakeDevice::new(&uid, 0, 0));
+        self.system.lock().unwrap().aggregate_devices.push(id);
+        let global = kAudioObjectPropertyScopeGlobal;
+        for selector in &[
+            kAudioAggregateDevicePropertyFullSubDeviceList,
+            AGGREGATE_DEVICE_PROPERTY_TAP_LIST,
+        ] {
+            self.insert(id, *selector, global, FakeValue::Strings(Vec::new()));
+        }
+        self.insert(
+            id,
+            kAudioAggregateDevicePropertyMasterSubDevice,
+            global,
+            FakeValue::String(String::new()),
+        );
+        id
+    }
+
+    // The input of the aggregate device carries the output of the tapped devices.
+    fn update_tapped_input(&self, id: AudioObjectID, tap_uids: &[String]) {
+        let taps: Vec<AudioObjectID> = self.system.lock().unwrap().taps.keys().cloned().collect();
+        let channels: u32 = taps
+            .into_iter()
+            .filter(|tap| {
+                self.string_value(*tap, TAP_PROPERTY_UID)
+                    .map_or(false, |uid| tap_uids.contains(&uid))
+            })
+            .map(|tap| {
+                let device = self.system.lock().unwrap().taps[&tap];
+                self.channels(device, kAudioDevicePropertyScopeOutput)
+            })
+            .sum();
+        let sample_rate = self.sample_rate(id);
+        self.insert_streams(
+            id,
+            kAudioDevicePropertyScopeInput,
+            channels,
+            sample_rate,
+            None,
+            (0, 0),
+        );
+    }
+
+    fn sample_rate(&self, id: AudioObjectID) -> f64 {
+        let system = self.system.lock().unwrap();
+        match system.properties.get(&(
+            id,
+            kAudioDevicePropertyNominalSampleRate,
+            kAudioObjectPropertyScopeGlobal,
+        )) {
+            Some(FakeValue::Data(bytes)) => unsafe { *(bytes.as_ptr() as *const f64) },
+            _ => 0.0,
+        }
+    }
+
+    fn insert(
+        &self,
+        id: AudioObjectID,
+        selector: AudioObjectPropertySelector,
+        scope: AudioObjectPropertyScope,
+        value: FakeValue,
+    ) {
+        let mut system = self.system.lock().unwrap();
+        system.properties.insert((id, selector, scope), value);
+    }
+
+    fn notify(&self, id: AudioObjectID, selector: AudioObjectPropertySelector) {
+        // Don't hold the lock while calling the listeners since they may query the properties.
+        let listeners: Vec<Listener> = {
+            let system = self.system.lock().unwrap();
+            system
+                .listeners
+                .iter()
+                .filter(|l| l.id == id && l.selector == selector)
+                .cloned()
+                .collect()
+        };
+        for l in listeners {
+            let address = AudioObjectPropertyAddress {
+                mSelector: l.selector,
+                mScope: l.scope,
+                mElement: l.element,
+            };
+            (l.listener)(id, 1, &address, l.data as *mut c_void);
+        }
+    }
+}
+
+impl HardwareAbstraction for FakeHardware {
+    fn has_property(&self, id: AudioObjectID, address: &AudioObjectPropertyAddress) -> bool {
+        self.system.lock().unwrap().find_key(id, address).is_some()
+    }
+
+    fn get_property_data_size(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        _qualifier_size: usize,
+        _qualifier_data: *const c_void,
+        size: *mut usize,
+    ) -> OSStatus {
+        let system = self.system.lock().unwrap();
+        let value = match system.find_key(id, address) {
+            Some(key) => &system.properties[&key],
+            None => return kAudioHardwareUnknownPropertyError as OSStatus,
+        };
+        let value_size = match value {
+            FakeValue::Data(bytes) => bytes.len(),
+            FakeValue::String(_) => mem::size_of::<CFStringRef>(),
+            FakeValue::Names(_) | FakeValue::Translation(_) => {
+                mem::size_of::<AudioValueTranslation>()
+            }
+            FakeValue::Strings(_) => mem::size_of::<CFArrayRef>(),
+        };
+        unsafe {
+            *size = value_size;
+        }
+        NO_ERR
+    }
+
+    fn get_property_data(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        _qualifier_size: usize,
+        qualifier_data: *const c_void,
+        size: *mut usize,
+        data: *mut c_void,
+    ) -> OSStatus {
+        let plugin = self.system.lock().unwrap().plugin;
+        if id == plugin && address.mSelector == kAudioPlugInCreateAggregateDevice {
+            let description = unsafe { &*(qualifier_data as *const CFMutableDictRef) };
+            let device = self.create_aggregate_device(description);
+            unsafe {
+                *(data as *mut AudioObjectID) = device;
+            }
+            return NO_ERR;
+        }
+        if id == plugin && address.mSelector == kAudioPlugInDestroyAggregateDevice {
+            let device = unsafe { *(data as *const AudioObjectID) };
+            let found = {
+                let mut system = self.system.lock().unwrap();
+                let count = system.aggregate_devices.len();
+                system.aggregate_devices.retain(|d| *d != device);
+                system.aggregate_devices.len() != count
+            };
+            if !found {
+                return kAudioHardwareBadObjectError as OSStatus;
+            }
+            self.remove_device(device);
+            return NO_ERR;
+        }
+
+        let value = {
+            let system = self.system.lock().unwrap();
+            match system.find_key(id, address) {
+                Some(key) => system.properties[&key].clone(),
+                None => return kAudioHardwareUnknownPropertyError as OSStatus,
+            }
+        };
+        unsafe {
+            match value {
+                FakeValue::Data(bytes) => {
+                    let count = cmp::min(*size, bytes.len());
+                    ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, count);
+                    *size = count;
+                }
+                FakeValue::String(string) => {
+                    if *size < mem::size_of::<CFStringRef>() {
+                        return kAudioHardwareBadPropertySizeError as OSStatus;
+                    }
+                    *(data as *mut CFStringRef) = cfstringref_from_string(&string);
+                    *size = mem::size_of::<CFStringRef>();
+                }
+                FakeValue::Names(names) => {
+                    if *size < mem::size_of::<AudioValueTranslation>() {
+                        return kAudioHardwareBadPropertySizeError as OSStatus;
+                    }
+                    let translation = &mut *(data as *mut AudioValueTranslation);
+                    let key = *(translation.mInputData as *const u32);
+                    match names.iter().find(|(k, _)| *k == key) {
+                        Some((_, name)) => {
+                            *(translation.mOutputData as *mut CFStringRef) =
+                                cfstringref_from_string(name);
+                        }
+                        None => return kAudioHardwareUnknownPropertyError as OSStatus,
+                    }
+                }
+                FakeValue::Translation(object) => {
+                    if *size < mem::size_of::<AudioValueTranslation>() {
+                        return kAudioHardwareBadPropertySizeError as OSStatus;
+                    }
+                    let translation = &mut *(data as *mut AudioValueTranslation);
+                    *(translation.mOutputData as *mut AudioObjectID) = object;
+                }
+                FakeValue::Strings(strings) => {
+                    if *size < mem::size_of::<CFArrayRef>() {
+                        return kAudioHardwareBadPropertySizeError as OSStatus;
+                    }
+                    let array = CFArrayCreateMutable(ptr::null(), 0, &kCFTypeArrayCallBacks);
+                    for string in strings {
+                        let string = cfstringref_from_string(&string);
+                        CFArrayAppendValue(array, string as *const c_void);
+                        CFRelease(string as *const c_void);
+                    }
+                    *(data as *mut CFArrayRef) = array as CFArrayRef;
+                    *size = mem::size_of::<CFArrayRef>();
+                }
+            }
+        }
+        NO_ERR
+    }
+
+    fn set_property_data(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        size: usize,
+        data: *const c_void,
+    ) -> OSStatus {
+        let (key, value) = {
+            let system = self.system.lock().unwrap();
+            match system.find_key(id, address) {
+                Some(key) => (key, system.properties[&key].clone()),
+                None => return kAudioHardwareUnknownPropertyError as OSStatus,
+            }
+        };
+        // Keep the type of the CoreFoundation values.
+        let value = match value {
+            FakeValue::String(_) => {
+                assert_eq!(size, mem::size_of::<CFStringRef>());
+                FakeValue::String(string_from_cfstringref(unsafe {
+                    *(data as *const CFStringRef)
+                }))
+            }
+            FakeValue::Strings(_) => {
+                assert_eq!(size, mem::size_of::<CFArrayRef>());
+                let array = unsafe { *(data as *const CFArrayRef) };
+                let count = unsafe { CFArrayGetCount(array) };
+                let strings: Vec<String> = (0..count)
+                    .map(|i| {
+                        string_from_cfstringref(unsafe {
+                            CFArrayGetValueAtIndex(array, i) as CFStringRef
+                        })
+                    })
+                    .collect();
+                if key.1 == AGGREGATE_DEVICE_PROPERTY_TAP_LIST {
+                    self.update_tapped_input(id, &strings);
+                }
+                FakeValue::Strings(strings)
+            }
+            _ => {
+                FakeValue::Data(unsafe { slice::from_raw_parts(data as *const u8, size).to_vec() })
+            }
+        };
+        self.set_value(key.0, key.1, key.2, value);
+        NO_ERR
+    }
+
+    fn add_property_listener(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        listener: audio_object_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        let mut system = self.system.lock().unwrap();
+        system.listeners.push(Listener {
+            id,
+            selector: address.mSelector,
+            scope: address.mScope,
+            element: address.mElement,
+            listener,
+            data: data as usize,
+        });
+        NO_ERR
+    }
+
+    fn remove_property_listener(
+        &self,
+        id: AudioObjectID,
+        address: &AudioObjectPropertyAddress,
+        listener: audio_object_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        let mut system = self.system.lock().unwrap();
+        let index = system.listeners.iter().position(|l| {
+            l.id == id
+                && l.selector == address.mSelector
+                && l.scope == address.mScope
+                && l.listener as usize == listener as usize
+                && l.data == data as usize
+        });
+        match index {
+            Some(index) => {
+                system.listeners.remove(index);
+                NO_ERR
+            }
+            None => kAudioHardwareIllegalOperationError as OSStatus,
+        }
+    }
+
+    fn create_process_tap(&self, device_uid: CFStringRef, tap: *mut AudioObjectID) -> OSStatus {
+        let uid = string_from_cfstringref(device_uid);
+        let device = match self.find_device_by_uid(&uid) {
+            Some(device) => device,
+            None => return kAudioHardwareBadDeviceError as OSStatus,
+        };
+        if self.channels(device, kAudioDevicePropertyScopeOutput) == 0 {
+            return kAudioHardwareIllegalOperationError as OSStatus;
+        }
+        let id = {
+            let mut system = self.system.lock().unwrap();
+            let id = system.allocate_id();
+            system.taps.insert(id, device);
+            id
+        };
+        self.insert(
+            id,
+            TAP_PROPERTY_UID,
+            kAudioObjectPropertyScopeGlobal,
+            FakeValue::String(format!("{} tap {}", uid, id)),
+        );
+        unsafe {
+            *tap = id;
+        }
+        NO_ERR
+    }
+
+    fn destroy_process_tap(&self, tap: AudioObjectID) -> OSStatus {
+        let mut system = self.system.lock().unwrap();
+        if system.taps.remove(&tap).is_none() {
+            return kAudioHardwareBadObjectError as OSStatus;
+        }
+        system.properties.retain(|key, _| key.0 != tap);
+        NO_ERR
+    }
+}
+
+fn default_device_selector(devtype: DeviceType) -> AudioObjectPropertySelector {
+    match devtype {
+        DeviceType::INPUT => kAudioHardwarePropertyDefaultInputDevice,
+        DeviceType::OUTPUT => kAudioHardwarePropertyDefaultOutputDevice,
+        _ => panic!("Invalid type"),
+    }
+}
+
+// Read a CFString owned by the caller.
+fn string_from_cfstringref(string: CFStringRef) -> String {
+    assert!(!string.is_null());
+    unsafe {
+        CFRetain(string as *const c_void);
+    }
+    StringRef::new(string as _).into_string()
+}
+
+// An AudioChannelLayout of the channel descriptions of `labels`: tag, bitmap, number of
+// descriptions, and then the descriptions composed of label, flags, and three coordinates.
+pub fn channel_layout_bytes(labels: &[AudioChannelLabel]) -> Vec<u8> {
+    let mut words: Vec<u32> = vec![
+        kAudioChannelLayoutTag_UseChannelDescriptions,
+        0,
+        labels.len() as u32,
+    ];
+    for label in labels {
+        words.extend_from_slice(&[*label, 0, 0, 0, 0]);
+    }
+    bytes_of_slice(&words)
+}
+
+pub fn bytes_of<T: Copy>(value: &T) -> Vec<u8> {
+    bytes_of_slice(slice::from_ref(value))
+}
+
+pub fn bytes_of_slice<T: Copy>(values: &[T]) -> Vec<u8> {
+    let size = mem::size_of::<T>() * values.len();
+    unsafe { slice::from_raw_parts(values.as_ptr() as *const u8, size).to_vec() }
+}
@@ -0,0 +1,177 @@
+use super::*;
+
+// GainRamp
+// ------------------------------------
+#[test]
+fn test_gain_ramp_frames() {
+    assert_eq!(gain_ramp_frames(Duration::from_millis(10), 48_000), 480);
+    assert_eq!(gain_ramp_frames(Duration::from_millis(10), 44_100), 441);
+    assert_eq!(gain_ramp_frames(Duration::from_millis(0), 48_000), 0);
+}
+
+#[test]
+fn test_gain_ramp_fade_in_f32() {
+    let mut ramp = GainRamp::default();
+    assert!(ramp.is_silent());
+    ramp.fade_in(4);
+    // Two channels in each frame.
+    let mut data = vec![1.0_f32; 12];
+    ramp.apply(&mut data, 2);
+    assert_eq!(
+        data,
+        vec![0.25, 0.25, 0.5, 0.5, 0.75, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]
+    );
+    assert!(!ramp.is_silent());
+
+    // The data is untouched once the fade-in is done.
+    let mut data = vec![0.5_f32; 4];
+    ramp.apply(&mut data, 2);
+    assert_eq!(data, vec![0.5; 4]);
+}
+
+#[test]
+fn test_gain_ramp_fade_out_i16() {
+    let mut ramp = GainRamp::default();
+    ramp.fade_in(0);
+    let mut data = vec![1000_i16; 2];
+    ramp.apply(&mut data, 1);
+    assert_eq!(data, vec![1000, 1000]);
+
+    ramp.fade_out(4);
+    let mut data = vec![1000_i16; 6];
+    ramp.apply(&mut data, 1);
+    assert_eq!(data, vec![750, 500, 250, 0, 0, 0]);
+    assert!(ramp.is_silent());
+}
+
+#[test]
+fn test_gain_ramp_reverse() {
+    let mut ramp = GainRamp::default();
+    ramp.fade_in(4);
+    let mut data = vec![1.0_f32; 2];
+    ramp.apply(&mut data, 1);
+    assert_eq!(data, vec![0.25, 0.5]);
+
+    // The fade-out starts from the current gain.
+    ramp.fade_out(4);
+    let mut data = vec![1.0_f32; 3];
+    ramp.apply(&mut data, 1);
+    assert_eq!(data, vec![0.25, 0.0, 0.0]);
+    assert!(ramp.is_silent());
+}
+
+#[test]
+fn test_gain_ramp_apply_to_buffer() {
+    let mut ramp = GainRamp::default();
+    ramp.fade_in(2);
+    let mut data = vec![100_i16; 4];
+    ramp.apply_to_buffer(StreamFormat::S16LE, data.as_mut_ptr() as *mut c_void, 2, 2);
+    assert_eq!(data, vec![50, 50, 100, 100]);
+
+    let mut ramp = GainRamp::default();
+    ramp.fade_in(2);
+    let mut data = vec![1.0_f32; 3];
+    ramp.apply_to_buffer(
+        StreamFormat::Float32LE,
+        data.as_mut_ptr() as *mut c_void,
+        1,
+        3,
+    );
+    assert_eq!(data, vec![0.5, 1.0, 1.0]);
+}
+
+#[test]
+fn test_gain_ramp_apply_to_big_endian_buffer() {
+    // The samples are scaled in the native order and kept big-endian.
+    let mut ramp = GainRamp::default();
+    ramp.fade_in(2);
+    let mut data = vec![1000_i16.to_be(); 4];
+    ramp.apply_to_buffer(StreamFormat::S16BE, data.as_mut_ptr() as *mut c_void, 2, 2);
+    let data: Vec<i16> = data.into_iter().map(i16::from_be).collect();
+    assert_eq!(data, vec![500, 500, 1000, 1000]);
+
+    let mut ramp = GainRamp::default();
+    ramp.fade_in(4);
+    let mut data = vec![0.5_f32.to_bits().to_be(); 3];
+    ramp.apply_to_buffer(
+        StreamFormat::Float32BE,
+        data.as_mut_ptr() as *mut c_void,
+        1,
+        3,
+    );
+    let data: Vec<f32> = data
+        .into_iter()
+        .map(|bits| f32::from_bits(u32::from_be(bits)))
+        .collect();
+    assert_eq!(data, vec![0.125, 0.25, 0.375]);
+}
+
+#[test]
+fn test_gain_ramp_apply_to_planes() {
+    // Each channel gets the same ramp, which then goes on where the channels end.
+    let mut ramp = GainRamp::default();
+    ramp.fade_in(4);
+    let mut left = vec![100_i16; 2];
+    let mut right = vec![-100_i16; 2];
+    let planes = [
+        left.as_mut_ptr() as *mut c_void,
+        right.as_mut_ptr() as *mut c_void,
+    ];
+    ramp.apply_to_planes(StreamFormat::S16LE, planes.iter().cloned(), 2);
+    assert_eq!(left, vec![25, 50]);
+    assert_eq!(right, vec![-25, -50]);
+    let mut data = vec![100_i16; 2];
+    ramp.apply_to_buffer(StreamFormat::S16LE, data.as_mut_ptr() as *mut c_void, 1, 2);
+    assert_eq!(data, vec![75, 100]);
+}
+
+// apply_gain
+// ------------------------------------
+#[test]
+fn test_apply_gain() {
+    let mut data = vec![1.0_f32, -0.5, f32::NAN];
+    apply_gain(
+        StreamFormat::Float32LE,
+        data.as_mut_ptr() as *mut c_void,
+        2,
+        0.5,
+    );
+    assert_eq!(&data[..2], &[0.5, -0.25]);
+
+    // The muted samples are silent whatever they are.
+    apply_gain(
+        StreamFormat::Float32LE,
+        data.as_mut_ptr() as *mut c_void,
+        3,
+        0.0,
+    );
+    assert_eq!(data, vec![0.0; 3]);
+
+    let mut data = vec![1000_i16, -1000];
+    apply_gain(
+        StreamFormat::S16LE,
+        data.as_mut_ptr() as *mut c_void,
+        2,
+        0.25,
+    );
+    assert_eq!(data, vec![250, -250]);
+
+    let mut data = vec![(-1000_i16).to_be(), 1000_i16.to_be()];
+    apply_gain(
+        StreamFormat::S16BE,
+        data.as_mut_ptr() as *mut c_void,
+        2,
+        0.25,
+    );
+    let data: Vec<i16> = data.into_iter().map(i16::from_be).collect();
+    assert_eq!(data, vec![-250, 250]);
+
+    let mut data = vec![(-0.5_f32).to_bits().to_be()];
+    apply_gain(
+        StreamFormat::Float32BE,
+        data.as_mut_ptr() as *mut c_void,
+        1,
+        0.5,
+    );
+    assert_eq!(f32::from_bits(u32::from_be(data[0])), -0.25);
+}
@@ -0,0 +1,337 @@
+use super::fake_hardware::{FakeDevice, FakeHardware};
+use super::*;
+
+// Run the device queries against the in-memory fake system instead of the real hardware.
+
+// audiounit_get_devices_of_type
+// ------------------------------------
+#[test]
+fn test_fake_get_devices_of_type() {
+    let hardware = FakeHardware::new();
+    let mic = hardware.add_device(FakeDevice::new("mic", 1, 0));
+    let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+    let headset = hardware.add_device(FakeDevice::new("headset", 1, 2));
+
+    assert_eq!(
+        audiounit_get_devices_of_type(&*hardware, DeviceType::INPUT | DeviceType::OUTPUT),
+        vec![mic, speaker, headset]
+    );
+    assert_eq!(
+        audiounit_get_devices_of_type(&*hardware, DeviceType::INPUT),
+        vec![mic, headset]
+    );
+    assert_eq!(
+        audiounit_get_devices_of_type(&*hardware, DeviceType::OUTPUT),
+        vec![speaker, headset]
+    );
+
+    hardware.remove_device(headset);
+    assert_eq!(
+        audiounit_get_devices_of_type(&*hardware, DeviceType::INPUT),
+        vec![mic]
+    );
+    assert_eq!(
+        audiounit_get_devices_of_type(&*hardware, DeviceType::OUTPUT),
+        vec![speaker]
+    );
+}
+
+#[test]
+fn test_fake_get_devices_of_type_without_aggregate_device() {
+    let hardware = FakeHardware::new();
+    let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+    let uid = format!("{}_12345", PRIVATE_AGGREGATE_DEVICE_NAME);
+    hardware.add_device(FakeDevice::new(&uid, 1, 2));
+
+    assert_eq!(
+        audiounit_get_devices_of_type(&*hardware, DeviceType::INPUT | DeviceType::OUTPUT),
+        vec![speaker]
+    );
+    assert!(audiounit_get_devices_of_type(&*hardware, DeviceType::INPUT).is_empty());
+}
+
+// audiounit_get_default_device_id
+// ------------------------------------
+#[test]
+fn test_fake_get_default_device_id() {
+    let hardware = FakeHardware::new();
+    assert_eq!(
+        audiounit_get_default_device_id(&*hardware, DeviceType::OUTPUT),
+        kAudioObjectUnknown
+    );
+
+    let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+    hardware.set_default_device(speaker, DeviceType::OUTPUT);
+    assert_eq!(
+        audiounit_get_default_device_id(&*hardware, DeviceType::OUTPUT),
+        speaker
+    );
+    assert_eq!(
+        audiounit_get_default_device_id(&*hardware, DeviceType::INPUT),
+        kAudioObjectUnknown
+    );
+
+    hardware.remove_device(speaker);
+    assert_eq!(
+        audiounit_get_default_device_id(&*hardware, DeviceType::OUTPUT),
+        kAudioObjectUnknown
+    );
+}
+
+// get_device_group_id
+// ------------------------------------
+#[test]
+fn test_fake_get_device_group_id() {
+    const BUILTIN: u32 = 0x626C_746E; // "bltn"
+    const INTERNAL_MIC: u32 = 0x696D_6963; // "imic"
+    const INTERNAL_SPEAKER: u32 = 0x6973_706B; // "ispk"
+    const HEADPHONE: u32 = 0x6864_706E; // "hdpn"
+
+    let hardware = FakeHardware::new();
+
+    let mut builtin = FakeDevice::new("builtin", 1, 2);
+    builtin.transport_type = BUILTIN;
+    builtin.input_source = Some((INTERNAL_MIC, String::from("Internal Microphone")));
+    builtin.output_source = Some((HEADPHONE, String::from("Headphones")));
+    let builtin = hardware.add_device(builtin);
+    assert_eq!(
+        get_device_group_id(&*hardware, builtin, DeviceType::INPUT).unwrap(),
+        CString::new("builtin-internal-mic|spk").unwrap()
+    );
+    assert_eq!(
+        get_device_group_id(&*hardware, builtin, DeviceType::OUTPUT).unwrap(),
+        CString::new("builtin-external-mic|hdpn").unwrap()
+    );
+
+    // The source name is used as the device label.
+    assert_eq!(
+        get_device_label(&*hardware, builtin, DeviceType::OUTPUT)
+            .unwrap()
+            .into_string(),
+        "Headphones"
+    );
+
+    // Switching the source changes the group.
+    hardware.set_property(
+        builtin,
+        kAudioDevicePropertyDataSource,
+        kAudioDevicePropertyScopeOutput,
+        INTERNAL_SPEAKER,
+    );
+    assert_eq!(
+        get_device_group_id(&*hardware, builtin, DeviceType::OUTPUT).unwrap(),
+        CString::new("builtin-internal-mic|spk").unwrap()
+    );
+
+    // The model uid is used for the other devices.
+    let usb = hardware.add_device(FakeDevice::new("usb", 1, 2));
+    assert_eq!(
+        get_device_group_id(&*hardware, usb, DeviceType::INPUT).unwrap(),
+        CString::new("usb model").unwrap()
+    );
+
+    let mut no_model = FakeDevice::new("no-model", 0, 2);
+    no_model.model_uid = None;
+    let no_model = hardware.add_device(no_model);
+    assert!(get_device_group_id(&*hardware, no_model, DeviceType::OUTPUT).is_err());
+}
+
+// get_presentation_latency
+// ------------------------------------
+#[test]
+fn test_fake_get_presentation_latency() {
+    let hardware = FakeHardware::new();
+    let mut device = FakeDevice::new("device", 1, 2);
+    device.input_latency = (10, 20);
+    device.output_latency = (100, 200);
+    let device = hardware.add_device(device);
+
+    assert_eq!(
+        get_presentation_latency(&*hardware, device, DeviceType::INPUT),
+        30
+    );
+    assert_eq!(
+        get_presentation_latency(&*hardware, device, DeviceType::OUTPUT),
+        300
+    );
+
+    // No stream, no latency.
+    let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+    assert_eq!(
+        get_presentation_latency(&*hardware, speaker, DeviceType::INPUT),
+        0
+    );
+}
+
+// get_device_channel_layout
+// ------------------------------------
+#[test]
+fn test_fake_get_device_channel_layout() {
+    let hardware = FakeHardware::new();
+    let mut device = FakeDevice::new("device", 2, 2);
+    device.input_layout = vec![kAudioChannelLabel_Left, kAudioChannelLabel_Right];
+    let device = hardware.add_device(device);
+
+    assert_eq!(
+        get_device_channel_layout(&*hardware, device, DeviceType::INPUT),
+        vec![mixer::Channel::FrontLeft, mixer::Channel::FrontRight]
+    );
+    // No layout.
+    assert!(get_device_channel_layout(&*hardware, device, DeviceType::OUTPUT).is_empty());
+}
+
+// create_cubeb_device_info
+// ------------------------------------
+#[test]
+fn test_fake_create_cubeb_device_info() {
+    let hardware = FakeHardware::new();
+    let mut speaker = FakeDevice::new("speaker", 0, 6);
+    speaker.output_latency = (11, 22);
+    speaker.buffer_frame_size_range = (32.0, 1024.0);
+    let speaker = hardware.add_device(speaker);
+    hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+    assert!(create_cubeb_device_info(&*hardware, speaker, DeviceType::INPUT).is_err());
+
+    let mut info = create_cubeb_device_info(&*hardware, speaker, DeviceType::OUTPUT).unwrap();
+    assert_eq!(info.devid, speaker as ffi::cubeb_devid);
+    assert_eq!(
+        unsafe { CStr::from_ptr(info.device_id) },
+        CString::new("speaker").unwrap().as_c_str()
+    );
+    assert_eq!(
+        unsafe { CStr::from_ptr(info.friendly_name) },
+        CString::new("speaker name").unwrap().as_c_str()
+    );
+    assert_eq!(info.max_channels, 6);
+    assert_eq!(info.preferred, ffi::CUBEB_DEVICE_PREF_ALL);
+    assert_eq!(info.default_rate, 48_000);
+    assert_eq!(info.min_rate, 44_100);
+    assert_eq!(info.max_rate, 96_000);
+    assert_eq!(info.latency_lo, 33 + 32);
+    assert_eq!(info.latency_hi, 33 + 1024);
+    destroy_cubeb_device_info(&mut info);
+}
+
+// Property listeners
+// ------------------------------------
+#[test]
+fn test_fake_property_listener() {
+    use std::sync::atomic::AtomicU32;
+
+    extern "C" fn listener(
+        id: AudioObjectID,
+        number_of_addresses: u32,
+        addresses: *const AudioObjectPropertyAddress,
+        data: *mut c_void,
+    ) -> OSStatus {
+        assert_eq!(id, kAudioObjectSystemObject);
+        assert_eq!(number_of_addresses, 1);
+        let address = unsafe { &*addresses };
+        assert_eq!(address.mSelector, kAudioHardwarePropertyDevices);
+        // The fake system is still usable inside the listener.
+        let (hardware, count) = unsafe { &*(data as *const (Arc<FakeHardware>, AtomicU32)) };
+        count.store(
+            audiounit_get_devices(&**hardware).len() as u32,
+            Ordering::SeqCst,
+        );
+        NO_ERR
+    }
+
+    let hardware = FakeHardware::new();
+    let listener_data = (hardware.clone(), AtomicU32::new(0));
+    let count = &listener_data.1;
+    let address = get_property_address(
+        Property::HardwareDevices,
+        DeviceType::INPUT | DeviceType::OUTPUT,
+    );
+    let data = &listener_data as *const (Arc<FakeHardware>, AtomicU32) as *mut c_void;
+    assert_eq!(
+        audio_object_add_property_listener(
+            &*hardware,
+            kAudioObjectSystemObject,
+            &address,
+            listener,
+            data
+        ),
+        NO_ERR
+    );
+
+    hardware.add_device(FakeDevice::new("mic", 1, 0));
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    hardware.add_device(FakeDevice::new("speaker", 0, 2));
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    assert_eq!(
+        audio_object_remove_property_listener(
+            &*hardware,
+            kAudioObjectSystemObject,
+            &address,
+            listener,
+            data
+        ),
+        NO_ERR
+    );
+    assert_eq!(
+        hardware.listener_count(kAudioObjectSystemObject, kAudioHardwarePropertyDevices),
+        0
+    );
+    hardware.add_device(FakeDevice::new("headset", 1, 2));
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+}
+
+// AggregateDevice::new_loopback
+// ------------------------------------
+#[test]
+fn test_fake_create_loopback_aggregate_device() {
+    let hardware = FakeHardware::new();
+    let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+
+    let device = AggregateDevice::new_loopback(&hardware.platform(), speaker).unwrap();
+    let aggregate = device.get_device_id();
+    assert_eq!(hardware.aggregate_devices(), vec![aggregate]);
+    let taps = hardware.taps();
+    assert_eq!(taps.len(), 1);
+    let (tap, tapped_device) = taps[0];
+    assert_eq!(tapped_device, speaker);
+
+    // The speaker drives the aggregate device, whose input carries what the speaker plays.
+    assert_eq!(
+        hardware.strings(aggregate, kAudioAggregateDevicePropertyFullSubDeviceList),
+        vec![String::from("speaker")]
+    );
+    let tap_uid = AggregateDevice::get_tap_uid(&*hardware, tap)
+        .unwrap()
+        .into_string();
+    assert_eq!(
+        hardware.strings(aggregate, AGGREGATE_DEVICE_PROPERTY_TAP_LIST),
+        vec![tap_uid]
+    );
+    assert_eq!(
+        get_channel_count(&*hardware, aggregate, DeviceType::INPUT).unwrap(),
+        2
+    );
+    // The aggregate device is private, so it's not listed as an input device.
+    assert_eq!(
+        audiounit_get_devices_of_type(&*hardware, DeviceType::INPUT | DeviceType::OUTPUT),
+        vec![speaker]
+    );
+
+    drop(device);
+    assert!(hardware.aggregate_devices().is_empty());
+    assert!(hardware.taps().is_empty());
+}
+
+#[test]
+fn test_fake_create_loopback_aggregate_device_without_output() {
+    let hardware = FakeHardware::new();
+    let mic = hardware.add_device(FakeDevice::new("mic", 1, 0));
+
+    // Only the output devices can be tapped.
+    assert_eq!(
+        AggregateDevice::new_loopback(&hardware.platform(), mic).unwrap_err(),
+        kAudioHardwareIllegalOperationError as OSStatus
+    );
+    assert!(hardware.aggregate_devices().is_empty());
+    assert!(hardware.taps().is_empty());
+}
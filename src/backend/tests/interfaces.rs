@@ -536,7 +536,7 @@ fn test_ops_stream_reset_default_device() {
     test_default_output_stream_operation("stream: reset default device", |stream| {
         assert_eq!(
             unsafe { OPS.stream_reset_default_device.unwrap()(stream) },
-            ffi::CUBEB_ERROR_NOT_SUPPORTED
+            ffi::CUBEB_OK
         );
     });
 }
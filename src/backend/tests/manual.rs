@@ -201,13 +201,19 @@ fn test_add_then_remove_listeners() {
 
     fn add_listeners(stream: &AudioUnitStream, listeners: &Vec<device_property_listener>) {
         for listener in listeners {
-            assert_eq!(stream.add_device_listener(listener), NO_ERR);
+            assert_eq!(
+                stream.core_stream_data.add_device_listener(listener),
+                NO_ERR
+            );
         }
     }
 
     fn remove_listeners(stream: &AudioUnitStream, listeners: &Vec<device_property_listener>) {
         for listener in listeners {
-            assert_eq!(stream.remove_device_listener(listener), NO_ERR);
+            assert_eq!(
+                stream.core_stream_data.remove_device_listener(listener),
+                NO_ERR
+            );
         }
     }
 }
@@ -409,10 +415,21 @@ fn test_stream_tester() {
             // Feed silence data to output buffer
             if !output_buffer.is_null() {
                 let stm = unsafe { &mut *(stream as *mut AudioUnitStream) };
-                let channels = stm.core_stream_data.output_stream_params.channels();
+                let channels = stm
+                    .core_stream_data
+                    .output
+                    .as_ref()
+                    .unwrap()
+                    .stream_params
+                    .channels();
                 let samples = nframes as usize * channels as usize;
-                let sample_size =
-                    cubeb_sample_size(stm.core_stream_data.output_stream_params.format());
+                let sample_size = stm
+                    .core_stream_data
+                    .output
+                    .as_ref()
+                    .unwrap()
+                    .format
+                    .sample_size();
                 unsafe {
                     ptr::write_bytes(output_buffer, 0, samples * sample_size);
                 }
@@ -3,10 +3,23 @@ use super::*;
 mod aggregate_device;
 mod api;
 mod backlog;
+mod buffer_list;
+mod buffer_manager;
+mod channel_map;
 mod device_change;
 mod device_property;
+mod drift;
+mod error;
+mod fake_hardware;
+mod gain;
+mod hardware;
 mod interfaces;
 mod manual;
 mod parallel;
+mod planar;
+mod position;
+mod simulated_audio_unit;
+mod simulation;
+mod stats;
 mod tone;
 mod utils;
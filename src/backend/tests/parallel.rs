@@ -21,18 +21,24 @@ fn test_parallel_ops_init_streams_in_parallel_input() {
             let mut in_buffer_frame_sizes = vec![];
 
             for stream in streams {
-                latency_frames.push(stream.latency_frames);
-
-                assert!(!stream.core_stream_data.input_unit.is_null());
+                latency_frames.push(stream.shared.latency_frames);
+
+                assert!(!stream
+                    .core_stream_data
+                    .input
+                    .as_ref()
+                    .unwrap()
+                    .unit
+                    .is_null());
                 let in_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                    stream.core_stream_data.input_unit,
+                    stream.core_stream_data.input.as_ref().unwrap().unit,
                     Scope::Input,
                     PropertyScope::Output,
                 )
                 .unwrap();
                 in_buffer_frame_sizes.push(in_buffer_frame_size);
 
-                assert!(stream.core_stream_data.output_unit.is_null());
+                assert!(stream.core_stream_data.output.is_none());
             }
 
             // Make sure all the latency frames are same as the first stream's one.
@@ -65,13 +71,19 @@ fn test_parallel_ops_init_streams_in_parallel_output() {
             let mut out_buffer_frame_sizes = vec![];
 
             for stream in streams {
-                latency_frames.push(stream.latency_frames);
+                latency_frames.push(stream.shared.latency_frames);
 
-                assert!(stream.core_stream_data.input_unit.is_null());
+                assert!(stream.core_stream_data.input.is_none());
 
-                assert!(!stream.core_stream_data.output_unit.is_null());
+                assert!(!stream
+                    .core_stream_data
+                    .output
+                    .as_ref()
+                    .unwrap()
+                    .unit
+                    .is_null());
                 let out_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                    stream.core_stream_data.output_unit,
+                    stream.core_stream_data.output.as_ref().unwrap().unit,
                     Scope::Output,
                     PropertyScope::Input,
                 )
@@ -110,20 +122,32 @@ fn test_parallel_ops_init_streams_in_parallel_duplex() {
             let mut out_buffer_frame_sizes = vec![];
 
             for stream in streams {
-                latency_frames.push(stream.latency_frames);
-
-                assert!(!stream.core_stream_data.input_unit.is_null());
+                latency_frames.push(stream.shared.latency_frames);
+
+                assert!(!stream
+                    .core_stream_data
+                    .input
+                    .as_ref()
+                    .unwrap()
+                    .unit
+                    .is_null());
                 let in_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                    stream.core_stream_data.input_unit,
+                    stream.core_stream_data.input.as_ref().unwrap().unit,
                     Scope::Input,
                     PropertyScope::Output,
                 )
                 .unwrap();
                 in_buffer_frame_sizes.push(in_buffer_frame_size);
 
-                assert!(!stream.core_stream_data.output_unit.is_null());
+                assert!(!stream
+                    .core_stream_data
+                    .output
+                    .as_ref()
+                    .unwrap()
+                    .unit
+                    .is_null());
                 let out_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                    stream.core_stream_data.output_unit,
+                    stream.core_stream_data.output.as_ref().unwrap().unit,
                     Scope::Output,
                     PropertyScope::Input,
                 )
@@ -272,18 +296,24 @@ fn test_parallel_init_streams_in_parallel_input() {
         let mut in_buffer_frame_sizes = vec![];
 
         for stream in streams {
-            latency_frames.push(stream.latency_frames);
-
-            assert!(!stream.core_stream_data.input_unit.is_null());
+            latency_frames.push(stream.shared.latency_frames);
+
+            assert!(!stream
+                .core_stream_data
+                .input
+                .as_ref()
+                .unwrap()
+                .unit
+                .is_null());
             let in_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                stream.core_stream_data.input_unit,
+                stream.core_stream_data.input.as_ref().unwrap().unit,
                 Scope::Input,
                 PropertyScope::Output,
             )
             .unwrap();
             in_buffer_frame_sizes.push(in_buffer_frame_size);
 
-            assert!(stream.core_stream_data.output_unit.is_null());
+            assert!(stream.core_stream_data.output.is_none());
         }
 
         // Make sure all the latency frames are same as the first stream's one.
@@ -312,13 +342,19 @@ fn test_parallel_init_streams_in_parallel_output() {
         let mut out_buffer_frame_sizes = vec![];
 
         for stream in streams {
-            latency_frames.push(stream.latency_frames);
+            latency_frames.push(stream.shared.latency_frames);
 
-            assert!(stream.core_stream_data.input_unit.is_null());
+            assert!(stream.core_stream_data.input.is_none());
 
-            assert!(!stream.core_stream_data.output_unit.is_null());
+            assert!(!stream
+                .core_stream_data
+                .output
+                .as_ref()
+                .unwrap()
+                .unit
+                .is_null());
             let out_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                stream.core_stream_data.output_unit,
+                stream.core_stream_data.output.as_ref().unwrap().unit,
                 Scope::Output,
                 PropertyScope::Input,
             )
@@ -353,20 +389,32 @@ fn test_parallel_init_streams_in_parallel_duplex() {
         let mut out_buffer_frame_sizes = vec![];
 
         for stream in streams {
-            latency_frames.push(stream.latency_frames);
-
-            assert!(!stream.core_stream_data.input_unit.is_null());
+            latency_frames.push(stream.shared.latency_frames);
+
+            assert!(!stream
+                .core_stream_data
+                .input
+                .as_ref()
+                .unwrap()
+                .unit
+                .is_null());
             let in_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                stream.core_stream_data.input_unit,
+                stream.core_stream_data.input.as_ref().unwrap().unit,
                 Scope::Input,
                 PropertyScope::Output,
             )
             .unwrap();
             in_buffer_frame_sizes.push(in_buffer_frame_size);
 
-            assert!(!stream.core_stream_data.output_unit.is_null());
+            assert!(!stream
+                .core_stream_data
+                .output
+                .as_ref()
+                .unwrap()
+                .unit
+                .is_null());
             let out_buffer_frame_size = test_audiounit_get_buffer_frame_size(
-                stream.core_stream_data.output_unit,
+                stream.core_stream_data.output.as_ref().unwrap().unit,
                 Scope::Output,
                 PropertyScope::Input,
             )
@@ -538,6 +586,7 @@ fn test_set_buffer_frame_size_in_parallel_in_scope(scope: Scope) {
         let unit_value = units.last().unwrap().get_inner() as usize;
         join_handles.push(thread::spawn(move || {
             let status = audio_unit_set_property(
+                &CoreAudioUnits,
                 unit_value as AudioUnit,
                 kAudioDevicePropertyBufferFrameSize,
                 unit_scope,
@@ -0,0 +1,169 @@
+use super::*;
+
+// interleave, deinterleave
+// ------------------------------------
+#[test]
+fn test_deinterleave_and_interleave() {
+    // 2 frames of 3 channels of 2-byte samples.
+    let frames: Vec<u8> = (0..12).collect();
+    let mut planes = vec![0; 12];
+    deinterleave(&frames, &mut planes, 3, 2);
+    assert_eq!(planes, [0, 1, 6, 7, 2, 3, 8, 9, 4, 5, 10, 11]);
+
+    let mut interleaved = vec![0; 12];
+    interleave(&planes, &mut interleaved, 3, 2);
+    assert_eq!(interleaved, frames);
+}
+
+#[test]
+fn test_interleave_fewer_frames() {
+    // The channels have room for 3 frames, and only the first one is interleaved.
+    let planes: Vec<u8> = (0..6).collect();
+    let mut frames = vec![0xff; 4];
+    interleave(&planes, &mut frames[..2], 2, 1);
+    assert_eq!(frames, [0, 3, 0xff, 0xff]);
+}
+
+// PlanarCallback
+// ------------------------------------
+// Swap the two channels of the input into the output, and write `written` frames, or all of
+// them.
+struct Swapper {
+    written: Option<i64>,
+    has_input: Vec<bool>,
+}
+
+extern "C" fn swapper_data_callback(
+    _stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: i64,
+) -> i64 {
+    let swapper = unsafe { &mut *(user_ptr as *mut Swapper) };
+    let frames = nframes as usize;
+    swapper.has_input.push(!input_buffer.is_null());
+    if output_buffer.is_null() {
+        return nframes;
+    }
+    let outputs = unsafe { slice::from_raw_parts(output_buffer as *const *mut i16, 2) };
+    for (channel, output) in outputs.iter().enumerate() {
+        let output = unsafe { slice::from_raw_parts_mut(*output, frames) };
+        if input_buffer.is_null() {
+            for sample in output.iter_mut() {
+                *sample = channel as i16;
+            }
+        } else {
+            let inputs = unsafe { slice::from_raw_parts(input_buffer as *const *const i16, 2) };
+            let input = unsafe { slice::from_raw_parts(inputs[1 - channel], frames) };
+            output.copy_from_slice(input);
+        }
+    }
+    swapper.written.unwrap_or(nframes)
+}
+
+fn call_planar(
+    planar: &mut PlanarCallback,
+    input: Option<&[i16]>,
+    output: &mut [i16],
+    frames: usize,
+) -> i64 {
+    planar_data_callback(
+        ptr::null_mut(),
+        planar as *mut PlanarCallback as *mut c_void,
+        input.map_or(ptr::null(), |input| input.as_ptr() as *const c_void),
+        output.as_mut_ptr() as *mut c_void,
+        frames as i64,
+    )
+}
+
+#[test]
+fn test_planar_callback_duplex() {
+    let mut swapper = Swapper {
+        written: None,
+        has_input: Vec::new(),
+    };
+    let mut planar = PlanarCallback::new(
+        Some(swapper_data_callback),
+        &mut swapper as *mut Swapper as *mut c_void,
+        mem::size_of::<i16>(),
+        2,
+        2,
+        3,
+    );
+    let input = [1, -1, 2, -2, 3, -3];
+    let mut output = [0; 6];
+    assert_eq!(call_planar(&mut planar, Some(&input), &mut output, 3), 3);
+    assert_eq!(output, [-1, 1, -2, 2, -3, 3]);
+
+    // The calls of fewer frames use the start of the channels, and the calls of more frames are
+    // split.
+    let input = [4, -4];
+    let mut output = [0; 2];
+    assert_eq!(call_planar(&mut planar, Some(&input), &mut output, 1), 1);
+    assert_eq!(output, [-4, 4]);
+    let input = [5, -5, 6, -6, 7, -7, 8, -8];
+    let mut output = [0; 8];
+    assert_eq!(call_planar(&mut planar, Some(&input), &mut output, 4), 4);
+    assert_eq!(output, [-5, 5, -6, 6, -7, 7, -8, 8]);
+    assert_eq!(swapper.has_input, [true, true, true, true]);
+}
+
+#[test]
+fn test_planar_callback_draining() {
+    let mut swapper = Swapper {
+        written: Some(1),
+        has_input: Vec::new(),
+    };
+    let mut planar = PlanarCallback::new(
+        Some(swapper_data_callback),
+        &mut swapper as *mut Swapper as *mut c_void,
+        mem::size_of::<i16>(),
+        0,
+        2,
+        2,
+    );
+    // Only the frames written by the data callback are interleaved, and the data callback isn't
+    // called again once it's drained.
+    let mut output = [9; 6];
+    assert_eq!(call_planar(&mut planar, None, &mut output, 3), 1);
+    assert_eq!(output, [0, 1, 9, 9, 9, 9]);
+    assert_eq!(swapper.has_input, [false]);
+}
+
+#[test]
+fn test_planar_callback_with_buffers() {
+    let mut swapper = Swapper {
+        written: None,
+        has_input: Vec::new(),
+    };
+    let mut planar = PlanarCallback::new(
+        Some(swapper_data_callback),
+        &mut swapper as *mut Swapper as *mut c_void,
+        mem::size_of::<i16>(),
+        2,
+        2,
+        1,
+    );
+    fn buffer(data: &mut [i16]) -> AudioBuffer {
+        AudioBuffer {
+            mNumberChannels: 1,
+            mDataByteSize: mem::size_of_val(data) as u32,
+            mData: data.as_mut_ptr() as *mut c_void,
+        }
+    }
+    // The data callback gets the buffers themselves, whatever their frames.
+    let mut left = [1, 2, 3];
+    let mut right = [-1, -2, -3];
+    let input = [buffer(&mut left), buffer(&mut right)];
+    let mut first = [0; 3];
+    let mut second = [0; 3];
+    let output = [buffer(&mut first), buffer(&mut second)];
+    assert_eq!(
+        planar.call_with_buffers(ptr::null_mut(), Some(&input), Some(&output), 3),
+        3
+    );
+    assert_eq!(first, [-1, -2, -3]);
+    assert_eq!(second, [1, 2, 3]);
+    assert_eq!(swapper.has_input, [true]);
+}
@@ -0,0 +1,66 @@
+use super::*;
+use std::thread;
+
+// StreamTimestamp
+// ------------------------------------
+#[test]
+fn test_timestamp_position_at() {
+    let timestamp = StreamTimestamp {
+        position: 48_000,
+        host_time_ns: 2_000_000_000,
+    };
+    let limit = 96_000;
+    assert_eq!(timestamp.position_at(2_000_000_000, 48_000, limit), 48_000);
+    // 10 ms after and before the timestamp.
+    assert_eq!(timestamp.position_at(2_010_000_000, 48_000, limit), 48_480);
+    assert_eq!(timestamp.position_at(1_990_000_000, 48_000, limit), 47_520);
+    // The position stops at the limit and doesn't go below 0.
+    assert_eq!(timestamp.position_at(4_000_000_000, 48_000, limit), limit);
+    assert_eq!(timestamp.position_at(1_900_000_000, 48_000, limit), 43_200);
+    assert_eq!(timestamp.position_at(0, 48_000, limit), 0);
+}
+
+// PositionClock
+// ------------------------------------
+#[test]
+fn test_position_clock() {
+    let clock = PositionClock::default();
+    assert_eq!(clock.read(), (StreamTimestamp::default(), 0, 0));
+    // Nothing is queued before the first update.
+    assert_eq!(clock.position_at(1_000_000_000), 0);
+
+    let timestamp = StreamTimestamp {
+        position: 512,
+        host_time_ns: 1_000_000_000,
+    };
+    clock.update(timestamp, 1024, 48_000);
+    assert_eq!(clock.read(), (timestamp, 1024, 48_000));
+    assert_eq!(clock.position_at(1_005_000_000), 752);
+    assert_eq!(clock.position_at(1_100_000_000), 1024);
+}
+
+#[test]
+fn test_position_clock_consistent_snapshots() {
+    let clock = Arc::new(PositionClock::default());
+    let writer = {
+        let clock = clock.clone();
+        thread::spawn(move || {
+            for i in 1..10_000_u64 {
+                clock.update(
+                    StreamTimestamp {
+                        position: i,
+                        host_time_ns: i * 1_000,
+                    },
+                    i * 2,
+                    48_000,
+                );
+            }
+        })
+    };
+    for _ in 0..10_000 {
+        let (timestamp, queued, _) = clock.read();
+        assert_eq!(timestamp.host_time_ns, timestamp.position * 1_000);
+        assert_eq!(queued, timestamp.position * 2);
+    }
+    writer.join().unwrap();
+}
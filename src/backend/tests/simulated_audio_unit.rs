@@ -0,0 +1,1055 @@
+use super::fake_hardware::{self, bytes_of, FakeHardware};
+use super::*;
+use std::collections::HashMap;
+use std::sync::MutexGuard;
+use std::thread::{self, ThreadId};
+
+// SimulatedAudioUnits: AudioUnits driven by a virtual clock
+// ------------------------------------------------------------------------------------------------
+// Tests run a stream on a platform made of the simulation and a fake system, and then call
+// `advance` to fire the registered input and render callbacks on the current thread, as if the
+// time has passed by. The frames rendered by the input units carry
+// their (1-based) frame index so the tests can check the data continuity, and the data rendered
+// by the output units is recorded so the tests can check what the device would play.
+
+const FIRST_UNIT_HANDLE: usize = 0x1000;
+const NS_PER_SECOND: f64 = 1_000_000_000.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SimulatedFormat {
+    pub rate: f64,
+    pub channels: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    // The hardware formats of the devices without a format set by `set_device_format`.
+    pub default_input: SimulatedFormat,
+    pub default_output: SimulatedFormat,
+    pub buffer_frame_size: u32,
+    // The frames in each callback varies randomly within
+    // [buffer_frame_size - jitter_frames, buffer_frame_size + jitter_frames].
+    pub jitter_frames: u32,
+    pub seed: u64,
+    // How long it takes to get the first callback after the unit is started.
+    pub input_start_delay: Duration,
+    pub output_start_delay: Duration,
+    // The value of kAudioUnitProperty_Latency.
+    pub latency_seconds: f64,
+    // The buffers of the AudioBufferLists of the callbacks, each holding some of the channels,
+    // like the devices of several streams. The lists given to AudioUnitRender must have room for
+    // them.
+    pub buffers_per_list: u32,
+    // Added to the input of each channel times the (0-based) index of the channel, so the
+    // channels can be told apart.
+    pub input_channel_step: f32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            default_input: SimulatedFormat {
+                rate: 48_000.0,
+                channels: 1,
+            },
+            default_output: SimulatedFormat {
+                rate: 48_000.0,
+                channels: 2,
+            },
+            buffer_frame_size: 512,
+            jitter_frames: 0,
+            seed: 0x2545_F491_4F6C_DD1D,
+            input_start_delay: Duration::from_millis(0),
+            output_start_delay: Duration::from_millis(0),
+            latency_seconds: 0.0,
+            buffers_per_list: 1,
+            input_channel_step: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Callback {
+    procedure: AURenderCallback,
+    ref_con: usize,
+}
+
+impl Callback {
+    fn new(callback: &AURenderCallbackStruct) -> Self {
+        Self {
+            procedure: callback.inputProc,
+            ref_con: callback.inputProcRefCon as usize,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Listener {
+    id: AudioUnitPropertyID,
+    listener: audio_unit_property_listener_proc,
+    data: usize,
+}
+
+type PropertyKey = (AudioUnitPropertyID, AudioUnitScope, AudioUnitElement);
+
+struct SimulatedUnit {
+    properties: HashMap<PropertyKey, Vec<u8>>,
+    parameters: HashMap<PropertyKey, AudioUnitParameterValue>,
+    listeners: Vec<Listener>,
+    render_callback: Option<Callback>,
+    input_callback: Option<Callback>,
+    buffer_frame_size: u32,
+    initialized: bool,
+    running: bool,
+    started_ns: u64,
+    frames_since_start: u64,
+    callbacks: usize,
+    input_frames: u64,
+    input_buffer: Vec<u8>,
+    // The input split into the buffers of the list, when it has several buffers.
+    split_input: Vec<Vec<u8>>,
+    rendered_output: Vec<u8>,
+    pending_render_errors: u32,
+}
+
+impl SimulatedUnit {
+    fn new(buffer_frame_size: u32, device: AudioObjectID) -> Self {
+        let mut unit = Self {
+            properties: HashMap::new(),
+            parameters: HashMap::new(),
+            listeners: Vec::new(),
+            render_callback: None,
+            input_callback: None,
+            buffer_frame_size,
+            initialized: false,
+            running: false,
+            started_ns: 0,
+            frames_since_start: 0,
+            callbacks: 0,
+            input_frames: 0,
+            input_buffer: Vec::new(),
+            split_input: Vec::new(),
+            rendered_output: Vec::new(),
+            pending_render_errors: 0,
+        };
+        // AUHAL enables the output and disables the input by default.
+        unit.set_u32(
+            kAudioOutputUnitProperty_EnableIO,
+            kAudioUnitScope_Output,
+            AU_OUT_BUS,
+            1,
+        );
+        unit.set_u32(
+            kAudioOutputUnitProperty_EnableIO,
+            kAudioUnitScope_Input,
+            AU_IN_BUS,
+            0,
+        );
+        unit.set_u32(
+            kAudioOutputUnitProperty_CurrentDevice,
+            kAudioUnitScope_Global,
+            0,
+            device,
+        );
+        unit.parameters
+            .insert((kHALOutputParam_Volume, kAudioUnitScope_Global, 0), 1.0);
+        unit
+    }
+
+    fn get_u32(&self, id: AudioUnitPropertyID, scope: AudioUnitScope, element: u32) -> u32 {
+        self.properties
+            .get(&(id, scope, element))
+            .map_or(0, |bytes| unsafe { *(bytes.as_ptr() as *const u32) })
+    }
+
+    fn set_u32(&mut self, id: AudioUnitPropertyID, scope: AudioUnitScope, element: u32, v: u32) {
+        self.properties.insert((id, scope, element), bytes_of(&v));
+    }
+
+    fn input_enabled(&self) -> bool {
+        self.get_u32(
+            kAudioOutputUnitProperty_EnableIO,
+            kAudioUnitScope_Input,
+            AU_IN_BUS,
+        ) != 0
+    }
+
+    fn output_enabled(&self) -> bool {
+        self.get_u32(
+            kAudioOutputUnitProperty_EnableIO,
+            kAudioUnitScope_Output,
+            AU_OUT_BUS,
+        ) != 0
+    }
+
+    fn device(&self) -> AudioObjectID {
+        self.get_u32(
+            kAudioOutputUnitProperty_CurrentDevice,
+            kAudioUnitScope_Global,
+            0,
+        )
+    }
+
+    fn client_format(&self, input: bool) -> Option<AudioStreamBasicDescription> {
+        let key = if input {
+            (
+                kAudioUnitProperty_StreamFormat,
+                kAudioUnitScope_Output,
+                AU_IN_BUS,
+            )
+        } else {
+            (
+                kAudioUnitProperty_StreamFormat,
+                kAudioUnitScope_Input,
+                AU_OUT_BUS,
+            )
+        };
+        self.properties
+            .get(&key)
+            .map(|bytes| unsafe { *(bytes.as_ptr() as *const AudioStreamBasicDescription) })
+    }
+}
+
+struct SimulationState {
+    config: SimulationConfig,
+    units: HashMap<usize, SimulatedUnit>,
+    next_handle: usize,
+    device_formats: HashMap<(AudioObjectID, bool), SimulatedFormat>,
+    now_ns: u64,
+    // The host time when the virtual clock is at 0, so the timestamps of the callbacks are
+    // comparable with `mach_absolute_time`.
+    host_time_base: u64,
+    random_state: u64,
+    new_unit_error: Option<OSStatus>,
+}
+
+fn ns_to_host_time(ns: u64) -> u64 {
+    (ns as f64 * f64::from(HOST_TIME_TO_NS_RATIO.1) / f64::from(HOST_TIME_TO_NS_RATIO.0)) as u64
+}
+
+impl SimulationState {
+    fn hardware_format(&self, unit: &SimulatedUnit, input: bool) -> SimulatedFormat {
+        match self.device_formats.get(&(unit.device(), input)) {
+            Some(format) => *format,
+            None if input => self.config.default_input,
+            None => self.config.default_output,
+        }
+    }
+
+    // A xorshift generator, so the jitter is reproducible by the seed.
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.random_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.random_state = x;
+        x
+    }
+
+    fn callback_frames(&mut self, buffer_frame_size: u32) -> u32 {
+        let jitter = i64::from(self.config.jitter_frames);
+        if jitter == 0 {
+            return buffer_frame_size;
+        }
+        let offset = (self.next_random() % (2 * jitter as u64 + 1)) as i64 - jitter;
+        cmp::max(1, i64::from(buffer_frame_size) + offset) as u32
+    }
+
+    fn unit(&self, unit: AudioUnit) -> std::result::Result<&SimulatedUnit, OSStatus> {
+        self.units
+            .get(&(unit as usize))
+            .ok_or(kAudio_ParamError as OSStatus)
+    }
+
+    fn unit_mut(&mut self, unit: AudioUnit) -> std::result::Result<&mut SimulatedUnit, OSStatus> {
+        self.units
+            .get_mut(&(unit as usize))
+            .ok_or(kAudio_ParamError as OSStatus)
+    }
+
+    fn get_property_bytes(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+    ) -> std::result::Result<Vec<u8>, OSStatus> {
+        let simulated = self.unit(unit)?;
+        match property {
+            kAudioUnitProperty_StreamFormat => {
+                let input = element == AU_IN_BUS;
+                let client_side = (input && scope == kAudioUnitScope_Output)
+                    || (!input && scope == kAudioUnitScope_Input);
+                if client_side {
+                    if let Some(desc) = simulated.client_format(input) {
+                        return Ok(bytes_of(&desc));
+                    }
+                }
+                let format = self.hardware_format(simulated, input);
+                Ok(bytes_of(&float_description(format)))
+            }
+            kAudioDevicePropertyBufferFrameSize => Ok(bytes_of(&simulated.buffer_frame_size)),
+            kAudioUnitProperty_Latency => Ok(bytes_of(&self.config.latency_seconds)),
+            kAudioOutputUnitProperty_IsRunning => Ok(bytes_of(&u32::from(simulated.running))),
+            kAudioUnitProperty_AudioChannelLayout
+                if scope == kAudioUnitScope_Output && element == AU_OUT_BUS =>
+            {
+                let channels = self.hardware_format(simulated, false).channels;
+                Ok(channel_layout_bytes(channels))
+            }
+            _ => simulated
+                .properties
+                .get(&(property, scope, element))
+                .cloned()
+                .ok_or(kAudioUnitErr_InvalidProperty),
+        }
+    }
+}
+
+struct Tick {
+    unit: AudioUnit,
+    frames: u32,
+    sample_time: f64,
+    host_time: u64,
+    input: Option<Callback>,
+    // The render callback and the (channels, sample size) of the output data.
+    output: Option<(Callback, u32, u32)>,
+    // The buffers of the output data.
+    buffers: u32,
+}
+
+pub struct SimulatedAudioUnits {
+    state: Mutex<SimulationState>,
+    // Held while running the callbacks. Like the lock held by the IO thread of the real
+    // AudioUnit, stopping or disposing the units from other threads waits for the running
+    // callback.
+    render_lock: Mutex<()>,
+    rendering_thread: Mutex<Option<ThreadId>>,
+    // The system of the devices the units run on.
+    hardware: Arc<FakeHardware>,
+}
+
+impl SimulatedAudioUnits {
+    pub fn new(hardware: &Arc<FakeHardware>, config: SimulationConfig) -> Arc<Self> {
+        let random_state = cmp::max(config.seed, 1);
+        Arc::new(Self {
+            state: Mutex::new(SimulationState {
+                config,
+                units: HashMap::new(),
+                next_handle: FIRST_UNIT_HANDLE,
+                device_formats: HashMap::new(),
+                now_ns: 0,
+                host_time_base: unsafe { mach_absolute_time() },
+                random_state,
+                new_unit_error: None,
+            }),
+            render_lock: Mutex::new(()),
+            rendering_thread: Mutex::new(None),
+            hardware: hardware.clone(),
+        })
+    }
+
+    pub fn set_device_format(
+        &self,
+        device: AudioObjectID,
+        devtype: DeviceType,
+        rate: f64,
+        channels: u32,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let input = match devtype {
+            DeviceType::INPUT => true,
+            DeviceType::OUTPUT => false,
+            _ => panic!("Invalid type"),
+        };
+        state
+            .device_formats
+            .insert((device, input), SimulatedFormat { rate, channels });
+    }
+
+    pub fn set_jitter_frames(&self, frames: u32) {
+        self.state.lock().unwrap().config.jitter_frames = frames;
+    }
+
+    // Make AudioComponentInstanceNew fail with `error` until it's reset to None.
+    pub fn set_new_unit_error(&self, error: Option<OSStatus>) {
+        self.state.lock().unwrap().new_unit_error = error;
+    }
+
+    // Make the next `count` AudioUnitRender calls on the input `unit` fail with
+    // kAudioUnitErr_CannotDoInCurrentContext.
+    pub fn inject_render_errors(&self, unit: AudioUnit, count: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.unit_mut(unit).unwrap().pending_render_errors += count;
+    }
+
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos(self.state.lock().unwrap().now_ns)
+    }
+
+    pub fn units(&self) -> Vec<AudioUnit> {
+        let state = self.state.lock().unwrap();
+        let mut units: Vec<usize> = state.units.keys().cloned().collect();
+        units.sort();
+        units.into_iter().map(|unit| unit as AudioUnit).collect()
+    }
+
+    pub fn is_running(&self, unit: AudioUnit) -> bool {
+        let state = self.state.lock().unwrap();
+        state.unit(unit).map_or(false, |u| u.running)
+    }
+
+    pub fn buffer_frame_size(&self, unit: AudioUnit) -> u32 {
+        let state = self.state.lock().unwrap();
+        state.unit(unit).unwrap().buffer_frame_size
+    }
+
+    pub fn callback_count(&self, unit: AudioUnit) -> usize {
+        let state = self.state.lock().unwrap();
+        state.unit(unit).map_or(0, |u| u.callbacks)
+    }
+
+    pub fn take_rendered_output(&self, unit: AudioUnit) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        mem::replace(
+            &mut state.unit_mut(unit).unwrap().rendered_output,
+            Vec::new(),
+        )
+    }
+
+    // Move the virtual clock forward and fire all the callbacks due in the meantime, on the
+    // current thread, in the order of their time.
+    pub fn advance(&self, duration: Duration) {
+        let target = {
+            let state = self.state.lock().unwrap();
+            state.now_ns + duration.as_nanos() as u64
+        };
+        while let Some(tick) = self.next_tick(target) {
+            self.fire(tick);
+        }
+        let mut state = self.state.lock().unwrap();
+        state.now_ns = cmp::max(state.now_ns, target);
+    }
+
+    fn next_tick(&self, target: u64) -> Option<Tick> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        let next_time = |unit: &SimulatedUnit, rate: f64| -> u64 {
+            unit.started_ns + (unit.frames_since_start as f64 * NS_PER_SECOND / rate) as u64
+        };
+
+        // Ties go to the unit created first.
+        let mut due: Option<(u64, usize)> = None;
+        for (handle, unit) in state.units.iter() {
+            let active = (unit.input_enabled() && unit.input_callback.is_some())
+                || (unit.output_enabled() && unit.render_callback.is_some());
+            if !unit.running || !active {
+                continue;
+            }
+            let rate = state.hardware_format(unit, !unit.output_enabled()).rate;
+            let time = next_time(unit, rate);
+            if time <= target && due.map_or(true, |d| (time, *handle) < d) {
+                due = Some((time, *handle));
+            }
+        }
+        let (time, handle) = due?;
+
+        let buffer_frame_size = state.units[&handle].buffer_frame_size;
+        let frames = state.callback_frames(buffer_frame_size);
+        state.now_ns = cmp::max(state.now_ns, time);
+
+        let unit = &state.units[&handle];
+        let input = if unit.input_enabled() {
+            unit.input_callback
+        } else {
+            None
+        };
+        let mut buffers = state.config.buffers_per_list;
+        let output = match (unit.output_enabled(), unit.render_callback) {
+            (true, Some(callback)) => {
+                let desc = unit
+                    .client_format(false)
+                    .unwrap_or_else(|| float_description(state.hardware_format(unit, false)));
+                // The data that isn't interleaved has a buffer per channel.
+                let sample_size = if buffer_count(&desc) > 1 {
+                    buffers = desc.mChannelsPerFrame;
+                    desc.mBytesPerFrame
+                } else {
+                    desc.mBytesPerFrame / desc.mChannelsPerFrame
+                };
+                Some((callback, desc.mChannelsPerFrame, sample_size))
+            }
+            _ => None,
+        };
+        let sample_time = unit.frames_since_start as f64;
+
+        let unit = state.units.get_mut(&handle).unwrap();
+        unit.frames_since_start += u64::from(frames);
+        unit.callbacks += 1;
+
+        Some(Tick {
+            unit: handle as AudioUnit,
+            frames,
+            sample_time,
+            host_time: state.host_time_base + ns_to_host_time(time),
+            input,
+            output,
+            buffers,
+        })
+    }
+
+    fn fire(&self, tick: Tick) {
+        let _render = self.render_lock.lock().unwrap();
+        *self.rendering_thread.lock().unwrap() = Some(thread::current().id());
+
+        let mut timestamp: AudioTimeStamp = unsafe { mem::zeroed() };
+        timestamp.mSampleTime = tick.sample_time;
+        timestamp.mHostTime = tick.host_time;
+        timestamp.mFlags = kAudioTimeStampSampleHostTimeValid;
+
+        if let Some(callback) = tick.input {
+            let mut flags: AudioUnitRenderActionFlags = 0;
+            unsafe {
+                callback.procedure.unwrap()(
+                    callback.ref_con as *mut c_void,
+                    &mut flags,
+                    &timestamp,
+                    AU_IN_BUS,
+                    tick.frames,
+                    ptr::null_mut(),
+                );
+            }
+        }
+
+        if let Some((callback, channels, sample_size)) = tick.output {
+            let mut data = vec![0_u8; (tick.frames * channels * sample_size) as usize];
+            let sample_size = sample_size as usize;
+            // The buffers of the list, and the list, when the channels are split.
+            let mut split_data: Vec<Vec<u8>> = split_channels(channels, tick.buffers)
+                .iter()
+                .map(|c| vec![0_u8; tick.frames as usize * *c as usize * sample_size])
+                .collect();
+            let mut split_list = BufferList::new(cmp::max(split_data.len(), 1));
+            let mut list = AudioBufferList {
+                mNumberBuffers: 1,
+                mBuffers: [AudioBuffer {
+                    mNumberChannels: channels,
+                    mDataByteSize: data.len() as u32,
+                    mData: data.as_mut_ptr() as *mut c_void,
+                }],
+            };
+            let list_ptr: *mut AudioBufferList = if tick.buffers > 1 {
+                fill_list(
+                    &mut split_list,
+                    &split_channels(channels, tick.buffers),
+                    &mut split_data,
+                )
+            } else {
+                &mut list
+            };
+            let mut flags: AudioUnitRenderActionFlags = 0;
+            unsafe {
+                callback.procedure.unwrap()(
+                    callback.ref_con as *mut c_void,
+                    &mut flags,
+                    &timestamp,
+                    AU_OUT_BUS,
+                    tick.frames,
+                    list_ptr,
+                );
+            }
+            if tick.buffers > 1 {
+                // Record the output interleaved.
+                gather_buffers(
+                    split_list.buffers_mut(),
+                    &mut data,
+                    channels as usize,
+                    sample_size,
+                    tick.frames as usize,
+                );
+            }
+            let mut state = self.state.lock().unwrap();
+            if let Ok(unit) = state.unit_mut(tick.unit) {
+                unit.rendered_output.extend_from_slice(&data);
+            }
+        }
+
+        *self.rendering_thread.lock().unwrap() = None;
+    }
+
+    // Wait for the running callback unless it's the caller.
+    fn wait_for_rendering(&self) -> Option<MutexGuard<()>> {
+        let rendering_thread = *self.rendering_thread.lock().unwrap();
+        if rendering_thread == Some(thread::current().id()) {
+            None
+        } else {
+            Some(self.render_lock.lock().unwrap())
+        }
+    }
+
+    fn fire_listeners(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+    ) {
+        // Don't hold the lock while calling the listeners since they may query the properties.
+        let listeners: Vec<Listener> = {
+            let state = self.state.lock().unwrap();
+            match state.unit(unit) {
+                Ok(u) => u.listeners.iter().filter(|l| l.id == id).cloned().collect(),
+                Err(_) => return,
+            }
+        };
+        for l in listeners {
+            (l.listener)(l.data as *mut c_void, unit, id, scope, element);
+        }
+    }
+}
+
+impl AudioUnitAbstraction for SimulatedAudioUnits {
+    fn new_unit(&self, desc: &AudioComponentDescription, unit: &mut AudioUnit) -> OSStatus {
+        if desc.componentType != kAudioUnitType_Output {
+            return AUDIO_COMPONENT_NOT_FOUND;
+        }
+        // The default output unit follows the system default output device.
+        let device = if desc.componentSubType == kAudioUnitSubType_DefaultOutput {
+            audiounit_get_default_device_id(&*self.hardware, DeviceType::OUTPUT)
+        } else {
+            kAudioObjectUnknown
+        };
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = state.new_unit_error {
+            return error;
+        }
+        let handle = state.next_handle;
+        state.next_handle += 0x10;
+        let simulated = SimulatedUnit::new(state.config.buffer_frame_size, device);
+        state.units.insert(handle, simulated);
+        *unit = handle as AudioUnit;
+        NO_ERR
+    }
+
+    fn dispose(&self, unit: AudioUnit) -> OSStatus {
+        let _render = self.wait_for_rendering();
+        let mut state = self.state.lock().unwrap();
+        match state.units.remove(&(unit as usize)) {
+            Some(_) => NO_ERR,
+            None => kAudio_ParamError as OSStatus,
+        }
+    }
+
+    fn get_property_info(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        size: *mut usize,
+        writable: *mut bool,
+    ) -> OSStatus {
+        let state = self.state.lock().unwrap();
+        match state.get_property_bytes(unit, property, scope, element) {
+            Ok(bytes) => {
+                unsafe {
+                    *size = bytes.len();
+                    if !writable.is_null() {
+                        *writable = true;
+                    }
+                }
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn get_property(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        data: *mut c_void,
+        size: *mut usize,
+    ) -> OSStatus {
+        let state = self.state.lock().unwrap();
+        match state.get_property_bytes(unit, property, scope, element) {
+            Ok(bytes) => {
+                unsafe {
+                    let count = cmp::min(*size, bytes.len());
+                    ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, count);
+                    *size = count;
+                }
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn set_property(
+        &self,
+        unit: AudioUnit,
+        property: AudioUnitPropertyID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        data: *const c_void,
+        size: usize,
+    ) -> OSStatus {
+        let bytes = unsafe { slice::from_raw_parts(data as *const u8, size).to_vec() };
+        {
+            let mut guard = self.state.lock().unwrap();
+            let state = &mut *guard;
+            let hardware_input_rate = match state.unit(unit) {
+                Ok(u) => state.hardware_format(u, true).rate,
+                Err(status) => return status,
+            };
+            let simulated = state.unit_mut(unit).unwrap();
+            match property {
+                kAudioUnitProperty_SetRenderCallback => {
+                    let callback = unsafe { &*(data as *const AURenderCallbackStruct) };
+                    simulated.render_callback = Some(Callback::new(callback));
+                }
+                kAudioOutputUnitProperty_SetInputCallback => {
+                    let callback = unsafe { &*(data as *const AURenderCallbackStruct) };
+                    simulated.input_callback = Some(Callback::new(callback));
+                }
+                kAudioDevicePropertyBufferFrameSize => {
+                    simulated.buffer_frame_size = unsafe { *(data as *const u32) };
+                }
+                kAudioUnitProperty_StreamFormat => {
+                    let input = element == AU_IN_BUS;
+                    let client_side = (input && scope == kAudioUnitScope_Output)
+                        || (!input && scope == kAudioUnitScope_Input);
+                    if !client_side {
+                        return kAudioUnitErr_PropertyNotWritable;
+                    }
+                    // AUHAL can't convert the sample rate of the input.
+                    let desc = unsafe { &*(data as *const AudioStreamBasicDescription) };
+                    if input && desc.mSampleRate != hardware_input_rate {
+                        return kAudioUnitErr_FormatNotSupported;
+                    }
+                    simulated
+                        .properties
+                        .insert((property, scope, element), bytes);
+                }
+                _ => {
+                    simulated
+                        .properties
+                        .insert((property, scope, element), bytes);
+                }
+            }
+        }
+        if property == kAudioDevicePropertyBufferFrameSize {
+            self.fire_listeners(unit, property, scope, element);
+        }
+        NO_ERR
+    }
+
+    fn get_parameter(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitParameterID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        value: *mut AudioUnitParameterValue,
+    ) -> OSStatus {
+        let state = self.state.lock().unwrap();
+        let simulated = match state.unit(unit) {
+            Ok(u) => u,
+            Err(status) => return status,
+        };
+        match simulated.parameters.get(&(id, scope, element)) {
+            Some(v) => {
+                unsafe {
+                    *value = *v;
+                }
+                NO_ERR
+            }
+            None => kAudioUnitErr_InvalidParameter,
+        }
+    }
+
+    fn set_parameter(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitParameterID,
+        scope: AudioUnitScope,
+        element: AudioUnitElement,
+        value: AudioUnitParameterValue,
+        _buffer_offset_in_frames: UInt32,
+    ) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.unit_mut(unit) {
+            Ok(simulated) => {
+                simulated.parameters.insert((id, scope, element), value);
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn initialize(&self, unit: AudioUnit) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.unit_mut(unit) {
+            Ok(simulated) => {
+                simulated.initialized = true;
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn uninitialize(&self, unit: AudioUnit) -> OSStatus {
+        let _render = self.wait_for_rendering();
+        let mut state = self.state.lock().unwrap();
+        match state.unit_mut(unit) {
+            Ok(simulated) => {
+                simulated.initialized = false;
+                simulated.running = false;
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn start(&self, unit: AudioUnit) -> OSStatus {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let now = state.now_ns;
+        let (input_delay, output_delay) = (
+            state.config.input_start_delay.as_nanos() as u64,
+            state.config.output_start_delay.as_nanos() as u64,
+        );
+        let simulated = match state.unit_mut(unit) {
+            Ok(u) => u,
+            Err(status) => return status,
+        };
+        if !simulated.initialized {
+            return kAudioUnitErr_Uninitialized;
+        }
+        if !simulated.running {
+            simulated.running = true;
+            simulated.frames_since_start = 0;
+            simulated.started_ns = now
+                + if simulated.output_enabled() {
+                    output_delay
+                } else {
+                    input_delay
+                };
+        }
+        NO_ERR
+    }
+
+    fn stop(&self, unit: AudioUnit) -> OSStatus {
+        let _render = self.wait_for_rendering();
+        let mut state = self.state.lock().unwrap();
+        match state.unit_mut(unit) {
+            Ok(simulated) => {
+                simulated.running = false;
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn render(
+        &self,
+        unit: AudioUnit,
+        _io_action_flags: *mut AudioUnitRenderActionFlags,
+        _in_time_stamp: *const AudioTimeStamp,
+        in_output_bus_number: u32,
+        in_number_frames: u32,
+        io_data: *mut AudioBufferList,
+    ) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        let mut buffers = state.config.buffers_per_list;
+        let channel_step = state.config.input_channel_step;
+        let simulated = match state.unit_mut(unit) {
+            Ok(u) => u,
+            Err(status) => return status,
+        };
+        if in_output_bus_number != AU_IN_BUS || !simulated.input_enabled() {
+            return kAudioUnitErr_InvalidElement;
+        }
+        if simulated.pending_render_errors > 0 {
+            simulated.pending_render_errors -= 1;
+            return kAudioUnitErr_CannotDoInCurrentContext;
+        }
+        let desc = match simulated.client_format(true) {
+            Some(desc) => desc,
+            None => return kAudioUnitErr_Uninitialized,
+        };
+
+        let channels = desc.mChannelsPerFrame as usize;
+        if buffer_count(&desc) > 1 {
+            buffers = desc.mChannelsPerFrame;
+        }
+        let frames = in_number_frames as usize;
+        let sample_size = (desc.mBitsPerChannel / 8) as usize;
+        let first_frame = simulated.input_frames + 1;
+        simulated.input_frames += in_number_frames as u64;
+        simulated
+            .input_buffer
+            .resize(frames * channels * sample_size, 0);
+        let is_float = desc.mFormatFlags & kAudioFormatFlagIsFloat != 0;
+        for (i, frame) in simulated
+            .input_buffer
+            .chunks_mut(channels * sample_size)
+            .enumerate()
+        {
+            let index = first_frame + i as u64;
+            for (channel, sample) in frame.chunks_mut(sample_size).enumerate() {
+                let offset = channel_step * channel as f32;
+                if is_float {
+                    let value = (index % (1 << 24)) as f32 + offset;
+                    sample.copy_from_slice(&bytes_of(&value));
+                } else {
+                    let value = ((index % 32768) as i16).wrapping_add(offset as i16);
+                    sample.copy_from_slice(&bytes_of(&value));
+                }
+            }
+        }
+
+        let list = unsafe { &mut *io_data };
+        if buffers > 1 {
+            // Split the channels into the buffers of the list.
+            let split = split_channels(channels as u32, buffers);
+            simulated.split_input = split
+                .iter()
+                .map(|c| vec![0_u8; frames * *c as usize * sample_size])
+                .collect();
+            let mut split_list = BufferList::new(split.len());
+            let split_list_ptr = fill_list(&mut split_list, &split, &mut simulated.split_input);
+            scatter_buffers(
+                &simulated.input_buffer,
+                split_list.buffers_mut(),
+                channels,
+                sample_size,
+                frames,
+            );
+            let split_list = unsafe { &*split_list_ptr };
+            list.mNumberBuffers = split_list.mNumberBuffers;
+            let source =
+                unsafe { slice::from_raw_parts(split_list.mBuffers.as_ptr(), split.len()) };
+            let target =
+                unsafe { slice::from_raw_parts_mut(list.mBuffers.as_mut_ptr(), split.len()) };
+            target.copy_from_slice(source);
+            return NO_ERR;
+        }
+        list.mNumberBuffers = 1;
+        list.mBuffers[0].mNumberChannels = channels as u32;
+        list.mBuffers[0].mDataByteSize = simulated.input_buffer.len() as u32;
+        list.mBuffers[0].mData = simulated.input_buffer.as_mut_ptr() as *mut c_void;
+        NO_ERR
+    }
+
+    fn add_property_listener(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitPropertyID,
+        listener: audio_unit_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.unit_mut(unit) {
+            Ok(simulated) => {
+                simulated.listeners.push(Listener {
+                    id,
+                    listener,
+                    data: data as usize,
+                });
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn remove_property_listener_with_user_data(
+        &self,
+        unit: AudioUnit,
+        id: AudioUnitPropertyID,
+        listener: audio_unit_property_listener_proc,
+        data: *mut c_void,
+    ) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.unit_mut(unit) {
+            Ok(simulated) => {
+                simulated.listeners.retain(|l| {
+                    l.id != id
+                        || l.listener as usize != listener as usize
+                        || l.data != data as usize
+                });
+                NO_ERR
+            }
+            Err(status) => status,
+        }
+    }
+}
+
+fn float_description(format: SimulatedFormat) -> AudioStreamBasicDescription {
+    AudioStreamBasicDescription {
+        mSampleRate: format.rate,
+        mFormatID: kAudioFormatLinearPCM,
+        mFormatFlags: kAudioFormatFlagIsFloat | kAudioFormatFlagIsPacked,
+        mBytesPerPacket: 4 * format.channels,
+        mFramesPerPacket: 1,
+        mBytesPerFrame: 4 * format.channels,
+        mChannelsPerFrame: format.channels,
+        mBitsPerChannel: 32,
+        mReserved: 0,
+    }
+}
+
+fn channel_layout_bytes(channels: u32) -> Vec<u8> {
+    const LABELS: [AudioChannelLabel; 6] = [
+        kAudioChannelLabel_Left,
+        kAudioChannelLabel_Right,
+        kAudioChannelLabel_Center,
+        kAudioChannelLabel_LFEScreen,
+        kAudioChannelLabel_LeftSurround,
+        kAudioChannelLabel_RightSurround,
+    ];
+    let channels = cmp::max(channels, 1);
+    let labels: Vec<AudioChannelLabel> = (0..channels as usize)
+        .map(|i| {
+            if channels == 1 {
+                kAudioChannelLabel_Mono
+            } else {
+                *LABELS.get(i).unwrap_or(&kAudioChannelLabel_Unknown)
+            }
+        })
+        .collect();
+    fake_hardware::channel_layout_bytes(&labels)
+}
+
+// The channels of each of the `buffers` buffers splitting `channels` channels, in order. There are
+// fewer buffers if there are fewer channels.
+fn split_channels(channels: u32, buffers: u32) -> Vec<u32> {
+    let buffers = cmp::max(cmp::min(buffers, channels), 1);
+    (0..buffers)
+        .map(|i| channels / buffers + if i < channels % buffers { 1 } else { 0 })
+        .collect()
+}
+
+// Point the buffers of `list` to `data`, holding the `channels` of each buffer.
+fn fill_list(
+    list: &mut BufferList,
+    channels: &[u32],
+    data: &mut [Vec<u8>],
+) -> *mut AudioBufferList {
+    assert_eq!(channels.len(), data.len());
+    unsafe { (*list.as_mut_ptr()).mNumberBuffers = channels.len() as u32 };
+    for ((buffer, channels), data) in list
+        .buffers_mut()
+        .iter_mut()
+        .zip(channels.iter())
+        .zip(data.iter_mut())
+    {
+        buffer.mNumberChannels = *channels;
+        buffer.mDataByteSize = data.len() as u32;
+        buffer.mData = data.as_mut_ptr() as *mut c_void;
+    }
+    list.as_mut_ptr()
+}
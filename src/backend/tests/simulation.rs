@@ -0,0 +1,2737 @@
+use super::fake_hardware::{FakeDevice, FakeHardware};
+use super::simulated_audio_unit::{SimulatedAudioUnits, SimulatedFormat, SimulationConfig};
+use super::*;
+use std::sync::atomic::AtomicPtr;
+use std::thread;
+
+// Run the streams against the in-memory fake system and the simulated AudioUnits, which fire
+// the data callbacks on the test thread when the virtual clock advances.
+
+const LATENCY_FRAMES: u32 = 256;
+const OUTPUT_CHANNELS: usize = 2;
+
+#[derive(Default)]
+struct Recorder {
+    // The first channel of the input data delivered to the data callback.
+    input: Mutex<Vec<f32>>,
+    states: Mutex<Vec<ffi::cubeb_state>>,
+    device_changes: AtomicUsize,
+    // The stream whose output reinit choices the device changed callback records, if set.
+    stream: AtomicPtr<ffi::cubeb_stream>,
+    output_choices: Mutex<Vec<ReinitChoice>>,
+    written_frames: AtomicUsize,
+    // Stop writing the output data, and start draining, after writing these frames.
+    frames_limit: Option<usize>,
+}
+
+impl Recorder {
+    fn with_frames_limit(frames: usize) -> Self {
+        Self {
+            frames_limit: Some(frames),
+            ..Default::default()
+        }
+    }
+
+    fn input(&self) -> Vec<f32> {
+        self.input.lock().unwrap().clone()
+    }
+
+    fn states(&self) -> Vec<ffi::cubeb_state> {
+        self.states.lock().unwrap().clone()
+    }
+}
+
+extern "C" fn data_callback(
+    stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: i64,
+) -> i64 {
+    assert!(!stream.is_null());
+    let recorder = unsafe { &*(user_ptr as *const Recorder) };
+    let frames = nframes as usize;
+
+    if !input_buffer.is_null() {
+        let input = unsafe { slice::from_raw_parts(input_buffer as *const f32, frames) };
+        recorder.input.lock().unwrap().extend_from_slice(input);
+    }
+
+    if output_buffer.is_null() {
+        return nframes;
+    }
+
+    // Write the (1-based) index of the frame in each channel.
+    let written = recorder.written_frames.load(Ordering::SeqCst);
+    let frames_to_write = recorder
+        .frames_limit
+        .map_or(frames, |limit| cmp::min(frames, limit - written));
+    let output =
+        unsafe { slice::from_raw_parts_mut(output_buffer as *mut f32, frames * OUTPUT_CHANNELS) };
+    for (i, frame) in output
+        .chunks_mut(OUTPUT_CHANNELS)
+        .take(frames_to_write)
+        .enumerate()
+    {
+        for sample in frame.iter_mut() {
+            *sample = (written + i + 1) as f32;
+        }
+    }
+    recorder
+        .written_frames
+        .fetch_add(frames_to_write, Ordering::SeqCst);
+    frames_to_write as i64
+}
+
+extern "C" fn state_callback(
+    stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    state: ffi::cubeb_state,
+) {
+    assert!(!stream.is_null());
+    let recorder = unsafe { &*(user_ptr as *const Recorder) };
+    recorder.states.lock().unwrap().push(state);
+}
+
+extern "C" fn device_changed_callback(user_ptr: *mut c_void) {
+    let recorder = unsafe { &*(user_ptr as *const Recorder) };
+    recorder.device_changes.fetch_add(1, Ordering::SeqCst);
+    let stream = recorder.stream.load(Ordering::SeqCst);
+    if stream.is_null() {
+        return;
+    }
+    let mut choice = ReinitChoice::FollowDefault;
+    let r = unsafe {
+        crate::capi::audiounit_rust_stream_get_reinit_choice(
+            stream,
+            ffi::CUBEB_DEVICE_TYPE_OUTPUT,
+            &mut choice,
+        )
+    };
+    assert_eq!(r, ffi::CUBEB_OK);
+    recorder.output_choices.lock().unwrap().push(choice);
+}
+
+fn stream_params(channels: u32, layout: ffi::cubeb_channel_layout) -> ffi::cubeb_stream_params {
+    let mut params = ffi::cubeb_stream_params::default();
+    params.format = ffi::CUBEB_SAMPLE_FLOAT32NE;
+    params.rate = 48_000;
+    params.channels = channels;
+    params.layout = layout;
+    params.prefs = ffi::CUBEB_STREAM_PREF_NONE;
+    params
+}
+
+// Run `operation` on a context running on the fake system and the simulated AudioUnits.
+fn test_simulated_context_operation<F>(
+    hardware: &Arc<FakeHardware>,
+    units: &Arc<SimulatedAudioUnits>,
+    operation: F,
+) where
+    F: FnOnce(*mut ffi::cubeb),
+{
+    let platform = Platform::new(hardware.clone(), units.clone());
+    let context = Box::new(AudioUnitContext::with_platform(platform));
+    let context_ptr = Box::into_raw(context) as *mut ffi::cubeb;
+    operation(context_ptr);
+    unsafe { OPS.destroy.unwrap()(context_ptr) }
+}
+
+fn test_simulated_stream_operation<F>(
+    name: &'static str,
+    config: SimulationConfig,
+    has_input: bool,
+    recorder: &Recorder,
+    operation: F,
+) where
+    F: FnOnce(&FakeHardware, &SimulatedAudioUnits, &mut AudioUnitStream),
+{
+    let hardware = FakeHardware::new();
+    let units = SimulatedAudioUnits::new(&hardware, config);
+
+    let device = hardware.add_device(FakeDevice::new("duplex", 1, 2));
+    hardware.set_default_device(device, DeviceType::INPUT);
+    hardware.set_default_device(device, DeviceType::OUTPUT);
+
+    let mut input_params = stream_params(1, ffi::CUBEB_LAYOUT_MONO);
+    let mut output_params = stream_params(OUTPUT_CHANNELS as u32, ffi::CUBEB_LAYOUT_STEREO);
+    let input_params_ptr = if has_input {
+        &mut input_params as *mut ffi::cubeb_stream_params
+    } else {
+        ptr::null_mut()
+    };
+
+    test_simulated_context_operation(&hardware, &units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let stream_name = CString::new(name).expect("Failed to create stream name");
+        assert_eq!(
+            unsafe {
+                OPS.stream_init.unwrap()(
+                    context_ptr,
+                    &mut stream,
+                    stream_name.as_ptr(),
+                    ptr::null_mut(),
+                    input_params_ptr,
+                    ptr::null_mut(),
+                    &mut output_params,
+                    LATENCY_FRAMES,
+                    Some(data_callback),
+                    Some(state_callback),
+                    recorder as *const Recorder as *mut c_void,
+                )
+            },
+            ffi::CUBEB_OK
+        );
+        assert!(!stream.is_null());
+        operation(&hardware, &units, unsafe {
+            &mut *(stream as *mut AudioUnitStream)
+        });
+        unsafe {
+            OPS.stream_destroy.unwrap()(stream);
+        }
+    });
+}
+
+fn rendered_frames(units: &SimulatedAudioUnits, unit: AudioUnit) -> Vec<f32> {
+    let bytes = units.take_rendered_output(unit);
+    let samples = unsafe {
+        slice::from_raw_parts(
+            bytes.as_ptr() as *const f32,
+            bytes.len() / mem::size_of::<f32>(),
+        )
+    };
+    samples
+        .chunks(OUTPUT_CHANNELS)
+        .map(|frame| {
+            assert!(frame.iter().all(|s| *s == frame[0]));
+            frame[0]
+        })
+        .collect()
+}
+
+// The frames faded in after starting the stream, by `DEFAULT_GAIN_RAMP` at 48 kHz.
+const FADE_IN_FRAMES: usize = 480;
+
+// Check the `index`-th rendered frame after starting the stream is `value` faded in.
+fn assert_faded_in_frame(index: usize, frame: f32, value: f32) {
+    if index > FADE_IN_FRAMES {
+        assert_eq!(frame, value);
+        return;
+    }
+    let gain = (index + 1) as f32 / FADE_IN_FRAMES as f32;
+    let expected = value * gain.min(1.0);
+    assert!(
+        approx_eq!(f32, frame, expected, epsilon = 0.05),
+        "frame {}: {} != {}",
+        index,
+        frame,
+        expected
+    );
+}
+
+fn wait_for_reinit(stream: &AudioUnitStream) {
+    let mut waited = Duration::from_millis(0);
+    while stream.shared.reinit_pending.load(Ordering::SeqCst) {
+        assert!(waited < Duration::from_secs(5), "reinit never finished");
+        thread::sleep(Duration::from_millis(1));
+        waited += Duration::from_millis(1);
+    }
+}
+
+#[test]
+fn test_simulated_output_stream() {
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated output",
+        SimulationConfig::default(),
+        false,
+        &recorder,
+        |_, units, stream| {
+            assert!(stream.core_stream_data.input.is_none());
+            let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+            assert_eq!(units.buffer_frame_size(unit), LATENCY_FRAMES);
+
+            assert!(stream.start().is_ok());
+            assert!(units.is_running(unit));
+            units.advance(Duration::from_millis(100));
+            assert!(stream.stop().is_ok());
+            assert!(!units.is_running(unit));
+
+            // 4800 frames are due in 100 ms, so the callback at 4864 is not.
+            let callbacks = units.callback_count(unit);
+            assert_eq!(callbacks, 19);
+            let frames = rendered_frames(units, unit);
+            assert_eq!(frames.len(), callbacks * LATENCY_FRAMES as usize);
+            for (i, frame) in frames.iter().enumerate() {
+                assert_faded_in_frame(i, *frame, (i + 1) as f32);
+            }
+        },
+    );
+    assert_eq!(
+        recorder.states(),
+        vec![ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_STOPPED]
+    );
+}
+
+#[test]
+fn test_simulated_output_stream_drain() {
+    const FRAMES: usize = 1000;
+    let recorder = Recorder::with_frames_limit(FRAMES);
+    test_simulated_stream_operation(
+        "simulated drain",
+        SimulationConfig::default(),
+        false,
+        &recorder,
+        |_, units, stream| {
+            let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(100));
+
+            // The unit stops itself in the callback after the data is drained.
+            assert!(!units.is_running(unit));
+            let frames = rendered_frames(units, unit);
+            let padded = (FRAMES / LATENCY_FRAMES as usize + 1) * LATENCY_FRAMES as usize;
+            assert_eq!(frames.len(), padded + LATENCY_FRAMES as usize);
+            for (i, frame) in frames.iter().enumerate() {
+                let expected = if i < FRAMES { (i + 1) as f32 } else { 0.0 };
+                assert_faded_in_frame(i, *frame, expected);
+            }
+        },
+    );
+    assert_eq!(recorder.written_frames.load(Ordering::SeqCst), FRAMES);
+    assert_eq!(
+        recorder.states(),
+        vec![ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_DRAINED]
+    );
+}
+
+#[test]
+fn test_simulated_output_timestamp() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            None,
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                // Nothing is played before the first render callback.
+                assert_eq!(stream.timestamp().unwrap(), StreamTimestamp::default());
+                assert_eq!(stream.interpolated_position().unwrap(), 0);
+
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+                let first = stream.timestamp().unwrap();
+                units.advance(Duration::from_millis(20));
+                let second = stream.timestamp().unwrap();
+                assert!(stream.stop().is_ok());
+
+                // The host time moves along with the position, at the stream rate.
+                assert!(second.position > first.position);
+                let elapsed_ns = (second.position - first.position) * 1_000_000_000 / 48_000;
+                let host_elapsed_ns = second.host_time_ns - first.host_time_ns;
+                assert!(
+                    cmp::max(elapsed_ns, host_elapsed_ns) - cmp::min(elapsed_ns, host_elapsed_ns)
+                        < 1_000
+                );
+
+                let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+                let rendered = rendered_frames(units, unit).len() as u64;
+                assert!(second.position < rendered);
+
+                // The interpolated position stops at the end of the frames queued before stopping.
+                let stm = stream as *mut AudioUnitStream as *mut ffi::cubeb_stream;
+                let mut position = 0;
+                let mut host_time_ns = 0;
+                unsafe {
+                    assert_eq!(
+                        crate::capi::audiounit_rust_stream_get_timestamp(
+                            stm,
+                            &mut position,
+                            &mut host_time_ns
+                        ),
+                        ffi::CUBEB_OK
+                    );
+                    assert_eq!(position, second.position);
+                    assert_eq!(host_time_ns, second.host_time_ns);
+                    assert_eq!(
+                        crate::capi::audiounit_rust_stream_get_interpolated_position(
+                            stm,
+                            &mut position
+                        ),
+                        ffi::CUBEB_OK
+                    );
+                }
+                assert!(position <= rendered);
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+
+        // The input-only stream has no output timestamp.
+        let mic = hardware.add_device(FakeDevice::new("mic", 1, 0));
+        hardware.set_default_device(mic, DeviceType::INPUT);
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            None,
+            &recorder,
+            |stream| {
+                assert_eq!(stream.timestamp().unwrap_err(), Error::error());
+                assert_eq!(stream.interpolated_position().unwrap_err(), Error::error());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+    });
+}
+
+#[test]
+fn test_simulated_output_gain_ramps() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        let headphones = hardware.add_device(FakeDevice::new("headphones", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            None,
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+                let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+                let frames = rendered_frames(units, unit);
+                assert!(frames.len() > FADE_IN_FRAMES);
+                for (i, frame) in frames.iter().enumerate() {
+                    assert_faded_in_frame(i, *frame, (i + 1) as f32);
+                }
+
+                // The render callback fades out and reports the silence.
+                stream.shared.fading_out.store(true, Ordering::SeqCst);
+                units.advance(Duration::from_millis(20));
+                let frames = rendered_frames(units, unit);
+                assert!(frames.len() > FADE_IN_FRAMES);
+                assert!(frames[0] > 0.0);
+                assert!(frames[FADE_IN_FRAMES..].iter().all(|frame| *frame == 0.0));
+                assert!(stream.shared.faded_out.load(Ordering::SeqCst));
+
+                // Stopping doesn't wait for the fade-out done already, and the next start
+                // fades in again.
+                assert!(stream.stop().is_ok());
+                assert!(!stream.shared.fading_out.load(Ordering::SeqCst));
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+                let frames = rendered_frames(units, unit);
+                assert!(frames[0] > 0.0 && frames[0] * 100.0 < frames[FADE_IN_FRAMES]);
+
+                // The output fades in on the new device after switching devices.
+                hardware.set_default_device(headphones, DeviceType::OUTPUT);
+                wait_for_reinit(stream);
+                let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+                assert!(units.is_running(unit));
+                units.advance(Duration::from_millis(20));
+                let frames = rendered_frames(units, unit);
+                assert!(frames[0] > 0.0 && frames[0] * 100.0 < frames[FADE_IN_FRAMES]);
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_output_fade_out_without_rendering() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        let usb = hardware.add_device(FakeDevice::new("usb", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let mut params = stereo_params();
+        test_simulated_context_operation(hardware, units, |context_ptr| {
+            let context = unsafe { &mut *(context_ptr as *mut AudioUnitContext) };
+            let options = StreamOptions {
+                stream_name: None,
+                input_device: StreamDevice::Default,
+                input_stream_params: None,
+                output_device: StreamDevice::Id(usb),
+                output_stream_params: Some(unsafe { StreamParamsRef::from_ptr(&mut params) }),
+                latency_frames: LATENCY_FRAMES,
+                // The clock doesn't advance while the stream waits for a fade-out, so waiting
+                // for one would take the whole timeout.
+                gain_ramp: Duration::from_secs(60),
+                input_buffer_margin: DEFAULT_INPUT_BUFFER_MARGIN,
+                sample_format: None,
+                planar: false,
+                input_channel_map: None,
+                output_channel_map: None,
+                data_callback: Some(data_callback),
+                state_callback: Some(state_callback),
+                user_ptr: &recorder as *const Recorder as *mut c_void,
+            };
+            let cubeb_stream = context.stream_init_with_options(options).unwrap();
+            // Destroyed through the ops below, as the streams created through the C API.
+            let stream_ptr = cubeb_stream.as_ptr();
+            mem::forget(cubeb_stream);
+            let stream = unsafe { &mut *(stream_ptr as *mut AudioUnitStream) };
+            let start = Instant::now();
+
+            // A stream that never started has nothing to fade out.
+            assert!(stream.stop().is_ok());
+
+            // Neither has a removed device.
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(20));
+            hardware.remove_device(usb);
+            wait_for_reinit(stream);
+
+            // Nor a stopped output unit.
+            let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+            assert!(units.is_running(unit));
+            assert!(stop_audiounit(&**units, unit).is_ok());
+            assert!(stream.stop().is_ok());
+
+            assert!(start.elapsed() < Duration::from_secs(5));
+            unsafe {
+                OPS.stream_destroy.unwrap()(stream_ptr);
+            }
+        });
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_duplex_stream() {
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated duplex",
+        SimulationConfig::default(),
+        true,
+        &recorder,
+        |_, units, stream| {
+            let input_unit = stream.core_stream_data.input.as_ref().unwrap().unit;
+            let output_unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+            assert_ne!(input_unit, output_unit);
+            assert_eq!(units.buffer_frame_size(input_unit), LATENCY_FRAMES);
+
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(200));
+            assert!(stream.stop().is_ok());
+            assert_eq!(
+                units.callback_count(input_unit),
+                units.callback_count(output_unit)
+            );
+        },
+    );
+    // The input callbacks come right before the output callbacks, so the input frames reach the
+    // data callback in order, without any gap or silence.
+    let input = recorder.input();
+    assert_eq!(input.len(), recorder.written_frames.load(Ordering::SeqCst));
+    for (i, frame) in input.iter().enumerate() {
+        assert_eq!(*frame, (i + 1) as f32);
+    }
+}
+
+#[test]
+fn test_simulated_input_gain_and_mute() {
+    test_simulated_units(|hardware, units| {
+        let mic = hardware.add_device(FakeDevice::new("mic", 1, 0));
+        hardware.set_default_device(mic, DeviceType::INPUT);
+
+        let recorder = Recorder::default();
+        let mut lengths = Vec::new();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            None,
+            &recorder,
+            |stream| {
+                assert_eq!(stream.input_gain().unwrap(), 1.0);
+                assert!(!stream.input_mute().unwrap());
+                assert_eq!(
+                    stream.set_input_gain(1.5).unwrap_err(),
+                    Error::invalid_parameter()
+                );
+
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+                lengths.push(recorder.input().len());
+                assert!(stream.set_input_gain(0.5).is_ok());
+                assert_eq!(stream.input_gain().unwrap(), 0.5);
+                units.advance(Duration::from_millis(20));
+                lengths.push(recorder.input().len());
+
+                // The mute is set through the C API.
+                let stm = stream as *mut AudioUnitStream as *mut ffi::cubeb_stream;
+                let mut mute = 0;
+                unsafe {
+                    assert_eq!(
+                        crate::capi::audiounit_rust_stream_set_input_mute(stm, 1),
+                        ffi::CUBEB_OK
+                    );
+                    assert_eq!(
+                        crate::capi::audiounit_rust_stream_get_input_mute(stm, &mut mute),
+                        ffi::CUBEB_OK
+                    );
+                }
+                assert_eq!(mute, 1);
+                units.advance(Duration::from_millis(20));
+                lengths.push(recorder.input().len());
+
+                // The gain is kept after unmuting.
+                assert!(stream.set_input_mute(false).is_ok());
+                units.advance(Duration::from_millis(20));
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+
+        let input = recorder.input();
+        assert!(input.len() > lengths[2]);
+        for (i, frame) in input.iter().enumerate() {
+            let value = (i + 1) as f32;
+            let expected = if i < lengths[0] {
+                value
+            } else if i >= lengths[1] && i < lengths[2] {
+                0.0
+            } else {
+                value * 0.5
+            };
+            assert_eq!(*frame, expected);
+        }
+    });
+}
+
+#[test]
+fn test_simulated_input_latency() {
+    test_simulated_units(|hardware, units| {
+        let mut mic = FakeDevice::new("mic", 1, 0);
+        mic.input_latency = (100, 20);
+        let mic = hardware.add_device(mic);
+        hardware.set_default_device(mic, DeviceType::INPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            None,
+            &recorder,
+            |stream| {
+                assert_eq!(stream.input_latency().unwrap(), 0);
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+                assert!(stream.stop().is_ok());
+
+                // At least the device and stream latency of the device.
+                let stm = stream as *mut AudioUnitStream as *mut ffi::cubeb_stream;
+                let mut latency = 0;
+                assert_eq!(
+                    unsafe {
+                        crate::capi::audiounit_rust_stream_get_input_latency(stm, &mut latency)
+                    },
+                    ffi::CUBEB_OK
+                );
+                assert!(latency >= 120);
+                assert_eq!(stream.input_latency().unwrap(), latency);
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+
+        // The output-only stream has no input latency.
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            None,
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                assert_eq!(stream.input_latency().unwrap_err(), Error::error());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+    });
+}
+
+#[test]
+fn test_simulated_input_device_volume() {
+    test_simulated_units(|hardware, units| {
+        let mic = hardware.add_device(FakeDevice::new("mic", 1, 0));
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(mic, DeviceType::INPUT);
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                // The device has no input volume.
+                assert_eq!(
+                    stream.input_device_volume().unwrap_err(),
+                    Error::not_supported()
+                );
+                assert_eq!(
+                    stream.set_input_device_volume(0.5).unwrap_err(),
+                    Error::not_supported()
+                );
+
+                hardware.set_property(
+                    mic,
+                    kAudioDevicePropertyVolumeScalar,
+                    kAudioDevicePropertyScopeInput,
+                    1.0_f32,
+                );
+                assert_eq!(stream.input_device_volume().unwrap(), 1.0);
+                assert!(stream.set_input_device_volume(0.25).is_ok());
+                assert_eq!(stream.input_device_volume().unwrap(), 0.25);
+                assert_eq!(
+                    stream.set_input_device_volume(-1.0).unwrap_err(),
+                    Error::invalid_parameter()
+                );
+                // The software gain is independent of the device volume.
+                assert_eq!(stream.input_gain().unwrap(), 1.0);
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+
+        // The output-only stream has no input controls.
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            None,
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                assert_eq!(stream.set_input_gain(0.5).unwrap_err(), Error::error());
+                assert_eq!(stream.input_mute().unwrap_err(), Error::error());
+                assert_eq!(stream.input_device_volume().unwrap_err(), Error::error());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+    });
+}
+
+#[test]
+fn test_simulated_duplex_stream_with_jitter() {
+    let mut config = SimulationConfig::default();
+    config.jitter_frames = 64;
+    config.output_start_delay = Duration::from_millis(3);
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated duplex with jitter",
+        config,
+        true,
+        &recorder,
+        |_, units, stream| {
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(500));
+            assert!(stream.stop().is_ok());
+        },
+    );
+    // The glitches may be filled with silence or dropped, but the input frames are never
+    // duplicated or reordered.
+    let input: Vec<f32> = recorder
+        .input()
+        .into_iter()
+        .filter(|frame| *frame != 0.0)
+        .collect();
+    assert!(!input.is_empty());
+    for pair in input.windows(2) {
+        assert!(pair[0] < pair[1]);
+    }
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+#[test]
+fn test_simulated_duplex_stream_on_separate_clocks() {
+    test_simulated_units(|hardware, units| {
+        // The input device also has an output, so no aggregate device is used.
+        let headset = hardware.add_device(FakeDevice::new("headset", 1, 2));
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(headset, DeviceType::INPUT);
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                assert!(!stream.core_stream_data.should_use_aggregate_device());
+                assert!(stream
+                    .core_stream_data
+                    .input
+                    .as_ref()
+                    .unwrap()
+                    .side
+                    .drift_compensator
+                    .is_some());
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(500));
+                assert!(stream.stop().is_ok());
+                // The ratios of the compensation are in the stats.
+                let stats = stream.stats();
+                assert!(stats.min_drift_ratio > 0.0);
+                assert!(stats.min_drift_ratio <= stats.drift_ratio);
+                assert!(stats.drift_ratio <= stats.max_drift_ratio);
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        // The input frames go through the compensation in order.
+        let input: Vec<f32> = recorder
+            .input()
+            .into_iter()
+            .filter(|frame| *frame != 0.0)
+            .collect();
+        assert!(!input.is_empty());
+        for pair in input.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+
+    // The input and the output on the same device share the clock.
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated duplex on one clock",
+        SimulationConfig::default(),
+        true,
+        &recorder,
+        |_, _, stream| {
+            assert!(stream
+                .core_stream_data
+                .input
+                .as_ref()
+                .unwrap()
+                .side
+                .drift_compensator
+                .is_none());
+        },
+    );
+}
+
+#[test]
+fn test_simulated_multichannel_duplex_stream() {
+    test_simulated_units(|hardware, units| {
+        let interface = hardware.add_device(FakeDevice::new("interface", 32, 2));
+        // The AudioUnit renders the 32 channels of the interface, rather than the single
+        // channel of the default input format of the simulation, which would be mixed.
+        units.set_device_format(interface, DeviceType::INPUT, 48_000.0, 32);
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(interface, DeviceType::INPUT);
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(32, ffi::CUBEB_LAYOUT_UNDEFINED),
+            )),
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                // The input buffer holds a few callbacks of all the channels.
+                let capacity = stream
+                    .core_stream_data
+                    .input
+                    .as_ref()
+                    .unwrap()
+                    .side
+                    .buffer_manager
+                    .capacity();
+                assert!(capacity >= 32 * 4 * LATENCY_FRAMES as usize);
+
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(500));
+                assert!(stream.stop().is_ok());
+                assert_eq!(stream.glitch_stats().input_overruns, 0);
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.input().is_empty());
+    });
+}
+
+#[test]
+fn test_simulated_glitch_stats() {
+    test_simulated_units(|hardware, units| {
+        let headset = hardware.add_device(FakeDevice::new("headset", 1, 2));
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(headset, DeviceType::INPUT);
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            Some((kAudioObjectUnknown, stereo_params())),
+            &recorder,
+            |stream| {
+                assert_eq!(stream.glitch_stats(), GlitchStats::default());
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(100));
+                assert!(stream.stop().is_ok());
+
+                // The input reserve is filled with silence when the output starts.
+                let stats = stream.glitch_stats();
+                assert!(stats.silence_frames > 0);
+                assert_eq!(stats.input_overruns, 0);
+                assert_eq!(stats.output_underruns, 0);
+
+                let stm = stream as *mut AudioUnitStream as *mut ffi::cubeb_stream;
+                let mut last = GlitchStats::default();
+                let mut current = stats;
+                unsafe {
+                    assert_eq!(
+                        crate::capi::audiounit_rust_stream_reset_glitch_stats(stm, &mut last),
+                        ffi::CUBEB_OK
+                    );
+                    assert_eq!(
+                        crate::capi::audiounit_rust_stream_get_glitch_stats(stm, &mut current),
+                        ffi::CUBEB_OK
+                    );
+                }
+                assert_eq!(last, stats);
+                assert_eq!(current, GlitchStats::default());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+    });
+}
+
+#[test]
+fn test_simulated_stream_stats() {
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated stream stats",
+        SimulationConfig::default(),
+        true,
+        &recorder,
+        |_, units, stream| {
+            assert_eq!(stream.stats().reinits, 0);
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(100));
+
+            let stats = stream.stats();
+            assert!(stats.input_callback_frames.count > 0);
+            assert!(stats.output_callback_frames.count > 0);
+            assert!(stats.output_callback_frames.max > 0);
+            assert_eq!(
+                stats.output_callback_duration_us.count,
+                stats.output_callback_frames.count
+            );
+            assert!(stats.min_output_latency_frames <= stats.max_output_latency_frames);
+
+            assert!(stream.reset_default_device().is_ok());
+            wait_for_reinit(stream);
+            let stats = stream.stats();
+            assert_eq!(stats.reinits, 1);
+            assert_eq!(stats.last_reinit_reason, ReinitReason::ResetDefaultDevice);
+            assert!(stream.stop().is_ok());
+        },
+    );
+}
+
+#[test]
+fn test_simulated_reinit_on_render_error() {
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated reinit",
+        SimulationConfig::default(),
+        true,
+        &recorder,
+        |hardware, units, stream| {
+            let input_unit = stream.core_stream_data.input.as_ref().unwrap().unit;
+            let output_unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(20));
+            let frames_before_error = recorder.input().len();
+            assert!(frames_before_error > 0);
+
+            // The input unit fails to render once, so the stream feeds silence and reinitializes
+            // its units.
+            units.inject_render_errors(input_unit, 1);
+            units.advance(Duration::from_millis(5));
+            wait_for_reinit(stream);
+
+            let new_units = units.units();
+            assert_eq!(new_units.len(), 2);
+            assert!(!new_units.contains(&input_unit));
+            assert!(!new_units.contains(&output_unit));
+            assert!(new_units.iter().all(|unit| units.is_running(*unit)));
+            assert_eq!(
+                stream.core_stream_data.input.as_ref().unwrap().unit,
+                new_units[0]
+            );
+            assert_eq!(
+                stream.core_stream_data.output.as_ref().unwrap().unit,
+                new_units[1]
+            );
+
+            // Only the listeners of the new inner stream are registered.
+            assert_eq!(
+                hardware.listener_count(
+                    kAudioObjectSystemObject,
+                    kAudioHardwarePropertyDefaultOutputDevice
+                ),
+                1
+            );
+
+            // The data keeps flowing through the new units.
+            let frames_after_reinit = recorder.input().len();
+            units.advance(Duration::from_millis(20));
+            assert!(recorder.input().len() > frames_after_reinit);
+            assert!(stream.stop().is_ok());
+        },
+    );
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+#[test]
+fn test_simulated_reinit_rolls_back_on_failure() {
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated reinit rollback",
+        SimulationConfig::default(),
+        true,
+        &recorder,
+        |hardware, units, stream| {
+            let input_unit = stream.core_stream_data.input.as_ref().unwrap().unit;
+            let output_unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(20));
+
+            // No new unit can be created, so the stream keeps running on its current units.
+            units.set_new_unit_error(Some(kAudioUnitErr_FailedInitialization));
+            units.inject_render_errors(input_unit, 1);
+            units.advance(Duration::from_millis(5));
+            wait_for_reinit(stream);
+
+            assert_eq!(units.units(), vec![input_unit, output_unit]);
+            assert!(units.is_running(input_unit));
+            assert!(units.is_running(output_unit));
+            assert_eq!(
+                stream.core_stream_data.input.as_ref().unwrap().unit,
+                input_unit
+            );
+            assert_eq!(
+                stream.core_stream_data.output.as_ref().unwrap().unit,
+                output_unit
+            );
+            assert_eq!(
+                hardware.listener_count(
+                    kAudioObjectSystemObject,
+                    kAudioHardwarePropertyDefaultOutputDevice
+                ),
+                1
+            );
+
+            let frames_after_reinit = recorder.input().len();
+            units.advance(Duration::from_millis(20));
+            assert!(recorder.input().len() > frames_after_reinit);
+            assert!(stream.stop().is_ok());
+        },
+    );
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+// InputHalf::setup, OutputHalf::setup
+// ------------------------------------
+fn test_simulated_units<F>(operation: F)
+where
+    F: FnOnce(&Arc<FakeHardware>, &Arc<SimulatedAudioUnits>),
+{
+    let hardware = FakeHardware::new();
+    let units = SimulatedAudioUnits::new(&hardware, SimulationConfig::default());
+    operation(&hardware, &units);
+}
+
+#[test]
+fn test_simulated_create_audiounit_without_component() {
+    test_simulated_units(|_, units| {
+        // There is no simulated AudioComponent but the output units.
+        let mut desc = get_audiounit_description(device_flags::DEV_OUTPUT);
+        desc.componentType = kAudioUnitType_Mixer;
+        let error = create_audiounit_by_description(&**units, desc).unwrap_err();
+        assert_eq!(error.operation(), Operation::CreateAudioUnit);
+        assert_eq!(error.status(), AUDIO_COMPONENT_NOT_FOUND);
+        assert_eq!(Error::from(error), Error::error());
+        assert!(units.units().is_empty());
+    });
+}
+
+#[test]
+fn test_simulated_input_half_setup() {
+    test_simulated_units(|hardware, units| {
+        let mic = hardware.add_device(FakeDevice::new("mic", 2, 0));
+        hardware.set_default_device(mic, DeviceType::INPUT);
+        units.set_device_format(mic, DeviceType::INPUT, 44_100.0, 2);
+        let device = create_device_info(&**hardware, mic, DeviceType::INPUT).unwrap();
+        let params = StreamParams::from(stream_params(1, ffi::CUBEB_LAYOUT_MONO));
+
+        let mut input = InputHalf::new(
+            Platform::new(hardware.clone(), units.clone()),
+            params,
+            device.clone(),
+        );
+        assert!(input.unit.is_null());
+        assert!(input
+            .setup(&device, LATENCY_FRAMES, ptr::null_mut())
+            .is_ok());
+        assert!(!input.unit.is_null());
+        assert_eq!(input.hw_rate, 44_100.0);
+        assert_eq!(input.desc.mSampleRate, 48_000.0);
+        assert_eq!(input.desc.mChannelsPerFrame, 1);
+        assert_eq!(units.buffer_frame_size(input.unit), LATENCY_FRAMES);
+        // The stereo channels of the mic, which has no layout, are mixed into the mono channel of
+        // the stream.
+        assert!(input.side.mixer.is_some());
+        assert!(input.side.device_layout.is_empty());
+
+        // The AudioUnit delivers the data in the requested format, but at the device rate. Since
+        // the stream mixes the channels itself, the AudioUnit delivers the 2 channels of the mic
+        // rather than the channel of `input.desc`.
+        let mut client_desc = AudioStreamBasicDescription::default();
+        let mut size = mem::size_of::<AudioStreamBasicDescription>();
+        assert_eq!(
+            audio_unit_get_property(
+                &**units,
+                input.unit,
+                kAudioUnitProperty_StreamFormat,
+                kAudioUnitScope_Output,
+                AU_IN_BUS,
+                &mut client_desc,
+                &mut size,
+            ),
+            NO_ERR
+        );
+        assert_eq!(client_desc.mSampleRate, 44_100.0);
+        assert_eq!(client_desc.mChannelsPerFrame, 2);
+
+        input.close();
+        assert!(input.unit.is_null());
+        assert!(units.units().is_empty());
+    });
+}
+
+#[test]
+fn test_simulated_output_half_setup() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+        let device = create_device_info(&**hardware, speaker, DeviceType::OUTPUT).unwrap();
+        let params = StreamParams::from(stream_params(2, ffi::CUBEB_LAYOUT_STEREO));
+
+        let mut output = OutputHalf::new(
+            Platform::new(hardware.clone(), units.clone()),
+            params,
+            device.clone(),
+        );
+        assert!(output
+            .setup(&device, LATENCY_FRAMES, ptr::null_mut())
+            .is_ok());
+        assert!(!output.unit.is_null());
+        assert_eq!(output.hw_rate, 48_000.0);
+        assert_eq!(output.desc.mChannelsPerFrame, 2);
+        assert_eq!(units.buffer_frame_size(output.unit), LATENCY_FRAMES);
+        // The layouts match, so there is no need to remix.
+        assert!(output.side.mixer.is_none());
+        output.close();
+        assert!(units.units().is_empty());
+    });
+}
+
+#[test]
+fn test_simulated_output_half_setup_with_mixer() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("surround", 0, 6));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+        units.set_device_format(speaker, DeviceType::OUTPUT, 48_000.0, 6);
+        let device = create_device_info(&**hardware, speaker, DeviceType::OUTPUT).unwrap();
+        let params = StreamParams::from(stream_params(2, ffi::CUBEB_LAYOUT_STEREO));
+
+        let mut output = OutputHalf::new(
+            Platform::new(hardware.clone(), units.clone()),
+            params,
+            device.clone(),
+        );
+        assert!(output
+            .setup(&device, LATENCY_FRAMES, ptr::null_mut())
+            .is_ok());
+        // The stereo data is remixed into the 6 channels of the device.
+        assert!(output.side.mixer.is_some());
+        assert_eq!(output.side.device_layout.len(), 6);
+        assert_eq!(output.desc.mChannelsPerFrame, 6);
+        assert_eq!(output.desc.mBytesPerFrame, 6 * mem::size_of::<f32>() as u32);
+        output.close();
+    });
+}
+
+#[test]
+fn test_simulated_input_half_setup_failure() {
+    test_simulated_units(|hardware, units| {
+        let mic = hardware.add_device(FakeDevice::new("mic", 1, 0));
+        hardware.set_default_device(mic, DeviceType::INPUT);
+        let device = create_device_info(&**hardware, mic, DeviceType::INPUT).unwrap();
+        let params = StreamParams::from(stream_params(1, ffi::CUBEB_LAYOUT_MONO));
+
+        let mut input = InputHalf::new(
+            Platform::new(hardware.clone(), units.clone()),
+            params,
+            device.clone(),
+        );
+        units.set_new_unit_error(Some(kAudioHardwareBadDeviceError as OSStatus));
+        let error = input
+            .setup(&device, LATENCY_FRAMES, ptr::null_mut())
+            .unwrap_err();
+        assert_eq!(error.operation(), Operation::CreateAudioUnit);
+        assert_eq!(error.status(), kAudioHardwareBadDeviceError as OSStatus);
+        assert_eq!(error.device(), mic);
+        assert_eq!(Error::from(error), Error::device_unavailable());
+        assert!(units.units().is_empty());
+    });
+}
+
+// Custom streams
+// ------------------------------------
+// Run `operation` on a stream with the given (device, params) settings, if the stream can be
+// created, and return the result of the creation.
+fn test_simulated_custom_stream_operation<F>(
+    hardware: &Arc<FakeHardware>,
+    units: &Arc<SimulatedAudioUnits>,
+    input: Option<(AudioObjectID, ffi::cubeb_stream_params)>,
+    output: Option<(AudioObjectID, ffi::cubeb_stream_params)>,
+    recorder: &Recorder,
+    operation: F,
+) -> i32
+where
+    F: FnOnce(&mut AudioUnitStream),
+{
+    let (input_device, mut input_params) = input.map_or((ptr::null(), None), |(id, params)| {
+        (id as usize as ffi::cubeb_devid, Some(params))
+    });
+    let (output_device, mut output_params) = output.map_or((ptr::null(), None), |(id, params)| {
+        (id as usize as ffi::cubeb_devid, Some(params))
+    });
+    let params_ptr = |params: &mut Option<ffi::cubeb_stream_params>| {
+        params
+            .as_mut()
+            .map_or(ptr::null_mut(), |p| p as *mut ffi::cubeb_stream_params)
+    };
+    let input_params_ptr = params_ptr(&mut input_params);
+    let output_params_ptr = params_ptr(&mut output_params);
+
+    let mut result = ffi::CUBEB_ERROR;
+    test_simulated_context_operation(hardware, units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let stream_name = CString::new("simulated custom").expect("Failed to create stream name");
+        result = unsafe {
+            OPS.stream_init.unwrap()(
+                context_ptr,
+                &mut stream,
+                stream_name.as_ptr(),
+                input_device,
+                input_params_ptr,
+                output_device,
+                output_params_ptr,
+                LATENCY_FRAMES,
+                Some(data_callback),
+                Some(state_callback),
+                recorder as *const Recorder as *mut c_void,
+            )
+        };
+        if result == ffi::CUBEB_OK {
+            assert!(!stream.is_null());
+            operation(unsafe { &mut *(stream as *mut AudioUnitStream) });
+            unsafe {
+                OPS.stream_destroy.unwrap()(stream);
+            }
+        }
+    });
+    result
+}
+
+// Sample formats
+// ------------------------------------
+#[test]
+fn test_simulated_sample_format_after_reinit() {
+    fn assert_s32_output(stream: &AudioUnitStream) {
+        let output = stream.core_stream_data.output.as_ref().unwrap();
+        assert_eq!(output.format, StreamFormat::S32LE);
+        assert_ne!(
+            output.desc.mFormatFlags & kAudioFormatFlagIsSignedInteger,
+            0
+        );
+        assert_eq!(output.desc.mBitsPerChannel, 32);
+    }
+
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        let headphones = hardware.add_device(FakeDevice::new("headphones", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let mut output_params = stereo_params();
+        let mut result = ffi::CUBEB_ERROR;
+        test_simulated_context_operation(hardware, units, |context_ptr| {
+            let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+            let stream_name = CString::new("simulated format").expect("Failed to create name");
+            let options = crate::capi::StreamInitOptions {
+                has_sample_format: 1,
+                sample_format: StreamFormat::S32LE as u32,
+                ..Default::default()
+            };
+            result = unsafe {
+                crate::capi::audiounit_rust_stream_init_with_options(
+                    context_ptr,
+                    &mut stream,
+                    stream_name.as_ptr(),
+                    ptr::null_mut(),
+                    &mut output_params,
+                    LATENCY_FRAMES,
+                    Some(data_callback),
+                    Some(state_callback),
+                    &recorder as *const Recorder as *mut c_void,
+                    &options,
+                )
+            };
+            assert_eq!(result, ffi::CUBEB_OK);
+            let stream = unsafe { &mut *(stream as *mut AudioUnitStream) };
+            assert_s32_output(stream);
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(20));
+
+            // The stream on the new device keeps the format of the data callback, instead of
+            // the one of the stream parameters.
+            hardware.set_default_device(headphones, DeviceType::OUTPUT);
+            wait_for_reinit(stream);
+            let output = stream.core_stream_data.output.as_ref().unwrap();
+            assert_eq!(output.device.id, headphones);
+            assert_s32_output(stream);
+            assert!(stream.stop().is_ok());
+            unsafe {
+                OPS.stream_destroy.unwrap()(
+                    stream as *mut AudioUnitStream as *mut ffi::cubeb_stream,
+                );
+            }
+        });
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_unknown_sample_format() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let mut output_params = stereo_params();
+        test_simulated_context_operation(hardware, units, |context_ptr| {
+            let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+            let options = crate::capi::StreamInitOptions {
+                has_sample_format: 1,
+                sample_format: StreamFormat::Float64LE as u32 + 1,
+                ..Default::default()
+            };
+            let result = unsafe {
+                crate::capi::audiounit_rust_stream_init_with_options(
+                    context_ptr,
+                    &mut stream,
+                    ptr::null(),
+                    ptr::null_mut(),
+                    &mut output_params,
+                    LATENCY_FRAMES,
+                    Some(data_callback),
+                    Some(state_callback),
+                    &recorder as *const Recorder as *mut c_void,
+                    &options,
+                )
+            };
+            assert_eq!(result, ffi::CUBEB_ERROR_INVALID_FORMAT);
+            assert!(stream.is_null());
+        });
+    });
+}
+
+// Loopback
+// ------------------------------------
+fn loopback_params() -> ffi::cubeb_stream_params {
+    let mut params = stream_params(1, ffi::CUBEB_LAYOUT_MONO);
+    params.prefs = ffi::CUBEB_STREAM_PREF_LOOPBACK;
+    params
+}
+
+#[test]
+fn test_simulated_loopback_stream() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((kAudioObjectUnknown, loopback_params())),
+            None,
+            &recorder,
+            |stream| {
+                // The input unit captures the aggregate device tapping the speaker.
+                let aggregate = stream.core_stream_data.aggregate_device.get_device_id();
+                assert_eq!(hardware.aggregate_devices(), vec![aggregate]);
+                let taps = hardware.taps();
+                assert_eq!(taps.len(), 1);
+                assert_eq!(taps[0].1, speaker);
+                assert!(stream.core_stream_data.output.is_none());
+                let input = stream.core_stream_data.input.as_ref().unwrap();
+                assert_eq!(input.device.id, speaker);
+                let mut device = kAudioObjectUnknown;
+                let mut size = mem::size_of::<AudioObjectID>();
+                assert_eq!(
+                    audio_unit_get_property(
+                        &**units,
+                        input.unit,
+                        kAudioOutputUnitProperty_CurrentDevice,
+                        kAudioUnitScope_Global,
+                        0,
+                        &mut device,
+                        &mut size,
+                    ),
+                    NO_ERR
+                );
+                assert_eq!(device, aggregate);
+
+                // The stream follows the default output device instead of the default input.
+                assert_eq!(
+                    hardware.listener_count(
+                        kAudioObjectSystemObject,
+                        kAudioHardwarePropertyDefaultOutputDevice
+                    ),
+                    1
+                );
+                assert_eq!(
+                    hardware.listener_count(
+                        kAudioObjectSystemObject,
+                        kAudioHardwarePropertyDefaultInputDevice
+                    ),
+                    0
+                );
+
+                // The captured data goes through the normal input path.
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(100));
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+
+        let input = recorder.input();
+        assert!(!input.is_empty());
+        for (i, frame) in input.iter().enumerate() {
+            assert_eq!(*frame, (i + 1) as f32);
+        }
+        // The tap and the aggregate device go away with the stream.
+        assert!(hardware.aggregate_devices().is_empty());
+        assert!(hardware.taps().is_empty());
+        assert!(units.units().is_empty());
+    });
+}
+
+#[test]
+fn test_simulated_loopback_output_stream() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 1, 2));
+        hardware.set_default_device(speaker, DeviceType::INPUT);
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        // Only the input can capture the output device.
+        let recorder = Recorder::default();
+        let mut output_params = stream_params(OUTPUT_CHANNELS as u32, ffi::CUBEB_LAYOUT_STEREO);
+        output_params.prefs = ffi::CUBEB_STREAM_PREF_LOOPBACK;
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            Some((kAudioObjectUnknown, output_params)),
+            &recorder,
+            |_| panic!("The stream should not be created"),
+        );
+        assert_eq!(result, ffi::CUBEB_ERROR_NOT_SUPPORTED);
+        assert!(hardware.aggregate_devices().is_empty());
+        assert!(units.units().is_empty());
+    });
+}
+
+// reset_default_device
+// ------------------------------------
+#[test]
+fn test_simulated_reset_default_device() {
+    test_simulated_units(|hardware, units| {
+        let mic = hardware.add_device(FakeDevice::new("mic", 1, 0));
+        let default_mic = hardware.add_device(FakeDevice::new("default mic", 1, 0));
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(default_mic, DeviceType::INPUT);
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((mic, stream_params(1, ffi::CUBEB_LAYOUT_MONO))),
+            Some((
+                kAudioObjectUnknown,
+                stream_params(OUTPUT_CHANNELS as u32, ffi::CUBEB_LAYOUT_STEREO),
+            )),
+            &recorder,
+            |stream| {
+                assert_eq!(
+                    stream.core_stream_data.input.as_ref().unwrap().device.id,
+                    mic
+                );
+                assert!(stream
+                    .register_device_changed_callback(Some(device_changed_callback))
+                    .is_ok());
+                assert!(stream.set_volume(0.5).is_ok());
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(100));
+                let position = stream.position().unwrap();
+                assert!(position > 0);
+
+                // The pinned input moves to the default input, and the stream keeps running.
+                assert!(stream.reset_default_device().is_ok());
+                wait_for_reinit(stream);
+                let input = stream.core_stream_data.input.as_ref().unwrap();
+                assert_eq!(input.device.id, default_mic);
+                assert!(input
+                    .device
+                    .flags
+                    .contains(device_flags::DEV_SYSTEM_DEFAULT));
+                let output = stream.core_stream_data.output.as_ref().unwrap();
+                assert_eq!(output.device.id, speaker);
+                assert!(units.is_running(input.unit));
+                assert!(units.is_running(output.unit));
+                assert_eq!(get_volume(&**units, output.unit).unwrap(), 0.5);
+                assert!(stream.device_changed_callback.lock().unwrap().is_some());
+                assert_eq!(recorder.device_changes.load(Ordering::SeqCst), 1);
+                assert_eq!(
+                    stream.reinit_choice(DeviceType::INPUT),
+                    Ok(ReinitChoice::FollowDefault)
+                );
+
+                // The position never goes backward.
+                assert!(stream.position().unwrap() >= position);
+                let frames_after_reset = recorder.input().len();
+                units.advance(Duration::from_millis(100));
+                assert!(recorder.input().len() > frames_after_reset);
+                assert!(stream.position().unwrap() > position);
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+// Reinit policy
+// ------------------------------------
+fn stereo_params() -> ffi::cubeb_stream_params {
+    stream_params(OUTPUT_CHANNELS as u32, ffi::CUBEB_LAYOUT_STEREO)
+}
+
+#[test]
+fn test_simulated_reinit_keeps_selected_output() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 1, 2));
+        let usb = hardware.add_device(FakeDevice::new("usb", 0, 2));
+        let headphones = hardware.add_device(FakeDevice::new("headphones", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::INPUT);
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((
+                kAudioObjectUnknown,
+                stream_params(1, ffi::CUBEB_LAYOUT_MONO),
+            )),
+            Some((usb, stereo_params())),
+            &recorder,
+            |stream| {
+                assert!(stream
+                    .register_device_changed_callback(Some(device_changed_callback))
+                    .is_ok());
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+
+                // The stream pinned to the usb device doesn't follow the default output.
+                assert_eq!(
+                    hardware.listener_count(
+                        kAudioObjectSystemObject,
+                        kAudioHardwarePropertyDefaultOutputDevice
+                    ),
+                    0
+                );
+                hardware.set_default_device(headphones, DeviceType::OUTPUT);
+                wait_for_reinit(stream);
+                assert_eq!(
+                    stream.core_stream_data.output.as_ref().unwrap().device.id,
+                    usb
+                );
+                assert_eq!(recorder.device_changes.load(Ordering::SeqCst), 0);
+
+                // The reinit caused by a render error keeps the usb device, and the input keeps
+                // following the default input.
+                let input_unit = stream.core_stream_data.input.as_ref().unwrap().unit;
+                units.inject_render_errors(input_unit, 1);
+                units.advance(Duration::from_millis(5));
+                wait_for_reinit(stream);
+                assert_eq!(
+                    stream.core_stream_data.output.as_ref().unwrap().device.id,
+                    usb
+                );
+                assert_eq!(
+                    stream.reinit_choice(DeviceType::OUTPUT),
+                    Ok(ReinitChoice::KeepSelected)
+                );
+                assert_eq!(
+                    stream.reinit_choice(DeviceType::INPUT),
+                    Ok(ReinitChoice::FollowDefault)
+                );
+                assert_eq!(recorder.device_changes.load(Ordering::SeqCst), 0);
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_reinit_falls_back_when_selected_output_is_gone() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        let usb = hardware.add_device(FakeDevice::new("usb", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            None,
+            Some((usb, stereo_params())),
+            &recorder,
+            |stream| {
+                assert!(stream
+                    .register_device_changed_callback(Some(device_changed_callback))
+                    .is_ok());
+                recorder.stream.store(
+                    stream as *mut AudioUnitStream as *mut ffi::cubeb_stream,
+                    Ordering::SeqCst,
+                );
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+                assert!(stream.reinit_choice(DeviceType::OUTPUT).is_err());
+
+                // The usb device is unplugged, so the stream moves to the default output.
+                hardware.remove_device(usb);
+                wait_for_reinit(stream);
+                let output = stream.core_stream_data.output.as_ref().unwrap();
+                assert_eq!(output.device.id, speaker);
+                assert!(units.is_running(output.unit));
+                assert_eq!(
+                    stream.reinit_choice(DeviceType::OUTPUT),
+                    Ok(ReinitChoice::FallBackToDefault)
+                );
+                assert!(stream.reinit_choice(DeviceType::INPUT).is_err());
+                assert_eq!(recorder.device_changes.load(Ordering::SeqCst), 1);
+                // The device changed callback sees the choice.
+                assert_eq!(
+                    *recorder.output_choices.lock().unwrap(),
+                    vec![ReinitChoice::FallBackToDefault]
+                );
+
+                // The stream follows the default output from now on.
+                assert_eq!(
+                    hardware.listener_count(
+                        kAudioObjectSystemObject,
+                        kAudioHardwarePropertyDefaultOutputDevice
+                    ),
+                    1
+                );
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+// Open by UID
+// ------------------------------------
+fn test_simulated_output_stream_by_uid_operation<F>(
+    hardware: &Arc<FakeHardware>,
+    units: &Arc<SimulatedAudioUnits>,
+    output_uid: &str,
+    recorder: &Recorder,
+    operation: F,
+) -> i32
+where
+    F: FnOnce(&mut AudioUnitStream),
+{
+    let output_uid = CString::new(output_uid).expect("Failed to create device uid");
+    let mut output_params = stereo_params();
+    let mut result = ffi::CUBEB_ERROR;
+    test_simulated_context_operation(hardware, units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let stream_name = CString::new("simulated by uid").expect("Failed to create stream name");
+        let options = crate::capi::StreamInitOptions {
+            output_device_uid: output_uid.as_ptr(),
+            ..Default::default()
+        };
+        result = unsafe {
+            crate::capi::audiounit_rust_stream_init_with_options(
+                context_ptr,
+                &mut stream,
+                stream_name.as_ptr(),
+                ptr::null_mut(),
+                &mut output_params,
+                LATENCY_FRAMES,
+                Some(data_callback),
+                Some(state_callback),
+                recorder as *const Recorder as *mut c_void,
+                &options,
+            )
+        };
+        if result == ffi::CUBEB_OK {
+            assert!(!stream.is_null());
+            operation(unsafe { &mut *(stream as *mut AudioUnitStream) });
+            unsafe {
+                OPS.stream_destroy.unwrap()(stream);
+            }
+        }
+    });
+    result
+}
+
+#[test]
+fn test_simulated_stream_by_uid_follows_replugged_device() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        let usb = hardware.add_device(FakeDevice::new("usb", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_output_stream_by_uid_operation(
+            hardware,
+            units,
+            "usb",
+            &recorder,
+            |stream| {
+                assert!(stream
+                    .register_device_changed_callback(Some(device_changed_callback))
+                    .is_ok());
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(20));
+                let output = stream.core_stream_data.output.as_ref().unwrap();
+                assert_eq!(output.device.id, usb);
+                assert_eq!(output.device.uid, Some(String::from("usb")));
+
+                // The stream runs on the default output while the usb device is unplugged.
+                hardware.remove_device(usb);
+                wait_for_reinit(stream);
+                assert_eq!(
+                    stream.core_stream_data.output.as_ref().unwrap().device.id,
+                    speaker
+                );
+                assert_eq!(
+                    stream.reinit_choice(DeviceType::OUTPUT),
+                    Ok(ReinitChoice::FallBackToDefault)
+                );
+                assert_eq!(recorder.device_changes.load(Ordering::SeqCst), 1);
+
+                // Plugging in another device doesn't move the stream.
+                hardware.add_device(FakeDevice::new("headphones", 0, 2));
+                wait_for_reinit(stream);
+                assert_eq!(
+                    stream.core_stream_data.output.as_ref().unwrap().device.id,
+                    speaker
+                );
+                assert_eq!(recorder.device_changes.load(Ordering::SeqCst), 1);
+
+                // The usb device is plugged back in with a new id.
+                let replugged = hardware.add_device(FakeDevice::new("usb", 0, 2));
+                assert_ne!(replugged, usb);
+                wait_for_reinit(stream);
+                let output = stream.core_stream_data.output.as_ref().unwrap();
+                assert_eq!(output.device.id, replugged);
+                assert!(units.is_running(output.unit));
+                assert_eq!(
+                    stream.reinit_choice(DeviceType::OUTPUT),
+                    Ok(ReinitChoice::KeepSelected)
+                );
+                assert_eq!(recorder.device_changes.load(Ordering::SeqCst), 2);
+                assert_eq!(
+                    hardware
+                        .listener_count(kAudioObjectSystemObject, kAudioHardwarePropertyDevices),
+                    0
+                );
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_stream_by_unknown_uid() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_output_stream_by_uid_operation(
+            hardware,
+            units,
+            "usb",
+            &recorder,
+            |_stream| {
+                panic!("The stream should not be created");
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_ERROR_DEVICE_UNAVAILABLE);
+    });
+}
+
+// Planar
+// ------------------------------------
+// Record the input of the first channel and write the (1-based) index of the frame in the left
+// channel, and its opposite in the right channel, one buffer per channel.
+extern "C" fn planar_recorder_data_callback(
+    stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: i64,
+) -> i64 {
+    assert!(!stream.is_null());
+    let recorder = unsafe { &*(user_ptr as *const Recorder) };
+    let frames = nframes as usize;
+
+    if !input_buffer.is_null() {
+        let channels = unsafe { slice::from_raw_parts(input_buffer as *const *const f32, 1) };
+        let input = unsafe { slice::from_raw_parts(channels[0], frames) };
+        recorder.input.lock().unwrap().extend_from_slice(input);
+    }
+
+    if !output_buffer.is_null() {
+        let channels =
+            unsafe { slice::from_raw_parts(output_buffer as *const *mut f32, OUTPUT_CHANNELS) };
+        let left = unsafe { slice::from_raw_parts_mut(channels[0], frames) };
+        let right = unsafe { slice::from_raw_parts_mut(channels[1], frames) };
+        let written = recorder.written_frames.fetch_add(frames, Ordering::SeqCst);
+        for (i, (left, right)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+            *left = (written + i + 1) as f32;
+            *right = -*left;
+        }
+    }
+    nframes
+}
+
+// Run `operation` on a planar stream in Float32LE on the default devices, if it can be created,
+// and return the result of the creation.
+fn test_simulated_planar_stream_operation<F>(
+    hardware: &Arc<FakeHardware>,
+    units: &Arc<SimulatedAudioUnits>,
+    input: Option<ffi::cubeb_stream_params>,
+    output: Option<ffi::cubeb_stream_params>,
+    recorder: &Recorder,
+    operation: F,
+) -> i32
+where
+    F: FnOnce(&mut AudioUnitStream),
+{
+    let mut input_params = input;
+    let mut output_params = output;
+    let params_ptr = |params: &mut Option<ffi::cubeb_stream_params>| {
+        params
+            .as_mut()
+            .map_or(ptr::null_mut(), |p| p as *mut ffi::cubeb_stream_params)
+    };
+    let input_params_ptr = params_ptr(&mut input_params);
+    let output_params_ptr = params_ptr(&mut output_params);
+
+    let mut result = ffi::CUBEB_ERROR;
+    test_simulated_context_operation(hardware, units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let stream_name = CString::new("simulated planar").expect("Failed to create name");
+        let options = crate::capi::StreamInitOptions {
+            has_sample_format: 1,
+            sample_format: StreamFormat::Float32LE as u32,
+            planar: 1,
+            ..Default::default()
+        };
+        result = unsafe {
+            crate::capi::audiounit_rust_stream_init_with_options(
+                context_ptr,
+                &mut stream,
+                stream_name.as_ptr(),
+                input_params_ptr,
+                output_params_ptr,
+                LATENCY_FRAMES,
+                Some(planar_recorder_data_callback),
+                Some(state_callback),
+                recorder as *const Recorder as *mut c_void,
+                &options,
+            )
+        };
+        if result == ffi::CUBEB_OK {
+            assert!(!stream.is_null());
+            operation(unsafe { &mut *(stream as *mut AudioUnitStream) });
+            unsafe {
+                OPS.stream_destroy.unwrap()(stream);
+            }
+        }
+    });
+    result
+}
+
+// The samples of the rendered output of `unit`, in Float32LE.
+fn rendered_samples(units: &SimulatedAudioUnits, unit: AudioUnit) -> Vec<f32> {
+    let bytes = units.take_rendered_output(unit);
+    bytes
+        .chunks(mem::size_of::<f32>())
+        .map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]))
+        .collect()
+}
+
+#[test]
+fn test_simulated_planar_duplex_stream() {
+    test_simulated_units(|hardware, units| {
+        let headset = hardware.add_device(FakeDevice::new("headset", 2, 0));
+        // The input AudioUnit renders both channels of the headset, as the stereo stream takes
+        // them, without mixing.
+        units.set_device_format(headset, DeviceType::INPUT, 48_000.0, 2);
+        // The stereo output is mixed into the 4 channels of the device.
+        let surround = hardware.add_device(FakeDevice::new("surround", 0, 4));
+        hardware.set_default_device(headset, DeviceType::INPUT);
+        hardware.set_default_device(surround, DeviceType::OUTPUT);
+        units.set_device_format(surround, DeviceType::OUTPUT, 48_000.0, 4);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_planar_stream_operation(
+            hardware,
+            units,
+            Some(stream_params(2, ffi::CUBEB_LAYOUT_STEREO)),
+            Some(stereo_params()),
+            &recorder,
+            |stream| {
+                let input = stream.core_stream_data.input.as_ref().unwrap();
+                assert_eq!(input.device.id, headset);
+                // The AudioUnits take a buffer per channel, which go through the interleaved
+                // data of the duplex stream.
+                assert_eq!(input.side.rendered_buffers, 2);
+                assert!(stream.core_stream_data.planar.is_some());
+                assert!(!stream.core_stream_data.direct_planar);
+                let output = stream.core_stream_data.output.as_ref().unwrap();
+                assert!(output.side.mixer.is_some());
+                let unit = output.unit;
+                // The buffers of the callbacks are allocated by the setup.
+                let buffers = |stream: &AudioUnitStream| {
+                    let input = stream.core_stream_data.input.as_ref().unwrap();
+                    let output = stream.core_stream_data.output.as_ref().unwrap();
+                    (
+                        input.side.gathered_input.as_ptr(),
+                        input.side.gathered_input.capacity(),
+                        output.side.scattered_output.as_ptr(),
+                        output.side.scattered_output.capacity(),
+                    )
+                };
+                let allocated = buffers(stream);
+                assert_eq!(allocated.1, LATENCY_FRAMES as usize * 2 * 4);
+                assert_eq!(allocated.3, LATENCY_FRAMES as usize * 4 * 4);
+
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(200));
+                assert!(stream.stop().is_ok());
+                assert_eq!(buffers(stream), allocated);
+
+                // The channels of the data callback are interleaved, and then mixed.
+                let samples = rendered_samples(units, unit);
+                assert!(!samples.is_empty());
+                for frame in samples.chunks(4) {
+                    assert_eq!(frame[1], -frame[0]);
+                    assert_eq!(&frame[2..], &[0.0, 0.0]);
+                }
+                assert!(samples.chunks(4).any(|frame| frame[0] > 0.0));
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        // The input frames reach the first channel in order.
+        let input: Vec<f32> = recorder
+            .input()
+            .into_iter()
+            .filter(|frame| *frame != 0.0)
+            .collect();
+        assert!(!input.is_empty());
+        for pair in input.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_planar_output_stream() {
+    test_simulated_units(|hardware, units| {
+        let speaker = hardware.add_device(FakeDevice::new("speaker", 0, 2));
+        hardware.set_default_device(speaker, DeviceType::OUTPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_planar_stream_operation(
+            hardware,
+            units,
+            None,
+            Some(stereo_params()),
+            &recorder,
+            |stream| {
+                // The data callback fills the buffers of the AudioUnit.
+                assert!(stream.core_stream_data.direct_planar);
+                let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(100));
+                assert!(stream.stop().is_ok());
+
+                let samples = rendered_samples(units, unit);
+                assert!(samples.len() > 2 * FADE_IN_FRAMES);
+                for (i, frame) in samples.chunks(2).enumerate() {
+                    assert_faded_in_frame(i, frame[0], (i + 1) as f32);
+                    assert_eq!(frame[1], -frame[0]);
+                }
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_planar_input_stream() {
+    test_simulated_units(|hardware, units| {
+        let headset = hardware.add_device(FakeDevice::new("headset", 2, 0));
+        units.set_device_format(headset, DeviceType::INPUT, 48_000.0, 2);
+        hardware.set_default_device(headset, DeviceType::INPUT);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_planar_stream_operation(
+            hardware,
+            units,
+            Some(stream_params(2, ffi::CUBEB_LAYOUT_STEREO)),
+            None,
+            &recorder,
+            |stream| {
+                // The data callback gets the buffers rendered by the AudioUnit.
+                assert!(stream.core_stream_data.direct_planar);
+                assert_eq!(
+                    stream
+                        .core_stream_data
+                        .input
+                        .as_ref()
+                        .unwrap()
+                        .side
+                        .rendered_buffers,
+                    2
+                );
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(100));
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+        // Every frame of the device reaches the first channel, in order.
+        let input = recorder.input();
+        assert!(!input.is_empty());
+        for (i, frame) in input.iter().enumerate() {
+            assert_eq!(*frame, (i + 1) as f32);
+        }
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+// Split buffers
+// ------------------------------------
+const SPLIT_INPUT_CHANNELS: usize = 4;
+
+// Record the input of the first channel, checking all the channels of the frame match.
+extern "C" fn split_input_data_callback(
+    stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: i64,
+) -> i64 {
+    assert!(!stream.is_null());
+    assert!(output_buffer.is_null());
+    let recorder = unsafe { &*(user_ptr as *const Recorder) };
+    let samples = unsafe {
+        slice::from_raw_parts(
+            input_buffer as *const f32,
+            nframes as usize * SPLIT_INPUT_CHANNELS,
+        )
+    };
+    let mut input = recorder.input.lock().unwrap();
+    for frame in samples.chunks(SPLIT_INPUT_CHANNELS) {
+        assert!(frame.iter().all(|s| *s == frame[0]));
+        input.push(frame[0]);
+    }
+    nframes
+}
+
+#[test]
+fn test_simulated_output_stream_with_split_buffers() {
+    let recorder = Recorder::default();
+    test_simulated_stream_operation(
+        "simulated split output",
+        SimulationConfig {
+            buffers_per_list: 2,
+            ..Default::default()
+        },
+        false,
+        &recorder,
+        |_, units, stream| {
+            let unit = stream.core_stream_data.output.as_ref().unwrap().unit;
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(100));
+            assert!(stream.stop().is_ok());
+
+            // Each channel is rendered into its own buffer.
+            let frames = rendered_frames(units, unit);
+            assert_eq!(
+                frames.len(),
+                units.callback_count(unit) * LATENCY_FRAMES as usize
+            );
+            for (i, frame) in frames.iter().enumerate() {
+                assert_faded_in_frame(i, *frame, (i + 1) as f32);
+            }
+        },
+    );
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+#[test]
+fn test_simulated_input_stream_with_split_buffers() {
+    let hardware = FakeHardware::new();
+    let units = SimulatedAudioUnits::new(
+        &hardware,
+        SimulationConfig {
+            buffers_per_list: 3,
+            ..Default::default()
+        },
+    );
+
+    // The AudioUnit renders the 4 channels of the interface, instead of the single channel of
+    // the default input format of the simulation, which would be mixed into the 4 channels of
+    // the stream. They come in buffers of 2, 1 and 1 channels.
+    let interface =
+        hardware.add_device(FakeDevice::new("interface", SPLIT_INPUT_CHANNELS as u32, 0));
+    units.set_device_format(
+        interface,
+        DeviceType::INPUT,
+        48_000.0,
+        SPLIT_INPUT_CHANNELS as u32,
+    );
+    hardware.set_default_device(interface, DeviceType::INPUT);
+
+    let recorder = Recorder::default();
+    let mut input_params = stream_params(SPLIT_INPUT_CHANNELS as u32, ffi::CUBEB_LAYOUT_UNDEFINED);
+    test_simulated_context_operation(&hardware, &units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let stream_name = CString::new("simulated split input").expect("Failed to create name");
+        assert_eq!(
+            unsafe {
+                OPS.stream_init.unwrap()(
+                    context_ptr,
+                    &mut stream,
+                    stream_name.as_ptr(),
+                    ptr::null_mut(),
+                    &mut input_params,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    LATENCY_FRAMES,
+                    Some(split_input_data_callback),
+                    Some(state_callback),
+                    &recorder as *const Recorder as *mut c_void,
+                )
+            },
+            ffi::CUBEB_OK
+        );
+        assert!(!stream.is_null());
+        let stream_ref = unsafe { &mut *(stream as *mut AudioUnitStream) };
+        assert!(stream_ref.start().is_ok());
+        units.advance(Duration::from_millis(100));
+        assert!(stream_ref.stop().is_ok());
+        unsafe {
+            OPS.stream_destroy.unwrap()(stream);
+        }
+    });
+
+    // The channels are gathered back in order, each frame holding its index in all of them.
+    let input = recorder.input();
+    assert!(!input.is_empty());
+    for (i, frame) in input.iter().enumerate() {
+        assert_eq!(*frame, (i + 1) as f32);
+    }
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+// Channel map
+// ------------------------------------
+// The input of a channel is its frame index plus this step times the index of the channel.
+const INPUT_CHANNEL_STEP: f32 = 10_000.0;
+
+// Record the input of the first channel, checking the second channel is the 10th of the device
+// when the first one is the 5th.
+extern "C" fn channel_map_data_callback(
+    stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: i64,
+) -> i64 {
+    assert!(!stream.is_null());
+    assert!(output_buffer.is_null());
+    let recorder = unsafe { &*(user_ptr as *const Recorder) };
+    let samples =
+        unsafe { slice::from_raw_parts(input_buffer as *const f32, nframes as usize * 2) };
+    let mut input = recorder.input.lock().unwrap();
+    for frame in samples.chunks(2) {
+        assert_eq!(frame[1], frame[0] + 5.0 * INPUT_CHANNEL_STEP);
+        input.push(frame[0]);
+    }
+    nframes
+}
+
+// Create an input stream on the 16 channels of the "interface", capturing the channels of `map`.
+// The channels are the output channels of the interface, captured by a loopback stream, when
+// `devtype` is `DeviceType::OUTPUT`.
+fn test_simulated_input_channel_map_operation<F>(
+    devtype: DeviceType,
+    map: *const u32,
+    recorder: &Recorder,
+    operation: F,
+) -> i32
+where
+    F: FnOnce(&SimulatedAudioUnits, &mut AudioUnitStream),
+{
+    let hardware = FakeHardware::new();
+    let units = SimulatedAudioUnits::new(
+        &hardware,
+        SimulationConfig {
+            // The aggregate device of a loopback stream, created with the stream, renders the
+            // channels of the interface.
+            default_input: SimulatedFormat {
+                rate: 48_000.0,
+                channels: 16,
+            },
+            buffers_per_list: 3,
+            input_channel_step: INPUT_CHANNEL_STEP,
+            ..Default::default()
+        },
+    );
+    let loopback = devtype == DeviceType::OUTPUT;
+    let interface = if loopback {
+        hardware.add_device(FakeDevice::new("interface", 0, 16))
+    } else {
+        hardware.add_device(FakeDevice::new("interface", 16, 0))
+    };
+    hardware.set_default_device(interface, devtype);
+    units.set_device_format(interface, devtype, 48_000.0, 16);
+
+    let uid = CString::new("interface").expect("Failed to create device uid");
+    let mut input_params = stream_params(2, ffi::CUBEB_LAYOUT_UNDEFINED);
+    if loopback {
+        input_params.prefs = ffi::CUBEB_STREAM_PREF_LOOPBACK;
+    }
+    let mut result = ffi::CUBEB_ERROR;
+    test_simulated_context_operation(&hardware, &units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let stream_name = CString::new("simulated channel map").expect("Failed to create name");
+        let options = crate::capi::StreamInitOptions {
+            input_device_uid: uid.as_ptr(),
+            input_channel_map: map,
+            ..Default::default()
+        };
+        result = unsafe {
+            crate::capi::audiounit_rust_stream_init_with_options(
+                context_ptr,
+                &mut stream,
+                stream_name.as_ptr(),
+                &mut input_params,
+                ptr::null_mut(),
+                LATENCY_FRAMES,
+                Some(channel_map_data_callback),
+                Some(state_callback),
+                recorder as *const Recorder as *mut c_void,
+                &options,
+            )
+        };
+        if result == ffi::CUBEB_OK {
+            operation(&units, unsafe { &mut *(stream as *mut AudioUnitStream) });
+            unsafe {
+                OPS.stream_destroy.unwrap()(stream);
+            }
+        }
+    });
+    result
+}
+
+#[test]
+fn test_simulated_input_channel_map() {
+    let recorder = Recorder::default();
+    let map: [u32; 2] = [4, 9];
+    let result = test_simulated_input_channel_map_operation(
+        DeviceType::INPUT,
+        map.as_ptr(),
+        &recorder,
+        |units, stream| {
+            let input = stream.core_stream_data.input.as_ref().unwrap();
+            assert_eq!(input.desc.mChannelsPerFrame, 2);
+            assert_eq!(input.side.rendered_channels, 16);
+
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(100));
+            assert!(stream.stop().is_ok());
+        },
+    );
+    assert_eq!(result, ffi::CUBEB_OK);
+
+    // The 5th channel of the device reaches the first channel of the stream.
+    let input = recorder.input();
+    assert!(!input.is_empty());
+    for (i, frame) in input.iter().enumerate() {
+        assert_eq!(*frame, (i + 1) as f32 + 4.0 * INPUT_CHANNEL_STEP);
+    }
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+#[test]
+fn test_simulated_loopback_input_channel_map() {
+    let recorder = Recorder::default();
+    let map: [u32; 2] = [4, 9];
+    let result = test_simulated_input_channel_map_operation(
+        DeviceType::OUTPUT,
+        map.as_ptr(),
+        &recorder,
+        |units, stream| {
+            // The map is checked against the output channels of the captured device.
+            let input = stream.core_stream_data.input.as_ref().unwrap();
+            assert_eq!(input.desc.mChannelsPerFrame, 2);
+            assert_eq!(input.side.rendered_channels, 16);
+            assert_eq!(input.side.rendered_map, vec![4, 9]);
+
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(100));
+            assert!(stream.stop().is_ok());
+        },
+    );
+    assert_eq!(result, ffi::CUBEB_OK);
+
+    // The 5th output channel of the device reaches the first channel of the stream.
+    let input = recorder.input();
+    assert!(!input.is_empty());
+    for (i, frame) in input.iter().enumerate() {
+        assert_eq!(*frame, (i + 1) as f32 + 4.0 * INPUT_CHANNEL_STEP);
+    }
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+
+    // The device has no 17th output channel.
+    let map: [u32; 2] = [4, 16];
+    assert_eq!(
+        test_simulated_input_channel_map_operation(
+            DeviceType::OUTPUT,
+            map.as_ptr(),
+            &recorder,
+            |_, _| {}
+        ),
+        ffi::CUBEB_ERROR_INVALID_PARAMETER
+    );
+}
+
+#[test]
+fn test_simulated_invalid_input_channel_map() {
+    let recorder = Recorder::default();
+    // The device has no 17th channel.
+    let map: [u32; 2] = [4, 16];
+    assert_eq!(
+        test_simulated_input_channel_map_operation(
+            DeviceType::INPUT,
+            map.as_ptr(),
+            &recorder,
+            |_, _| {}
+        ),
+        ffi::CUBEB_ERROR_INVALID_PARAMETER
+    );
+    // A null map leaves the input on the first channels.
+    assert_eq!(
+        test_simulated_input_channel_map_operation(
+            DeviceType::INPUT,
+            ptr::null(),
+            &recorder,
+            |_, stream| {
+                let input = stream.core_stream_data.input.as_ref().unwrap();
+                assert!(input.side.channel_map.is_none());
+            }
+        ),
+        ffi::CUBEB_OK
+    );
+}
+
+// Create a stereo output stream on the 8 channels of the "interface", playing on the channels of
+// `map`.
+fn test_simulated_output_channel_map_operation<F>(
+    map: *const u32,
+    recorder: &Recorder,
+    operation: F,
+) -> i32
+where
+    F: FnOnce(&SimulatedAudioUnits, &mut AudioUnitStream),
+{
+    let hardware = FakeHardware::new();
+    let units = SimulatedAudioUnits::new(&hardware, SimulationConfig::default());
+    let interface = hardware.add_device(FakeDevice::new("interface", 0, 8));
+    hardware.set_default_device(interface, DeviceType::OUTPUT);
+    units.set_device_format(interface, DeviceType::OUTPUT, 48_000.0, 8);
+
+    let uid = CString::new("interface").expect("Failed to create device uid");
+    let mut output_params = stereo_params();
+    let mut result = ffi::CUBEB_ERROR;
+    test_simulated_context_operation(&hardware, &units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let stream_name = CString::new("simulated channel map").expect("Failed to create name");
+        let options = crate::capi::StreamInitOptions {
+            output_device_uid: uid.as_ptr(),
+            output_channel_map: map,
+            ..Default::default()
+        };
+        result = unsafe {
+            crate::capi::audiounit_rust_stream_init_with_options(
+                context_ptr,
+                &mut stream,
+                stream_name.as_ptr(),
+                ptr::null_mut(),
+                &mut output_params,
+                LATENCY_FRAMES,
+                Some(data_callback),
+                Some(state_callback),
+                recorder as *const Recorder as *mut c_void,
+                &options,
+            )
+        };
+        if result == ffi::CUBEB_OK {
+            operation(&units, unsafe { &mut *(stream as *mut AudioUnitStream) });
+            unsafe {
+                OPS.stream_destroy.unwrap()(stream);
+            }
+        }
+    });
+    result
+}
+
+#[test]
+fn test_simulated_output_channel_map() {
+    let recorder = Recorder::default();
+    let map: [u32; 2] = [2, 3];
+    let result =
+        test_simulated_output_channel_map_operation(map.as_ptr(), &recorder, |units, stream| {
+            let output = stream.core_stream_data.output.as_ref().unwrap();
+            assert!(output.side.mixer.is_some());
+            assert_eq!(output.desc.mChannelsPerFrame, 8);
+            let unit = output.unit;
+
+            assert!(stream.start().is_ok());
+            units.advance(Duration::from_millis(100));
+            assert!(stream.stop().is_ok());
+
+            // The stereo channels play on the 3rd and 4th channels, and the others are silent.
+            let bytes = units.take_rendered_output(unit);
+            let samples = unsafe {
+                slice::from_raw_parts(
+                    bytes.as_ptr() as *const f32,
+                    bytes.len() / mem::size_of::<f32>(),
+                )
+            };
+            assert!(!samples.is_empty());
+            for (i, frame) in samples.chunks(8).enumerate() {
+                assert_faded_in_frame(i, frame[2], (i + 1) as f32);
+                assert_eq!(frame[3], frame[2]);
+                assert_eq!(&frame[..2], &[0.0, 0.0]);
+                assert_eq!(&frame[4..], &[0.0; 4]);
+            }
+        });
+    assert_eq!(result, ffi::CUBEB_OK);
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+#[test]
+fn test_simulated_invalid_output_channel_map() {
+    let recorder = Recorder::default();
+    // The device has no 9th channel.
+    let map: [u32; 2] = [2, 8];
+    assert_eq!(
+        test_simulated_output_channel_map_operation(map.as_ptr(), &recorder, |_, _| {}),
+        ffi::CUBEB_ERROR_INVALID_PARAMETER
+    );
+    // A null map leaves the output mixed by the layouts.
+    assert_eq!(
+        test_simulated_output_channel_map_operation(ptr::null(), &recorder, |_, stream| {
+            let output = stream.core_stream_data.output.as_ref().unwrap();
+            assert!(output.side.channel_map.is_none());
+        }),
+        ffi::CUBEB_OK
+    );
+}
+
+#[test]
+fn test_simulated_duplex_channel_maps() {
+    let hardware = FakeHardware::new();
+    let units = SimulatedAudioUnits::new(
+        &hardware,
+        SimulationConfig {
+            input_channel_step: INPUT_CHANNEL_STEP,
+            ..Default::default()
+        },
+    );
+    let interface = hardware.add_device(FakeDevice::new("interface", 16, 8));
+    hardware.set_default_device(interface, DeviceType::INPUT);
+    hardware.set_default_device(interface, DeviceType::OUTPUT);
+    units.set_device_format(interface, DeviceType::INPUT, 48_000.0, 16);
+    units.set_device_format(interface, DeviceType::OUTPUT, 48_000.0, 8);
+
+    // Both sides of the stream are mapped.
+    let input_map: [u32; 2] = [4, 9];
+    let output_map: [u32; 2] = [2, 3];
+    let recorder = Recorder::default();
+    let mut input_params = stream_params(2, ffi::CUBEB_LAYOUT_UNDEFINED);
+    let mut output_params = stereo_params();
+    test_simulated_context_operation(&hardware, &units, |context_ptr| {
+        let mut stream: *mut ffi::cubeb_stream = ptr::null_mut();
+        let options = crate::capi::StreamInitOptions {
+            input_channel_map: input_map.as_ptr(),
+            output_channel_map: output_map.as_ptr(),
+            ..Default::default()
+        };
+        let result = unsafe {
+            crate::capi::audiounit_rust_stream_init_with_options(
+                context_ptr,
+                &mut stream,
+                ptr::null(),
+                &mut input_params,
+                &mut output_params,
+                LATENCY_FRAMES,
+                Some(data_callback),
+                Some(state_callback),
+                &recorder as *const Recorder as *mut c_void,
+                &options,
+            )
+        };
+        assert_eq!(result, ffi::CUBEB_OK);
+        let stream = unsafe { &mut *(stream as *mut AudioUnitStream) };
+        let input = stream.core_stream_data.input.as_ref().unwrap();
+        assert_eq!(input.desc.mChannelsPerFrame, 2);
+        assert_eq!(input.side.rendered_channels, 16);
+        let output = stream.core_stream_data.output.as_ref().unwrap();
+        assert_eq!(output.desc.mChannelsPerFrame, 8);
+        let unit = output.unit;
+
+        assert!(stream.start().is_ok());
+        units.advance(Duration::from_millis(100));
+        assert!(stream.stop().is_ok());
+
+        // The stereo channels play on the 3rd and 4th channels, and the others are silent.
+        let bytes = units.take_rendered_output(unit);
+        let samples = unsafe {
+            slice::from_raw_parts(
+                bytes.as_ptr() as *const f32,
+                bytes.len() / mem::size_of::<f32>(),
+            )
+        };
+        assert!(!samples.is_empty());
+        for frame in samples.chunks(8) {
+            assert_eq!(frame[3], frame[2]);
+            assert_eq!(&frame[..2], &[0.0, 0.0]);
+            assert_eq!(&frame[4..], &[0.0; 4]);
+        }
+        unsafe {
+            OPS.stream_destroy.unwrap()(stream as *mut AudioUnitStream as *mut ffi::cubeb_stream);
+        }
+    });
+
+    // The 5th and 10th channels of the device reach the stream, after the silence buffered by
+    // the duplex stream.
+    let input = recorder.input();
+    assert!(input.iter().any(|sample| *sample != 0.0));
+    for frame in input.chunks(2).filter(|frame| frame[0] != 0.0) {
+        assert!(frame[0] > 4.0 * INPUT_CHANNEL_STEP && frame[0] < 5.0 * INPUT_CHANNEL_STEP);
+        assert_eq!(frame[1], frame[0] + 5.0 * INPUT_CHANNEL_STEP);
+    }
+    assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+}
+
+// Input mixer
+// ------------------------------------
+#[test]
+fn test_simulated_input_downmix() {
+    test_simulated_units(|hardware, units| {
+        let mut mic = FakeDevice::new("mic", 2, 0);
+        mic.input_layout = vec![kAudioChannelLabel_Left, kAudioChannelLabel_Right];
+        let mic = hardware.add_device(mic);
+        hardware.set_default_device(mic, DeviceType::INPUT);
+        units.set_device_format(mic, DeviceType::INPUT, 48_000.0, 2);
+
+        let recorder = Recorder::default();
+        let result = test_simulated_custom_stream_operation(
+            hardware,
+            units,
+            Some((mic, stream_params(1, ffi::CUBEB_LAYOUT_MONO))),
+            None,
+            &recorder,
+            |stream| {
+                let input = stream.core_stream_data.input.as_ref().unwrap();
+                assert!(input.side.mixer.is_some());
+                assert_eq!(input.side.rendered_channels, 2);
+
+                assert!(stream.start().is_ok());
+                units.advance(Duration::from_millis(100));
+                assert!(stream.stop().is_ok());
+            },
+        );
+        assert_eq!(result, ffi::CUBEB_OK);
+
+        // Both channels of the mic carry the index of the frame, and are mixed into the mono
+        // channel of the stream.
+        let input = recorder.input();
+        assert!(!input.is_empty());
+        for (i, sample) in input.iter().enumerate() {
+            let expected = std::f32::consts::FRAC_1_SQRT_2 * 2.0 * (i + 1) as f32;
+            assert!((sample - expected).abs() <= expected * 1e-5);
+        }
+        assert!(!recorder.states().contains(&ffi::CUBEB_STATE_ERROR));
+    });
+}
+
+#[test]
+fn test_simulated_input_upmix() {
+    test_simulated_units(|hardware, units| {
+        let mut mic = FakeDevice::new("mic", 1, 0);
+        mic.input_layout = vec![kAudioChannelLabel_Mono];
+        let mic = hardware.add_device(mic);
+        hardware.set_default_device(mic, DeviceType::INPUT);
+        units.set_device_format(mic, DeviceType::INPUT, 48_000.0, 1);
+        let device = create_device_info(&**hardware, mic, DeviceType::INPUT).unwrap();
+        let params = StreamParams::from(stream_params(2, ffi::CUBEB_LAYOUT_STEREO));
+
+        let mut input = InputHalf::new(
+            Platform::new(hardware.clone(), units.clone()),
+            params,
+            device.clone(),
+        );
+        assert!(input
+            .setup(&device, LATENCY_FRAMES, ptr::null_mut())
+            .is_ok());
+        // The mono channel of the mic is mixed into the stereo channels of the stream.
+        assert!(input.side.mixer.is_some());
+        assert_eq!(input.side.device_layout.len(), 1);
+        assert_eq!(input.side.rendered_channels, 1);
+        assert_eq!(input.desc.mChannelsPerFrame, 2);
+        input.close();
+    });
+}
+
+#[test]
+fn test_simulated_input_layouts() {
+    test_simulated_units(|hardware, units| {
+        let setup = |layout: Vec<AudioChannelLabel>| {
+            let mut mic = FakeDevice::new("mic", 2, 0);
+            mic.input_layout = layout;
+            let mic = hardware.add_device(mic);
+            hardware.set_default_device(mic, DeviceType::INPUT);
+            units.set_device_format(mic, DeviceType::INPUT, 48_000.0, 2);
+            let device = create_device_info(&**hardware, mic, DeviceType::INPUT).unwrap();
+            let params = StreamParams::from(stream_params(2, ffi::CUBEB_LAYOUT_STEREO));
+            let mut input = InputHalf::new(
+                Platform::new(hardware.clone(), units.clone()),
+                params,
+                device.clone(),
+            );
+            assert!(input
+                .setup(&device, LATENCY_FRAMES, ptr::null_mut())
+                .is_ok());
+            let mixed = input.side.mixer.is_some();
+            let device_layout = input.side.device_layout.clone();
+            input.close();
+            (mixed, device_layout)
+        };
+
+        // The channels of a device without a layout are taken in order.
+        assert_eq!(setup(Vec::new()), (false, Vec::new()));
+        assert_eq!(
+            setup(vec![kAudioChannelLabel_Left, kAudioChannelLabel_Right]),
+            (
+                false,
+                vec![mixer::Channel::FrontLeft, mixer::Channel::FrontRight]
+            )
+        );
+        // The channels of the device are mixed into the ones of the stream in their order.
+        assert_eq!(
+            setup(vec![kAudioChannelLabel_Right, kAudioChannelLabel_Left]),
+            (
+                true,
+                vec![mixer::Channel::FrontRight, mixer::Channel::FrontLeft]
+            )
+        );
+    });
+}
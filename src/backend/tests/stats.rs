@@ -0,0 +1,142 @@
+use super::*;
+use std::thread;
+
+// GlitchCounters
+// ------------------------------------
+#[test]
+fn test_glitch_counters() {
+    let counters = GlitchCounters::default();
+    assert_eq!(counters.snapshot(), GlitchStats::default());
+
+    counters.add_input_overrun();
+    counters.add_silence_frames(128);
+    counters.add_silence_frames(64);
+    counters.add_dropped_frames(32);
+    counters.add_output_underrun();
+    let stats = GlitchStats {
+        input_overruns: 1,
+        silence_frames: 192,
+        dropped_frames: 32,
+        output_underruns: 1,
+        deadline_misses: 0,
+    };
+    assert_eq!(counters.snapshot(), stats);
+
+    // The reset returns the counts before the reset.
+    assert_eq!(counters.reset(), stats);
+    assert_eq!(counters.snapshot(), GlitchStats::default());
+}
+
+#[test]
+fn test_glitch_counters_deadline() {
+    let counters = GlitchCounters::default();
+    // 480 frames at 48 kHz last 10 ms.
+    counters.add_callback_time(9_000_000, 480, 48_000.0);
+    counters.add_callback_time(10_000_000, 480, 48_000.0);
+    assert_eq!(counters.snapshot().deadline_misses, 0);
+    counters.add_callback_time(10_000_001, 480, 48_000.0);
+    assert_eq!(counters.snapshot().deadline_misses, 1);
+    // No deadline without a rate.
+    counters.add_callback_time(10_000_001, 480, 0.0);
+    assert_eq!(counters.snapshot().deadline_misses, 1);
+}
+
+// Histogram
+// ------------------------------------
+#[test]
+fn test_histogram_buckets() {
+    assert_eq!(Histogram::bucket(0), 0);
+    assert_eq!(Histogram::bucket(1), 1);
+    assert_eq!(Histogram::bucket(2), 2);
+    assert_eq!(Histogram::bucket(3), 2);
+    assert_eq!(Histogram::bucket(512), 10);
+    assert_eq!(Histogram::bucket(1023), 10);
+    assert_eq!(Histogram::bucket(u64::max_value()), HISTOGRAM_BUCKETS - 1);
+}
+
+#[test]
+fn test_histogram_snapshot() {
+    let histogram = Histogram::default();
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot, HistogramSnapshot::default());
+    assert_eq!(snapshot.mean(), None);
+    assert_eq!(snapshot.percentile(50.0), None);
+
+    for _ in 0..9 {
+        histogram.record(512);
+    }
+    histogram.record(1_500);
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count, 10);
+    assert_eq!(snapshot.sum, 9 * 512 + 1_500);
+    assert_eq!(snapshot.max, 1_500);
+    assert_eq!(snapshot.buckets[10], 9);
+    assert_eq!(snapshot.buckets[11], 1);
+    assert_eq!(snapshot.mean(), Some(610.8));
+    assert_eq!(snapshot.percentile(50.0), Some(1_023));
+    assert_eq!(snapshot.percentile(90.0), Some(1_023));
+    assert_eq!(snapshot.percentile(99.0), Some(1_500));
+}
+
+#[test]
+fn test_histogram_from_threads() {
+    let histogram = Arc::new(Histogram::default());
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let histogram = histogram.clone();
+            thread::spawn(move || {
+                for value in 0..1_000 {
+                    histogram.record(value);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count, 4_000);
+    assert_eq!(snapshot.buckets.iter().sum::<u64>(), 4_000);
+    assert_eq!(snapshot.sum, 4 * 999 * 1_000 / 2);
+    assert_eq!(snapshot.max, 999);
+}
+
+// StatsCollector
+// ------------------------------------
+#[test]
+fn test_stats_collector() {
+    let collector = StatsCollector::default();
+    let stats = collector.snapshot(GlitchStats::default());
+    assert_eq!(stats, StreamStats::default());
+    assert_eq!(stats.last_reinit_reason, ReinitReason::NoReinit);
+
+    collector.output.record(512, 2_500);
+    collector.set_output_latency(600);
+    collector.set_output_latency(400);
+    collector.set_output_latency(500);
+    collector.set_input_buffered_frames(128);
+    collector.set_drift_ratio(1.000_2);
+    collector.set_drift_ratio(0.999_9);
+    collector.set_drift_ratio(1.000_1);
+    collector.add_reinit(ReinitReason::DefaultOutputChanged);
+    collector.add_reinit(ReinitReason::DeviceRemoved);
+
+    let glitches = GlitchStats {
+        output_underruns: 1,
+        ..GlitchStats::default()
+    };
+    let stats = collector.snapshot(glitches);
+    assert_eq!(stats.glitches, glitches);
+    assert_eq!(stats.output_callback_frames.max, 512);
+    assert_eq!(stats.output_callback_duration_us.max, 2);
+    assert_eq!(stats.input_callback_frames.count, 0);
+    assert_eq!(stats.output_latency_frames, 500);
+    assert_eq!(stats.min_output_latency_frames, 400);
+    assert_eq!(stats.max_output_latency_frames, 600);
+    assert_eq!(stats.input_buffered_frames, 128);
+    assert_eq!(stats.drift_ratio, 1.000_1);
+    assert_eq!(stats.min_drift_ratio, 0.999_9);
+    assert_eq!(stats.max_drift_ratio, 1.000_2);
+    assert_eq!(stats.reinits, 2);
+    assert_eq!(stats.last_reinit_reason, ReinitReason::DeviceRemoved);
+}
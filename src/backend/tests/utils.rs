@@ -326,14 +326,14 @@ impl TestDeviceInfo {
     }
 
     fn get_label(id: AudioObjectID, scope: Scope) -> String {
-        match get_device_uid(id, scope.into()) {
+        match get_device_uid(&CoreAudioHardware, id, scope.into()) {
             Ok(uid) => uid.into_string(),
             Err(status) => format!("Unknow. Error: {}", status).to_string(),
         }
     }
 
     fn get_uid(id: AudioObjectID, scope: Scope) -> String {
-        match get_device_label(id, scope.into()) {
+        match get_device_label(&CoreAudioHardware, id, scope.into()) {
             Ok(label) => label.into_string(),
             Err(status) => format!("Unknown. Error: {}", status).to_string(),
         }
@@ -462,7 +462,7 @@ pub fn test_get_master_device(id: AudioObjectID) -> String {
     let mut master: CFStringRef = ptr::null_mut();
     let mut size = mem::size_of::<CFStringRef>();
     assert_eq!(
-        audio_object_get_property_data(id, &address, &mut size, &mut master),
+        audio_object_get_property_data(&CoreAudioHardware, id, &address, &mut size, &mut master),
         NO_ERR
     );
     assert!(!master.is_null());
@@ -506,6 +506,7 @@ pub fn test_audiounit_scope_is_enabled(unit: AudioUnit, scope: Scope) -> bool {
     let mut size = mem::size_of::<UInt32>();
     assert_eq!(
         audio_unit_get_property(
+            &CoreAudioUnits,
             unit,
             kAudioOutputUnitProperty_HasIO,
             scope,
@@ -987,7 +988,7 @@ impl TestDevicePlugger {
             return None;
         }
         let device = device.unwrap();
-        let uid = get_device_global_uid(device);
+        let uid = get_device_global_uid(&CoreAudioHardware, device);
         if uid.is_err() {
             return None;
         }
@@ -1131,7 +1132,8 @@ fn test_get_raw_stream<F>(
         state_callback,
         global_latency_frames.unwrap(),
     );
-    stream.core_stream_data = CoreStreamData::new(&stream, None, None);
+    *stream.core_stream_data =
+        CoreStreamData::new(stream.shared.clone(), Platform::default(), None, None);
 
     operation(&mut stream);
 }
@@ -1256,9 +1258,21 @@ fn test_ops_stream_operation_with_default_callbacks<F>(
         // Feed silence data to output buffer
         if !output_buffer.is_null() {
             let stm = unsafe { &mut *(stream as *mut AudioUnitStream) };
-            let channels = stm.core_stream_data.output_stream_params.channels();
+            let channels = stm
+                .core_stream_data
+                .output
+                .as_ref()
+                .unwrap()
+                .stream_params
+                .channels();
             let samples = nframes as usize * channels as usize;
-            let sample_size = cubeb_sample_size(stm.core_stream_data.output_stream_params.format());
+            let sample_size = stm
+                .core_stream_data
+                .output
+                .as_ref()
+                .unwrap()
+                .format
+                .sample_size();
             unsafe {
                 ptr::write_bytes(output_buffer, 0, samples * sample_size);
             }
@@ -2,7 +2,6 @@
 //
 // This program is made available under an ISC-style license.  See the
 // accompanying file LICENSE for details.
-use cubeb_backend::SampleFormat as fmt;
 use std::mem;
 
 pub fn allocate_array_by_size<T: Clone + Default>(size: usize) -> Vec<T> {
@@ -28,13 +27,6 @@ pub fn retake_forgotten_vec<T>(ptr: *mut T, len: usize) -> Vec<T> {
     unsafe { Vec::from_raw_parts(ptr, len, len) }
 }
 
-pub fn cubeb_sample_size(format: fmt) -> usize {
-    match format {
-        fmt::S16LE | fmt::S16BE | fmt::S16NE => mem::size_of::<i16>(),
-        fmt::Float32LE | fmt::Float32BE | fmt::Float32NE => mem::size_of::<f32>(),
-    }
-}
-
 struct Finalizer<F: FnOnce()>(Option<F>);
 
 impl<F: FnOnce()> Drop for Finalizer<F> {
@@ -60,23 +52,6 @@ fn test_forget_vec_and_retake_it() {
     }
 }
 
-#[test]
-fn test_cubeb_sample_size() {
-    let pairs = [
-        (fmt::S16LE, mem::size_of::<i16>()),
-        (fmt::S16BE, mem::size_of::<i16>()),
-        (fmt::S16NE, mem::size_of::<i16>()),
-        (fmt::Float32LE, mem::size_of::<f32>()),
-        (fmt::Float32BE, mem::size_of::<f32>()),
-        (fmt::Float32NE, mem::size_of::<f32>()),
-    ];
-
-    for pair in pairs.iter() {
-        let (fotmat, size) = pair;
-        assert_eq!(cubeb_sample_size(*fotmat), *size);
-    }
-}
-
 #[test]
 fn test_finally() {
     let mut x = 0;
@@ -3,9 +3,17 @@
 // This program is made available under an ISC-style license.  See the
 // accompanying file LICENSE for details.
 
-use crate::backend::AudioUnitContext;
-use cubeb_backend::{capi, ffi};
-use std::os::raw::{c_char, c_int};
+use crate::backend::{
+    AudioUnitContext, AudioUnitStream, GlitchStats, ReinitChoice, StreamDevice, StreamFormat,
+    StreamOptions, StreamStats, DEFAULT_GAIN_RAMP, DEFAULT_INPUT_BUFFER_MARGIN,
+};
+use cubeb_backend::{capi, ffi, DeviceType, Result, StreamParamsRef};
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
 
 /// # Safety
 ///
@@ -17,3 +25,393 @@ pub unsafe extern "C" fn audiounit_rust_init(
 ) -> c_int {
     capi::capi_init::<AudioUnitContext>(c, context_name)
 }
+
+/// The options of `audiounit_rust_stream_init_with_options`. Each of them is unset when it's
+/// zeroed, so a zeroed struct initializes a stream like `cubeb_stream_init` on the default
+/// devices.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct StreamInitOptions {
+    /// The UIDs of the devices, the `device_id` in the `cubeb_device_info`, instead of their
+    /// `devid`. A null UID selects the default device. The stream switches back to the selected
+    /// devices when they are replugged.
+    pub input_device_uid: *const c_char,
+    pub output_device_uid: *const c_char,
+    /// If `has_sample_format` is not 0, the data is exchanged with the data callback in
+    /// `sample_format`, one of the `StreamFormat` values, instead of the format of the stream
+    /// parameters. The input of the formats cubeb doesn't have must be captured at the rate of
+    /// the device.
+    pub has_sample_format: c_int,
+    pub sample_format: u32,
+    /// If not 0, the data callback gets and fills one buffer per channel. Its input buffer is an
+    /// array of pointers to the input channels, and its output buffer an array of pointers to
+    /// the output channels, each holding the `nframes` samples of its channel. The buffers of a
+    /// stream of one direction that needs no resampling or mixing are the ones of the AudioUnit.
+    pub planar: c_int,
+    /// If not null, the input device channels captured instead of its first channels. The map
+    /// holds the 0-based device channel of each channel of the input stream, in order, so it has
+    /// `input_stream_params->channels` items, each less than the channel count of the device.
+    pub input_channel_map: *const u32,
+    /// If not null, the output device channels played instead of mixing the output channels
+    /// into the channels of the device by their layouts. The map holds the 0-based device
+    /// channel of each channel of the output stream, in order, so it has
+    /// `output_stream_params->channels` items, each less than the channel count of the device.
+    /// The other device channels are silent.
+    pub output_channel_map: *const u32,
+}
+
+impl Default for StreamInitOptions {
+    fn default() -> Self {
+        Self {
+            input_device_uid: ptr::null(),
+            output_device_uid: ptr::null(),
+            has_sample_format: 0,
+            sample_format: 0,
+            planar: 0,
+            input_channel_map: ptr::null(),
+            output_channel_map: ptr::null(),
+        }
+    }
+}
+
+/// Initialize a stream like `cubeb_stream_init`, with the `options` of this backend. Null
+/// `options` leave all of them unset. Return `CUBEB_ERROR_INVALID_FORMAT` if the sample format
+/// isn't one of the `StreamFormat` values.
+///
+/// # Safety
+///
+/// `context` must be a context created by `audiounit_rust_init`. The stream name and the UIDs
+/// must be null or valid C strings, the channel maps must be null or point to as many values as
+/// the channels of their stream parameters, and the other arguments must be valid for
+/// `cubeb_stream_init`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn audiounit_rust_stream_init_with_options(
+    context: *mut ffi::cubeb,
+    stream: *mut *mut ffi::cubeb_stream,
+    stream_name: *const c_char,
+    input_stream_params: *mut ffi::cubeb_stream_params,
+    output_stream_params: *mut ffi::cubeb_stream_params,
+    latency_frames: u32,
+    data_callback: ffi::cubeb_data_callback,
+    state_callback: ffi::cubeb_state_callback,
+    user_ptr: *mut c_void,
+    options: *const StreamInitOptions,
+) -> c_int {
+    fn device(uid: *const c_char) -> Option<StreamDevice> {
+        if uid.is_null() {
+            return Some(StreamDevice::Default);
+        }
+        let uid = unsafe { CStr::from_ptr(uid) };
+        uid.to_str()
+            .ok()
+            .map(|uid| StreamDevice::Uid(uid.to_string()))
+    }
+
+    // The map of a side of the stream, as long as the channels of its stream parameters.
+    unsafe fn channel_map<'a>(
+        map: *const u32,
+        params: *const ffi::cubeb_stream_params,
+    ) -> Option<&'a [u32]> {
+        if map.is_null() {
+            None
+        } else {
+            Some(slice::from_raw_parts(map, (*params).channels as usize))
+        }
+    }
+
+    if context.is_null() || stream.is_null() {
+        return ffi::CUBEB_ERROR_INVALID_PARAMETER;
+    }
+    let options = if options.is_null() {
+        StreamInitOptions::default()
+    } else {
+        *options
+    };
+    let (input_device, output_device) = match (
+        device(options.input_device_uid),
+        device(options.output_device_uid),
+    ) {
+        (Some(input), Some(output)) => (input, output),
+        _ => return ffi::CUBEB_ERROR_INVALID_PARAMETER,
+    };
+    if (input_stream_params.is_null()
+        && (!options.input_device_uid.is_null() || !options.input_channel_map.is_null()))
+        || (output_stream_params.is_null()
+            && (!options.output_device_uid.is_null() || !options.output_channel_map.is_null()))
+    {
+        return ffi::CUBEB_ERROR_INVALID_PARAMETER;
+    }
+    let sample_format = if options.has_sample_format == 0 {
+        None
+    } else {
+        match StreamFormat::try_from(options.sample_format) {
+            Ok(format) => Some(format),
+            Err(()) => return ffi::CUBEB_ERROR_INVALID_FORMAT,
+        }
+    };
+
+    let ctx = &mut *(context as *mut AudioUnitContext);
+    let options = StreamOptions {
+        stream_name: if stream_name.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(stream_name))
+        },
+        input_device,
+        input_stream_params: if input_stream_params.is_null() {
+            None
+        } else {
+            Some(StreamParamsRef::from_ptr(input_stream_params))
+        },
+        output_device,
+        output_stream_params: if output_stream_params.is_null() {
+            None
+        } else {
+            Some(StreamParamsRef::from_ptr(output_stream_params))
+        },
+        latency_frames,
+        gain_ramp: DEFAULT_GAIN_RAMP,
+        input_buffer_margin: DEFAULT_INPUT_BUFFER_MARGIN,
+        sample_format,
+        planar: options.planar != 0,
+        input_channel_map: channel_map(options.input_channel_map, input_stream_params),
+        output_channel_map: channel_map(options.output_channel_map, output_stream_params),
+        data_callback,
+        state_callback,
+        user_ptr,
+    };
+    match ctx.stream_init_with_options(options) {
+        Ok(s) => {
+            *stream = s.as_ptr();
+            // Leaking pointer across C FFI boundary.
+            mem::forget(s);
+            ffi::CUBEB_OK
+        }
+        Err(e) => e.raw_code(),
+    }
+}
+
+fn result_code(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => ffi::CUBEB_OK,
+        Err(e) => e.raw_code(),
+    }
+}
+
+// Write the value got by `get` to `value`, or return the error code.
+unsafe fn get_value<T>(value: *mut T, get: impl FnOnce() -> Result<T>) -> c_int {
+    if value.is_null() {
+        return ffi::CUBEB_ERROR_INVALID_PARAMETER;
+    }
+    match get() {
+        Ok(v) => {
+            *value = v;
+            ffi::CUBEB_OK
+        }
+        Err(e) => e.raw_code(),
+    }
+}
+
+/// Scale the samples captured by the stream by `gain`, in [0.0, 1.0].
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_set_input_gain(
+    stream: *mut ffi::cubeb_stream,
+    gain: f32,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    result_code(stm.set_input_gain(gain))
+}
+
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_input_gain(
+    stream: *mut ffi::cubeb_stream,
+    gain: *mut f32,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(gain, || stm.input_gain())
+}
+
+/// Replace the samples captured by the stream by silence if `mute` is not 0.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_set_input_mute(
+    stream: *mut ffi::cubeb_stream,
+    mute: c_int,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    result_code(stm.set_input_mute(mute != 0))
+}
+
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_input_mute(
+    stream: *mut ffi::cubeb_stream,
+    mute: *mut c_int,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(mute, || stm.input_mute().map(c_int::from))
+}
+
+/// Set the hardware volume, in [0.0, 1.0], of the device captured by the stream. Return
+/// `CUBEB_ERROR_NOT_SUPPORTED` if the device has no input volume.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_set_input_device_volume(
+    stream: *mut ffi::cubeb_stream,
+    volume: f32,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    result_code(stm.set_input_device_volume(volume))
+}
+
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_input_device_volume(
+    stream: *mut ffi::cubeb_stream,
+    volume: *mut f32,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(volume, || stm.input_device_volume())
+}
+
+/// Get the latency, in frames of the input device, from the input data being captured to it
+/// being delivered to the data callback.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_input_latency(
+    stream: *mut ffi::cubeb_stream,
+    latency: *mut u32,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(latency, || stm.input_latency())
+}
+
+/// Get the position, in frames, and the host time, in nanoseconds of `mach_absolute_time`, when
+/// the frame at the position is presented, sampled in the last render callback.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet. `position` and
+/// `host_time_ns` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_timestamp(
+    stream: *mut ffi::cubeb_stream,
+    position: *mut u64,
+    host_time_ns: *mut u64,
+) -> c_int {
+    if position.is_null() || host_time_ns.is_null() {
+        return ffi::CUBEB_ERROR_INVALID_PARAMETER;
+    }
+    let stm = &*(stream as *const AudioUnitStream);
+    match stm.timestamp() {
+        Ok(timestamp) => {
+            *position = timestamp.position;
+            *host_time_ns = timestamp.host_time_ns;
+            ffi::CUBEB_OK
+        }
+        Err(e) => e.raw_code(),
+    }
+}
+
+/// Get the position, in frames, presented now, extrapolated from the last render callback.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_interpolated_position(
+    stream: *mut ffi::cubeb_stream,
+    position: *mut u64,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(position, || stm.interpolated_position())
+}
+
+/// Get the glitches of the stream since it's created or the counters are reset.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_glitch_stats(
+    stream: *mut ffi::cubeb_stream,
+    stats: *mut GlitchStats,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(stats, || Ok(stm.glitch_stats()))
+}
+
+/// Reset the glitch counters of the stream. The glitches counted until the reset are written to
+/// `stats` unless it's null.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_reset_glitch_stats(
+    stream: *mut ffi::cubeb_stream,
+    stats: *mut GlitchStats,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    let last = stm.reset_glitch_stats();
+    if !stats.is_null() {
+        *stats = last;
+    }
+    ffi::CUBEB_OK
+}
+
+/// Get the statistics of the stream: the glitches, the histograms of the frames and the duration
+/// of the callbacks, the buffered input, the output latency and the reinits.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_stats(
+    stream: *mut ffi::cubeb_stream,
+    stats: *mut StreamStats,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(stats, || Ok(stm.stats()))
+}
+
+/// Get how the last reinit picked the device of the `side` of the stream, either
+/// `CUBEB_DEVICE_TYPE_INPUT` or `CUBEB_DEVICE_TYPE_OUTPUT`. It's meant to be called from the
+/// device changed callback. Return `CUBEB_ERROR` if the stream has no such side or wasn't
+/// reinitialized yet.
+///
+/// # Safety
+///
+/// `stream` must be a stream created by this backend and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn audiounit_rust_stream_get_reinit_choice(
+    stream: *mut ffi::cubeb_stream,
+    side: ffi::cubeb_device_type,
+    choice: *mut ReinitChoice,
+) -> c_int {
+    let stm = &*(stream as *const AudioUnitStream);
+    get_value(choice, || {
+        stm.reinit_choice(DeviceType::from_bits_truncate(side))
+    })
+}
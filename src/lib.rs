@@ -17,4 +17,16 @@ extern crate mach;
 mod backend;
 mod capi;
 
-pub use crate::capi::audiounit_rust_init;
+pub use crate::backend::{
+    GlitchStats, HistogramSnapshot, ReinitChoice, ReinitReason, StreamFormat, StreamStats,
+};
+pub use crate::capi::{
+    audiounit_rust_init, audiounit_rust_stream_get_glitch_stats,
+    audiounit_rust_stream_get_input_device_volume, audiounit_rust_stream_get_input_gain,
+    audiounit_rust_stream_get_input_latency, audiounit_rust_stream_get_input_mute,
+    audiounit_rust_stream_get_interpolated_position, audiounit_rust_stream_get_reinit_choice,
+    audiounit_rust_stream_get_stats, audiounit_rust_stream_get_timestamp,
+    audiounit_rust_stream_init_with_options, audiounit_rust_stream_reset_glitch_stats,
+    audiounit_rust_stream_set_input_device_volume, audiounit_rust_stream_set_input_gain,
+    audiounit_rust_stream_set_input_mute, StreamInitOptions,
+};